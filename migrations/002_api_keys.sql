@@ -0,0 +1,18 @@
+-- migrations/002_api_keys.sql
+-- Выполняется и при старте сервера (идемпотентно), поэтому только IF NOT EXISTS
+
+-- API-ключи: хранится только SHA-256 хеш, сам ключ показывается один раз при создании
+CREATE TABLE IF NOT EXISTS api_keys (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    principal_id VARCHAR(100) NOT NULL,
+    key_prefix VARCHAR(16) NOT NULL,
+    key_hash CHAR(64) NOT NULL UNIQUE,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMP,
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_principal_id ON api_keys(principal_id);
+CREATE INDEX IF NOT EXISTS idx_query_audit_log_user_id ON query_audit_log(user_id);
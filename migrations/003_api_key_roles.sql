@@ -0,0 +1,16 @@
+-- migrations/003_api_key_roles.sql
+-- Роли вместо флага is_admin. Выполняется и при старте сервера (идемпотентно)
+
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS role VARCHAR(20) NOT NULL DEFAULT 'analyst'
+    CHECK (role IN ('viewer', 'analyst', 'admin'));
+
+DO $$
+BEGIN
+    IF EXISTS (
+        SELECT FROM information_schema.columns
+        WHERE table_schema = 'public' AND table_name = 'api_keys' AND column_name = 'is_admin'
+    ) THEN
+        UPDATE api_keys SET role = 'admin' WHERE is_admin;
+        ALTER TABLE api_keys DROP COLUMN is_admin;
+    END IF;
+END $$;
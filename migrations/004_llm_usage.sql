@@ -0,0 +1,16 @@
+-- migrations/004_llm_usage.sql
+-- Дневные счетчики запросов и расхода LLM по пользователям (день - по UTC).
+-- Выполняется и при старте сервера (идемпотентно)
+
+CREATE TABLE IF NOT EXISTS llm_usage_daily (
+    user_id VARCHAR(100) NOT NULL,
+    day DATE NOT NULL,
+    requests BIGINT NOT NULL DEFAULT 0,
+    rate_limited BIGINT NOT NULL DEFAULT 0,
+    llm_calls BIGINT NOT NULL DEFAULT 0,
+    llm_tokens BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, day)
+);
+
+CREATE INDEX IF NOT EXISTS idx_llm_usage_daily_day ON llm_usage_daily(day);
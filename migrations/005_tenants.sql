@@ -0,0 +1,14 @@
+-- migrations/005_tenants.sql
+-- Арендаторы (банки-эмитенты) и привязка к ним ключей и аудита.
+-- Выполняется и при старте сервера (идемпотентно)
+
+CREATE TABLE IF NOT EXISTS tenants (
+    id VARCHAR(64) PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    -- Значения issuer_bank_name, доступные арендатору
+    issuer_bank_names TEXT[] NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(64);
+ALTER TABLE query_audit_log ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(64);
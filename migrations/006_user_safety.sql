@@ -0,0 +1,38 @@
+-- migrations/006_user_safety.sql
+-- Нарушения политики безопасности, баны и настройки модерации.
+-- Выполняется и при старте сервера (идемпотентно)
+
+CREATE TABLE IF NOT EXISTS user_violations (
+    id BIGSERIAL PRIMARY KEY,
+    user_id VARCHAR(100) NOT NULL,
+    violation_type VARCHAR(30) NOT NULL,
+    message TEXT NOT NULL,
+    rule_id VARCHAR(100),
+    -- Сколько предупреждений добавило нарушение
+    warnings INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    -- Предупреждения сняты администратором
+    cleared_at TIMESTAMP,
+    cleared_by VARCHAR(100)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_violations_user ON user_violations(user_id, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS user_bans (
+    user_id VARCHAR(100) PRIMARY KEY,
+    banned_until TIMESTAMP NOT NULL,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    lifted_at TIMESTAMP,
+    lifted_by VARCHAR(100)
+);
+
+-- Одна строка: настройки, измененные через /api/admin/safety/settings (важнее переменных окружения)
+CREATE TABLE IF NOT EXISTS safety_settings (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    max_warnings INTEGER NOT NULL,
+    ban_duration_hours INTEGER NOT NULL,
+    warning_decay_hours INTEGER NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_by VARCHAR(100)
+);
@@ -0,0 +1,16 @@
+-- migrations/007_query_role.sql
+-- Роль для SQL пользователей и модели: только SELECT на transactions.
+-- Сервер переключается на нее (SET LOCAL ROLE) внутри транзакции READ ONLY.
+-- Выполняется и при старте сервера (идемпотентно)
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = 'analytics_reader') THEN
+        CREATE ROLE analytics_reader NOLOGIN;
+    END IF;
+END
+$$;
+
+GRANT USAGE ON SCHEMA public TO analytics_reader;
+GRANT SELECT ON transactions TO analytics_reader;
+GRANT analytics_reader TO CURRENT_USER;
@@ -0,0 +1,22 @@
+-- migrations/008_tenant_rls.sql
+-- Изоляция арендаторов на уровне строк для роли analytics_reader (см. 007_query_role.sql).
+-- Сервер задает банки арендатора в транзакции запроса:
+--   set_config('app.issuer_banks', '["Kaspi Bank"]', true) - только эти банки,
+--   set_config('app.issuer_banks', '*', true) - все банки (принципал без арендатора).
+-- Без настройки строки не видны. Владелец таблицы (сервер) политике не подчиняется.
+-- Выполняется и при старте сервера (идемпотентно)
+
+ALTER TABLE transactions ENABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON transactions;
+CREATE POLICY tenant_isolation ON transactions FOR SELECT TO analytics_reader
+    USING (
+        CASE current_setting('app.issuer_banks', true)
+            WHEN '*' THEN true
+            ELSE issuer_bank_name = ANY (ARRAY(
+                SELECT jsonb_array_elements_text(
+                    COALESCE(NULLIF(current_setting('app.issuer_banks', true), ''), '[]')::jsonb
+                )
+            ))
+        END
+    );
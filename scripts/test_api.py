@@ -6,9 +6,12 @@
 
 import requests
 import json
+import os
 import sys
 
 API_URL = "http://localhost:3000/api"
+# API-ключ (создается командой: cargo run -- keys create --name <name>)
+HEADERS = {"X-API-Key": os.environ.get("API_KEY", "")}
 
 def test_health():
     """Проверка health endpoint"""
@@ -33,6 +36,7 @@ def test_query(question):
         response = requests.post(
             f"{API_URL}/query",
             json={"question": question},
+            headers=HEADERS,
             timeout=30
         )
         response.raise_for_status()
@@ -3,6 +3,8 @@
 # Тестирование Payment Analytics Backend API
 
 BASE_URL="http://localhost:3000"
+# API-ключ (создается командой: cargo run -- keys create --name <name>)
+API_KEY="${API_KEY:-}"
 
 echo "🧪 Тестирование Payment Analytics Backend API"
 echo "=============================================="
@@ -18,6 +20,7 @@ echo ""
 # 2. Простой запрос
 echo "2️⃣  Простой запрос: Сколько всего транзакций?"
 curl -s -X POST "$BASE_URL/api/query" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{"question": "Сколько всего транзакций?"}' | jq '.' || echo "❌ Query failed"
 echo ""
@@ -27,6 +30,7 @@ echo ""
 # 3. Запрос с фильтрацией
 echo "3️⃣  Запрос с фильтрацией: Топ 5 мерчантов по объему"
 curl -s -X POST "$BASE_URL/api/query" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{"question": "Топ 5 мерчантов по объему транзакций"}' | jq '.' || echo "❌ Query failed"
 echo ""
@@ -36,6 +40,7 @@ echo ""
 # 4. Запрос по категориям
 echo "4️⃣  Запрос по категориям: Объем транзакций по категориям MCC"
 curl -s -X POST "$BASE_URL/api/query" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{"question": "Объем транзакций по категориям MCC"}' | jq '.' || echo "❌ Query failed"
 echo ""
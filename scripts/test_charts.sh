@@ -3,6 +3,8 @@
 # Специальный тест для проверки данных диаграмм
 
 API_URL="http://localhost:3000/api/query"
+# API-ключ (создается командой: cargo run -- keys create --name <name>)
+API_KEY="${API_KEY:-}"
 
 echo "📊 Тестирование данных для диаграмм"
 echo "===================================="
@@ -19,6 +21,7 @@ test_chart() {
     echo "━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━"
     
     RESPONSE=$(curl -s -X POST "$API_URL" \
+      -H "X-API-Key: $API_KEY" \
       -H "Content-Type: application/json" \
       -d "{
         \"question\": \"$question\",
@@ -4,6 +4,8 @@
 # Проверяет: таблицы, диаграммы, анализ
 
 API_URL="http://localhost:3000/api/query"
+# API-ключ (создается командой: cargo run -- keys create --name <name>)
+API_KEY="${API_KEY:-}"
 
 echo "🧪 Тестирование Payment Analytics API"
 echo "======================================"
@@ -42,6 +44,7 @@ print_table() {
 # Тест 1: Простой запрос (COUNT)
 print_test "1. Простой запрос - количество транзакций"
 RESPONSE=$(curl -s -X POST "$API_URL" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{
     "question": "Сколько всего транзакций?",
@@ -55,6 +58,7 @@ echo "$RESPONSE" | jq '.data'
 # Тест 2: Запрос для таблицы (TOP N)
 print_test "2. Запрос для таблицы - Топ-5 категорий MCC"
 RESPONSE=$(curl -s -X POST "$API_URL" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{
     "question": "Показать топ-5 категорий MCC по количеству транзакций",
@@ -68,6 +72,7 @@ print_table "$RESPONSE"
 # Тест 3: Запрос для Bar диаграммы
 print_test "3. Запрос для Bar диаграммы - Транзакции по типам"
 RESPONSE=$(curl -s -X POST "$API_URL" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{
     "question": "Сколько транзакций каждого типа? Покажи по типам транзакций",
@@ -83,6 +88,7 @@ echo "$RESPONSE" | jq '.data'
 # Тест 4: Запрос для Line диаграммы (временной ряд)
 print_test "4. Запрос для Line диаграммы - Динамика по дням"
 RESPONSE=$(curl -s -X POST "$API_URL" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{
     "question": "Показать динамику транзакций по дням за последние 7 дней",
@@ -98,6 +104,7 @@ echo "$RESPONSE" | jq '.data'
 # Тест 5: Запрос для Pie диаграммы (доли)
 print_test "5. Запрос для Pie диаграммы - Доли по валютам"
 RESPONSE=$(curl -s -X POST "$API_URL" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{
     "question": "Показать распределение транзакций по валютам",
@@ -113,6 +120,7 @@ echo "$RESPONSE" | jq '.data'
 # Тест 6: Сложный аналитический запрос
 print_test "6. Сложный запрос - Анализ по городам"
 RESPONSE=$(curl -s -X POST "$API_URL" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{
     "question": "Топ-10 городов по объему транзакций в KZT",
@@ -127,6 +135,7 @@ print_table "$RESPONSE"
 print_test "7. Тест кэширования"
 echo "Первый запрос (будет закэширован):"
 RESPONSE1=$(curl -s -X POST "$API_URL" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{
     "question": "Сколько транзакций типа POS?",
@@ -138,6 +147,7 @@ echo "$RESPONSE1" | jq '{cached, execution_time_ms}'
 echo ""
 echo "Второй запрос (из кэша):"
 RESPONSE2=$(curl -s -X POST "$API_URL" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{
     "question": "Сколько транзакций типа POS?",
@@ -149,6 +159,7 @@ echo "$RESPONSE2" | jq '{cached, execution_time_ms}'
 # Тест 8: Проверка формата данных для фронтенда
 print_test "8. Формат данных для фронтенда"
 RESPONSE=$(curl -s -X POST "$API_URL" \
+  -H "X-API-Key: $API_KEY" \
   -H "Content-Type: application/json" \
   -d '{
     "question": "Топ-5 категорий MCC",
@@ -1,24 +1,60 @@
 use crate::config::Config;
-use crate::utils::language::{detect_language, Language};
+use crate::privacy::{Audience, ColumnPolicy};
+use crate::utils::language::Language;
 use anyhow::Result;
 use rig::completion::CompletionRequest;
-use rig::completion::request::CompletionModel;
 use rig::completion::message::AssistantContent;
 use rig::message::{Message, UserContent};
 use rig::one_or_many::OneOrMany;
+use std::sync::Arc;
 use rig::client::completion::CompletionClient;
-use super::insights::{AnalysisResult, ChartType};
+use crate::llm::error::{complete, complete_ollama_format, LlmError};
+use super::insights::AnalysisResult;
+use super::schema::{gemini_schema, parse_analysis, ANALYSIS_SCHEMA};
+use super::stats::facts_for_prompt;
+
+/// Версия шаблона промпта анализа (возвращается клиенту в `meta`)
+pub const ANALYSIS_PROMPT_VERSION: &str = "analysis-v3";
+/// Версия промпта общего анализа подзапросов составного вопроса
+pub const COMPOUND_ANALYSIS_PROMPT_VERSION: &str = "analysis-compound-v3";
+
+/// Сколько раз просим модель исправить ответ, не прошедший проверку по схеме
+const MAX_REPAIR_ATTEMPTS: u32 = 2;
+/// Попытки вызова при временной ошибке провайдера (см. `retry_delay`)
+const MAX_CALL_ATTEMPTS: u32 = 3;
+/// Дольше этого подсказку провайдера о повторе (`Retry-After`) не ждем
+const MAX_RETRY_AFTER_SECS: u64 = 5;
+
+const ANALYSIS_PREAMBLE: &str = "You are a data analyst expert. Analyze query results and provide structured insights in JSON format.";
+
+/// Подзапрос составного вопроса для общего анализа
+pub struct AnalysisPart<'a> {
+    pub question: &'a str,
+    pub sql: &'a str,
+    pub data: &'a [serde_json::Value],
+}
 
 pub struct AnalysisClient {
     config: Config,
+    column_policy: Arc<ColumnPolicy>,
+    http: reqwest::Client,
 }
 
 impl AnalysisClient {
-    pub fn new(config: Config) -> Self {
-        Self { config }
+    pub fn new(config: Config, column_policy: Arc<ColumnPolicy>) -> Self {
+        Self { config, column_policy, http: reqwest::Client::new() }
+    }
+
+    fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.config.llm_timeout_secs)
     }
 
     /// Analyze SQL query results and generate human-readable insights
+    #[tracing::instrument(
+        name = "llm.analyze",
+        skip_all,
+        fields(provider = %self.config.llm_provider, rows = data.len())
+    )]
     pub async fn analyze_results(
         &self,
         question: &str,
@@ -26,54 +62,121 @@ impl AnalysisClient {
         data: &[serde_json::Value],
         language: &Language,
     ) -> Result<AnalysisResult> {
-        // Build prompt for analysis
-        let prompt = build_analysis_prompt(question, sql, data, language);
+        // В промпт не попадают идентификаторы карт и транзакций: политика для LLM
+        let mut prompt_data = data.to_vec();
+        self.column_policy.apply(sql, &mut prompt_data, Audience::Llm);
         
-        // Generate analysis using LLM
-        let analysis_text = self.generate_analysis(&prompt).await?;
+        // Build prompt for analysis
+        let prompt = build_analysis_prompt(question, sql, &prompt_data, language);
         
-        // Parse structured response
-        let result = parse_analysis_response(&analysis_text, data, language)?;
+        // Ответ модели ограничен JSON-схемой и строго проверяется
+        self.generate_analysis(&prompt, data).await
+    }
+
+    /// Один анализ на все подзапросы составного вопроса
+    #[tracing::instrument(
+        name = "llm.analyze_compound",
+        skip_all,
+        fields(provider = %self.config.llm_provider, parts = parts.len())
+    )]
+    pub async fn analyze_compound(
+        &self,
+        question: &str,
+        parts: &[AnalysisPart<'_>],
+        language: &Language,
+    ) -> Result<AnalysisResult> {
+        let sections: Vec<String> = parts
+            .iter()
+            .enumerate()
+            .map(|(idx, part)| {
+                let mut prompt_data = part.data.to_vec();
+                self.column_policy.apply(part.sql, &mut prompt_data, Audience::Llm);
+                format!(
+                    "SUB-QUESTION {}: {}\nSQL QUERY: {}\nQUERY RESULTS:\n{}",
+                    idx + 1,
+                    part.question,
+                    part.sql,
+                    summarize_data(&prompt_data)
+                )
+            })
+            .collect();
+        let results = format!(
+            "The question was split into {} sub-queries. Cover ALL of them in ONE combined answer: \
+            the headline answers the whole question, insights compare results across sub-queries where it makes sense.\n\n{}",
+            parts.len(),
+            sections.join("\n\n")
+        );
         
-        Ok(result)
+        let prompt = build_prompt(question, &results, language);
+        let all_data: Vec<serde_json::Value> = parts.iter().flat_map(|part| part.data.iter().cloned()).collect();
+        self.generate_analysis(&prompt, &all_data).await
     }
 
-    async fn generate_analysis(&self, prompt: &str) -> Result<String> {
-        // Retry logic for Gemini (sometimes returns empty response)
+    /// Анализ в режиме JSON: ответ проверяется по ANALYSIS_SCHEMA, при нарушениях модель получает
+    /// промпт исправления со списком ошибок (не больше MAX_REPAIR_ATTEMPTS раз)
+    async fn generate_analysis(&self, prompt: &str, data: &[serde_json::Value]) -> Result<AnalysisResult> {
+        let mut current_prompt = prompt.to_string();
+        let mut repairs = 0;
+        loop {
+            let text = self.call_with_retries(&current_prompt).await?;
+            match parse_analysis(&text) {
+                Ok(mut result) => {
+                    if repairs > 0 {
+                        tracing::info!("Analysis response fixed after {} repair attempts", repairs);
+                    }
+                    result.data = data.to_vec();
+                    return Ok(result);
+                }
+                Err(violations) if repairs < MAX_REPAIR_ATTEMPTS => {
+                    repairs += 1;
+                    tracing::warn!(
+                        "Analysis response violates the schema, asking for a fix (attempt {}/{}): {}",
+                        repairs, MAX_REPAIR_ATTEMPTS, violations.join("; ")
+                    );
+                    current_prompt = build_repair_prompt(prompt, &text, &violations);
+                }
+                Err(violations) => {
+                    return Err(anyhow::anyhow!(
+                        "Analysis response violates the schema after {} repair attempts: {}",
+                        repairs, violations.join("; ")
+                    ));
+                }
+            }
+        }
+    }
+    
+    async fn call_with_retries(&self, prompt: &str) -> Result<String> {
         let mut attempts = 0;
-        let max_attempts = 3;
-        
         loop {
             attempts += 1;
             match self.call_analysis_direct(prompt).await {
-                Ok(text) if !text.trim().is_empty() => {
-                    return Ok(text);
-                }
-                Ok(_) => {
-                    if attempts >= max_attempts {
-                        return Err(anyhow::anyhow!("Empty response from LLM after {} attempts", max_attempts));
-                    }
-                    tracing::warn!("Empty response from LLM, retrying (attempt {}/{})", attempts, max_attempts);
-                    tokio::time::sleep(tokio::time::Duration::from_millis(500 * attempts as u64)).await;
-                }
+                Ok(text) => return Ok(text),
                 Err(e) => {
-                    if attempts >= max_attempts {
-                        return Err(e);
-                    }
-                    tracing::warn!("LLM error, retrying (attempt {}/{}): {}", attempts, max_attempts, e);
-                    tokio::time::sleep(tokio::time::Duration::from_millis(500 * attempts as u64)).await;
+                    let delay = match retry_delay(&e, attempts) {
+                        Some(delay) if attempts < MAX_CALL_ATTEMPTS => delay,
+                        _ => return Err(e),
+                    };
+                    tracing::warn!("LLM error, retrying (attempt {}/{}): {}", attempts, MAX_CALL_ATTEMPTS, e);
+                    tokio::time::sleep(delay).await;
                 }
             }
         }
     }
     
     async fn call_analysis_direct(&self, prompt: &str) -> Result<String> {
-        // Direct implementation using rig-core
         match self.config.llm_provider.as_str() {
             "ollama" => {
-                let client = rig::providers::ollama::Client::new();
-                let model = &self.config.ollama_model;
-                self.call_analysis_ollama_direct(&client, model, prompt).await
+                // `format` со схемой ответа: Ollama ограничивает генерацию грамматикой по схеме
+                let body = serde_json::json!({
+                    "model": self.config.ollama_model,
+                    "messages": [
+                        { "role": "system", "content": ANALYSIS_PREAMBLE },
+                        { "role": "user", "content": prompt }
+                    ],
+                    "format": *ANALYSIS_SCHEMA,
+                    "options": { "temperature": 0.7, "num_predict": 1024 }
+                });
+                complete_ollama_format(&self.http, &self.config.ollama_url, body, self.timeout()).await
             }
             "gemini" => {
                 let api_key = self.config.gemini_api_key.clone()
@@ -87,49 +190,6 @@ impl AnalysisClient {
         }
     }
     
-    async fn call_analysis_ollama_direct(
-        &self,
-        client: &rig::providers::ollama::Client,
-        model: &str,
-        prompt: &str,
-    ) -> Result<String> {
-        let comp_model = client.completion_model(model);
-        
-        let request = CompletionRequest {
-            preamble: Some(
-                "You are a data analyst expert. Analyze query results and provide structured insights in JSON format.".to_string(),
-            ),
-            chat_history: OneOrMany::one(Message::User {
-                content: OneOrMany::one(UserContent::text(prompt)),
-            }),
-            documents: vec![],
-            tools: vec![],
-            temperature: Some(0.7),
-            max_tokens: Some(1024),
-            tool_choice: None,
-            additional_params: None,
-        };
-        
-        let response = comp_model
-            .completion(request)
-            .await
-            .map_err(|e| anyhow::anyhow!("LLM analysis error: {}", e))?;
-        
-        let mut text_parts = Vec::new();
-        for content in response.choice.iter() {
-            if let AssistantContent::Text(text) = content {
-                text_parts.push(text.text.clone());
-            }
-        }
-        
-        let text = text_parts.join(" ").trim().to_string();
-        if text.is_empty() {
-            return Err(anyhow::anyhow!("Empty analysis response from LLM"));
-        }
-        
-        Ok(text)
-    }
-    
     async fn call_analysis_gemini_direct(
         &self,
         client: &rig::providers::gemini::Client,
@@ -138,6 +198,7 @@ impl AnalysisClient {
     ) -> Result<String> {
         let comp_model = client.completion_model(model);
         
+        // responseMimeType + responseSchema: Gemini возвращает JSON по схеме ответа
         let mut additional_params = serde_json::Map::new();
         additional_params.insert(
             "generationConfig".to_string(),
@@ -145,14 +206,14 @@ impl AnalysisClient {
                 "temperature": 0.7,
                 "maxOutputTokens": 1024,
                 "topP": 0.95,
-                "topK": 40
+                "topK": 40,
+                "responseMimeType": "application/json",
+                "responseSchema": gemini_schema(&ANALYSIS_SCHEMA)
             })
         );
         
         let request = CompletionRequest {
-            preamble: Some(
-                "You are a data analyst expert. Analyze query results and provide structured insights in JSON format.".to_string(),
-            ),
+            preamble: Some(ANALYSIS_PREAMBLE.to_string()),
             chat_history: OneOrMany::one(Message::User {
                 content: OneOrMany::one(UserContent::text(prompt)),
             }),
@@ -164,10 +225,7 @@ impl AnalysisClient {
             additional_params: Some(serde_json::Value::Object(additional_params)),
         };
         
-        let response = comp_model
-            .completion(request)
-            .await
-            .map_err(|e| anyhow::anyhow!("Gemini analysis error: {}", e))?;
+        let response = complete(&comp_model, request, self.timeout(), "Gemini").await?;
         
         let mut text_parts = Vec::new();
         for content in response.choice.iter() {
@@ -176,18 +234,20 @@ impl AnalysisClient {
             }
         }
         
-        let text = text_parts.join(" ").trim().to_string();
-        if text.is_empty() {
-            return Err(anyhow::anyhow!("Empty analysis response from Gemini"));
-        }
-        
-        Ok(text)
+        Ok(text_parts.join(" ").trim().to_string())
     }
 
 }
 
 fn build_analysis_prompt(question: &str, sql: &str, data: &[serde_json::Value], language: &Language) -> String {
-    let data_summary = if data.len() <= 10 {
+    let results = format!("SQL QUERY: {}\n\nQUERY RESULTS:\n{}", sql, summarize_data(data));
+    build_prompt(question, &results, language)
+}
+
+/// Данные для промпта: небольшой результат целиком, большой - первые строки;
+/// факты (доли, рост, тренд, выбросы) посчитаны по всем строкам
+fn summarize_data(data: &[serde_json::Value]) -> String {
+    let rows = if data.len() <= 10 {
         format!("Full data: {}", serde_json::to_string(data).unwrap_or_default())
     } else {
         format!(
@@ -196,11 +256,20 @@ fn build_analysis_prompt(question: &str, sql: &str, data: &[serde_json::Value],
             data.len() - 5
         )
     };
-    
+    match facts_for_prompt(data) {
+        Some(facts) => format!("{}\n\n{}", rows, facts),
+        None => rows,
+    }
+}
+
+/// Промпт анализа; `results` - SQL и результаты одного или нескольких запросов
+fn build_prompt(question: &str, results: &str, language: &Language) -> String {
     let (language_name, language_instruction) = match language {
         Language::Russian => ("Russian", "Отвечайте на русском языке. Все тексты (headline, insights, explanation, suggested_questions) должны быть на русском языке."),
         Language::English => ("English", "Respond in English. All texts (headline, insights, explanation, suggested_questions) should be in English."),
         Language::Kazakh => ("Kazakh", "Қазақ тілінде жауап беріңіз. Барлық мәтіндер (headline, insights, explanation, suggested_questions) қазақ тілінде болуы керек."),
+        Language::Uzbek => ("Uzbek", "O'zbek tilida (lotin yozuvida) javob bering. Barcha matnlar (headline, insights, explanation, suggested_questions) o'zbek tilida bo'lishi kerak."),
+        Language::Kyrgyz => ("Kyrgyz", "Кыргыз тилинде жооп бериңиз. Бардык тексттер (headline, insights, explanation, suggested_questions) кыргыз тилинде болушу керек."),
     };
     
     format!(
@@ -210,10 +279,7 @@ fn build_analysis_prompt(question: &str, sql: &str, data: &[serde_json::Value],
 
 USER QUESTION: {question}
 
-SQL QUERY: {sql}
-
-QUERY RESULTS:
-{data_summary}
+{results}
 
 CRITICAL: You MUST return ONLY valid JSON, no markdown, no code blocks, no explanations outside JSON.
 
@@ -237,6 +303,7 @@ Rules:
 - insights: 2-3 key findings with significance (High/Medium/Low) in {language_name} - each insight should be meaningful and actionable. If result is a single number, provide insights about what this number means in context.
 - explanation: Detailed analysis in {language_name} - MUST be comprehensive (4-6 sentences), explaining what the data means, trends, patterns, and implications. Do NOT just repeat the numbers, provide context and interpretation. For single values, explain what this number means, compare it to expectations, provide context about typical values, and explain business implications.
 - suggested_questions: 2-3 follow-up questions in {language_name} that would help users explore the data further
+- numbers: when FACTS are given, take totals, shares, changes, trends and outliers from them - do NOT estimate these from the sample rows.
 - chart_type: One of: Bar, Line, Pie, Table, Trend (or null if not applicable). Use Table ONLY when user explicitly asks for a table or when data has multiple rows with categories. For single aggregated values (COUNT, SUM, AVG without GROUP BY), ALWAYS use null.
 
 CRITICAL: 
@@ -248,151 +315,58 @@ CRITICAL:
     )
 }
 
-fn parse_analysis_response(text: &str, data: &[serde_json::Value], language: &Language) -> Result<AnalysisResult> {
-    tracing::debug!("Raw analysis response: {}", text);
-    
-    // Try to extract JSON from the response
-    let json_text = extract_json_from_text(text);
-    tracing::debug!("Extracted JSON: {}", json_text);
-    
-    // Parse JSON
-    let parsed: serde_json::Value = serde_json::from_str(&json_text)
-        .map_err(|e| {
-            tracing::error!("JSON parse error: {} for text: {}", e, json_text);
-            anyhow::anyhow!("Failed to parse analysis JSON: {}. Raw text: {}", e, text)
-        })?;
-    
-    // Extract fields with better error handling
-    let headline = parsed["headline"]
-        .as_str()
-        .map(|s| s.to_string())
-        .or_else(|| parsed["summary"].as_str().map(|s| s.to_string()))
-        .unwrap_or_else(|| {
-            // Fallback: generate headline from data
-            let (found_records, analysis_complete) = match language {
-                Language::Russian => ("Найдено {} записей", "Анализ завершен"),
-                Language::English => ("Found {} records", "Analysis complete"),
-                Language::Kazakh => ("{} жазба табылды", "Талдау аяқталды"),
-            };
-            if let Some(first_row) = data.first() {
-                if let Some(obj) = first_row.as_object() {
-                    if let Some(count) = obj.values().next() {
-                        return found_records.replace("{}", &count.to_string());
-                    }
-                }
-            }
-            analysis_complete.to_string()
-        });
-    
-    let insights = parsed["insights"]
-        .as_array()
-        .map(|arr| {
-            arr.iter()
-                .filter_map(|v| {
-                    let title = v["title"].as_str()?;
-                    let description = v["description"].as_str()?;
-                    let significance_str = v["significance"].as_str().unwrap_or("Low");
-                    
-                    Some(super::insights::Insight {
-                        title: title.to_string(),
-                        description: description.to_string(),
-                        significance: match significance_str {
-                            "High" => super::insights::InsightSignificance::High,
-                            "Medium" => super::insights::InsightSignificance::Medium,
-                            _ => super::insights::InsightSignificance::Low,
-                        },
-                    })
-                })
-                .collect()
-        })
-        .unwrap_or_default();
-    
-    let explanation = parsed["explanation"]
-        .as_str()
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| {
-            // Fallback explanation
-            let (result_contains, value, first_values) = match language {
-                Language::Russian => (
-                    "Результат запроса содержит {} строк данных. {}",
-                    "Значение: {:?}",
-                    "Первые значения: {:?}",
-                ),
-                Language::English => (
-                    "Query result contains {} rows of data. {}",
-                    "Value: {:?}",
-                    "First values: {:?}",
-                ),
-                Language::Kazakh => (
-                    "Сұрау нәтижесі {} жол деректерді қамтиды. {}",
-                    "Мән: {:?}",
-                    "Алғашқы мәндер: {:?}",
-                ),
-            };
-            format!("{}{}", 
-                result_contains.replace("{}", &data.len().to_string()),
-                if data.len() == 1 {
-                    value.replace("{:?}", &format!("{:?}", data[0]))
-                } else {
-                    first_values.replace("{:?}", &format!("{:?}", &data[..data.len().min(3)]))
-                }
-            )
-        });
-    
-    let suggested_questions = parsed["suggested_questions"]
-        .as_array()
-        .map(|arr| {
-            arr.iter()
-                .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                .collect()
-        })
-        .unwrap_or_else(|| {
-            match language {
-                Language::Russian => vec![
-                    "Показать детализацию".to_string(),
-                    "Сравнить с другими периодами".to_string(),
-                ],
-                Language::English => vec![
-                    "Show details".to_string(),
-                    "Compare with other periods".to_string(),
-                ],
-                Language::Kazakh => vec![
-                    "Толық мәліметтерді көрсету".to_string(),
-                    "Басқа кезеңдермен салыстыру".to_string(),
-                ],
-            }
-        });
-    
-    let chart_type = parsed["chart_type"]
-        .as_str()
-        .and_then(|s| match s {
-            "Bar" => Some(ChartType::Bar),
-            "Line" => Some(ChartType::Line),
-            "Pie" => Some(ChartType::Pie),
-            "Table" => Some(ChartType::Table),
-            "Trend" => Some(ChartType::Trend),
-            _ => None,
-        });
-    
-    Ok(AnalysisResult {
-        headline,
-        insights,
-        explanation,
-        suggested_questions,
-        chart_type,
-        data: data.to_vec(),
-    })
+/// Промпт исправления: исходная задача, прошлый ответ и нарушения схемы
+fn build_repair_prompt(prompt: &str, previous: &str, violations: &[String]) -> String {
+    format!(
+        r#"{prompt}
+
+Your previous answer did not match the required JSON schema.
+
+PREVIOUS ANSWER:
+{previous}
+
+SCHEMA VIOLATIONS:
+- {violations}
+
+Return the corrected answer: ONLY one JSON object with the required structure, no markdown, no text outside JSON."#,
+        violations = violations.join("\n- ")
+    )
 }
 
-fn extract_json_from_text(text: &str) -> String {
-    // Try to find JSON object in the text
-    if let Some(start) = text.find('{') {
-        if let Some(end) = text.rfind('}') {
-            return text[start..=end].to_string();
+/// Пауза перед повтором вызова; None - повтор бесполезен и ошибка возвращается сразу.
+/// Повторяются только недоступность провайдера и 429 с короткой подсказкой `Retry-After`;
+/// таймаут (ответ задержался бы в разы), квота, ошибки API (например, неверный ключ) - нет.
+fn retry_delay(error: &anyhow::Error, attempt: u32) -> Option<std::time::Duration> {
+    match error.downcast_ref::<LlmError>()? {
+        LlmError::Unavailable { .. } => Some(std::time::Duration::from_millis(500 * attempt as u64)),
+        LlmError::RateLimited { retry_after_secs: Some(secs), .. } if *secs <= MAX_RETRY_AFTER_SECS => {
+            Some(std::time::Duration::from_secs(*secs))
         }
+        _ => None,
     }
-    
-    // If no JSON found, return the text as-is (will fail parsing, but that's ok)
-    text.to_string()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_retry_delay() {
+        let provider = || "ollama".to_string();
+        let error = |e: LlmError| anyhow::Error::from(e);
+        assert_eq!(
+            retry_delay(&error(LlmError::Unavailable { provider: provider(), message: "connection refused".into() }), 2),
+            Some(Duration::from_millis(1000))
+        );
+        let rate_limited = |retry_after_secs| LlmError::RateLimited { provider: provider(), message: "slow down".into(), retry_after_secs };
+        assert_eq!(retry_delay(&error(rate_limited(Some(2))), 1), Some(Duration::from_secs(2)));
+        assert_eq!(retry_delay(&error(rate_limited(Some(60))), 1), None);
+        assert_eq!(retry_delay(&error(rate_limited(None)), 1), None);
+
+        assert_eq!(retry_delay(&error(LlmError::QuotaExceeded("Daily LLM quota exceeded".into())), 1), None);
+        assert_eq!(retry_delay(&error(LlmError::Provider { provider: provider(), message: "invalid API key".into() }), 1), None);
+        assert_eq!(retry_delay(&error(LlmError::Timeout { provider: provider(), secs: 60 }), 1), None);
+        assert_eq!(retry_delay(&anyhow::anyhow!("GEMINI_API_KEY not set"), 1), None);
+    }
+}
@@ -1,43 +1,52 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// Structured analysis result with text, insights, and data
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct AnalysisResult {
     /// Main answer to the user's question (headline)
+    #[schemars(length(min = 1))]
     pub headline: String,
     
     /// Key insights and findings
+    #[schemars(length(min = 1, max = 5))]
     pub insights: Vec<Insight>,
     
     /// Detailed explanation
+    #[schemars(length(min = 1))]
     pub explanation: String,
     
     /// Suggested next questions
+    #[schemars(length(max = 5))]
     pub suggested_questions: Vec<String>,
     
     /// Chart type recommendation (if applicable)
     pub chart_type: Option<ChartType>,
     
     /// Raw data for tables/charts
+    #[serde(default)]
+    #[schemars(skip)]
     pub data: Vec<serde_json::Value>,
 }
 
 /// Individual insight or finding
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Insight {
+    #[schemars(length(min = 1))]
     pub title: String,
+    #[schemars(length(min = 1))]
     pub description: String,
     pub significance: InsightSignificance,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum InsightSignificance {
     High,
     Medium,
     Low,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum ChartType {
     Bar,
     Line,
@@ -46,3 +55,4 @@ pub enum ChartType {
     Trend,
 }
 
+
@@ -1,6 +1,11 @@
 mod client;
 mod insights;
+mod schema;
+mod stats;
+mod verify;
 
-pub use client::AnalysisClient;
+pub use client::{AnalysisClient, AnalysisPart, ANALYSIS_PROMPT_VERSION, COMPOUND_ANALYSIS_PROMPT_VERSION};
 pub use insights::{AnalysisResult, Insight, InsightSignificance, ChartType};
 
+pub use stats::{format_number, format_percent, statistical_insights, MAX_INSIGHTS};
+pub use verify::{verify_analysis, AnalysisVerification};
@@ -0,0 +1,230 @@
+use super::insights::AnalysisResult;
+use schemars::generate::SchemaSettings;
+use serde_json::{Map, Value};
+use std::sync::LazyLock;
+
+/// JSON Schema ответа анализа, построенная по serde-типам `AnalysisResult` (без поля `data` -
+/// его заполняет backend). Вложенные типы встроены: `$ref` не понимают ни грамматики Ollama, ни Gemini.
+pub static ANALYSIS_SCHEMA: LazyLock<Value> = LazyLock::new(|| {
+    let schema = SchemaSettings::draft2020_12()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator()
+        .into_root_schema_for::<AnalysisResult>();
+    let mut schema = serde_json::to_value(schema).expect("analysis schema serializes");
+    if let Some(obj) = schema.as_object_mut() {
+        obj.remove("$schema");
+        obj.remove("title");
+    }
+    schema
+});
+
+/// Ключи схемы Gemini `responseSchema` (подмножество OpenAPI 3.0)
+const GEMINI_SCHEMA_KEYS: &[&str] = &["type", "format", "description", "nullable", "enum", "properties", "required", "items"];
+
+/// Схема в формате Gemini: `"type": ["string", "null"]` становится `"nullable": true`,
+/// minItems/maxItems - min_items/max_items (так их читает `Schema` rig-core),
+/// неподдерживаемые ключи (minLength, $schema, ...) отбрасываются
+pub fn gemini_schema(schema: &Value) -> Value {
+    let Some(obj) = schema.as_object() else {
+        return schema.clone();
+    };
+    let mut out = Map::new();
+    for (key, value) in obj {
+        match key.as_str() {
+            "type" => match value {
+                Value::Array(types) => {
+                    let non_null: Vec<&Value> = types.iter().filter(|t| t.as_str() != Some("null")).collect();
+                    if let Some(first) = non_null.first() {
+                        out.insert("type".to_string(), (*first).clone());
+                    }
+                    if non_null.len() < types.len() {
+                        out.insert("nullable".to_string(), Value::Bool(true));
+                    }
+                }
+                other => {
+                    out.insert("type".to_string(), other.clone());
+                }
+            },
+            "enum" => {
+                let values = value.as_array().map(|values| values.iter().filter(|v| !v.is_null()).cloned().collect()).unwrap_or_default();
+                out.insert("enum".to_string(), Value::Array(values));
+            }
+            "properties" => {
+                let properties = value
+                    .as_object()
+                    .map(|properties| properties.iter().map(|(name, schema)| (name.clone(), gemini_schema(schema))).collect())
+                    .unwrap_or_default();
+                out.insert("properties".to_string(), Value::Object(properties));
+            }
+            "items" => {
+                out.insert("items".to_string(), gemini_schema(value));
+            }
+            "minItems" => {
+                out.insert("min_items".to_string(), value.clone());
+            }
+            "maxItems" => {
+                out.insert("max_items".to_string(), value.clone());
+            }
+            key if GEMINI_SCHEMA_KEYS.contains(&key) => {
+                out.insert(key.to_string(), value.clone());
+            }
+            _ => {}
+        }
+    }
+    Value::Object(out)
+}
+
+/// Проверяет значение по схеме (type, enum, required, properties, items, min/maxItems, minLength).
+/// Возвращает нарушения с путем к полю - они же уходят модели в промпт исправления.
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    let mut violations = Vec::new();
+    validate_at(schema, value, "$", &mut violations);
+    violations
+}
+
+fn validate_at(schema: &Value, value: &Value, path: &str, violations: &mut Vec<String>) {
+    let types: Vec<&str> = match &schema["type"] {
+        Value::String(t) => vec![t.as_str()],
+        Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    if !types.is_empty() && !types.iter().any(|t| type_matches(t, value)) {
+        violations.push(format!("{}: expected {}, got {}", path, types.join(" or "), type_name(value)));
+        return;
+    }
+    if let Some(allowed) = schema["enum"].as_array() {
+        if !allowed.contains(value) {
+            let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+            violations.push(format!("{}: {} is not one of {}", path, value, allowed.join(", ")));
+        }
+    }
+    match value {
+        Value::Object(obj) => {
+            for name in schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+                if !obj.contains_key(name) {
+                    violations.push(format!("{}.{}: required field is missing", path, name));
+                }
+            }
+            if let Some(properties) = schema["properties"].as_object() {
+                for (name, property) in properties {
+                    if let Some(field) = obj.get(name) {
+                        validate_at(property, field, &format!("{}.{}", path, name), violations);
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min) = schema["minItems"].as_u64() {
+                if (items.len() as u64) < min {
+                    violations.push(format!("{}: at least {} items required, got {}", path, min, items.len()));
+                }
+            }
+            if let Some(max) = schema["maxItems"].as_u64() {
+                if items.len() as u64 > max {
+                    violations.push(format!("{}: at most {} items allowed, got {}", path, max, items.len()));
+                }
+            }
+            for (idx, item) in items.iter().enumerate() {
+                validate_at(&schema["items"], item, &format!("{}[{}]", path, idx), violations);
+            }
+        }
+        Value::String(text) => {
+            if let Some(min) = schema["minLength"].as_u64() {
+                if (text.trim().chars().count() as u64) < min {
+                    violations.push(format!("{}: must not be empty", path));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn type_matches(expected: &str, value: &Value) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Object(_) => "object",
+        Value::Array(_) => "array",
+        Value::String(_) => "string",
+        Value::Number(_) => "number",
+        Value::Bool(_) => "boolean",
+        Value::Null => "null",
+    }
+}
+
+/// Строгий разбор ответа модели: только JSON-объект, соответствующий ANALYSIS_SCHEMA
+pub fn parse_analysis(text: &str) -> Result<AnalysisResult, Vec<String>> {
+    let value: Value = serde_json::from_str(text.trim()).map_err(|e| vec![format!("$: invalid JSON: {}", e)])?;
+    let violations = validate(&ANALYSIS_SCHEMA, &value);
+    if !violations.is_empty() {
+        return Err(violations);
+    }
+    serde_json::from_value(value).map_err(|e| vec![format!("$: {}", e)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid() -> Value {
+        serde_json::json!({
+            "headline": "Всего 5000 транзакций",
+            "insights": [{"title": "Объем", "description": "Данные за два года", "significance": "High"}],
+            "explanation": "Подробности",
+            "suggested_questions": ["Сравнить по месяцам"],
+            "chart_type": null
+        })
+    }
+
+    #[test]
+    fn test_schema_follows_serde_types() {
+        let properties = ANALYSIS_SCHEMA["properties"].as_object().unwrap();
+        assert!(properties.contains_key("headline") && properties.contains_key("chart_type"));
+        assert!(!properties.contains_key("data"));
+        assert_eq!(ANALYSIS_SCHEMA["properties"]["insights"]["items"]["properties"]["significance"]["enum"], serde_json::json!(["High", "Medium", "Low"]));
+        assert!(ANALYSIS_SCHEMA.get("$defs").is_none());
+    }
+
+    #[test]
+    fn test_gemini_schema() {
+        let schema = gemini_schema(&ANALYSIS_SCHEMA);
+        let chart_type = &schema["properties"]["chart_type"];
+        assert_eq!(chart_type["type"], "string");
+        assert_eq!(chart_type["nullable"], true);
+        assert_eq!(chart_type["enum"], serde_json::json!(["Bar", "Line", "Pie", "Table", "Trend"]));
+        assert!(schema["properties"]["headline"].get("minLength").is_none());
+        assert_eq!(schema["properties"]["insights"]["max_items"], 5);
+        let _: rig::providers::gemini::completion::gemini_api_types::Schema = serde_json::from_value(schema).unwrap();
+    }
+
+    #[test]
+    fn test_parse_analysis() {
+        let result = parse_analysis(&valid().to_string()).unwrap();
+        assert_eq!(result.headline, "Всего 5000 транзакций");
+        assert!(result.chart_type.is_none());
+
+        let mut bad = valid();
+        bad["insights"][0]["significance"] = Value::String("Critical".to_string());
+        bad["headline"] = Value::String(" ".to_string());
+        bad.as_object_mut().unwrap().remove("explanation");
+        let violations = parse_analysis(&bad.to_string()).unwrap_err();
+        assert_eq!(violations.len(), 3, "{:?}", violations);
+        assert!(violations.iter().any(|v| v.starts_with("$.insights[0].significance")));
+        assert!(violations.iter().any(|v| v.starts_with("$.headline")));
+        assert!(violations.iter().any(|v| v.starts_with("$.explanation")));
+
+        // JSON в markdown - тоже нарушение: в режиме JSON модель должна вернуть только объект
+        assert!(parse_analysis(&format!("```json\n{}\n```", valid())).is_err());
+    }
+}
@@ -0,0 +1,511 @@
+use super::insights::{Insight, InsightSignificance};
+use crate::i18n::{column_label, t, tf};
+use crate::llm::tools::numeric_value;
+use crate::utils::language::Language;
+use chrono::NaiveDate;
+use serde_json::Value;
+
+/// Для скольких числовых колонок считаются факты (самые важные - суммы и количества)
+const MAX_MEASURES: usize = 2;
+/// Концентрация: доля первых N категорий
+const TOP_N: usize = 3;
+const MAX_OUTLIERS: usize = 3;
+/// С этого числа строк выбросы ищутся по z-оценке, на меньших выборках - по IQR
+const Z_SCORE_MIN_ROWS: usize = 30;
+const Z_SCORE_THRESHOLD: f64 = 3.0;
+const IQR_MIN_ROWS: usize = 5;
+/// Сколько фактов попадает в `AnalysisResult.insights`
+pub const MAX_INSIGHTS: usize = 5;
+
+const TIME_COLUMN_HINTS: &[&str] = &["date", "day", "week", "month", "quarter", "year", "period", "hour", "time"];
+/// Неаддитивные метрики: доли от их суммы не имеют смысла
+const RATIO_COLUMN_HINTS: &[&str] = &["avg", "average", "mean", "median", "pct", "percent", "share", "rate", "ratio"];
+
+/// Факт, посчитанный по всем строкам результата без LLM
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fact {
+    /// Минимум, максимум и медиана колонки
+    Range { measure: String, min: f64, min_label: Option<String>, max: f64, max_label: Option<String>, median: f64 },
+    /// Доля крупнейшей категории в сумме
+    TopShare { measure: String, label: String, share: f64 },
+    /// Доля первых `n` категорий в сумме
+    Concentration { measure: String, n: usize, share: f64 },
+    /// Изменение последнего периода к предыдущему
+    Growth { measure: String, from_label: String, to_label: String, from: f64, to: f64, change_pct: f64 },
+    /// Наклон линейного тренда на период и он же в процентах от среднего
+    Trend { measure: String, slope: f64, slope_pct: f64, points: usize },
+    /// Значение вне обычного диапазона [low, high] (IQR или z-оценка)
+    Outlier { measure: String, label: Option<String>, value: f64, low: f64, high: f64 },
+}
+
+impl Fact {
+    pub fn significance(&self) -> InsightSignificance {
+        let level = |value: f64, high: f64, medium: f64| {
+            if value >= high {
+                InsightSignificance::High
+            } else if value >= medium {
+                InsightSignificance::Medium
+            } else {
+                InsightSignificance::Low
+            }
+        };
+        match self {
+            Fact::Range { .. } => InsightSignificance::Low,
+            Fact::TopShare { share, .. } => level(*share, 50.0, 25.0),
+            Fact::Concentration { share, .. } => level(*share, 80.0, 50.0),
+            Fact::Growth { change_pct, .. } => level(change_pct.abs(), 20.0, 5.0),
+            Fact::Trend { slope_pct, .. } => level(slope_pct.abs(), 5.0, 1.0),
+            Fact::Outlier { .. } => InsightSignificance::High,
+        }
+    }
+
+    /// Числа факта: обычные и проценты (их принимает проверка чисел анализа)
+    pub fn numbers(&self) -> (Vec<f64>, Vec<f64>) {
+        match self {
+            Fact::Range { min, max, median, .. } => (vec![*min, *max, *median], vec![]),
+            Fact::TopShare { share, .. } | Fact::Concentration { share, .. } => (vec![], vec![*share]),
+            Fact::Growth { from, to, change_pct, .. } => (vec![*from, *to, to - from], vec![*change_pct, change_pct.abs()]),
+            Fact::Trend { slope, slope_pct, .. } => (vec![*slope, slope.abs()], vec![*slope_pct, slope_pct.abs()]),
+            Fact::Outlier { value, low, high, .. } => (vec![*value, *low, *high], vec![]),
+        }
+    }
+
+    /// Пункт анализа на языке ответа
+    pub fn insight(&self, language: Language) -> Insight {
+        let num = |value: f64| format_number(value, language);
+        let pct = |value: f64| format_percent(value, language);
+        let with_label = |value: f64, label: &Option<String>| match label {
+            Some(label) => format!("{} ({})", num(value), label),
+            None => num(value),
+        };
+        let (title, description) = match self {
+            Fact::Range { measure, min, min_label, max, max_label, median } => (
+                "stats.range_title",
+                tf(language, "stats.range", &[
+                    ("measure", &column_label(language, measure)),
+                    ("min", &with_label(*min, min_label)),
+                    ("max", &with_label(*max, max_label)),
+                    ("median", &num(*median)),
+                ]),
+            ),
+            Fact::TopShare { measure, label, share } => (
+                "stats.top_share_title",
+                tf(language, "stats.top_share", &[("label", label), ("share", &pct(*share)), ("measure", &column_label(language, measure))]),
+            ),
+            Fact::Concentration { measure, n, share } => (
+                "stats.concentration_title",
+                tf(language, "stats.concentration", &[("n", n), ("share", &pct(*share)), ("measure", &column_label(language, measure))]),
+            ),
+            Fact::Growth { measure, from_label, to_label, from, to, change_pct } => (
+                "stats.growth_title",
+                tf(language, "stats.growth", &[
+                    ("measure", &column_label(language, measure)),
+                    ("change", &pct(*change_pct)),
+                    ("from", from_label),
+                    ("to", to_label),
+                    ("from_value", &num(*from)),
+                    ("to_value", &num(*to)),
+                ]),
+            ),
+            Fact::Trend { measure, slope, slope_pct, points } => {
+                let key = if slope_pct.abs() < 1.0 {
+                    "stats.trend_flat"
+                } else if *slope > 0.0 {
+                    "stats.trend_up"
+                } else {
+                    "stats.trend_down"
+                };
+                (
+                    "stats.trend_title",
+                    tf(language, key, &[
+                        ("measure", &column_label(language, measure)),
+                        ("slope", &num(slope.abs())),
+                        ("slope_pct", &pct(slope_pct.abs())),
+                        ("points", points),
+                    ]),
+                )
+            }
+            Fact::Outlier { measure, label, value, low, high } => (
+                "stats.outlier_title",
+                tf(language, "stats.outlier", &[
+                    ("measure", &column_label(language, measure)),
+                    ("value", &with_label(*value, label)),
+                    ("low", &num(*low)),
+                    ("high", &num(*high)),
+                ]),
+            ),
+        };
+        Insight { title: t(language, title).to_string(), description, significance: self.significance() }
+    }
+
+    /// Строка факта для промпта анализа
+    pub fn prompt_line(&self) -> String {
+        let num = |value: f64| format_number(value, Language::English);
+        let label = |label: &Option<String>| label.as_ref().map(|label| format!(" ({})", label)).unwrap_or_default();
+        match self {
+            Fact::Range { measure, min, min_label, max, max_label, median } => format!(
+                "{}: min {}{}, max {}{}, median {}",
+                measure, num(*min), label(min_label), num(*max), label(max_label), num(*median)
+            ),
+            Fact::TopShare { measure, label, share } => format!("{}: largest share is {} with {:.1}% of the total", measure, label, share),
+            Fact::Concentration { measure, n, share } => format!("{}: top {} account for {:.1}% of the total", measure, n, share),
+            Fact::Growth { measure, from_label, to_label, from, to, change_pct } => format!(
+                "{}: {} vs {}: {} -> {} ({:+.1}%)",
+                measure, to_label, from_label, num(*from), num(*to), change_pct
+            ),
+            Fact::Trend { measure, slope, slope_pct, points } => format!(
+                "{}: linear trend over {} periods is {} per period ({:+.1}% of the mean)",
+                measure, points, num(*slope), slope_pct
+            ),
+            Fact::Outlier { measure, label: outlier_label, value, low, high } => format!(
+                "{}: outlier {}{} outside the typical range {} - {}",
+                measure, num(*value), label(outlier_label), num(*low), num(*high)
+            ),
+        }
+    }
+}
+
+/// Роли колонок результата: категория, время и числовые метрики
+#[derive(Debug, PartialEq)]
+struct Columns {
+    label: Option<String>,
+    time: Option<String>,
+    measures: Vec<String>,
+}
+
+fn detect_columns(data: &[Value]) -> Columns {
+    let Some(first) = data.iter().find_map(Value::as_object) else {
+        return Columns { label: None, time: None, measures: vec![] };
+    };
+    let is_time = |name: &str, value: &Value| {
+        let name = name.to_lowercase();
+        TIME_COLUMN_HINTS.iter().any(|hint| name.contains(hint)) || value.as_str().is_some_and(|s| parse_date(s).is_some())
+    };
+    let is_id = |name: &str| {
+        let name = name.to_lowercase();
+        name == "id" || name.ends_with("_id") || name.contains("mcc") || name.contains("code") || name.contains("iso")
+    };
+
+    let time = first.iter().find(|(name, value)| is_time(name, value)).map(|(name, _)| name.clone());
+    let label = first
+        .iter()
+        .find(|(name, value)| value.is_string() && Some(*name) != time.as_ref())
+        .map(|(name, _)| name.clone());
+    let mut measures: Vec<String> = first
+        .iter()
+        .filter(|(name, value)| value.is_number() && Some(*name) != time.as_ref() && !is_id(name))
+        .map(|(name, _)| name.clone())
+        .collect();
+    // Сначала суммы, затем количества, затем остальное (средние, доли)
+    let priority = |name: &String| {
+        let name = name.to_lowercase();
+        if ["amount", "sum", "total", "volume", "revenue"].iter().any(|hint| name.contains(hint)) && !is_ratio(&name) {
+            0
+        } else if ["count", "cnt", "number", "transactions"].iter().any(|hint| name.contains(hint)) {
+            1
+        } else {
+            2
+        }
+    };
+    measures.sort_by_key(priority);
+    measures.truncate(MAX_MEASURES);
+    Columns { label, time, measures }
+}
+
+fn is_ratio(name: &str) -> bool {
+    let name = name.to_lowercase();
+    RATIO_COLUMN_HINTS.iter().any(|hint| name.contains(hint))
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// Подпись периода: дата без времени, для помесячных рядов - год и месяц
+fn period_label(column: &str, value: &Value) -> String {
+    match value {
+        Value::String(s) => match parse_date(s) {
+            Some(date) if column.to_lowercase().contains("month") => date.format("%Y-%m").to_string(),
+            Some(date) => date.to_string(),
+            None => s.clone(),
+        },
+        other => other.to_string(),
+    }
+}
+
+fn cell_label(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+/// Квантиль с линейной интерполяцией по отсортированным значениям
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let pos = (sorted.len() - 1) as f64 * q;
+    let (lower, upper) = (pos.floor() as usize, pos.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (pos - lower as f64)
+}
+
+/// Факты по всем строкам результата: разброс, доли и концентрация, рост к прошлому периоду, тренд, выбросы
+pub fn compute_facts(data: &[Value]) -> Vec<Fact> {
+    if data.len() < 2 {
+        return vec![];
+    }
+    let columns = detect_columns(data);
+    let mut facts = Vec::new();
+
+    for measure in &columns.measures {
+        // (подпись, значение); для временного ряда подпись - период
+        let mut points: Vec<(Option<String>, f64)> = data
+            .iter()
+            .filter_map(|row| {
+                let value = numeric_value(row.get(measure)?)?;
+                let label = match &columns.time {
+                    Some(time) => row.get(time).map(|period| period_label(time, period)),
+                    None => cell_label(columns.label.as_ref().and_then(|label| row.get(label))),
+                };
+                Some((label, value))
+            })
+            .collect();
+        if points.len() < 2 {
+            continue;
+        }
+        if columns.time.is_some() {
+            points.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        let values: Vec<f64> = points.iter().map(|(_, value)| *value).collect();
+        let mut sorted = values.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        let (min_idx, max_idx) = (
+            values.iter().enumerate().min_by(|a, b| a.1.total_cmp(b.1)).map(|(idx, _)| idx).unwrap_or(0),
+            values.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).map(|(idx, _)| idx).unwrap_or(0),
+        );
+        facts.push(Fact::Range {
+            measure: measure.clone(),
+            min: values[min_idx],
+            min_label: points[min_idx].0.clone(),
+            max: values[max_idx],
+            max_label: points[max_idx].0.clone(),
+            median: quantile(&sorted, 0.5),
+        });
+
+        // Доли - для аддитивных метрик по категориям
+        let total: f64 = values.iter().sum();
+        if columns.time.is_none() && columns.label.is_some() && !is_ratio(measure) && total > 0.0 && values.iter().all(|v| *v >= 0.0) {
+            if let Some(label) = &points[max_idx].0 {
+                facts.push(Fact::TopShare { measure: measure.clone(), label: label.clone(), share: values[max_idx] / total * 100.0 });
+            }
+            if values.len() > TOP_N + 1 {
+                let top: f64 = sorted.iter().rev().take(TOP_N).sum();
+                facts.push(Fact::Concentration { measure: measure.clone(), n: TOP_N, share: top / total * 100.0 });
+            }
+        }
+
+        // Динамика - для временного ряда
+        if columns.time.is_some() {
+            let (prev, last) = (&points[points.len() - 2], &points[points.len() - 1]);
+            if prev.1 != 0.0 {
+                facts.push(Fact::Growth {
+                    measure: measure.clone(),
+                    from_label: prev.0.clone().unwrap_or_default(),
+                    to_label: last.0.clone().unwrap_or_default(),
+                    from: prev.1,
+                    to: last.1,
+                    change_pct: (last.1 - prev.1) / prev.1.abs() * 100.0,
+                });
+            }
+            if values.len() >= 3 {
+                let slope = linear_slope(&values);
+                let mean = total / values.len() as f64;
+                if mean != 0.0 {
+                    facts.push(Fact::Trend { measure: measure.clone(), slope, slope_pct: slope / mean.abs() * 100.0, points: values.len() });
+                }
+            }
+        }
+
+        facts.extend(find_outliers(measure, &points, &sorted));
+    }
+    facts
+}
+
+/// Наклон МНК-прямой по номеру точки
+fn linear_slope(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    let mean_x = (n - 1.0) / 2.0;
+    let mean_y = values.iter().sum::<f64>() / n;
+    let (mut cov, mut var) = (0.0, 0.0);
+    for (idx, value) in values.iter().enumerate() {
+        let dx = idx as f64 - mean_x;
+        cov += dx * (value - mean_y);
+        var += dx * dx;
+    }
+    if var == 0.0 { 0.0 } else { cov / var }
+}
+
+/// Выбросы: z-оценка |z| >= 3 на больших выборках, правило Тьюки (1.5 IQR) на малых
+fn find_outliers(measure: &str, points: &[(Option<String>, f64)], sorted: &[f64]) -> Vec<Fact> {
+    let n = sorted.len();
+    if n < IQR_MIN_ROWS {
+        return vec![];
+    }
+    let (low, high) = if n >= Z_SCORE_MIN_ROWS {
+        let mean = sorted.iter().sum::<f64>() / n as f64;
+        let std = (sorted.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n as f64).sqrt();
+        if std == 0.0 {
+            return vec![];
+        }
+        (mean - Z_SCORE_THRESHOLD * std, mean + Z_SCORE_THRESHOLD * std)
+    } else {
+        let (q1, q3) = (quantile(sorted, 0.25), quantile(sorted, 0.75));
+        let iqr = q3 - q1;
+        if iqr == 0.0 {
+            return vec![];
+        }
+        (q1 - 1.5 * iqr, q3 + 1.5 * iqr)
+    };
+
+    let mut outliers: Vec<&(Option<String>, f64)> = points.iter().filter(|(_, value)| *value < low || *value > high).collect();
+    let distance = |value: f64| if value > high { value - high } else { low - value };
+    outliers.sort_by(|a, b| distance(b.1).total_cmp(&distance(a.1)));
+    outliers
+        .into_iter()
+        .take(MAX_OUTLIERS)
+        .map(|(label, value)| Fact::Outlier { measure: measure.to_string(), label: label.clone(), value: *value, low: low.max(sorted[0]), high: high.min(sorted[n - 1]) })
+        .collect()
+}
+
+/// Пункты анализа из фактов: сначала значимые, не больше MAX_INSIGHTS
+pub fn statistical_insights(data: &[Value], language: Language) -> Vec<Insight> {
+    let rank = |significance: &InsightSignificance| match significance {
+        InsightSignificance::High => 0,
+        InsightSignificance::Medium => 1,
+        InsightSignificance::Low => 2,
+    };
+    let mut insights: Vec<Insight> = compute_facts(data).iter().map(|fact| fact.insight(language)).collect();
+    insights.sort_by_key(|insight| rank(&insight.significance));
+    insights.truncate(MAX_INSIGHTS);
+    insights
+}
+
+/// Факты для промпта анализа: модель видит только первые строки, а факты посчитаны по всем
+pub fn facts_for_prompt(data: &[Value]) -> Option<String> {
+    let facts = compute_facts(data);
+    if facts.is_empty() {
+        return None;
+    }
+    let lines: Vec<String> = facts.iter().map(|fact| format!("- {}", fact.prompt_line())).collect();
+    Some(format!(
+        "FACTS computed from all {} rows (exact - use these numbers instead of estimating from the sample rows):\n{}",
+        data.len(),
+        lines.join("\n")
+    ))
+}
+
+/// Число с разделителем разрядов: целые суммы без дробной части, небольшие значения - до 2 знаков
+pub fn format_number(value: f64, language: Language) -> String {
+    let decimals = if value.fract().abs() < 1e-9 || value.abs() >= 1000.0 { 0 } else { 2 };
+    let formatted = format!("{:.*}", decimals, value.abs());
+    let (int_part, frac_part) = formatted.split_once('.').unwrap_or((formatted.as_str(), ""));
+    let mut out = String::new();
+    if value < 0.0 && formatted.chars().any(|c| c != '0' && c != '.') {
+        out.push('-');
+    }
+    for (idx, digit) in int_part.chars().enumerate() {
+        if idx > 0 && (int_part.len() - idx) % 3 == 0 {
+            out.push(' ');
+        }
+        out.push(digit);
+    }
+    let frac_part = frac_part.trim_end_matches('0');
+    if !frac_part.is_empty() {
+        out.push(decimal_separator(language));
+        out.push_str(frac_part);
+    }
+    out
+}
+
+/// Процент с одним знаком после запятой
+pub fn format_percent(value: f64, language: Language) -> String {
+    format!("{:.1}", value).replace('.', &decimal_separator(language).to_string())
+}
+
+fn decimal_separator(language: Language) -> char {
+    match language {
+        Language::English => '.',
+        _ => ',',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn kind(fact: &Fact) -> &'static str {
+        match fact {
+            Fact::Range { .. } => "range",
+            Fact::TopShare { .. } => "top_share",
+            Fact::Concentration { .. } => "concentration",
+            Fact::Growth { .. } => "growth",
+            Fact::Trend { .. } => "trend",
+            Fact::Outlier { .. } => "outlier",
+        }
+    }
+
+    #[test]
+    fn test_category_facts() {
+        let data: Vec<Value> = [("Almaty", 500.0), ("Astana", 200.0), ("Shymkent", 100.0), ("Aktobe", 100.0), ("Taraz", 50.0), ("Oral", 50.0)]
+            .iter()
+            .map(|(city, total)| json!({"merchant_city": city, "total_amount": total, "merchant_id": 7}))
+            .collect();
+        let facts = compute_facts(&data);
+        let kinds: Vec<&str> = facts.iter().map(kind).collect();
+        assert_eq!(kinds, vec!["range", "top_share", "concentration", "outlier"]);
+        assert_eq!(facts[0], Fact::Range {
+            measure: "total_amount".to_string(),
+            min: 50.0,
+            min_label: Some("Taraz".to_string()),
+            max: 500.0,
+            max_label: Some("Almaty".to_string()),
+            median: 100.0,
+        });
+        assert_eq!(facts[1], Fact::TopShare { measure: "total_amount".to_string(), label: "Almaty".to_string(), share: 50.0 });
+        assert!(matches!(facts[2], Fact::Concentration { n: 3, share, .. } if (share - 80.0).abs() < 1e-9));
+        assert!(matches!(&facts[3], Fact::Outlier { label: Some(label), value, .. } if label == "Almaty" && *value == 500.0));
+        assert!(matches!(facts[1].significance(), InsightSignificance::High));
+    }
+
+    #[test]
+    fn test_time_series_facts() {
+        let data: Vec<Value> = [100.0, 110.0, 120.0, 150.0]
+            .iter()
+            .enumerate()
+            .map(|(idx, cnt)| json!({"month": format!("2024-0{}-01T00:00:00", 4 - idx), "cnt": cnt}))
+            .collect();
+        let facts = compute_facts(&data);
+        // Ряд упорядочен по периоду, хотя строки пришли в обратном порядке
+        let growth = facts.iter().find(|fact| kind(fact) == "growth").unwrap();
+        assert!(matches!(growth, Fact::Growth { from_label, to_label, change_pct, .. }
+            if from_label == "2024-03" && to_label == "2024-04" && (change_pct + 100.0 / 11.0).abs() < 1e-9));
+        let trend = facts.iter().find(|fact| kind(fact) == "trend").unwrap();
+        assert!(matches!(trend, Fact::Trend { slope, points: 4, .. } if (*slope + 16.0).abs() < 1e-9));
+        assert!(!facts.iter().any(|fact| kind(fact) == "top_share"));
+    }
+
+    #[test]
+    fn test_insights_and_prompt() {
+        let data = vec![json!({"transaction_type": "POS", "cnt": 3000}), json!({"transaction_type": "ECOM", "cnt": 2000})];
+        let insights = statistical_insights(&data, Language::Russian);
+        assert!(matches!(insights[0].significance, InsightSignificance::High));
+        assert_eq!(insights[0].description, "POS - 60,0% от суммы показателя «Количество»");
+        assert!(facts_for_prompt(&data).unwrap().contains("- cnt: largest share is POS with 60.0% of the total"));
+        assert!(facts_for_prompt(&data[..1]).is_none());
+
+        assert_eq!(format_number(1234567.4, Language::Russian), "1 234 567");
+        assert_eq!(format_number(12.5, Language::Russian), "12,5");
+        assert_eq!(format_number(-0.25, Language::English), "-0.25");
+    }
+}
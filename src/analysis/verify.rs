@@ -0,0 +1,455 @@
+use super::insights::AnalysisResult;
+use super::stats::compute_facts;
+use crate::llm::tools::numeric_value;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::LazyLock;
+
+/// Число в тексте: группы разрядов через пробел/запятую/точку, дробная часть, множитель или `%`
+static NUMBER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)(\d{1,3}(?:[ \u{a0}\u{202f},.]\d{3})+(?:[.,]\d+)?|\d+(?:[.,]\d+)?)(?:\s?(%|процент\w*|percent\w*|пайыз\w*|foiz\w*|тыс\.?|тысяч\w*|мың|миң|ming|thousand\w*|млн\.?|миллион\w*|mln\.?|million\w*|млрд\.?|миллиард\w*|mlrd\.?|billion\w*|bn|k|m|b)\b|(%))?",
+    )
+    .expect("valid number regex")
+});
+
+/// Колонки, значения которых уже записаны в процентах (share_pct, change_pct, conversion_rate)
+const PERCENT_COLUMN_MARKERS: [&str; 7] = ["pct", "percent", "share", "rate", "ratio", "доля", "процент"];
+/// Допуск на округление, кроме цифр, которые модель отбросила: 0.5% значения
+const RELATIVE_TOLERANCE: f64 = 0.005;
+
+/// Итог проверки чисел анализа
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationStatus {
+    Verified,      // Все числа подтверждены данными (или чисел нет)
+    Corrected,     // Неверные числа заменены значениями из результата
+    Contradicted,  // Есть числа, которых нет в результате, - помечены в `claims`
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimStatus {
+    Supported,
+    Corrected,
+    Contradicted,
+}
+
+/// Числовое утверждение из текста анализа
+#[derive(Debug, Clone, Serialize)]
+pub struct ClaimCheck {
+    pub field: String,  // headline, insights[0].description, explanation
+    pub text: String,   // Как число записано в тексте
+    pub status: ClaimStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corrected_to: Option<String>,
+}
+
+/// Результат проверки (`meta.verification`)
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalysisVerification {
+    pub status: VerificationStatus,
+    pub claims_checked: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub claims: Vec<ClaimCheck>,
+}
+
+/// Число, найденное в тексте
+#[derive(Debug, Clone, PartialEq)]
+struct Claim {
+    start: usize,
+    end: usize,           // Конец цифр (без множителя)
+    text: String,         // Цифры вместе с множителем
+    values: Vec<f64>,     // Возможные прочтения ("12,345" - 12345 или 12.345)
+    multiplier: f64,
+    percent: bool,
+    decimals: usize,
+    group_separator: Option<char>,
+    decimal_separator: char,
+}
+
+impl Claim {
+    /// Половина единицы последнего показанного разряда: "1,2 млн" покрывает 1 150 000..1 250 000
+    fn tolerance(&self, expected: f64) -> f64 {
+        let half_unit = 0.5 * 10f64.powi(-(self.decimals as i32)) * self.multiplier;
+        half_unit.max(expected.abs() * RELATIVE_TOLERANCE)
+    }
+
+    fn matches(&self, expected: f64) -> bool {
+        self.values.iter().any(|value| (value * self.multiplier - expected).abs() <= self.tolerance(expected))
+    }
+
+    /// Значение в записи исходного числа: те же множитель, точность и разделители
+    fn format_like(&self, expected: f64) -> String {
+        let scaled = expected / self.multiplier;
+        let formatted = format!("{:.*}", self.decimals, scaled.abs());
+        let (int_part, frac_part) = formatted.split_once('.').unwrap_or((formatted.as_str(), ""));
+        let mut out = String::new();
+        if scaled < 0.0 && formatted.chars().any(|c| c != '0' && c != '.') {
+            out.push('-');
+        }
+        match self.group_separator {
+            Some(separator) => {
+                for (idx, digit) in int_part.chars().enumerate() {
+                    if idx > 0 && (int_part.len() - idx) % 3 == 0 {
+                        out.push(separator);
+                    }
+                    out.push(digit);
+                }
+            }
+            None => out.push_str(int_part),
+        }
+        if !frac_part.is_empty() {
+            out.push(self.decimal_separator);
+            out.push_str(frac_part);
+        }
+        out
+    }
+}
+
+/// Значения, которыми модель могла получить число: ячейки результата и то, что считает сам движок, -
+/// число строк, итог столбца, доли строк и топ-N в итоге, изменения между соседними строками
+/// (период к периоду) и между первой и последней, факты `compute_facts`. Произвольные попарные
+/// разности и отношения ячеек не принимаются: на большом результате ими подтверждается почти любое число.
+struct Candidates {
+    values: Vec<f64>,
+    percents: Vec<f64>,
+    /// Единственное число результата (один ряд, одна числовая колонка) - им можно исправить текст
+    single_value: Option<f64>,
+}
+
+impl Candidates {
+    fn build(datasets: &[&[Value]]) -> Self {
+        let mut values = Vec::new();
+        let mut percents = Vec::new();
+        let mut numeric_cells = 0;
+        let mut last_cell = None;
+
+        for data in datasets {
+            values.push(data.len() as f64);
+            // Факты из промпта анализа (рост, доля лидера, наклон тренда и пр.) - тоже подтвержденные числа
+            for fact in compute_facts(data) {
+                let (fact_values, fact_percents) = fact.numbers();
+                values.extend(fact_values);
+                percents.extend(fact_percents);
+            }
+            let mut columns: Vec<(String, Vec<f64>)> = Vec::new();
+            for row in data.iter() {
+                let Some(obj) = row.as_object() else { continue };
+                for (name, cell) in obj {
+                    let Some(value) = numeric_value(cell) else { continue };
+                    numeric_cells += 1;
+                    last_cell = Some(value);
+                    match columns.iter_mut().find(|(column, _)| column == name) {
+                        Some((_, column)) => column.push(value),
+                        None => columns.push((name.clone(), vec![value])),
+                    }
+                }
+            }
+
+            for (name, column) in &columns {
+                values.extend(column.iter().copied());
+                // Процент, записанный в данных как есть
+                let name = name.to_lowercase();
+                if PERCENT_COLUMN_MARKERS.iter().any(|marker| name.contains(marker)) {
+                    percents.extend(column.iter().copied());
+                }
+
+                let sum: f64 = column.iter().sum();
+                values.push(sum);
+                if sum != 0.0 {
+                    // Доля каждой строки и первых N строк (топ-N в отсортированном результате)
+                    let mut running = 0.0;
+                    for value in column {
+                        running += value;
+                        percents.push(value / sum * 100.0);
+                        percents.push(running / sum * 100.0);
+                    }
+                }
+
+                let pairs = column.windows(2).map(|pair| (pair[0], pair[1]));
+                let first_last = (column.len() > 2).then(|| (column[0], column[column.len() - 1]));
+                for (a, b) in pairs.chain(first_last) {
+                    values.push((b - a).abs());
+                    for (from, to) in [(a, b), (b, a)] {
+                        if from != 0.0 {
+                            percents.push((to - from) / from * 100.0);
+                            percents.push(((to - from) / from * 100.0).abs());
+                            values.push(to / from);
+                        }
+                    }
+                }
+            }
+        }
+
+        Self {
+            values,
+            percents,
+            single_value: if numeric_cells == 1 { last_cell } else { None },
+        }
+    }
+
+    fn supports(&self, claim: &Claim) -> bool {
+        let pool = if claim.percent { &self.percents } else { &self.values };
+        pool.iter().any(|expected| claim.matches(*expected))
+    }
+}
+
+/// Извлекает числа из текста. Пропускает то, что не является утверждением о данных: годы, даты и время,
+/// коды вроде MCC5411/Q1, небольшие целые без единиц ("топ-5", "3 города") и числа из вопроса.
+fn extract_claims(text: &str, question: &str) -> Vec<Claim> {
+    let question_numbers: Vec<f64> = NUMBER
+        .captures_iter(question)
+        .filter_map(|caps| parse_number(&caps[1]).into_iter().next())
+        .collect();
+
+    let mut claims = Vec::new();
+    for caps in NUMBER.captures_iter(text) {
+        let digits = caps.get(1).expect("number group");
+        let suffix = caps.get(2).or_else(|| caps.get(3)).map(|m| m.as_str().to_lowercase());
+        let (start, end) = (digits.start(), digits.end());
+
+        let before = text[..start].chars().next_back();
+        let after: Vec<char> = text[end..].chars().take(2).collect();
+        if before.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            continue;
+        }
+        // Части дат, времени и диапазонов кодов: 2024-01-15, 15.01.2024, 10:30, 1/4
+        let joined_after = matches!(after.first(), Some('-' | '/' | ':' | '.')) && after.get(1).is_some_and(|c| c.is_ascii_digit());
+        let joined_before = matches!(before, Some('/' | ':'))
+            || (before == Some('-') && text[..start].chars().rev().nth(1).is_some_and(|c| c.is_ascii_digit()));
+        if joined_after || joined_before {
+            continue;
+        }
+        if after.first().is_some_and(|c| c.is_ascii_alphabetic()) && suffix.is_none() {
+            continue;
+        }
+
+        let raw = digits.as_str();
+        let values = parse_number(raw);
+        let Some(&first) = values.first() else { continue };
+        let percent = suffix.as_deref().is_some_and(|s| {
+            s == "%" || s.starts_with("процент") || s.starts_with("percent") || s.starts_with("пайыз") || s.starts_with("foiz")
+        });
+        let multiplier = suffix.as_deref().map(multiplier_of).unwrap_or(1.0);
+        let plain_integer = !raw.contains([',', '.', ' ', '\u{a0}', '\u{202f}']);
+
+        if plain_integer && multiplier == 1.0 && !percent && ((1900.0..=2100.0).contains(&first) || first <= 31.0) {
+            continue;
+        }
+        if !percent && multiplier == 1.0 && question_numbers.iter().any(|n| values.contains(n)) {
+            continue;
+        }
+
+        let (group_separator, decimal_separator, decimals) = number_style(raw);
+        claims.push(Claim {
+            start,
+            end,
+            text: caps.get(0).expect("whole match").as_str().trim_end().to_string(),
+            values,
+            multiplier,
+            percent,
+            decimals,
+            group_separator,
+            decimal_separator,
+        });
+    }
+    claims
+}
+
+fn multiplier_of(suffix: &str) -> f64 {
+    let suffix = suffix.trim_end_matches('.');
+    if suffix.starts_with("тыс") || suffix == "мың" || suffix == "миң" || suffix == "ming" || suffix.starts_with("thousand") || suffix == "k" {
+        1e3
+    } else if suffix.starts_with("млн") || suffix.starts_with("миллион") || suffix == "mln" || suffix.starts_with("million") || suffix == "m" {
+        1e6
+    } else if suffix.starts_with("млрд") || suffix.starts_with("миллиард") || suffix == "mlrd" || suffix.starts_with("billion") || suffix == "bn" || suffix == "b" {
+        1e9
+    } else {
+        1.0
+    }
+}
+
+/// Все прочтения числа: "1 234,5" и "1,234.5" однозначны, а "12,345" - тысячи или дробь
+fn parse_number(raw: &str) -> Vec<f64> {
+    let separators: Vec<char> = raw.chars().filter(|c| !c.is_ascii_digit()).collect();
+    let digits_only = |s: &str| s.chars().filter(char::is_ascii_digit).collect::<String>();
+    let mut readings = Vec::new();
+    match separators.last() {
+        None => readings.push(raw.parse().ok()),
+        Some(&last) => {
+            let tail = raw.rsplit(last).next().unwrap_or("");
+            let first_sep = separators[0];
+            // Разделитель разрядов - только перед группой из 3 цифр; последний другой разделитель - дробный
+            let all_same = separators.iter().all(|c| *c == first_sep);
+            if tail.len() != 3 || (!all_same && last != first_sep) {
+                let (int_part, frac) = raw.rsplit_once(last).unwrap_or((raw, ""));
+                readings.push(format!("{}.{}", digits_only(int_part), frac).parse().ok());
+            } else {
+                readings.push(digits_only(raw).parse().ok());
+                if separators.len() == 1 && (last == ',' || last == '.') {
+                    readings.push(raw.replace(',', ".").parse().ok());
+                }
+            }
+        }
+    }
+    readings.into_iter().flatten().collect()
+}
+
+/// Разделитель разрядов, дробный разделитель и число знаков после запятой
+fn number_style(raw: &str) -> (Option<char>, char, usize) {
+    let values = parse_number(raw);
+    let separators: Vec<char> = raw.chars().filter(|c| !c.is_ascii_digit()).collect();
+    let Some(&last) = separators.last() else {
+        return (None, ',', 0);
+    };
+    let integer_reading = values.first().is_some_and(|v| v.fract() == 0.0) && raw.rsplit(last).next().is_some_and(|tail| tail.len() == 3);
+    if integer_reading && (separators.len() > 1 || last == ' ' || last == '\u{a0}' || last == '\u{202f}') {
+        return (Some(separators[0]), if separators[0] == ',' { '.' } else { ',' }, 0);
+    }
+    if integer_reading && separators.iter().all(|c| *c == last) {
+        return (Some(last), if last == ',' { '.' } else { ',' }, 0);
+    }
+    let decimals = raw.rsplit(last).next().map(str::len).unwrap_or(0);
+    let group = separators.iter().find(|c| **c != last).copied();
+    (group, last, decimals)
+}
+
+/// Проверяет числа в headline, insights и explanation по полному результату (всем наборам данных).
+/// Если в результате одно число, неверные значения заменяются им; остальные неподтвержденные помечаются.
+pub fn verify_analysis(analysis: &mut AnalysisResult, datasets: &[&[Value]], question: &str) -> AnalysisVerification {
+    let candidates = Candidates::build(datasets);
+    let mut claims = Vec::new();
+
+    let mut check = |field: String, text: &mut String| {
+        let mut rewritten = text.clone();
+        let mut field_claims = Vec::new();
+        // С конца, чтобы замены не сдвигали позиции следующих чисел
+        for claim in extract_claims(text, question).into_iter().rev() {
+            let (status, corrected_to) = if candidates.supports(&claim) {
+                (ClaimStatus::Supported, None)
+            } else if let (Some(expected), false) = (candidates.single_value, claim.percent) {
+                let corrected = claim.format_like(expected);
+                rewritten.replace_range(claim.start..claim.end, &corrected);
+                (ClaimStatus::Corrected, Some(corrected))
+            } else {
+                (ClaimStatus::Contradicted, None)
+            };
+            field_claims.push(ClaimCheck { field: field.clone(), text: claim.text, status, corrected_to });
+        }
+        field_claims.reverse();
+        claims.extend(field_claims);
+        *text = rewritten;
+    };
+
+    check("headline".to_string(), &mut analysis.headline);
+    for (idx, insight) in analysis.insights.iter_mut().enumerate() {
+        check(format!("insights[{}].title", idx), &mut insight.title);
+        check(format!("insights[{}].description", idx), &mut insight.description);
+    }
+    check("explanation".to_string(), &mut analysis.explanation);
+
+    let status = if claims.iter().any(|c| c.status == ClaimStatus::Contradicted) {
+        VerificationStatus::Contradicted
+    } else if claims.iter().any(|c| c.status == ClaimStatus::Corrected) {
+        VerificationStatus::Corrected
+    } else {
+        VerificationStatus::Verified
+    };
+    if status != VerificationStatus::Verified {
+        tracing::warn!("Analysis numbers do not match the data: {:?}", claims.iter().filter(|c| c.status != ClaimStatus::Supported).collect::<Vec<_>>());
+    }
+
+    AnalysisVerification {
+        status,
+        claims_checked: claims.len(),
+        claims,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{Insight, InsightSignificance};
+
+    fn analysis(headline: &str, description: &str) -> AnalysisResult {
+        AnalysisResult {
+            headline: headline.to_string(),
+            insights: vec![Insight { title: "Итог".to_string(), description: description.to_string(), significance: InsightSignificance::High }],
+            explanation: "Данные за 2024 год, топ-5 городов".to_string(),
+            suggested_questions: vec![],
+            chart_type: None,
+            data: vec![],
+        }
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("1 234 567"), vec![1234567.0]);
+        assert_eq!(parse_number("1,5"), vec![1.5]);
+        assert_eq!(parse_number("1,234.5"), vec![1234.5]);
+        assert_eq!(parse_number("1.234,5"), vec![1234.5]);
+        assert_eq!(parse_number("12,345"), vec![12345.0, 12.345]);
+        assert_eq!(number_style("1 234 567"), (Some(' '), ',', 0));
+        assert_eq!(number_style("1,5"), (None, ',', 1));
+    }
+
+    #[test]
+    fn test_extract_claims_skips_non_claims() {
+        let claims = extract_claims("За 2024-01-15 в MCC5411 топ-5: 1,2 млн тг, рост 15% к 10:30, всего 4 870", "Сколько в 2024?");
+        let texts: Vec<&str> = claims.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["1,2 млн", "15%", "4 870"]);
+        assert_eq!(claims[0].multiplier, 1e6);
+        assert!(claims[1].percent);
+    }
+
+    #[test]
+    fn test_verify_against_full_result() {
+        let data = vec![
+            serde_json::json!({"merchant_city": "Almaty", "total": 1_200_000.0}),
+            serde_json::json!({"merchant_city": "Astana", "total": 800_000.0}),
+            serde_json::json!({"merchant_city": "Shymkent", "total": 500_000.0}),
+        ];
+        // Сумма 2,5 млн, доля Алматы 48%, Алматы на 50% больше Астаны
+        let mut result = analysis("Всего 2,5 млн тенге", "Алматы - 48% объема, на 50% больше Астаны");
+        let verification = verify_analysis(&mut result, &[&data], "Топ городов");
+        assert_eq!(verification.status, VerificationStatus::Verified);
+        assert_eq!(verification.claims_checked, 3);
+
+        let mut result = analysis("Всего 3,1 млн тенге", "Алматы - 48% объема");
+        let verification = verify_analysis(&mut result, &[&data], "Топ городов");
+        assert_eq!(verification.status, VerificationStatus::Contradicted);
+        assert_eq!(verification.claims[0].field, "headline");
+        assert_eq!(verification.claims[0].status, ClaimStatus::Contradicted);
+        assert_eq!(result.headline, "Всего 3,1 млн тенге");
+    }
+
+    #[test]
+    fn test_invented_number_on_large_result() {
+        // 20 строк: 10 000 + 1 000 * i^2
+        let data: Vec<Value> = (0..20)
+            .map(|i| serde_json::json!({"merchant_name": format!("Merchant {}", i), "total": 10_000.0 + 1_000.0 * (i * i) as f64}))
+            .collect();
+        // Итог столбца и изменение между соседними строками подтверждаются
+        let mut result = analysis("Всего 2 670 000 тенге", "Merchant 1 больше Merchant 0 на 10%");
+        assert_eq!(verify_analysis(&mut result, &[&data], "Топ мерчантов").status, VerificationStatus::Verified);
+
+        // 80 000 - разность 9-й и 1-й строк, 250% - изменение между 4-й и 9-й: ни то, ни другое движок не считает
+        let mut result = analysis("Разрыв между мерчантами - 80 000 тенге", "Лидер больше на 250%");
+        let verification = verify_analysis(&mut result, &[&data], "Топ мерчантов");
+        assert_eq!(verification.status, VerificationStatus::Contradicted);
+        assert!(verification.claims.iter().all(|c| c.status == ClaimStatus::Contradicted));
+    }
+
+    #[test]
+    fn test_single_value_is_rewritten() {
+        let data = vec![serde_json::json!({"total_amount": 15_432_876.4})];
+        let mut result = analysis("Общая сумма - 12 345 678 тенге", "Около 15,4 млн тенге");
+        let verification = verify_analysis(&mut result, &[&data], "Общая сумма?");
+        assert_eq!(verification.status, VerificationStatus::Corrected);
+        assert_eq!(result.headline, "Общая сумма - 15 432 876 тенге");
+        assert_eq!(verification.claims[0].corrected_to.as_deref(), Some("15 432 876"));
+        assert_eq!(verification.claims[1].status, ClaimStatus::Supported);
+    }
+}
@@ -0,0 +1,183 @@
+use super::median;
+use super::series::Granularity;
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use serde::Serialize;
+
+/// Порог модифицированной z-оценки (Иглевич - Хоглин)
+pub const DEFAULT_THRESHOLD: f64 = 3.5;
+/// Коэффициент модифицированной z-оценки: 0.6745 * (x - медиана) / MAD
+const MAD_SCALE: f64 = 0.6745;
+
+/// Точка ряда с ожидаемым значением и оценкой отклонения
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoredPoint {
+    pub period: NaiveDateTime,
+    pub value: Option<f64>,  // None - значение неизвестно (период скрыт из-за MIN_GROUP_SIZE)
+    pub expected: f64,
+    pub score: f64,
+    pub anomaly: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Seasonality {
+    Weekday,    // Дневной ряд: день недели
+    HourOfDay,  // Почасовой ряд: час суток
+    MonthOfYear,  // Помесячный ряд: месяц года
+}
+
+impl Seasonality {
+    pub fn for_granularity(granularity: Granularity) -> Self {
+        match granularity {
+            Granularity::Daily => Seasonality::Weekday,
+            Granularity::Hourly => Seasonality::HourOfDay,
+            Granularity::Monthly => Seasonality::MonthOfYear,
+        }
+    }
+
+    fn key(self, period: NaiveDateTime) -> usize {
+        match self {
+            Seasonality::Weekday => period.weekday().num_days_from_monday() as usize,
+            Seasonality::HourOfDay => period.hour() as usize,
+            Seasonality::MonthOfYear => period.month0() as usize,
+        }
+    }
+
+    fn buckets(self) -> usize {
+        match self {
+            Seasonality::Weekday => 7,
+            Seasonality::HourOfDay => 24,
+            Seasonality::MonthOfYear => 12,
+        }
+    }
+
+    /// Полуширина скользящего окна: неделя для дней, сутки для часов, полгода для месяцев
+    fn half_window(self) -> usize {
+        match self {
+            Seasonality::Weekday => 7,
+            Seasonality::HourOfDay => 24,
+            Seasonality::MonthOfYear => 6,
+        }
+    }
+
+    /// Меньше двух сезонных циклов - базовую линию не построить
+    pub fn min_points(self) -> usize {
+        self.buckets() * 2
+    }
+}
+
+/// Поиск аномалий: сезонная поправка (медиана по дню недели / часу суток минус общая медиана),
+/// скользящая медиана очищенного ряда как базовая линия, модифицированная z-оценка остатков через MAD.
+/// Точка аномальна, если |score| >= threshold. Ряд должен идти с равным шагом (см. `forecast::fill_gaps`):
+/// окно берется по индексам. Неизвестные значения (None) не входят в медианы и не оцениваются.
+pub fn detect(series: &[(NaiveDateTime, Option<f64>)], seasonality: Seasonality, threshold: f64) -> Vec<ScoredPoint> {
+    let known: Vec<f64> = series.iter().filter_map(|(_, value)| *value).collect();
+    if known.is_empty() {
+        return vec![];
+    }
+    let overall = median(&known);
+
+    // Аддитивная сезонность: переживает нулевые значения, в отличие от мультипликативной
+    let mut offsets = vec![0.0; seasonality.buckets()];
+    for (key, offset) in offsets.iter_mut().enumerate() {
+        let bucket: Vec<f64> = series.iter().filter(|(period, _)| seasonality.key(*period) == key).filter_map(|(_, value)| *value).collect();
+        if bucket.len() >= 2 {
+            *offset = median(&bucket) - overall;
+        }
+    }
+    let offset = |period: NaiveDateTime| offsets[seasonality.key(period)];
+    let adjusted: Vec<Option<f64>> = series.iter().map(|(period, value)| value.map(|value| value - offset(*period))).collect();
+
+    let half = seasonality.half_window();
+    let expected: Vec<f64> = series
+        .iter()
+        .enumerate()
+        .map(|(idx, (period, _))| {
+            let window: Vec<f64> = adjusted[idx.saturating_sub(half)..(idx + half + 1).min(adjusted.len())].iter().flatten().copied().collect();
+            let baseline = if window.is_empty() { overall } else { median(&window) };
+            baseline + offset(*period)
+        })
+        .collect();
+
+    let residuals: Vec<Option<f64>> = series.iter().zip(&expected).map(|((_, value), expected)| value.map(|value| value - expected)).collect();
+    let known_residuals: Vec<f64> = residuals.iter().flatten().copied().collect();
+    let center = median(&known_residuals);
+    let deviations: Vec<f64> = known_residuals.iter().map(|r| (r - center).abs()).collect();
+    let mut mad = median(&deviations);
+    if mad == 0.0 {
+        // Больше половины остатков нулевые: берем среднее отклонение (1.2533 - его связь с σ)
+        mad = deviations.iter().sum::<f64>() / deviations.len() as f64 * 1.2533 * MAD_SCALE;
+    }
+
+    series
+        .iter()
+        .zip(expected)
+        .zip(residuals)
+        .map(|(((period, value), expected), residual)| {
+            let score = match residual {
+                Some(residual) if mad > 0.0 => MAD_SCALE * (residual - center) / mad,
+                _ => 0.0,
+            };
+            ScoredPoint { period: *period, value: *value, expected, score, anomaly: score.abs() >= threshold }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::forecast::fill_gaps;
+    use chrono::{Duration, NaiveDate};
+
+    fn daily(values: &[f64]) -> Vec<(NaiveDateTime, Option<f64>)> {
+        // 2024-01-01 - понедельник
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        values.iter().enumerate().map(|(idx, value)| (start + Duration::days(idx as i64), Some(*value))).collect()
+    }
+
+    #[test]
+    fn test_weekday_seasonality_is_not_anomalous() {
+        // Выходные стабильно ниже будней - это сезонность, а не аномалия
+        let values: Vec<f64> = (0..42).map(|day| if day % 7 >= 5 { 40.0 } else { 100.0 + (day % 3) as f64 }).collect();
+        let scored = detect(&daily(&values), Seasonality::Weekday, DEFAULT_THRESHOLD);
+        assert!(scored.iter().all(|point| !point.anomaly), "{:?}", scored.iter().filter(|p| p.anomaly).collect::<Vec<_>>());
+        assert!((scored[5].expected - 40.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_spike_and_drop() {
+        let mut values: Vec<f64> = (0..42).map(|day| if day % 7 >= 5 { 40.0 } else { 100.0 + (day % 3) as f64 }).collect();
+        values[17] = 300.0;  // Четверг
+        values[26] = 5.0;    // Суббота
+        let scored = detect(&daily(&values), Seasonality::Weekday, DEFAULT_THRESHOLD);
+        let flagged: Vec<usize> = scored.iter().enumerate().filter(|(_, p)| p.anomaly).map(|(idx, _)| idx).collect();
+        assert_eq!(flagged, vec![17, 26]);
+        assert!(scored[17].score > 0.0 && scored[26].score < 0.0);
+    }
+
+    #[test]
+    fn test_zero_day_and_unknown_period() {
+        // День без транзакций выпадает из результата SQL; после заполнения пропусков это ноль - провал
+        let values: Vec<f64> = (0..42).map(|day| if day % 7 >= 5 { 40.0 } else { 100.0 + (day % 3) as f64 }).collect();
+        let mut series: Vec<(NaiveDateTime, f64)> = daily(&values).into_iter().map(|(period, value)| (period, value.unwrap())).collect();
+        series.remove(24);  // Четверг
+        let mut filled: Vec<(NaiveDateTime, Option<f64>)> =
+            fill_gaps(&series, Granularity::Daily).into_iter().map(|(period, value)| (period, Some(value))).collect();
+        assert_eq!(filled.len(), 42);
+        // Скрытый из-за MIN_GROUP_SIZE период - неизвестен, а не ноль
+        filled[31].1 = None;
+
+        let scored = detect(&filled, Seasonality::Weekday, DEFAULT_THRESHOLD);
+        let flagged: Vec<usize> = scored.iter().enumerate().filter(|(_, p)| p.anomaly).map(|(idx, _)| idx).collect();
+        assert_eq!(flagged, vec![24]);
+        assert!(scored[24].score < 0.0 && scored[24].value == Some(0.0));
+        assert!(scored[31].value.is_none() && scored[31].score == 0.0);
+        assert!((scored[31].expected - 101.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn test_constant_series() {
+        let scored = detect(&daily(&[10.0; 20]), Seasonality::Weekday, DEFAULT_THRESHOLD);
+        assert!(scored.iter().all(|point| point.score == 0.0 && !point.anomaly));
+    }
+}
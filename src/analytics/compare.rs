@@ -0,0 +1,198 @@
+use serde_json::{Map, Value};
+
+/// Суффиксы колонок сравнения: `<метрика>_a`, `<метрика>_b`, `<метрика>_delta`, `<метрика>_delta_pct`
+pub const SUFFIX_A: &str = "_a";
+pub const SUFFIX_B: &str = "_b";
+pub const SUFFIX_DELTA: &str = "_delta";
+pub const SUFFIX_DELTA_PCT: &str = "_delta_pct";
+
+/// Строки результата за один период
+#[derive(Debug, Clone, Copy)]
+pub struct PeriodRows<'a> {
+    pub rows: &'a [Value],
+    /// Все группы периода в результате: LIMIT не достигнут, малые группы не скрыты.
+    /// Только тогда отсутствие строки означает ноль.
+    pub complete: bool,
+}
+
+/// Результат одного запроса, выполненного за два периода, выровненный по ключам-измерениям
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub keys: Vec<String>,      // Колонки-измерения (по ним выравниваются строки)
+    pub measures: Vec<String>,  // Числовые колонки, для которых считаются изменения
+    pub rows: Vec<Value>,
+}
+
+/// Выравнивает строки двух периодов по значениям колонок-измерений и считает изменения метрик.
+/// Метрики - числовые колонки (кроме идентификаторов и кодов), остальные колонки - ключи.
+/// Порядок - как в результате периода B, затем строки, которые есть только в A.
+/// Строки нет в одном из периодов - ее метрики там null; изменение считается от нуля, если результат
+/// этого периода полный, иначе (строка за пределами LIMIT или скрыта как малая группа) изменение null.
+pub fn compare_rows(period_a: PeriodRows, period_b: PeriodRows) -> Result<Comparison, String> {
+    let (rows_a, rows_b) = (period_a.rows, period_b.rows);
+    let columns: Vec<String> = rows_b
+        .iter()
+        .chain(rows_a)
+        .filter_map(Value::as_object)
+        .flat_map(|row| row.keys().cloned())
+        .fold(Vec::new(), |mut columns, column| {
+            if !columns.contains(&column) {
+                columns.push(column);
+            }
+            columns
+        });
+    let is_measure = |column: &String| {
+        !is_identifier(column)
+            && rows_a.iter().chain(rows_b).filter_map(|row| row.get(column)).any(Value::is_number)
+            && rows_a.iter().chain(rows_b).filter_map(|row| row.get(column)).all(|value| value.is_number() || value.is_null())
+    };
+    let (measures, keys): (Vec<String>, Vec<String>) = columns.into_iter().partition(is_measure);
+    if measures.is_empty() {
+        return Err("Comparison needs at least one numeric aggregate column".to_string());
+    }
+
+    let key_of = |row: &Value| -> Vec<Value> { keys.iter().map(|key| row.get(key).cloned().unwrap_or(Value::Null)).collect() };
+    let mut aligned: Vec<(Vec<Value>, Option<&Value>, Option<&Value>)> = Vec::new();
+    for row in rows_b {
+        let key = key_of(row);
+        if !aligned.iter().any(|(existing, _, _)| *existing == key) {
+            aligned.push((key, None, Some(row)));
+        }
+    }
+    for row in rows_a {
+        let key = key_of(row);
+        match aligned.iter_mut().find(|(existing, _, _)| *existing == key) {
+            Some((_, slot @ None, _)) => *slot = Some(row),
+            Some(_) => {}
+            None => aligned.push((key, Some(row), None)),
+        }
+    }
+
+    let rows = aligned
+        .into_iter()
+        .map(|(key, row_a, row_b)| {
+            let mut out = Map::new();
+            for (column, value) in keys.iter().zip(key) {
+                out.insert(column.clone(), value);
+            }
+            for measure in &measures {
+                let value = |row: Option<&Value>| row.and_then(|row| row.get(measure)).and_then(Value::as_f64);
+                let (a, b) = (value(row_a), value(row_b));
+                // Отсутствующая строка - ноль только в полном результате периода
+                let unknown = (row_a.is_none() && !period_a.complete) || (row_b.is_none() && !period_b.complete);
+                let delta = (!unknown && (a.is_some() || b.is_some())).then(|| b.unwrap_or(0.0) - a.unwrap_or(0.0));
+                let delta_pct = match (a, delta) {
+                    (Some(a), Some(delta)) if a != 0.0 => Some(round2(delta / a.abs() * 100.0)),
+                    _ => None,
+                };
+                out.insert(format!("{}{}", measure, SUFFIX_A), number(a));
+                out.insert(format!("{}{}", measure, SUFFIX_B), number(b));
+                out.insert(format!("{}{}", measure, SUFFIX_DELTA), number(delta.map(round2)));
+                out.insert(format!("{}{}", measure, SUFFIX_DELTA_PCT), number(delta_pct));
+            }
+            Value::Object(out)
+        })
+        .collect();
+
+    Ok(Comparison { keys, measures, rows })
+}
+
+/// Подпись строки сравнения: значения ключей через " / " (без ключей - пусто)
+pub fn row_label(comparison: &Comparison, row: &Value) -> String {
+    comparison
+        .keys
+        .iter()
+        .filter_map(|key| match row.get(key)? {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            other => Some(other.to_string()),
+        })
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+fn is_identifier(column: &str) -> bool {
+    let column = column.to_lowercase();
+    column == "id" || column.ends_with("_id") || column.contains("mcc") || column.contains("code") || column.contains("iso")
+        || ["year", "month", "day", "hour", "week", "quarter"].contains(&column.as_str())
+}
+
+fn number(value: Option<f64>) -> Value {
+    value.map(Value::from).unwrap_or(Value::Null)
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn full(rows: &[Value]) -> PeriodRows<'_> {
+        PeriodRows { rows, complete: true }
+    }
+
+    #[test]
+    fn test_compare_rows_aligns_by_keys() {
+        let a = vec![
+            json!({"mcc_category": "Food", "cnt": 10, "amount": 1000.0}),
+            json!({"mcc_category": "Travel", "cnt": 4, "amount": 800.0}),
+        ];
+        let b = vec![
+            json!({"mcc_category": "Travel", "cnt": 6, "amount": 900.0}),
+            json!({"mcc_category": "Fuel", "cnt": 5, "amount": 250.0}),
+        ];
+        let comparison = compare_rows(full(&a), full(&b)).unwrap();
+        assert_eq!(comparison.keys, vec!["mcc_category"]);
+        assert_eq!(comparison.measures, vec!["amount", "cnt"]);
+        assert_eq!(comparison.rows.len(), 3);
+
+        let travel = &comparison.rows[0];
+        assert_eq!(row_label(&comparison, travel), "Travel");
+        assert_eq!(travel["cnt_a"], json!(4.0));
+        assert_eq!(travel["cnt_delta"], json!(2.0));
+        assert_eq!(travel["cnt_delta_pct"], json!(50.0));
+
+        // Нет в периоде A - изменение от нуля, процента нет
+        let fuel = &comparison.rows[1];
+        assert_eq!(fuel["amount_a"], Value::Null);
+        assert_eq!(fuel["amount_delta"], json!(250.0));
+        assert_eq!(fuel["amount_delta_pct"], Value::Null);
+
+        let food = &comparison.rows[2];
+        assert_eq!(food["mcc_category"], json!("Food"));
+        assert_eq!(food["cnt_delta_pct"], json!(-100.0));
+    }
+
+    #[test]
+    fn test_compare_scalar_and_errors() {
+        let comparison = compare_rows(full(&[json!({"total": 200})]), full(&[json!({"total": 150})])).unwrap();
+        assert!(comparison.keys.is_empty());
+        assert_eq!(comparison.rows, vec![json!({"total_a": 200.0, "total_b": 150.0, "total_delta": -50.0, "total_delta_pct": -25.0})]);
+
+        // Коды и идентификаторы - ключи, а не метрики
+        let comparison = compare_rows(full(&[json!({"mcc": 5411, "cnt": 1})]), full(&[json!({"mcc": 5411, "cnt": 3})])).unwrap();
+        assert_eq!(comparison.keys, vec!["mcc"]);
+
+        assert!(compare_rows(full(&[json!({"city": "Almaty"})]), full(&[json!({"city": "Astana"})])).is_err());
+    }
+
+    #[test]
+    fn test_missing_row_in_truncated_period() {
+        // Топ-2 по LIMIT: Fuel в периоде A за пределами топа, а не ноль
+        let a = vec![json!({"mcc_category": "Food", "cnt": 10}), json!({"mcc_category": "Travel", "cnt": 8})];
+        let b = vec![json!({"mcc_category": "Food", "cnt": 12}), json!({"mcc_category": "Fuel", "cnt": 9})];
+        let comparison = compare_rows(PeriodRows { rows: &a, complete: false }, PeriodRows { rows: &b, complete: false }).unwrap();
+        let delta = |label: &str| comparison.rows.iter().find(|row| row["mcc_category"] == json!(label)).unwrap()["cnt_delta"].clone();
+        assert_eq!(delta("Food"), json!(2.0));
+        assert_eq!(delta("Fuel"), Value::Null);
+        assert_eq!(delta("Travel"), Value::Null);
+
+        // Полный период B: Travel там действительно нет
+        let comparison = compare_rows(PeriodRows { rows: &a, complete: false }, full(&b)).unwrap();
+        assert_eq!(comparison.rows[2]["cnt_delta"], json!(-8.0));
+        assert_eq!(comparison.rows[1]["cnt_delta"], Value::Null);
+    }
+}
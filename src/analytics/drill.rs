@@ -0,0 +1,312 @@
+use super::series::{check_dimension, DIMENSIONS};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Срок жизни токена детализации (как у контекста запросов)
+pub const DRILL_TOKEN_TTL_SECS: i64 = 24 * 3600;
+/// Сколько родительских запросов хранится одновременно; сверх этого вытесняется давно не использованный
+pub const DRILL_QUERY_CAPACITY: usize = 10_000;
+
+static SELECT_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\bselect\b").unwrap());
+static FROM_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\bfrom\b").unwrap());
+static GROUP_BY_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\bgroup\s+by\b").unwrap());
+
+/// Значение измерения, которым ограничена детализация
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DrillFilter {
+    pub dimension: String,
+    pub value: Value,  // Строка, число или null (NULL в данных)
+}
+
+impl DrillFilter {
+    /// Условие на строки `transactions`
+    pub fn condition(&self) -> String {
+        match &self.value {
+            Value::Null => format!("{} IS NULL", self.dimension),
+            Value::Number(n) => format!("{} = {}", self.dimension, n),
+            Value::String(s) => format!("{} = '{}'", self.dimension, s.replace('\'', "''")),
+            other => format!("{} = '{}'", self.dimension, other.to_string().replace('\'', "''")),
+        }
+    }
+
+    /// Подпись значения для заголовков
+    pub fn label(&self) -> String {
+        match &self.value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
+/// Условие на строки `transactions` для цепочки фильтров
+pub fn filters_condition(filters: &[DrillFilter]) -> Option<String> {
+    (!filters.is_empty()).then(|| filters.iter().map(DrillFilter::condition).collect::<Vec<_>>().join(" AND "))
+}
+
+/// Родительский запрос детализации: хранится на сервере, в токен попадает только его id
+#[derive(Debug, Clone)]
+pub struct DrillQuery {
+    pub subject: String,  // Принципал, выполнивший запрос
+    pub sql: String,
+    pub filters: Vec<DrillFilter>,  // Фильтры, под которыми выполнялся `sql` (кроме арендатора)
+}
+
+/// Содержимое токена: id родительского запроса и значение точки. Токен подписан, но не зашифрован.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrillClaims {
+    pub sub: String,  // Принципал, получивший токен
+    pub exp: usize,
+    pub query: String,  // Id `DrillQuery` в `DrillTokens`
+    pub point: DrillFilter,
+}
+
+/// Точка, которую детализируют: SQL и фильтры родителя из хранилища, значение точки из токена
+#[derive(Debug, Clone)]
+pub struct DrillTarget {
+    pub sql: String,
+    pub filters: Vec<DrillFilter>,
+    pub point: DrillFilter,
+}
+
+impl DrillTarget {
+    /// Все фильтры детализации: родительские и значение точки
+    pub fn path(&self) -> Vec<DrillFilter> {
+        self.filters.iter().cloned().chain(std::iter::once(self.point.clone())).collect()
+    }
+}
+
+struct StoredQuery {
+    query: DrillQuery,
+    expires_at: Instant,
+    used_at: Instant,
+}
+
+/// Выдает и проверяет токены детализации. Родительские запросы хранятся в памяти процесса
+/// `DRILL_TOKEN_TTL_SECS` (не больше `DRILL_QUERY_CAPACITY`, вытесняется давно не использованный),
+/// токены подписываются (HS256) случайным на процесс секретом, поэтому после перезапуска
+/// выданные токены недействительны.
+pub struct DrillTokens {
+    secret: String,
+    capacity: usize,
+    queries: Mutex<HashMap<String, StoredQuery>>,
+}
+
+impl DrillTokens {
+    pub fn new() -> Self {
+        Self::with_capacity(DRILL_QUERY_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            secret: rand::random::<[u8; 32]>().iter().map(|b| format!("{:02x}", b)).collect(),
+            capacity: capacity.max(1),
+            queries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Сохраняет родительский запрос и возвращает его id. Id зависит от принципала, SQL и фильтров:
+    /// повтор того же запроса продлевает существующую запись, а не добавляет новую.
+    pub fn store(&self, subject: &str, sql: &str, filters: &[DrillFilter]) -> String {
+        let mut hasher = DefaultHasher::new();
+        (subject, sql, serde_json::to_string(filters).unwrap_or_default()).hash(&mut hasher);
+        let id = format!("{:016x}", hasher.finish());
+
+        let now = Instant::now();
+        let mut queries = self.queries.lock().expect("drill query store");
+        if !queries.contains_key(&id) && queries.len() >= self.capacity {
+            queries.retain(|_, stored| stored.expires_at > now);
+            if queries.len() >= self.capacity {
+                let oldest = queries.iter().min_by_key(|(_, stored)| stored.used_at).map(|(id, _)| id.clone());
+                if let Some(oldest) = oldest {
+                    queries.remove(&oldest);
+                }
+            }
+        }
+        let query = DrillQuery { subject: subject.to_string(), sql: sql.to_string(), filters: filters.to_vec() };
+        let expires_at = now + Duration::from_secs(DRILL_TOKEN_TTL_SECS as u64);
+        queries.insert(id.clone(), StoredQuery { query, expires_at, used_at: now });
+        id
+    }
+
+    /// Токен точки сохраненного запроса `query`
+    pub fn sign(&self, subject: &str, query: &str, point: DrillFilter) -> Result<String, String> {
+        let claims = DrillClaims {
+            sub: subject.to_string(),
+            exp: (chrono::Utc::now().timestamp() + DRILL_TOKEN_TTL_SECS) as usize,
+            query: query.to_string(),
+            point,
+        };
+        encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(self.secret.as_bytes()))
+            .map_err(|e| format!("Failed to sign drill-down token: {}", e))
+    }
+
+    /// Проверяет подпись, срок и что токен выдан этому же принципалу; находит родительский запрос
+    pub fn resolve(&self, token: &str, subject: &str) -> Result<DrillTarget, String> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.validate_aud = false;
+        let claims = decode::<DrillClaims>(token, &DecodingKey::from_secret(self.secret.as_bytes()), &validation)
+            .map_err(|e| format!("Invalid drill-down token: {}", e))?
+            .claims;
+        if claims.sub != subject {
+            return Err("Drill-down token was issued to another user".to_string());
+        }
+        let now = Instant::now();
+        let mut queries = self.queries.lock().expect("drill query store");
+        let stored = queries
+            .get_mut(&claims.query)
+            .filter(|stored| stored.expires_at > now && stored.query.subject == subject)
+            .ok_or("Drill-down query has expired")?;
+        stored.used_at = now;
+        Ok(DrillTarget { sql: stored.query.sql.clone(), filters: stored.query.filters.clone(), point: claims.point })
+    }
+}
+
+impl Default for DrillTokens {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Проверяет, что запрос можно детализировать по измерению: один SELECT с GROUP BY,
+/// измерение есть в списке колонок
+pub fn check_drillable(sql: &str, dimension: &str) -> Result<(), String> {
+    split_query(sql, dimension).map(|_| ())
+}
+
+/// SQL следующего уровня: тот же запрос (фильтры, метрики, сортировка, LIMIT),
+/// сгруппированный по `target` вместо `dimension`. Значение точки подставляется
+/// фильтром строк `transactions` при выполнении.
+pub fn regroup(sql: &str, dimension: &str, target: &str) -> Result<String, String> {
+    let target = target.trim().to_lowercase();
+    check_dimension(&target)?;
+    if target == dimension || word_regex(&target).is_match(sql) {
+        return Err(format!("Query already uses '{}'", target));
+    }
+    let (select_list, middle, tail) = split_query(sql, dimension)?;
+    let column = word_regex(dimension);
+    Ok(format!(
+        "{}{}{}",
+        column.replace_all(select_list, target.as_str()),
+        middle,
+        column.replace_all(tail, target.as_str())
+    ))
+}
+
+/// Первое измерение результата, по которому можно детализировать
+pub fn drill_dimension<'a>(sql: &str, data: &'a [Value]) -> Option<&'a str> {
+    let row = data.first()?.as_object()?;
+    row.keys()
+        .map(String::as_str)
+        .filter(|column| DIMENSIONS.contains(column))
+        .find(|column| check_drillable(sql, column).is_ok())
+}
+
+/// SELECT-список, часть от FROM до GROUP BY (не меняется) и часть от GROUP BY
+fn split_query<'a>(sql: &'a str, dimension: &str) -> Result<(&'a str, &'a str, &'a str), String> {
+    if SELECT_RE.find_iter(sql).count() != 1 {
+        return Err("Only a single SELECT without subqueries can be drilled down".to_string());
+    }
+    let from = FROM_RE.find(sql).ok_or("Query has no FROM")?.start();
+    let group_by = GROUP_BY_RE.find(sql).filter(|m| m.start() > from).ok_or("Query has no GROUP BY")?.start();
+    let (select_list, middle, tail) = (&sql[..from], &sql[from..group_by], &sql[group_by..]);
+    let column = word_regex(dimension);
+    if !column.is_match(select_list) {
+        return Err(format!("Query does not select '{}'", dimension));
+    }
+    Ok((select_list, middle, tail))
+}
+
+fn word_regex(column: &str) -> Regex {
+    Regex::new(&format!(r"(?i)\b{}\b", regex::escape(column))).expect("column regex")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SQL: &str = "SELECT merchant_city, COUNT(*) AS cnt FROM transactions WHERE transaction_type = 'POS' \
+                       GROUP BY merchant_city ORDER BY cnt DESC LIMIT 10";
+
+    #[test]
+    fn test_regroup() {
+        assert_eq!(
+            regroup(SQL, "merchant_city", "Merchant_ID").unwrap(),
+            "SELECT merchant_id, COUNT(*) AS cnt FROM transactions WHERE transaction_type = 'POS' \
+             GROUP BY merchant_id ORDER BY cnt DESC LIMIT 10"
+        );
+        // Измерение в WHERE не меняется
+        let sql = "SELECT mcc_category, SUM(transaction_amount_kzt) FROM transactions WHERE mcc_category <> 'Unknown' GROUP BY 1";
+        assert_eq!(
+            regroup(sql, "mcc_category", "wallet_type").unwrap(),
+            "SELECT wallet_type, SUM(transaction_amount_kzt) FROM transactions WHERE mcc_category <> 'Unknown' GROUP BY 1"
+        );
+
+        assert!(regroup(SQL, "merchant_city", "transaction_type").is_err());
+        assert!(regroup(SQL, "merchant_city", "card_id").is_err());
+        assert!(check_drillable("SELECT COUNT(*) FROM transactions", "merchant_city").is_err());
+        assert!(check_drillable("SELECT merchant_city FROM (SELECT * FROM transactions) t GROUP BY 1", "merchant_city").is_err());
+
+        let data = vec![json!({"cnt": 5, "merchant_city": "Almaty"})];
+        assert_eq!(drill_dimension(SQL, &data), Some("merchant_city"));
+    }
+
+    #[test]
+    fn test_filters_and_tokens() {
+        let filters = vec![
+            DrillFilter { dimension: "merchant_city".to_string(), value: json!("Kyzyl'orda") },
+            DrillFilter { dimension: "merchant_mcc".to_string(), value: json!(5411) },
+            DrillFilter { dimension: "wallet_type".to_string(), value: Value::Null },
+        ];
+        assert_eq!(
+            filters_condition(&filters).unwrap(),
+            "merchant_city = 'Kyzyl''orda' AND merchant_mcc = 5411 AND wallet_type IS NULL"
+        );
+        assert_eq!(filters_condition(&[]), None);
+
+        let tokens = DrillTokens::new();
+        let query = tokens.store("alice", SQL, &filters[..1]);
+        let token = tokens.sign("alice", &query, filters[1].clone()).unwrap();
+        // В токене только id запроса и точка, SQL остается на сервере
+        let payload = decode::<Value>(&token, &DecodingKey::from_secret(tokens.secret.as_bytes()), &Validation::new(Algorithm::HS256))
+            .unwrap()
+            .claims;
+        assert_eq!(payload.as_object().unwrap().keys().collect::<Vec<_>>(), ["exp", "point", "query", "sub"]);
+        let target = tokens.resolve(&token, "alice").unwrap();
+        assert_eq!(target.sql, SQL);
+        assert_eq!(target.path(), filters[..2].to_vec());
+
+        assert!(tokens.resolve(&token, "bob").is_err());
+        assert!(DrillTokens::new().resolve(&token, "alice").is_err());
+        let unknown = tokens.sign("alice", "missing", filters[1].clone()).unwrap();
+        assert!(tokens.resolve(&unknown, "alice").is_err());
+    }
+
+    #[test]
+    fn test_query_store_is_bounded() {
+        let point = DrillFilter { dimension: "merchant_city".to_string(), value: json!("Almaty") };
+        let tokens = DrillTokens::with_capacity(2);
+        // Повтор того же запроса - та же запись
+        let first = tokens.store("alice", SQL, &[]);
+        assert_eq!(tokens.store("alice", SQL, &[]), first);
+        let bob = tokens.store("bob", SQL, &[]);
+        assert_ne!(bob, first);
+        assert_eq!(tokens.queries.lock().unwrap().len(), 2);
+
+        // Сверх емкости вытесняется давно не использованный запрос
+        let first_token = tokens.sign("alice", &first, point.clone()).unwrap();
+        let bob_token = tokens.sign("bob", &bob, point).unwrap();
+        tokens.resolve(&first_token, "alice").unwrap();
+        tokens.store("alice", "SELECT wallet_type, COUNT(*) FROM transactions GROUP BY 1", &[]);
+        assert_eq!(tokens.queries.lock().unwrap().len(), 2);
+        assert!(tokens.resolve(&first_token, "alice").is_ok());
+        assert!(tokens.resolve(&bob_token, "bob").is_err());
+    }
+}
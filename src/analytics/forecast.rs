@@ -0,0 +1,300 @@
+use super::series::{Granularity, SeriesMetric};
+use chrono::NaiveDateTime;
+use regex::Regex;
+use serde::Serialize;
+use std::sync::LazyLock;
+
+/// Горизонт по умолчанию и максимальный горизонт (в шагах ряда)
+pub fn default_horizon(granularity: Granularity) -> usize {
+    match granularity {
+        Granularity::Daily => 30,
+        Granularity::Hourly => 48,
+        Granularity::Monthly => 3,
+    }
+}
+
+pub fn max_horizon(granularity: Granularity) -> usize {
+    match granularity {
+        Granularity::Daily => 366,
+        Granularity::Hourly => 168,
+        Granularity::Monthly => 24,
+    }
+}
+
+/// Уровни прогнозного интервала и квантили нормального распределения
+const LEVELS: &[(u32, f64)] = &[(80, 1.2816), (90, 1.6449), (95, 1.96), (99, 2.5758)];
+pub const DEFAULT_LEVEL: u32 = 95;
+
+pub fn z_for_level(level: u32) -> Option<f64> {
+    LEVELS.iter().find(|(l, _)| *l == level).map(|(_, z)| *z)
+}
+
+/// Сетка параметров сглаживания: подбираются по сумме квадратов ошибок прогноза на шаг вперед
+const ALPHAS: &[f64] = &[0.05, 0.1, 0.15, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9];
+const BETAS: &[f64] = &[0.0, 0.01, 0.05, 0.1, 0.2];
+const GAMMAS: &[f64] = &[0.0, 0.05, 0.1, 0.2, 0.3, 0.5];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForecastMethod {
+    HoltWintersAdditive,  // Уровень, тренд и аддитивная сезонность (день недели, час суток, месяц года)
+    HoltLinear,           // Меньше двух сезонных циклов: только уровень и тренд
+}
+
+impl ForecastMethod {
+    pub fn label_key(self) -> &'static str {
+        match self {
+            ForecastMethod::HoltWintersAdditive => "analytics.method_holt_winters",
+            ForecastMethod::HoltLinear => "analytics.method_holt_linear",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SmoothingParams {
+    pub alpha: f64,
+    pub beta: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gamma: Option<f64>,
+}
+
+/// Прогнозная точка: значение и границы интервала
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Projection {
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forecast {
+    pub method: ForecastMethod,
+    pub params: SmoothingParams,
+    pub season_length: Option<usize>,
+    pub points: Vec<Projection>,
+}
+
+/// Прогноз на `horizon` шагов методом Хольта - Винтерса (аддитивная сезонность длиной `season`).
+/// Интервал: σ - среднеквадратичная ошибка прогноза на шаг вперед, дисперсия на h шагов - σ²·(1 + Σ c_j²),
+/// c_j = α(1 + jβ) + γ(1 - α)·[j кратно season]. Значения и нижние границы не опускаются ниже нуля.
+pub fn forecast(values: &[f64], season: usize, horizon: usize, z: f64) -> Result<Forecast, String> {
+    let seasonal = season > 1 && values.len() >= season * 2;
+    if !seasonal && values.len() < 3 {
+        return Err(format!("Not enough data for a forecast: {} points, at least 3 needed", values.len()));
+    }
+    let m = if seasonal { season } else { 1 };
+    let gammas: &[f64] = if seasonal { GAMMAS } else { &[0.0] };
+
+    let mut best: Option<(f64, SmoothingParams, Fit)> = None;
+    for &alpha in ALPHAS {
+        for &beta in BETAS {
+            for &gamma in gammas {
+                let fit = fit(values, m, alpha, beta, gamma);
+                if best.as_ref().is_none_or(|(sse, _, _)| fit.sse < *sse) {
+                    best = Some((fit.sse, SmoothingParams { alpha, beta, gamma: seasonal.then_some(gamma) }, fit));
+                }
+            }
+        }
+    }
+    let (sse, params, fit) = best.expect("parameter grid is not empty");
+    let gamma = params.gamma.unwrap_or(0.0);
+    let sigma = (sse / fit.errors.max(1) as f64).sqrt();
+
+    let mut variance_sum = 0.0;
+    let points = (1..=horizon)
+        .map(|h| {
+            if h > 1 {
+                let j = (h - 1) as f64;
+                let seasonal_term = if seasonal && (h - 1).is_multiple_of(m) { gamma * (1.0 - params.alpha) } else { 0.0 };
+                variance_sum += (params.alpha * (1.0 + j * params.beta) + seasonal_term).powi(2);
+            }
+            let value = fit.level + h as f64 * fit.trend + fit.seasons[(values.len() + h - 1) % m];
+            let margin = z * sigma * (1.0 + variance_sum).sqrt();
+            Projection { value: value.max(0.0), lower: (value - margin).max(0.0), upper: (value + margin).max(0.0) }
+        })
+        .collect();
+
+    Ok(Forecast {
+        method: if seasonal { ForecastMethod::HoltWintersAdditive } else { ForecastMethod::HoltLinear },
+        params,
+        season_length: seasonal.then_some(m),
+        points,
+    })
+}
+
+/// Состояние после прохода по ряду
+struct Fit {
+    level: f64,
+    trend: f64,
+    seasons: Vec<f64>,
+    sse: f64,
+    errors: usize,  // Сколько ошибок вошло в sse
+}
+
+fn fit(values: &[f64], m: usize, alpha: f64, beta: f64, gamma: f64) -> Fit {
+    let (mut level, mut trend, mut seasons) = if m > 1 {
+        // Начальные значения по первым двум циклам
+        let first = values[..m].iter().sum::<f64>() / m as f64;
+        let second = values[m..2 * m].iter().sum::<f64>() / m as f64;
+        (first, (second - first) / m as f64, values[..m].iter().map(|value| value - first).collect())
+    } else {
+        (values[0], values[1] - values[0], vec![0.0])
+    };
+    // Первый цикл ушел на начальные значения - его ошибки не учитываются
+    let warmup = m.max(2);
+    let (mut sse, mut errors) = (0.0, 0);
+    for (t, &value) in values.iter().enumerate() {
+        let season = seasons[t % m];
+        if t >= warmup {
+            let error = value - (level + trend + season);
+            sse += error * error;
+            errors += 1;
+        }
+        let previous = level;
+        level = alpha * (value - season) + (1.0 - alpha) * (level + trend);
+        trend = beta * (level - previous) + (1.0 - beta) * trend;
+        if m > 1 {
+            seasons[t % m] = gamma * (value - level) + (1.0 - gamma) * season;
+        }
+    }
+    Fit { level, trend, seasons, sse, errors }
+}
+
+/// Ряд с равным шагом: пропущенные периоды (нет транзакций или группа скрыта из-за MIN_GROUP_SIZE) - нули
+pub fn fill_gaps(series: &[(NaiveDateTime, f64)], granularity: Granularity) -> Vec<(NaiveDateTime, f64)> {
+    let mut filled: Vec<(NaiveDateTime, f64)> = Vec::with_capacity(series.len());
+    for &(period, value) in series {
+        if let Some(&(last, _)) = filled.last() {
+            let mut next = granularity.advance(last, 1);
+            while next < period {
+                filled.push((next, 0.0));
+                next = granularity.advance(next, 1);
+            }
+        }
+        filled.push((period, value));
+    }
+    filled
+}
+
+/// Вопрос-прогноз на естественном языке: метрика, шаг и горизонт
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForecastQuestion {
+    pub metric: SeriesMetric,
+    pub granularity: Granularity,
+    pub horizon: usize,
+}
+
+/// Явная просьба о прогнозе
+static FORECAST_WORDS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(?:прогноз|спрогнозир|предскаж|forecast|predict|projection|болжа|prognoz|bashorat|болжол|божомол)").expect("valid forecast regex")
+});
+
+/// Вопрос о будущем значении ("какой будет", "what will")
+static FUTURE_WORDS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(?:будет|будут|ожида|will|expected|болады|bo'ladi|boʻladi|болот)").expect("valid future regex")
+});
+
+/// Сумма, а не число транзакций
+static VOLUME_WORDS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(?:объ[её]м|сумм|оборот|volume|amount|sum|turnover|spend|көлем|сома|hajm|summa|aylanma|көлөм)").expect("valid volume regex")
+});
+
+/// Горизонт: (регулярное выражение, шаг, число шагов на единицу, есть ли число в группе 1)
+static HORIZONS: LazyLock<Vec<(Regex, Granularity, usize)>> = LazyLock::new(|| {
+    [
+        (r"(?:следующ\w*|ближайш\w*|next|келесі|keyingi|кийинки)\s+(\d{1,3})\s*(?:дн|день|дня|day|күн|kun)", Granularity::Daily, 1),
+        (r"(\d{1,3})\s*(?:дн\w*|день|дня|days?|күн\w*|kun\w*)\s*(?:вперед|вперёд|ahead|алға|oldinga|алдыга)", Granularity::Daily, 1),
+        (r"(?:следующ\w*|ближайш\w*|next|келесі|keyingi|кийинки)\s+(\d{1,2})\s*(?:месяц|month|ай|oy)", Granularity::Monthly, 1),
+        (r"(?:следующ\w*|ближайш\w*|next|келесі|keyingi|кийинки)\s+(\d{1,2})\s*(?:недел|week|апта|hafta|жума)", Granularity::Daily, 7),
+        (r"(?:следующ\w*|ближайш\w*|next|келесі|keyingi|кийинки)\s+(?:квартал|quarter|тоқсан|chorak|чейрек)", Granularity::Monthly, 3),
+        (r"(?:следующ\w*|ближайш\w*|next|келесі|keyingi|кийинки)\s+(?:месяц|month|ай|oy)", Granularity::Monthly, 1),
+        (r"(?:следующ\w*|ближайш\w*|next|келесі|keyingi|кийинки)\s+(?:недел|week|апта|hafta|жума)", Granularity::Daily, 7),
+        (r"(?:следующ\w*|ближайш\w*|next|келесі|keyingi|кийинки)\s+(?:год|year|жыл|yil)", Granularity::Monthly, 12),
+        (r"\b(?:завтра|tomorrow|ертең|ertaga|эртең)", Granularity::Daily, 1),
+    ]
+    .into_iter()
+    .map(|(pattern, granularity, unit)| (Regex::new(pattern).expect("valid horizon regex"), granularity, unit))
+    .collect()
+});
+
+/// Распознает вопрос-прогноз: явное слово "прогноз" или вопрос о будущем с горизонтом
+/// ("какой будет объем в следующем месяце"). Без горизонта - шаг день, горизонт по умолчанию.
+pub fn parse_forecast_question(question: &str) -> Option<ForecastQuestion> {
+    let text = question.to_lowercase();
+    let horizon = HORIZONS.iter().find_map(|(pattern, granularity, unit)| {
+        let captures = pattern.captures(&text)?;
+        let count = captures.get(1).and_then(|m| m.as_str().parse::<usize>().ok()).unwrap_or(1).max(1);
+        Some((*granularity, (count * unit).min(max_horizon(*granularity))))
+    });
+    let (granularity, horizon) = match (FORECAST_WORDS.is_match(&text), horizon) {
+        (_, Some(horizon)) if FORECAST_WORDS.is_match(&text) || FUTURE_WORDS.is_match(&text) => horizon,
+        (true, None) => (Granularity::Daily, default_horizon(Granularity::Daily)),
+        _ => return None,
+    };
+    let metric = if VOLUME_WORDS.is_match(&text) { SeriesMetric::Sum } else { SeriesMetric::Count };
+    Some(ForecastQuestion { metric, granularity, horizon })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn weekly_pattern(weeks: usize, growth: f64) -> Vec<f64> {
+        (0..weeks * 7)
+            .map(|day| {
+                let base = if day % 7 >= 5 { 40.0 } else { 100.0 };
+                base + growth * day as f64 + ((day * 7) % 5) as f64 - 2.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_holt_winters_keeps_weekday_effect() {
+        let values = weekly_pattern(8, 0.5);
+        let result = forecast(&values, 7, 14, 1.96).unwrap();
+        assert_eq!(result.method, ForecastMethod::HoltWintersAdditive);
+        assert_eq!(result.points.len(), 14);
+        // Ряд заканчивается воскресеньем: первые 5 прогнозных точек - будни, затем выходные
+        let weekday = result.points[0].value;
+        let weekend = result.points[5].value;
+        assert!(weekday > weekend + 40.0, "{} vs {}", weekday, weekend);
+        let expected = 100.0 + 0.5 * 56.0;
+        assert!((weekday - expected).abs() < 6.0, "{} vs {}", weekday, expected);
+        // Интервал содержит прогноз и расширяется с горизонтом
+        for point in &result.points {
+            assert!(point.lower <= point.value && point.value <= point.upper);
+        }
+        let width = |p: &Projection| p.upper - p.lower;
+        assert!(width(&result.points[13]) > width(&result.points[0]));
+    }
+
+    #[test]
+    fn test_short_series_and_gaps() {
+        let result = forecast(&[10.0, 12.0, 14.0, 16.0, 18.0], 7, 2, 1.96).unwrap();
+        assert_eq!(result.method, ForecastMethod::HoltLinear);
+        assert!(result.season_length.is_none() && result.params.gamma.is_none());
+        assert!((result.points[0].value - 20.0).abs() < 1.0);
+        assert!(forecast(&[1.0, 2.0], 7, 2, 1.96).is_err());
+
+        let day = |d: u32| NaiveDate::from_ymd_opt(2024, 1, d).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let filled = fill_gaps(&[(day(1), 5.0), (day(4), 7.0)], Granularity::Daily);
+        assert_eq!(filled, vec![(day(1), 5.0), (day(2), 0.0), (day(3), 0.0), (day(4), 7.0)]);
+    }
+
+    #[test]
+    fn test_parse_forecast_question() {
+        let question = |metric, granularity, horizon| Some(ForecastQuestion { metric, granularity, horizon });
+        assert_eq!(parse_forecast_question("What will volume be next month?"), question(SeriesMetric::Sum, Granularity::Monthly, 1));
+        assert_eq!(
+            parse_forecast_question("Сколько транзакций будет в ближайшие 14 дней?"),
+            question(SeriesMetric::Count, Granularity::Daily, 14)
+        );
+        assert_eq!(parse_forecast_question("прогноз оборота на следующую неделю"), question(SeriesMetric::Sum, Granularity::Daily, 7));
+        assert_eq!(parse_forecast_question("Forecast transactions"), question(SeriesMetric::Count, Granularity::Daily, 30));
+        assert_eq!(parse_forecast_question("Келесі айда транзакциялар саны қандай болады?"), question(SeriesMetric::Count, Granularity::Monthly, 1));
+        // Вопрос о прошлом и будущее без горизонта - не прогноз
+        assert_eq!(parse_forecast_question("Какой был объем в прошлом месяце?"), None);
+        assert_eq!(parse_forecast_question("What will the top city be?"), None);
+    }
+}
@@ -0,0 +1,21 @@
+pub mod anomalies;
+pub mod compare;
+pub mod drill;
+pub mod forecast;
+pub mod root_cause;
+pub mod series;
+
+/// Медиана значений (пустой срез - 0)
+pub fn median(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
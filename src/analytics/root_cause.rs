@@ -0,0 +1,234 @@
+use super::series::{check_dimension, PeriodAggregates, SeriesFilters, SeriesMetric, DIMENSIONS};
+use crate::llm::tools::{numeric_value, Period};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Сколько сегментов показывать отдельными шагами водопада (остальные - одним шагом "прочие")
+pub const WATERFALL_STEPS: usize = 8;
+pub const DEFAULT_TOP_DRIVERS: usize = 10;
+
+/// Метрика, два периода, измерения и общие фильтры
+#[derive(Debug, Clone, Deserialize)]
+pub struct RootCauseSpec {
+    #[serde(default)]
+    pub metric: SeriesMetric,
+    pub period_a: Period,  // Базовый период
+    pub period_b: Period,  // Период, изменение в котором объясняем
+    #[serde(default)]
+    pub dimensions: Vec<String>,  // Пусто - все измерения `series::DIMENSIONS`
+    #[serde(flatten)]
+    pub filters: SeriesFilters,
+}
+
+impl RootCauseSpec {
+    /// Проверяет периоды и измерения; возвращает измерения для разложения
+    pub fn validate(&self) -> Result<Vec<String>, String> {
+        for period in [&self.period_a, &self.period_b] {
+            if period.to < period.from {
+                return Err(format!("Period {}..{} ends before it starts", period.from, period.to));
+            }
+        }
+        if self.dimensions.is_empty() {
+            return Ok(DIMENSIONS.iter().map(|d| d.to_string()).collect());
+        }
+        let mut dimensions: Vec<String> = Vec::new();
+        for dimension in &self.dimensions {
+            let dimension = dimension.trim().to_lowercase();
+            check_dimension(&dimension)?;
+            if !dimensions.contains(&dimension) {
+                dimensions.push(dimension);
+            }
+        }
+        Ok(dimensions)
+    }
+
+    fn aggregates(&self) -> PeriodAggregates {
+        PeriodAggregates::new(self.metric.into(), &self.period_a, &self.period_b, self.filters.conditions())
+    }
+
+    /// Итог метрики за оба периода
+    pub fn totals_sql(&self) -> String {
+        let aggregates = self.aggregates();
+        format!("SELECT {} FROM transactions WHERE {};", aggregates.select, aggregates.condition)
+    }
+
+    /// Метрика за оба периода по сегментам измерения
+    pub fn dimension_sql(&self, dimension: &str) -> String {
+        let aggregates = self.aggregates();
+        format!(
+            "SELECT {d} AS segment, {select} FROM transactions WHERE {where_} GROUP BY {d};",
+            d = dimension,
+            select = aggregates.select,
+            where_ = aggregates.condition
+        )
+    }
+}
+
+/// Значение сегмента в двух периодах
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub segment: Option<String>,  // None - NULL в данных
+    pub period_a: f64,
+    pub period_b: f64,
+}
+
+impl Segment {
+    pub fn delta(&self) -> f64 {
+        self.period_b - self.period_a
+    }
+}
+
+/// Итог метрики (первая строка totals_sql)
+pub fn parse_totals(rows: &[Value]) -> (f64, f64) {
+    let row = rows.first();
+    let value = |column: &str| row.and_then(|row| row.get(column)).and_then(numeric_value).unwrap_or(0.0);
+    (value("period_a"), value("period_b"))
+}
+
+pub fn parse_segments(rows: &[Value]) -> Vec<Segment> {
+    rows.iter()
+        .map(|row| Segment {
+            segment: match row.get("segment") {
+                Some(Value::String(s)) => Some(s.clone()),
+                Some(Value::Null) | None => None,
+                Some(other) => Some(other.to_string()),
+            },
+            period_a: row.get("period_a").and_then(numeric_value).unwrap_or(0.0),
+            period_b: row.get("period_b").and_then(numeric_value).unwrap_or(0.0),
+        })
+        .collect()
+}
+
+/// Сегмент, изменение которого объясняет часть общего изменения
+#[derive(Debug, Clone, Serialize)]
+pub struct Driver {
+    pub dimension: String,
+    pub segment: Option<String>,
+    pub period_a: f64,
+    pub period_b: f64,
+    pub delta: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta_pct: Option<f64>,  // Изменение самого сегмента, %
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contribution_pct: Option<f64>,  // Доля в общем изменении, % (со знаком: против общего движения - отрицательная)
+}
+
+/// Сегменты всех измерений по убыванию |delta|
+pub fn rank_drivers(by_dimension: &[(String, Vec<Segment>)], total_delta: f64, top: usize) -> Vec<Driver> {
+    let mut drivers: Vec<Driver> = by_dimension
+        .iter()
+        .flat_map(|(dimension, segments)| {
+            segments.iter().filter(|segment| segment.delta() != 0.0).map(move |segment| Driver {
+                dimension: dimension.clone(),
+                segment: segment.segment.clone(),
+                period_a: segment.period_a,
+                period_b: segment.period_b,
+                delta: segment.delta(),
+                delta_pct: (segment.period_a != 0.0).then(|| segment.delta() / segment.period_a.abs() * 100.0),
+                contribution_pct: (total_delta != 0.0).then(|| segment.delta() / total_delta * 100.0),
+            })
+        })
+        .collect();
+    drivers.sort_by(|a, b| b.delta.abs().total_cmp(&a.delta.abs()));
+    drivers.truncate(top);
+    drivers
+}
+
+/// Измерение, лучше всего объясняющее изменение: в нем один сегмент дает наибольшую долю
+/// общего изменения (в его направлении)
+pub fn explanatory_dimension(by_dimension: &[(String, Vec<Segment>)], total_delta: f64) -> Option<&str> {
+    let score = |segments: &[Segment]| {
+        segments
+            .iter()
+            .map(|segment| if total_delta != 0.0 { segment.delta() / total_delta } else { segment.delta().abs() })
+            .fold(f64::MIN, f64::max)
+    };
+    by_dimension
+        .iter()
+        .filter(|(_, segments)| !segments.is_empty())
+        .max_by(|a, b| score(&a.1).total_cmp(&score(&b.1)))
+        .map(|(dimension, _)| dimension.as_str())
+}
+
+/// Шаг водопада: сегмент (None - "прочие") и его вклад
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaterfallStep {
+    pub segment: Option<Option<String>>,
+    pub delta: f64,
+}
+
+/// Шаги водопада от итога периода A к итогу периода B по сегментам одного измерения:
+/// крупнейшие изменения отдельно, остальное (включая скрытые малые группы) - одним шагом
+pub fn waterfall_steps(segments: &[Segment], total_a: f64, total_b: f64, max_steps: usize) -> Vec<WaterfallStep> {
+    let mut sorted: Vec<&Segment> = segments.iter().filter(|segment| segment.delta() != 0.0).collect();
+    sorted.sort_by(|a, b| b.delta().abs().total_cmp(&a.delta().abs()));
+    let mut steps: Vec<WaterfallStep> = sorted
+        .iter()
+        .take(max_steps)
+        .map(|segment| WaterfallStep { segment: Some(segment.segment.clone()), delta: segment.delta() })
+        .collect();
+    let explained: f64 = steps.iter().map(|step| step.delta).sum();
+    let rest = (total_b - total_a) - explained;
+    if rest.abs() > 1e-6 * total_a.abs().max(total_b.abs()).max(1.0) {
+        steps.push(WaterfallStep { segment: None, delta: rest });
+    }
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn spec(dimensions: &[&str]) -> RootCauseSpec {
+        RootCauseSpec {
+            metric: SeriesMetric::Sum,
+            period_a: Period { from: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(), to: NaiveDate::from_ymd_opt(2024, 1, 31).unwrap() },
+            period_b: Period { from: NaiveDate::from_ymd_opt(2024, 2, 1).unwrap(), to: NaiveDate::from_ymd_opt(2024, 2, 29).unwrap() },
+            dimensions: dimensions.iter().map(|d| d.to_string()).collect(),
+            filters: SeriesFilters { transaction_type: Some("POS".to_string()), ..Default::default() },
+        }
+    }
+
+    fn segment(name: &str, a: f64, b: f64) -> Segment {
+        Segment { segment: Some(name.to_string()), period_a: a, period_b: b }
+    }
+
+    #[test]
+    fn test_validate_and_sql() {
+        assert_eq!(spec(&[]).validate().unwrap().len(), DIMENSIONS.len());
+        assert_eq!(spec(&["Merchant_City", "merchant_city"]).validate().unwrap(), vec!["merchant_city"]);
+        assert!(spec(&["card_id"]).validate().is_err());
+
+        let sql = spec(&[]).dimension_sql("merchant_city");
+        assert!(sql.starts_with("SELECT merchant_city AS segment, COALESCE(SUM(transaction_amount_kzt) FILTER (WHERE transaction_timestamp >= '2024-01-01'"));
+        assert!(sql.contains("AND transaction_type = 'POS' GROUP BY merchant_city;"));
+        assert!(spec(&[]).totals_sql().ends_with("AND transaction_type = 'POS';"));
+    }
+
+    #[test]
+    fn test_drivers_and_waterfall() {
+        // Общее изменение +100: Алматы +150, Астана -30, Шымкент -20
+        let cities = vec![segment("Almaty", 500.0, 650.0), segment("Astana", 300.0, 270.0), segment("Shymkent", 200.0, 180.0)];
+        let types = vec![segment("POS", 700.0, 760.0), segment("ECOM", 300.0, 340.0)];
+        let by_dimension = vec![("merchant_city".to_string(), cities.clone()), ("transaction_type".to_string(), types)];
+
+        let drivers = rank_drivers(&by_dimension, 100.0, 3);
+        assert_eq!(drivers[0].segment.as_deref(), Some("Almaty"));
+        assert_eq!(drivers[0].contribution_pct, Some(150.0));
+        assert_eq!(drivers[0].delta_pct, Some(30.0));
+        assert_eq!(drivers[1].segment.as_deref(), Some("POS"));
+        assert_eq!(drivers.len(), 3);
+
+        assert_eq!(explanatory_dimension(&by_dimension, 100.0), Some("merchant_city"));
+
+        let steps = waterfall_steps(&cities, 1000.0, 1100.0, 2);
+        assert_eq!(steps, vec![
+            WaterfallStep { segment: Some(Some("Almaty".to_string())), delta: 150.0 },
+            WaterfallStep { segment: Some(Some("Astana".to_string())), delta: -30.0 },
+            WaterfallStep { segment: None, delta: -20.0 },
+        ]);
+        // Все сегменты показаны и сходятся с итогом - шага "прочие" нет
+        assert_eq!(waterfall_steps(&cities, 1000.0, 1100.0, 8).len(), 3);
+    }
+}
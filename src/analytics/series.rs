@@ -0,0 +1,315 @@
+use crate::llm::tools::{numeric_value, Period};
+use chrono::{DateTime, Duration, Months, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Ряд за последние дни данных, если для почасового ряда не задан период
+const HOURLY_DEFAULT_DAYS: i64 = 14;
+
+/// Метрика ряда
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeriesMetric {
+    #[default]
+    Count,  // Число транзакций
+    Sum,    // Сумма transaction_amount_kzt
+}
+
+impl SeriesMetric {
+    pub fn expression(self) -> &'static str {
+        match self {
+            SeriesMetric::Count => "COUNT(*)",
+            SeriesMetric::Sum => "COALESCE(SUM(transaction_amount_kzt), 0)::float8",
+        }
+    }
+
+    /// Ключ каталога с подписью метрики
+    pub fn label_key(self) -> &'static str {
+        match self {
+            SeriesMetric::Count => "analytics.metric_count",
+            SeriesMetric::Sum => "analytics.metric_sum",
+        }
+    }
+}
+
+/// Колонки-измерения: по ним группируют и фильтруют сравнение периодов (`compare_periods` агента),
+/// раскладывают изменение метрики и детализируют точки графиков
+pub const DIMENSIONS: &[&str] = &[
+    "merchant_city",
+    "mcc_category",
+    "issuer_bank_name",
+    "transaction_type",
+    "pos_entry_mode",
+    "wallet_type",
+    "merchant_id",
+    "merchant_mcc",
+    "transaction_currency",
+    "acquirer_country_iso",
+];
+
+/// Ошибка для модели/клиента, если колонки нет в `DIMENSIONS`
+pub fn check_dimension(column: &str) -> Result<(), String> {
+    match DIMENSIONS.contains(&column) {
+        true => Ok(()),
+        false => Err(format!("Unknown dimension '{}'. Use one of: {}", column, DIMENSIONS.join(", "))),
+    }
+}
+
+/// Метрика сравнения двух периодов
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeriodMetric {
+    #[default]
+    Count,
+    Sum,
+    Avg,
+}
+
+impl From<SeriesMetric> for PeriodMetric {
+    fn from(metric: SeriesMetric) -> Self {
+        match metric {
+            SeriesMetric::Count => PeriodMetric::Count,
+            SeriesMetric::Sum => PeriodMetric::Sum,
+        }
+    }
+}
+
+impl PeriodMetric {
+    /// Агрегат по строкам одного периода
+    fn over(self, period: &Period) -> String {
+        match self {
+            PeriodMetric::Count => format!("COUNT(*) FILTER (WHERE {})", period.condition()),
+            PeriodMetric::Sum => format!("COALESCE(SUM(transaction_amount_kzt) FILTER (WHERE {}), 0)::float8", period.condition()),
+            PeriodMetric::Avg => format!("ROUND(AVG(transaction_amount_kzt) FILTER (WHERE {}), 2)::float8", period.condition()),
+        }
+    }
+}
+
+/// Метрика за два периода в одной строке: SELECT-список `period_a, period_b`
+/// и условие на строки обоих периодов вместе с фильтрами
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeriodAggregates {
+    pub select: String,
+    pub condition: String,
+}
+
+impl PeriodAggregates {
+    pub fn new(metric: PeriodMetric, period_a: &Period, period_b: &Period, filters: Vec<String>) -> Self {
+        let mut conditions = vec![format!("(({}) OR ({}))", period_a.condition(), period_b.condition())];
+        conditions.extend(filters);
+        Self {
+            select: format!("{} AS period_a, {} AS period_b", metric.over(period_a), metric.over(period_b)),
+            condition: conditions.join(" AND "),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    #[default]
+    Daily,
+    Hourly,
+    Monthly,
+}
+
+impl Granularity {
+    fn trunc_unit(self) -> &'static str {
+        match self {
+            Granularity::Daily => "day",
+            Granularity::Hourly => "hour",
+            Granularity::Monthly => "month",
+        }
+    }
+
+    /// Длина сезонного цикла в точках: неделя, сутки, год
+    pub fn season_length(self) -> usize {
+        match self {
+            Granularity::Daily => 7,
+            Granularity::Hourly => 24,
+            Granularity::Monthly => 12,
+        }
+    }
+
+    /// Начало периода через `steps` шагов
+    pub fn advance(self, period: NaiveDateTime, steps: u32) -> NaiveDateTime {
+        match self {
+            Granularity::Daily => period + Duration::days(steps as i64),
+            Granularity::Hourly => period + Duration::hours(steps as i64),
+            Granularity::Monthly => period.checked_add_months(Months::new(steps)).unwrap_or(period),
+        }
+    }
+
+    /// Подпись точки ряда
+    pub fn label(self, period: NaiveDateTime) -> String {
+        match self {
+            Granularity::Daily => period.date().to_string(),
+            Granularity::Hourly => period.format("%Y-%m-%d %H:00").to_string(),
+            Granularity::Monthly => period.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+/// Необязательные фильтры ряда (сравнение на равенство)
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SeriesFilters {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merchant_city: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer_bank_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mcc_category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction_type: Option<String>,
+}
+
+impl SeriesFilters {
+    pub fn conditions(&self) -> Vec<String> {
+        [
+            ("merchant_city", &self.merchant_city),
+            ("issuer_bank_name", &self.issuer_bank_name),
+            ("mcc_category", &self.mcc_category),
+            ("transaction_type", &self.transaction_type),
+        ]
+        .into_iter()
+        .filter_map(|(column, value)| {
+            let value = value.as_deref()?.trim();
+            (!value.is_empty()).then(|| format!("{} = '{}'", column, value.replace('\'', "''")))
+        })
+        .collect()
+    }
+}
+
+/// Что считать: метрика, шаг, период (включительно) и фильтры
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SeriesSpec {
+    #[serde(default)]
+    pub metric: SeriesMetric,
+    #[serde(default)]
+    pub granularity: Granularity,
+    #[serde(default)]
+    pub from: Option<NaiveDate>,
+    #[serde(default)]
+    pub to: Option<NaiveDate>,
+    #[serde(flatten)]
+    pub filters: SeriesFilters,
+}
+
+impl SeriesSpec {
+    /// SQL ряда `period, value`. Без периода дневной ряд строится по всем данным,
+    /// почасовой - за последние HOURLY_DEFAULT_DAYS дней.
+    pub fn sql(&self) -> Result<String, String> {
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if to < from {
+                return Err(format!("Period {}..{} ends before it starts", from, to));
+            }
+        }
+        let mut conditions = Vec::new();
+        match (self.from, self.to, self.granularity) {
+            (None, None, Granularity::Hourly) => conditions.push(format!(
+                "transaction_timestamp >= (SELECT MAX(transaction_timestamp) FROM transactions) - INTERVAL '{} days'",
+                HOURLY_DEFAULT_DAYS
+            )),
+            (None, Some(to), Granularity::Hourly) => {
+                conditions.push(format!("transaction_timestamp >= '{}'", to - Duration::days(HOURLY_DEFAULT_DAYS - 1)))
+            }
+            (Some(from), _, _) => conditions.push(format!("transaction_timestamp >= '{}'", from)),
+            _ => {}
+        }
+        if let Some(to) = self.to {
+            conditions.push(format!("transaction_timestamp < '{}'", to + Duration::days(1)));
+        }
+        conditions.extend(self.filters.conditions());
+
+        let where_clause = match conditions.is_empty() {
+            true => String::new(),
+            false => format!(" WHERE {}", conditions.join(" AND ")),
+        };
+        Ok(format!(
+            "SELECT date_trunc('{}', transaction_timestamp) AS period, {} AS value FROM transactions{} GROUP BY 1 ORDER BY 1;",
+            self.granularity.trunc_unit(),
+            self.metric.expression(),
+            where_clause
+        ))
+    }
+}
+
+/// Время из ячейки результата: `2024-01-01 00:00:00` или RFC 3339
+pub fn parse_period(value: &Value) -> Option<NaiveDateTime> {
+    let text = value.as_str()?;
+    DateTime::parse_from_rfc3339(text)
+        .map(|dt| dt.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f"))
+        .ok()
+}
+
+/// Точки ряда по времени. Строки без периода (объединенные "прочие" группы) пропускаются.
+pub fn parse_series(rows: &[Value]) -> Vec<(NaiveDateTime, f64)> {
+    let mut series: Vec<(NaiveDateTime, f64)> = rows
+        .iter()
+        .filter_map(|row| Some((parse_period(row.get("period")?)?, numeric_value(row.get("value")?)?)))
+        .collect();
+    series.sort_by_key(|(period, _)| *period);
+    series
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_series_sql() {
+        let spec = SeriesSpec {
+            metric: SeriesMetric::Sum,
+            from: NaiveDate::from_ymd_opt(2024, 1, 1),
+            to: NaiveDate::from_ymd_opt(2024, 1, 31),
+            filters: SeriesFilters { merchant_city: Some("Almaty".to_string()), mcc_category: Some("Men's wear".to_string()), ..Default::default() },
+            ..Default::default()
+        };
+        assert_eq!(
+            spec.sql().unwrap(),
+            "SELECT date_trunc('day', transaction_timestamp) AS period, COALESCE(SUM(transaction_amount_kzt), 0)::float8 AS value \
+            FROM transactions WHERE transaction_timestamp >= '2024-01-01' AND transaction_timestamp < '2024-02-01' \
+            AND merchant_city = 'Almaty' AND mcc_category = 'Men''s wear' GROUP BY 1 ORDER BY 1;"
+        );
+
+        let hourly = SeriesSpec { granularity: Granularity::Hourly, ..Default::default() };
+        assert!(hourly.sql().unwrap().contains("(SELECT MAX(transaction_timestamp) FROM transactions) - INTERVAL '14 days'"));
+
+        let reversed = SeriesSpec { from: NaiveDate::from_ymd_opt(2024, 2, 1), to: NaiveDate::from_ymd_opt(2024, 1, 1), ..Default::default() };
+        assert!(reversed.sql().is_err());
+    }
+
+    #[test]
+    fn test_period_aggregates() {
+        let january = Period { from: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(), to: NaiveDate::from_ymd_opt(2024, 1, 31).unwrap() };
+        let february = Period { from: NaiveDate::from_ymd_opt(2024, 2, 1).unwrap(), to: NaiveDate::from_ymd_opt(2024, 2, 29).unwrap() };
+        let aggregates = PeriodAggregates::new(SeriesMetric::Sum.into(), &january, &february, vec!["wallet_type = 'Apple Pay'".to_string()]);
+        assert_eq!(
+            aggregates.select,
+            "COALESCE(SUM(transaction_amount_kzt) FILTER (WHERE transaction_timestamp >= '2024-01-01' AND transaction_timestamp < '2024-02-01'), 0)::float8 AS period_a, \
+            COALESCE(SUM(transaction_amount_kzt) FILTER (WHERE transaction_timestamp >= '2024-02-01' AND transaction_timestamp < '2024-03-01'), 0)::float8 AS period_b"
+        );
+        assert_eq!(
+            aggregates.condition,
+            "((transaction_timestamp >= '2024-01-01' AND transaction_timestamp < '2024-02-01') OR \
+            (transaction_timestamp >= '2024-02-01' AND transaction_timestamp < '2024-03-01')) AND wallet_type = 'Apple Pay'"
+        );
+
+        assert!(check_dimension("merchant_id").is_ok());
+        assert!(check_dimension("card_id").is_err());
+    }
+
+    #[test]
+    fn test_parse_series() {
+        let rows = vec![
+            serde_json::json!({"period": "2024-01-02 00:00:00", "value": 5}),
+            serde_json::json!({"period": "2024-01-01T00:00:00+00:00", "value": 3}),
+            serde_json::json!({"period": "Прочие", "value": 2}),
+        ];
+        let series = parse_series(&rows);
+        assert_eq!(series.len(), 2);
+        assert_eq!(Granularity::Daily.label(series[0].0), "2024-01-01");
+        assert_eq!(series[1].1, 5.0);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{
+    auth::{api_keys::{self, ApiKeyInfo, CreatedApiKey}, Principal, Role},
+    error::AppError,
+    state::AppState,
+};
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    #[serde(default)]
+    pub principal_id: Option<String>,  // По умолчанию совпадает с name
+    #[serde(default = "default_role")]
+    pub role: Role,
+    #[serde(default)]
+    pub tenant_id: Option<String>,  // Без арендатора - доступ ко всем банкам
+}
+
+fn default_role() -> Role {
+    Role::Analyst
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeApiKeyResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+pub async fn list_api_keys(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ApiKeyInfo>>, AppError> {
+    Ok(Json(api_keys::list_keys(&state.db).await?))
+}
+
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    Extension(admin): Extension<Principal>,
+    Json(req): Json<CreateApiKeyRequest>,
+) -> Result<Json<CreatedApiKey>, AppError> {
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("name must not be empty".to_string()));
+    }
+    let principal_id = req.principal_id.as_deref().map(str::trim).unwrap_or(name);
+
+    if let Some(tenant) = &req.tenant_id {
+        if state.tenants.get(tenant).is_none() {
+            return Err(AppError::BadRequest(format!("Unknown tenant '{}'", tenant)));
+        }
+    }
+
+    let created = api_keys::create_key(&state.db, name, principal_id, req.role, req.tenant_id.as_deref()).await?;
+    tracing::info!("API key {} created by {}", created.info.id, admin.id);
+    Ok(Json(created))
+}
+
+pub async fn revoke_api_key(
+    State(state): State<AppState>,
+    Extension(admin): Extension<Principal>,
+    Path(id): Path<i32>,
+) -> Result<Json<RevokeApiKeyResponse>, AppError> {
+    if !api_keys::revoke_key(&state.db, id).await? {
+        return Err(AppError::BadRequest(format!("API key {} not found or already revoked", id)));
+    }
+    tracing::info!("API key {} revoked by {}", id, admin.id);
+    Ok(Json(RevokeApiKeyResponse {
+        success: true,
+        message: format!("API key {} revoked", id),
+    }))
+}
@@ -0,0 +1,237 @@
+use crate::{
+    analysis::{verify_analysis, AnalysisResult, Insight, InsightSignificance},
+    api::{
+        models::{AgentMeta, AgentStopReason, BlockError, EvidenceStep, QueryRequest, QueryResponse, ResponseMeta},
+        query::{log_query_audit, AuditIdentity},
+        scoped::execute_scoped,
+    },
+    auth::Principal,
+    error::AppError,
+    i18n::{t, tf},
+    llm::{
+        prompts::{build_agent_prompt, AGENT_PROMPT_VERSION},
+        tools::{self, AgentAction, OBSERVATION_ROWS},
+        usage,
+    },
+    privacy::Audience,
+    state::AppState,
+    tenants::Tenant,
+    utils::language::Language,
+};
+use std::time::Instant;
+use tracing::Instrument;
+
+/// Режим агента: модель по шагам выбирает инструменты (run_sql, describe_column, compare_periods),
+/// пока не вернет итоговый отчет или не исчерпает AGENT_MAX_STEPS / AGENT_MAX_TOKENS.
+/// SQL каждого шага выполняется через `execute_scoped` - с теми же проверками, что и основной запрос.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn answer_with_agent(
+    state: &AppState,
+    principal: &Principal,
+    tenant: Option<&Tenant>,
+    audit: &AuditIdentity,
+    req: &QueryRequest,
+    question: &str,
+    language: Language,
+    mut meta: ResponseMeta,
+    start: Instant,
+) -> Result<QueryResponse, AppError> {
+    let max_steps = state.config.agent_max_steps;
+    let show_sql = req.include_sql && principal.role.can_view_sql();
+    let tokens_at_start = usage::current_usage().tokens;
+    let tokens_used = || usage::current_usage().tokens.saturating_sub(tokens_at_start);
+
+    let mut transcript = String::new();
+    let mut evidence: Vec<EvidenceStep> = Vec::new();
+    let mut report: Option<AnalysisResult> = None;
+    let mut stop_reason = AgentStopReason::StepLimit;
+    let mut last_sql: Option<String> = None;
+    let mut steps = 0;
+    let (mut generate_ms, mut execute_ms) = (0u64, 0u64);
+
+    for step in 1..=max_steps {
+        if tokens_used() >= state.config.agent_max_tokens {
+            stop_reason = AgentStopReason::TokenBudget;
+            break;
+        }
+        steps = step;
+
+        let generate_start = Instant::now();
+        let prompt = build_agent_prompt(question, &transcript, max_steps - step + 1, &language);
+        let response = state.llm.agent_step(&prompt)
+            .instrument(tracing::info_span!("agent_step", step))
+            .await;
+        generate_ms += generate_start.elapsed().as_millis() as u64;
+        let response = match response {
+            Ok(response) => response,
+            // Без единого шага отчет строить не из чего - ошибка модели возвращается клиенту
+            Err(e) if evidence.is_empty() => return Err(e.into()),
+            Err(e) => {
+                tracing::warn!("Agent step {} failed, building report from evidence: {}", step, e);
+                stop_reason = AgentStopReason::ModelError;
+                break;
+            }
+        };
+
+        let agent_step = match tools::parse_agent_step(&response) {
+            Ok(agent_step) => agent_step,
+            Err(message) => {
+                tracing::warn!("Agent step {} is not a valid tool call: {}", step, message);
+                transcript.push_str(&format!("Step {}: invalid response\nError: {}\n\n", step, message));
+                continue;
+            }
+        };
+        let tool = agent_step.action.tool();
+        let sql = match agent_step.action {
+            AgentAction::FinalAnswer(answer) => {
+                report = Some(answer.into_analysis());
+                stop_reason = AgentStopReason::FinalAnswer;
+                break;
+            }
+            AgentAction::RunSql { sql } => Ok(sql),
+            AgentAction::DescribeColumn { column } => tools::describe_column_sql(&column),
+            AgentAction::ComparePeriods(ref args) => tools::compare_periods_sql(args),
+        };
+        let args_json = agent_step.args.to_string();
+        transcript.push_str(&format!("Step {}: {} {}\n", step, tool, args_json));
+
+        // Неверные аргументы инструмента - такая же ошибка шага, как отклоненный SQL
+        let step_start = Instant::now();
+        let attempted_sql = sql.clone().unwrap_or_default();
+        let result = match sql {
+            Ok(sql) => execute_scoped(state, principal, tenant, &sql, req.use_cache, language)
+                .instrument(tracing::info_span!("agent_tool", step, tool))
+                .await
+                .map(|scoped| (sql, scoped)),
+            Err(message) => Err(AppError::BadRequest(message)),
+        };
+        let elapsed = step_start.elapsed().as_millis() as u64;
+        // SQL из run_sql показывается в поле `sql`, а не в аргументах
+        let args = match tool {
+            tools::RUN_SQL => serde_json::Value::Null,
+            _ => agent_step.args,
+        };
+
+        match result {
+            Ok((sql, mut scoped)) => {
+                execute_ms += scoped.execution_time_ms;
+                if tool == tools::COMPARE_PERIODS {
+                    tools::add_period_deltas(&mut scoped.data);
+                }
+                let _ = log_query_audit(state, audit, question, &sql, true, elapsed).await;
+
+                // Модели - первые строки результата, с политикой колонок для LLM
+                let mut observation: Vec<serde_json::Value> = scoped.data.iter().take(OBSERVATION_ROWS).cloned().collect();
+                state.column_policy.apply(&sql, &mut observation, Audience::Llm);
+                transcript.push_str(&format!(
+                    "Observation ({} rows{}): {}\n\n",
+                    scoped.row_count,
+                    if scoped.row_count > OBSERVATION_ROWS { format!(", first {} shown", OBSERVATION_ROWS) } else { String::new() },
+                    serde_json::Value::Array(observation)
+                ));
+
+                evidence.push(EvidenceStep {
+                    step,
+                    tool,
+                    thought: agent_step.thought,
+                    args,
+                    sql: if show_sql { sql.clone() } else { String::new() },
+                    row_count: scoped.row_count,
+                    data: scoped.data,
+                    execution_time_ms: scoped.execution_time_ms,
+                    error: None,
+                });
+                last_sql = Some(sql);
+            }
+            Err(e) => {
+                tracing::warn!("Agent step {} ({}) failed: {}", step, tool, e);
+                let _ = log_query_audit(state, audit, question, &attempted_sql, false, elapsed).await;
+                transcript.push_str(&format!("Error: {}\n\n", e));
+                let code = e.code();
+                evidence.push(EvidenceStep {
+                    step,
+                    tool,
+                    thought: agent_step.thought,
+                    args,
+                    sql: if show_sql { attempted_sql } else { String::new() },
+                    row_count: 0,
+                    data: vec![],
+                    execution_time_ms: 0,
+                    error: Some(BlockError { code, message: code.localized_message(language) }),
+                });
+            }
+        }
+    }
+
+    let tokens = tokens_used();
+    tracing::info!("Agent finished after {} steps ({:?}, {} tokens)", steps, stop_reason, tokens);
+    if stop_reason != AgentStopReason::FinalAnswer {
+        meta.fallback_analysis = true;
+    }
+    let analysis = match report {
+        Some(mut report) => {
+            let datasets: Vec<&[serde_json::Value]> = evidence.iter().map(|step| step.data.as_slice()).collect();
+            meta.verification = Some(verify_analysis(&mut report, &datasets, question));
+            report
+        }
+        None => fallback_report(&evidence, steps, language),
+    };
+
+    if let Some(sql) = last_sql {
+        let mut context = state.query_context.get_or_create_context(audit.user_id.clone()).await;
+        context.add_query(req.question.clone(), sql);
+        state.query_context.update_context(context).await;
+    }
+
+    meta.prompt_version = AGENT_PROMPT_VERSION.to_string();
+    meta.timings.generate_sql_ms = Some(generate_ms);
+    meta.timings.execute_ms = Some(execute_ms);
+    meta.agent = Some(AgentMeta { steps, tokens, stop_reason });
+
+    let total_time = start.elapsed().as_millis() as u64;
+    let _ = log_query_audit(state, audit, &req.question, "", true, total_time).await;
+
+    Ok(QueryResponse {
+        question: req.question.clone(),
+        sql: String::new(),
+        text_response: None,
+        data: vec![],
+        table: None,
+        chart_data: None,
+        execution_time_ms: total_time,
+        row_count: evidence.iter().map(|step| step.row_count).sum(),
+        cached: false,
+        blocks: vec![],
+        evidence,
+        clarification: None,
+        analysis: Some(analysis),
+        meta,
+    })
+}
+
+/// Отчет без LLM, если агент не дошел до final_answer: по одному пункту на шаг
+fn fallback_report(evidence: &[EvidenceStep], steps: usize, language: Language) -> AnalysisResult {
+    let insights = evidence
+        .iter()
+        .map(|step| Insight {
+            title: tf(language, "agent.step_title", &[("step", &step.step), ("tool", &step.tool)]),
+            description: match &step.error {
+                Some(error) => error.message.to_string(),
+                None => tf(language, "agent.step_rows", &[("count", &step.row_count)]),
+            },
+            significance: InsightSignificance::Medium,
+        })
+        .collect();
+
+    AnalysisResult {
+        headline: tf(language, "agent.stopped", &[("steps", &steps)]),
+        insights,
+        explanation: t(language, "agent.evidence_hint").to_string(),
+        suggested_questions: vec![
+            t(language, "analysis.show_details").to_string(),
+            t(language, "analysis.compare_periods").to_string(),
+        ],
+        chart_type: None,
+        data: vec![],
+    }
+}
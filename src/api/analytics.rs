@@ -0,0 +1,699 @@
+use crate::{
+    analysis::{format_number, format_percent, verify_analysis, AnalysisResult, AnalysisVerification},
+    analytics::{
+        anomalies::{self, Seasonality, DEFAULT_THRESHOLD},
+        forecast::{self, fill_gaps, ForecastMethod, ForecastQuestion, SmoothingParams, DEFAULT_LEVEL},
+        root_cause::{self, Driver, RootCauseSpec, DEFAULT_TOP_DRIVERS, WATERFALL_STEPS},
+        series::{parse_series, Granularity, SeriesMetric, SeriesSpec},
+    },
+    api::{
+        models::{ChartData, ChartDataset, DateRange, QueryRequest, QueryResponse, ResponseMeta},
+        query::{log_query_audit, AuditIdentity},
+        scoped::{execute_scoped, ScopedResult},
+    },
+    auth::Principal,
+    error::AppError,
+    i18n::{column_label, t, tf},
+    llm::{prompts::FORECAST_PROMPT_VERSION, tools::Period},
+    privacy::k_anonymity::GroupSuppression,
+    state::AppState,
+    utils::language::{apply_requested_language, response_language, Language},
+};
+use axum::{extract::State, Extension, Json};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Instant;
+use tracing::Instrument;
+
+#[derive(Debug, Deserialize)]
+pub struct AnomalyRequest {
+    #[serde(flatten)]
+    pub series: SeriesSpec,  // metric, granularity, from, to и фильтры
+    #[serde(default)]
+    pub threshold: Option<f64>,  // Порог |score|, по умолчанию 3.5
+    #[serde(default)]
+    pub include_sql: bool,
+    #[serde(default)]
+    pub use_cache: bool,
+    #[serde(default)]
+    pub language: Option<String>,  // Язык подписей диаграммы (ru, en, kk, uz, ky)
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnomalyResponse {
+    pub metric: SeriesMetric,
+    pub granularity: Granularity,
+    pub method: &'static str,
+    pub seasonality: Seasonality,
+    pub threshold: f64,
+    pub points: usize,
+    pub anomalies: Vec<AnomalyPoint>,  // По убыванию |score|
+    pub chart_data: ChartData,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub sql: String,
+    pub execution_time_ms: u64,
+    pub cached: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_range: Option<DateRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_suppression: Option<GroupSuppression>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unknown_periods: Vec<String>,  // Периоды меньше MIN_GROUP_SIZE: значение скрыто и не оценивается
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnomalyPoint {
+    pub period: String,
+    pub value: f64,
+    pub expected: f64,
+    pub score: f64,
+    pub direction: AnomalyDirection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyDirection {
+    Spike,
+    Drop,
+}
+
+/// Аномальные дни/часы ряда: скользящая медиана с сезонностью по дню недели (часу суток) и MAD
+pub async fn handle_anomalies(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Json(req): Json<AnomalyRequest>,
+) -> Result<Json<AnomalyResponse>, AppError> {
+    let start = Instant::now();
+    apply_requested_language(req.language.as_deref()).map_err(AppError::BadRequest)?;
+    let language = response_language();
+    let threshold = req.threshold.unwrap_or(DEFAULT_THRESHOLD);
+    if !threshold.is_finite() || threshold <= 0.0 {
+        return Err(AppError::BadRequest("threshold must be a positive number".to_string()));
+    }
+    let sql = req.series.sql().map_err(AppError::BadRequest)?;
+
+    let scoped = run_scoped_sql(&state, &principal, &sql, req.use_cache, language, "/api/analytics/anomalies").await?;
+    let series = parse_series(&scoped.data);
+    let seasonality = Seasonality::for_granularity(req.series.granularity);
+    if series.len() < seasonality.min_points() {
+        return Err(AppError::BadRequest(format!(
+            "Not enough data for anomaly detection: {} points, at least {} needed",
+            series.len(),
+            seasonality.min_points()
+        )));
+    }
+
+    // Периоды без транзакций в результат SQL не попадают - это нули (провал до нуля - аномалия);
+    // периоды, скрытые из-за MIN_GROUP_SIZE, - неизвестные значения
+    let granularity = req.series.granularity;
+    let hidden: Vec<NaiveDateTime> = scoped
+        .group_suppression
+        .as_ref()
+        .map(|suppression| parse_series(&suppression.suppressed_rows).into_iter().map(|(period, _)| period).collect())
+        .unwrap_or_default();
+    let mut periods = series.clone();
+    periods.extend(hidden.iter().map(|period| (*period, 0.0)));
+    periods.sort_by_key(|(period, _)| *period);
+    let filled: Vec<(NaiveDateTime, Option<f64>)> = fill_gaps(&periods, granularity)
+        .into_iter()
+        .map(|(period, value)| (period, (!hidden.contains(&period)).then_some(value)))
+        .collect();
+
+    let scored = anomalies::detect(&filled, seasonality, threshold);
+    let mut flagged: Vec<AnomalyPoint> = scored
+        .iter()
+        .filter(|point| point.anomaly)
+        .map(|point| AnomalyPoint {
+            period: granularity.label(point.period),
+            value: point.value.unwrap_or_default(),
+            expected: round2(point.expected),
+            score: round2(point.score),
+            direction: if point.score > 0.0 { AnomalyDirection::Spike } else { AnomalyDirection::Drop },
+        })
+        .collect();
+    flagged.sort_by(|a, b| b.score.abs().total_cmp(&a.score.abs()));
+    tracing::info!("Anomaly detection: {} points, {} flagged (threshold {})", scored.len(), flagged.len(), threshold);
+
+    let metric_label = t(language, req.series.metric.label_key());
+    let chart_data = ChartData {
+        chart_type: "line".to_string(),
+        labels: scored.iter().map(|point| granularity.label(point.period)).collect(),
+        datasets: vec![
+            // Скрытые периоды - NaN, в JSON это null
+            ChartDataset {
+                label: metric_label.to_string(),
+                data: scored.iter().map(|point| point.value.unwrap_or(f64::NAN)).collect(),
+                background_color: None,
+            },
+            ChartDataset {
+                label: t(language, "analytics.expected").to_string(),
+                data: scored.iter().map(|point| round2(point.expected)).collect(),
+                background_color: None,
+            },
+            // Точки вне аномалий - NaN, в JSON это null (разрыв линии)
+            ChartDataset {
+                label: t(language, "analytics.anomalies").to_string(),
+                data: scored.iter().map(|point| if point.anomaly { point.value.unwrap_or(f64::NAN) } else { f64::NAN }).collect(),
+                background_color: Some("#e53935".to_string()),
+            },
+        ],
+        title: Some(tf(language, "analytics.anomalies_title", &[("metric", &metric_label)])),
+        drill_tokens: None,
+    };
+
+    Ok(Json(AnomalyResponse {
+        metric: req.series.metric,
+        granularity,
+        method: "rolling_median_mad",
+        seasonality,
+        threshold,
+        points: scored.len(),
+        anomalies: flagged,
+        chart_data,
+        sql: if req.include_sql && principal.role.can_view_sql() { sql } else { String::new() },
+        execution_time_ms: start.elapsed().as_millis() as u64,
+        cached: scoped.cached,
+        date_range: scoped.date_range,
+        group_suppression: scoped.group_suppression,
+        unknown_periods: scored.iter().filter(|point| point.value.is_none()).map(|point| granularity.label(point.period)).collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RootCauseRequest {
+    #[serde(flatten)]
+    pub spec: RootCauseSpec,  // metric, period_a, period_b, dimensions и фильтры
+    #[serde(default)]
+    pub top: Option<usize>,  // Сколько факторов вернуть, по умолчанию 10
+    #[serde(default)]
+    pub include_analysis: bool,  // Дополнительно объяснить факторы через LLM
+    #[serde(default)]
+    pub question: Option<String>,  // Вопрос для LLM-анализа (по умолчанию - заголовок разложения)
+    #[serde(default)]
+    pub include_sql: bool,
+    #[serde(default)]
+    pub use_cache: bool,
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RootCauseResponse {
+    pub metric: SeriesMetric,
+    pub period_a: Period,
+    pub period_b: Period,
+    pub total_a: f64,
+    pub total_b: f64,
+    pub delta: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta_pct: Option<f64>,
+    pub dimensions: Vec<String>,
+    pub drivers: Vec<Driver>,  // Сегменты всех измерений по убыванию |delta|
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub waterfall_dimension: Option<String>,  // Измерение, по которому построен водопад
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chart_data: Option<ChartData>,
+    pub narrative: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analysis: Option<AnalysisResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<AnalysisVerification>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sql: Vec<String>,
+    pub execution_time_ms: u64,
+    pub cached: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_suppression: Option<GroupSuppression>,  // Суммарно по всем измерениям
+}
+
+/// Разложение изменения метрики между двумя периодами по сегментам измерений
+pub async fn handle_root_cause(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Json(req): Json<RootCauseRequest>,
+) -> Result<Json<RootCauseResponse>, AppError> {
+    let start = Instant::now();
+    apply_requested_language(req.language.as_deref()).map_err(AppError::BadRequest)?;
+    let language = response_language();
+    let spec = &req.spec;
+    let dimensions = spec.validate().map_err(AppError::BadRequest)?;
+    const ENDPOINT: &str = "/api/analytics/root-cause";
+
+    let totals_sql = spec.totals_sql();
+    let totals = run_scoped_sql(&state, &principal, &totals_sql, req.use_cache, language, ENDPOINT).await?;
+    let (total_a, total_b) = root_cause::parse_totals(&totals.data);
+    let total_delta = total_b - total_a;
+
+    let mut sqls = vec![totals_sql];
+    let mut cached = totals.cached;
+    let mut suppression: Option<GroupSuppression> = None;
+    let mut by_dimension = Vec::with_capacity(dimensions.len());
+    let other_bucket = t(language, "analysis.other_bucket");
+    for dimension in &dimensions {
+        let sql = spec.dimension_sql(dimension);
+        let scoped = run_scoped_sql(&state, &principal, &sql, req.use_cache, language, ENDPOINT).await?;
+        cached &= scoped.cached;
+        let mut segments = root_cause::parse_segments(&scoped.data);
+        if let Some(current) = scoped.group_suppression {
+            // Объединенные малые группы - не сегмент: их вклад попадает в шаг "прочие" водопада
+            if current.merged_into_other {
+                segments.retain(|segment| segment.segment.as_deref() != Some(other_bucket));
+            }
+            suppression = Some(match suppression {
+                Some(total) => GroupSuppression {
+                    suppressed_groups: total.suppressed_groups + current.suppressed_groups,
+                    merged_into_other: total.merged_into_other || current.merged_into_other,
+                    suppressed_rows: [total.suppressed_rows, current.suppressed_rows].concat(),
+                    ..total
+                },
+                None => current,
+            });
+        }
+        by_dimension.push((dimension.clone(), segments));
+        sqls.push(sql);
+    }
+
+    let drivers = root_cause::rank_drivers(&by_dimension, total_delta, req.top.unwrap_or(DEFAULT_TOP_DRIVERS).max(1));
+    let metric_label = t(language, spec.metric.label_key());
+    let period_a_label = period_label(language, &spec.period_a);
+    let period_b_label = period_label(language, &spec.period_b);
+    let segment_label = |segment: &Option<String>| segment.clone().unwrap_or_else(|| t(language, "analytics.no_value").to_string());
+
+    let waterfall_dimension = root_cause::explanatory_dimension(&by_dimension, total_delta).map(str::to_string);
+    let chart_data = waterfall_dimension.as_deref().map(|dimension| {
+        let segments = &by_dimension.iter().find(|(d, _)| d == dimension).expect("waterfall dimension").1;
+        let steps = root_cause::waterfall_steps(segments, total_a, total_b, WATERFALL_STEPS);
+        let mut labels = vec![period_a_label.clone()];
+        labels.extend(steps.iter().map(|step| match &step.segment {
+            Some(segment) => segment_label(segment),
+            None => other_bucket.to_string(),
+        }));
+        labels.push(period_b_label.clone());
+        let mut data = vec![total_a];
+        data.extend(steps.iter().map(|step| round2(step.delta)));
+        data.push(total_b);
+        // Первый и последний столбцы - итоги периодов, между ними - изменения сегментов
+        ChartData {
+            chart_type: "waterfall".to_string(),
+            labels,
+            datasets: vec![ChartDataset { label: metric_label.to_string(), data, background_color: None }],
+            title: Some(tf(language, "analytics.root_cause_title", &[
+                ("metric", &metric_label),
+                ("dimension", &column_label(language, dimension)),
+            ])),
+            drill_tokens: None,
+        }
+    });
+
+    let delta_pct = (total_a != 0.0).then(|| round2(total_delta / total_a.abs() * 100.0));
+    let narrative = root_cause_narrative(language, metric_label, (&period_a_label, &period_b_label), (total_a, total_b), delta_pct, &drivers);
+    tracing::info!(
+        "Root cause: {} -> {} across {} dimensions, {} drivers",
+        total_a,
+        total_b,
+        dimensions.len(),
+        drivers.len()
+    );
+
+    // Режим анализа: таблица факторов уходит в LLM, числа ответа сверяются с ней
+    let (analysis, verification) = if req.include_analysis && !drivers.is_empty() {
+        let question = req.question.clone().unwrap_or_else(|| {
+            tf(language, "analytics.root_cause_question", &[("metric", &metric_label), ("period_a", &period_a_label), ("period_b", &period_b_label)])
+        });
+        let rows: Vec<Value> = drivers
+            .iter()
+            .map(|driver| {
+                json!({
+                    "dimension": column_label(language, &driver.dimension),
+                    "segment": segment_label(&driver.segment),
+                    "period_a": driver.period_a,
+                    "period_b": driver.period_b,
+                    "delta": round2(driver.delta),
+                    "contribution_pct": driver.contribution_pct.map(round2),
+                })
+            })
+            .collect();
+        let totals_row = [json!({"period_a": total_a, "period_b": total_b, "delta": total_delta})];
+        match state.analysis.analyze_results(&question, &sqls[0], &rows, &language)
+            .instrument(tracing::info_span!("analyze"))
+            .await
+        {
+            Ok(mut analysis) => {
+                let verification = verify_analysis(&mut analysis, &[&rows, &totals_row], &question);
+                (Some(analysis), Some(verification))
+            }
+            Err(e) => {
+                // Без LLM остается детерминированный narrative
+                tracing::warn!("Root cause analysis failed, returning narrative only: {}", e);
+                (None, None)
+            }
+        }
+    } else {
+        (None, None)
+    };
+
+    Ok(Json(RootCauseResponse {
+        metric: spec.metric,
+        period_a: spec.period_a,
+        period_b: spec.period_b,
+        total_a,
+        total_b,
+        delta: round2(total_delta),
+        delta_pct,
+        dimensions,
+        drivers: drivers
+            .into_iter()
+            .map(|driver| Driver {
+                delta: round2(driver.delta),
+                delta_pct: driver.delta_pct.map(round2),
+                contribution_pct: driver.contribution_pct.map(round2),
+                ..driver
+            })
+            .collect(),
+        waterfall_dimension,
+        chart_data,
+        narrative,
+        analysis,
+        verification,
+        sql: if req.include_sql && principal.role.can_view_sql() { sqls } else { vec![] },
+        execution_time_ms: start.elapsed().as_millis() as u64,
+        cached,
+        group_suppression: suppression,
+    }))
+}
+
+fn period_label(language: Language, period: &Period) -> String {
+    tf(language, "analytics.period", &[("from", &period.from.to_string()), ("to", &period.to.to_string())])
+}
+
+/// Текст разложения: общее изменение и до трех главных факторов
+fn root_cause_narrative(
+    language: Language,
+    metric: &str,
+    (period_a, period_b): (&str, &str),
+    (total_a, total_b): (f64, f64),
+    delta_pct: Option<f64>,
+    drivers: &[Driver],
+) -> String {
+    let signed = |value: f64| match value > 0.0 {
+        true => format!("+{}", format_number(value, language)),
+        false => format_number(value, language),
+    };
+    let change = |delta: f64, pct: Option<f64>| match pct {
+        Some(pct) => format!("{}, {}{}%", signed(delta), if pct > 0.0 { "+" } else { "" }, format_percent(pct, language)),
+        None => signed(delta),
+    };
+    if drivers.is_empty() || total_a == total_b {
+        return tf(language, "analytics.root_cause_no_change", &[("metric", &metric), ("period_a", &period_a), ("period_b", &period_b)]);
+    }
+    let mut parts = vec![tf(language, "analytics.root_cause_summary", &[
+        ("metric", &metric),
+        ("period_a", &period_a),
+        ("period_b", &period_b),
+        ("from", &format_number(total_a, language)),
+        ("to", &format_number(total_b, language)),
+        ("change", &change(total_b - total_a, delta_pct)),
+    ])];
+    for driver in drivers.iter().take(3) {
+        parts.push(tf(language, "analytics.root_cause_driver", &[
+            ("dimension", &column_label(language, &driver.dimension)),
+            ("segment", &driver.segment.clone().unwrap_or_else(|| t(language, "analytics.no_value").to_string())),
+            ("change", &change(driver.delta, driver.delta_pct)),
+            ("share", &format_percent(driver.contribution_pct.unwrap_or(0.0), language)),
+        ]));
+    }
+    parts.join(" ")
+}
+
+/// Сколько последних периодов истории показывать на диаграмме прогноза
+const CHART_HISTORY: usize = 90;
+
+#[derive(Debug, Deserialize)]
+pub struct ForecastRequest {
+    #[serde(flatten)]
+    pub series: SeriesSpec,  // metric, granularity, from, to и фильтры
+    #[serde(default)]
+    pub horizon: Option<usize>,  // Шагов вперед: по умолчанию 30 дней, 48 часов или 3 месяца
+    #[serde(default)]
+    pub level: Option<u32>,  // Уровень интервала: 80, 90, 95 (по умолчанию) или 99
+    #[serde(default)]
+    pub include_sql: bool,
+    #[serde(default)]
+    pub use_cache: bool,
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ForecastResponse {
+    pub metric: SeriesMetric,
+    pub granularity: Granularity,
+    pub method: ForecastMethod,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub season_length: Option<usize>,
+    pub params: SmoothingParams,
+    pub level: u32,
+    pub history_points: usize,  // Периодов истории, по которым подобрана модель
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub incomplete_period: Option<String>,  // Текущий неполный период: в модель не входит, прогнозируется
+    pub forecast: Vec<ForecastPoint>,
+    pub chart_data: ChartData,
+    pub narrative: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub sql: String,
+    pub execution_time_ms: u64,
+    pub cached: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_range: Option<DateRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_suppression: Option<GroupSuppression>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ForecastPoint {
+    pub period: String,
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// Прогноз ряда на N шагов вперед (Хольт - Винтерс с сезонностью дня недели, часа суток или месяца года)
+pub async fn handle_forecast(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Json(req): Json<ForecastRequest>,
+) -> Result<Json<ForecastResponse>, AppError> {
+    apply_requested_language(req.language.as_deref()).map_err(AppError::BadRequest)?;
+    let language = response_language();
+    let mut response = build_forecast(&state, &principal, &req, language, "/api/analytics/forecast").await?;
+    if !(req.include_sql && principal.role.can_view_sql()) {
+        response.sql.clear();
+    }
+    Ok(Json(response))
+}
+
+/// Вопрос-прогноз из /api/query ("какой будет объем в следующем месяце"): SQL ряда строится без LLM,
+/// ответ - текст прогноза, точки прогноза и диаграмма
+pub(super) async fn answer_forecast(
+    state: &AppState,
+    principal: &Principal,
+    req: &QueryRequest,
+    question: ForecastQuestion,
+    language: Language,
+    mut meta: ResponseMeta,
+    start: Instant,
+) -> Result<QueryResponse, AppError> {
+    let forecast_req = ForecastRequest {
+        series: SeriesSpec { metric: question.metric, granularity: question.granularity, ..Default::default() },
+        horizon: Some(question.horizon),
+        level: None,
+        include_sql: req.include_sql,
+        use_cache: req.use_cache,
+        language: None,
+    };
+    let execute_start = Instant::now();
+    let response = build_forecast(state, principal, &forecast_req, language, &req.question).await?;
+    meta.timings.execute_ms = Some(execute_start.elapsed().as_millis() as u64);
+    meta.prompt_version = FORECAST_PROMPT_VERSION.to_string();
+    meta.date_range = response.date_range.clone();
+    meta.group_suppression = response.group_suppression.clone();
+
+    let data: Vec<Value> = response
+        .forecast
+        .iter()
+        .map(|point| json!({"period": point.period, "forecast": point.value, "lower": point.lower, "upper": point.upper}))
+        .collect();
+    Ok(QueryResponse {
+        question: req.question.clone(),
+        sql: if req.include_sql && principal.role.can_view_sql() { response.sql } else { String::new() },
+        text_response: Some(response.narrative),
+        row_count: data.len(),
+        data,
+        table: None,
+        chart_data: Some(response.chart_data),
+        execution_time_ms: start.elapsed().as_millis() as u64,
+        blocks: vec![],
+        evidence: vec![],
+        clarification: None,
+        analysis: None,
+        cached: response.cached,
+        meta,
+    })
+}
+
+/// Ряд, модель и диаграмма прогноза. Без `to` последний период ряда считается неполным:
+/// в подбор модели он не входит и прогнозируется вместе с `horizon` следующими.
+/// В тексте границы суммы за несколько периодов - суммы границ (оценка сверху для ширины интервала)
+async fn build_forecast(
+    state: &AppState,
+    principal: &Principal,
+    req: &ForecastRequest,
+    language: Language,
+    audit_question: &str,
+) -> Result<ForecastResponse, AppError> {
+    let start = Instant::now();
+    let granularity = req.series.granularity;
+    let horizon = req.horizon.unwrap_or_else(|| forecast::default_horizon(granularity));
+    if horizon == 0 || horizon > forecast::max_horizon(granularity) {
+        return Err(AppError::BadRequest(format!("horizon must be between 1 and {}", forecast::max_horizon(granularity))));
+    }
+    let level = req.level.unwrap_or(DEFAULT_LEVEL);
+    let z = forecast::z_for_level(level).ok_or_else(|| AppError::BadRequest("level must be one of 80, 90, 95, 99".to_string()))?;
+    let sql = req.series.sql().map_err(AppError::BadRequest)?;
+
+    let scoped = run_scoped_sql(state, principal, &sql, req.use_cache, language, audit_question).await?;
+    let mut series = fill_gaps(&parse_series(&scoped.data), granularity);
+    // Без `from` первый период начинается с первой транзакции и тоже неполный
+    if req.series.from.is_none() && series.len() > 1 {
+        series.remove(0);
+    }
+    let incomplete = match req.series.to {
+        None => series.pop(),
+        Some(_) => None,
+    };
+    let last = series.last().map(|(period, _)| *period).ok_or_else(|| AppError::BadRequest("No data for a forecast".to_string()))?;
+    let values: Vec<f64> = series.iter().map(|(_, value)| *value).collect();
+    let steps = horizon + usize::from(incomplete.is_some());
+    let model = forecast::forecast(&values, granularity.season_length(), steps, z).map_err(AppError::BadRequest)?;
+    tracing::info!("Forecast: {:?} on {} points, {} steps ahead", model.method, values.len(), steps);
+
+    let points: Vec<ForecastPoint> = model
+        .points
+        .iter()
+        .enumerate()
+        .map(|(idx, projection)| ForecastPoint {
+            period: granularity.label(granularity.advance(last, idx as u32 + 1)),
+            value: round2(projection.value),
+            lower: round2(projection.lower),
+            upper: round2(projection.upper),
+        })
+        .collect();
+
+    let metric_label = t(language, req.series.metric.label_key());
+    let history = &series[series.len().saturating_sub(CHART_HISTORY)..];
+    let gap = |count: usize| std::iter::repeat_n(f64::NAN, count);
+    let last_value = history.last().map(|(_, value)| *value).unwrap_or(f64::NAN);
+    let level_text = level.to_string();
+    // История и прогноз - отдельные линии; прогноз начинается с последней точки истории, чтобы линия не рвалась
+    let chart_data = ChartData {
+        chart_type: "line".to_string(),
+        labels: history
+            .iter()
+            .map(|(period, _)| granularity.label(*period))
+            .chain(points.iter().map(|point| point.period.clone()))
+            .collect(),
+        datasets: vec![
+            ChartDataset {
+                label: t(language, "analytics.history").to_string(),
+                data: history.iter().map(|(_, value)| *value).chain(gap(points.len())).collect(),
+                background_color: None,
+            },
+            ChartDataset {
+                label: t(language, "analytics.forecast").to_string(),
+                data: gap(history.len() - 1).chain([last_value]).chain(points.iter().map(|point| point.value)).collect(),
+                background_color: None,
+            },
+            ChartDataset {
+                label: tf(language, "analytics.lower_bound", &[("level", &level_text)]),
+                data: gap(history.len()).chain(points.iter().map(|point| point.lower)).collect(),
+                background_color: None,
+            },
+            ChartDataset {
+                label: tf(language, "analytics.upper_bound", &[("level", &level_text)]),
+                data: gap(history.len()).chain(points.iter().map(|point| point.upper)).collect(),
+                background_color: None,
+            },
+        ],
+        title: Some(tf(language, "analytics.forecast_title", &[("metric", &metric_label)])),
+        drill_tokens: None,
+    };
+
+    // Текст - о запрошенном горизонте, без текущего неполного периода
+    let requested = &points[points.len() - horizon..];
+    let period = match requested {
+        [single] => single.period.clone(),
+        [first, .., last] => tf(language, "analytics.period", &[("from", &first.period), ("to", &last.period)]),
+        [] => String::new(),
+    };
+    let sum = |value: fn(&ForecastPoint) -> f64| requested.iter().map(value).sum::<f64>();
+    let narrative = [
+        tf(language, "analytics.forecast_summary", &[
+            ("metric", &metric_label),
+            ("period", &period),
+            ("value", &format_number(sum(|point| point.value).round(), language)),
+            ("level", &level_text),
+            ("lower", &format_number(sum(|point| point.lower).round(), language)),
+            ("upper", &format_number(sum(|point| point.upper).round(), language)),
+        ]),
+        tf(language, "analytics.forecast_model", &[
+            ("method", &t(language, model.method.label_key())),
+            ("points", &values.len()),
+        ]),
+    ]
+    .join(" ");
+
+    Ok(ForecastResponse {
+        metric: req.series.metric,
+        granularity,
+        method: model.method,
+        season_length: model.season_length,
+        params: model.params,
+        level,
+        history_points: values.len(),
+        incomplete_period: incomplete.map(|(period, _)| granularity.label(period)),
+        forecast: points,
+        chart_data,
+        narrative,
+        sql,
+        execution_time_ms: start.elapsed().as_millis() as u64,
+        cached: scoped.cached,
+        date_range: scoped.date_range,
+        group_suppression: scoped.group_suppression,
+    })
+}
+
+/// SQL аналитического endpoint-а: те же ограничения, что у /api/query, и запись в аудит.
+/// В аудит вместо вопроса пишется путь endpoint-а (или исходный вопрос, если ответ на него)
+async fn run_scoped_sql(
+    state: &AppState,
+    principal: &Principal,
+    sql: &str,
+    use_cache: bool,
+    language: Language,
+    audit_question: &str,
+) -> Result<ScopedResult, AppError> {
+    let tenant = state.tenants.for_principal(principal);
+    let audit = AuditIdentity {
+        user_id: principal.user_id(None),
+        tenant_id: tenant.map(|t| t.id.clone()),
+    };
+    let query_start = Instant::now();
+    let result = execute_scoped(state, principal, tenant, sql, use_cache, language).await;
+    let _ = log_query_audit(state, &audit, audit_question, sql, result.is_ok(), query_start.elapsed().as_millis() as u64).await;
+    result
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
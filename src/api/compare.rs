@@ -0,0 +1,184 @@
+use crate::{
+    analysis::{verify_analysis, ANALYSIS_PROMPT_VERSION},
+    analytics::compare::{compare_rows, row_label, Comparison, PeriodRows, SUFFIX_A, SUFFIX_B},
+    api::{
+        models::{ChartData, ChartDataset, ComparePeriods, ComparisonMeta, OutputType, QueryRequest, QueryResponse, ResponseMeta},
+        query::{log_query_audit, AuditIdentity},
+        scoped::{execute_scoped_filtered, ScopedResult},
+    },
+    auth::Principal,
+    error::AppError,
+    i18n::{column_label, tf},
+    llm::{tools::Period, validator::query_limit},
+    state::AppState,
+    tenants::Tenant,
+    utils::{formatters, language::Language},
+};
+use std::time::Instant;
+use tracing::Instrument;
+
+/// Больше групп на диаграмме сравнения не показываем (таблица - полная)
+const MAX_CHART_GROUPS: usize = 20;
+
+/// Режим сравнения: один и тот же агрегат выполняется за период A и за период B
+/// (`transactions` ограничивается периодом так же, как фильтром арендатора), строки выравниваются
+/// по колонкам-измерениям, для метрик считаются абсолютные и процентные изменения
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn answer_comparison(
+    state: &AppState,
+    principal: &Principal,
+    tenant: Option<&Tenant>,
+    audit: &AuditIdentity,
+    req: &QueryRequest,
+    sql: &str,
+    periods: ComparePeriods,
+    language: Language,
+    mut meta: ResponseMeta,
+    start: Instant,
+) -> Result<QueryResponse, AppError> {
+    let execute_start = Instant::now();
+    let mut results = Vec::with_capacity(2);
+    for period in [periods.period_a, periods.period_b] {
+        let result = execute_scoped_filtered(state, principal, tenant, sql, Some(&period.condition()), req.use_cache, language)
+            .instrument(tracing::info_span!("execute_period", from = %period.from, to = %period.to))
+            .await;
+        match result {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                let _ = log_query_audit(state, audit, &req.question, sql, false, start.elapsed().as_millis() as u64).await;
+                return Err(e);
+            }
+        }
+    }
+    let (result_b, result_a) = (results.pop().expect("period B result"), results.pop().expect("period A result"));
+    meta.timings.execute_ms = Some(execute_start.elapsed().as_millis() as u64);
+
+    // Строки нет в периоде - ноль, только если результат периода полный
+    let limit = query_limit(sql);
+    let complete = |result: &ScopedResult| {
+        result.group_suppression.is_none() && limit.is_none_or(|limit| (result.row_count as u64) < limit)
+    };
+    let comparison = compare_rows(
+        PeriodRows { rows: &result_a.data, complete: complete(&result_a) },
+        PeriodRows { rows: &result_b.data, complete: complete(&result_b) },
+    )
+    .map_err(AppError::BadRequest)?;
+    tracing::info!(
+        "Comparison: {} + {} rows aligned into {} by {:?}",
+        result_a.row_count,
+        result_b.row_count,
+        comparison.rows.len(),
+        comparison.keys
+    );
+
+    let mut context = state.query_context.get_or_create_context(audit.user_id.clone()).await;
+    context.add_query(req.question.clone(), sql.to_string());
+    state.query_context.update_context(context).await;
+    let _ = log_query_audit(state, audit, &req.question, sql, true, start.elapsed().as_millis() as u64).await;
+
+    let label_a = period_label(language, &periods.period_a);
+    let label_b = period_label(language, &periods.period_b);
+    let (table, chart_data) = match req.output_type {
+        OutputType::Json => (None, None),
+        OutputType::Table => (Some(formatters::format_as_table(&comparison.rows, language)), None),
+        OutputType::Chart => (None, comparison_chart(&comparison, (&label_a, &label_b), language)),
+        OutputType::Auto => (
+            Some(formatters::format_as_table(&comparison.rows, language)),
+            comparison_chart(&comparison, (&label_a, &label_b), language),
+        ),
+    };
+
+    let analysis = if req.include_analysis {
+        let analyze_start = Instant::now();
+        meta.analysis_prompt_version = Some(ANALYSIS_PROMPT_VERSION.to_string());
+        let analysis = match state.analysis.analyze_results(&req.question, sql, &comparison.rows, &language)
+            .instrument(tracing::info_span!("analyze"))
+            .await
+        {
+            Ok(mut analysis) => {
+                meta.verification = Some(verify_analysis(&mut analysis, &[&comparison.rows], &req.question));
+                Some(analysis)
+            }
+            Err(e) => {
+                tracing::warn!("Comparison analysis failed, returning the table only: {}", e);
+                None
+            }
+        };
+        meta.timings.analyze_ms = Some(analyze_start.elapsed().as_millis() as u64);
+        analysis
+    } else {
+        None
+    };
+
+    meta.group_suppression = result_b.group_suppression.or(result_a.group_suppression);
+    meta.column_policies = result_b.column_policies;
+    meta.comparison = Some(ComparisonMeta {
+        period_a: periods.period_a,
+        period_b: periods.period_b,
+        keys: comparison.keys,
+        measures: comparison.measures,
+        rows_a: result_a.row_count,
+        rows_b: result_b.row_count,
+    });
+
+    Ok(QueryResponse {
+        question: req.question.clone(),
+        sql: if req.include_sql && principal.role.can_view_sql() { sql.to_string() } else { String::new() },
+        text_response: None,
+        row_count: comparison.rows.len(),
+        data: comparison.rows,
+        table,
+        chart_data,
+        execution_time_ms: start.elapsed().as_millis() as u64,
+        blocks: vec![],
+        evidence: vec![],
+        clarification: None,
+        analysis,
+        cached: result_a.cached && result_b.cached,
+        meta,
+    })
+}
+
+/// Сгруппированные столбцы: по паре (A, B) на строку сравнения для основной метрики
+fn comparison_chart(comparison: &Comparison, (label_a, label_b): (&str, &str), language: Language) -> Option<ChartData> {
+    let measure = primary_measure(&comparison.measures)?;
+    let rows = &comparison.rows[..comparison.rows.len().min(MAX_CHART_GROUPS)];
+    if rows.is_empty() {
+        return None;
+    }
+    let series = |suffix: &str| -> Vec<f64> {
+        let column = format!("{}{}", measure, suffix);
+        rows.iter().map(|row| row.get(&column).and_then(|value| value.as_f64()).unwrap_or(0.0)).collect()
+    };
+    let measure_label = column_label(language, measure);
+    Some(ChartData {
+        chart_type: "bar".to_string(),
+        labels: rows
+            .iter()
+            .map(|row| match row_label(comparison, row) {
+                label if label.is_empty() => measure_label.clone(),
+                label => label,
+            })
+            .collect(),
+        datasets: vec![
+            ChartDataset { label: label_a.to_string(), data: series(SUFFIX_A), background_color: Some("#90a4ae".to_string()) },
+            ChartDataset { label: label_b.to_string(), data: series(SUFFIX_B), background_color: Some("#1e88e5".to_string()) },
+        ],
+        title: Some(tf(language, "analytics.compare_title", &[("metric", &measure_label), ("period_a", &label_a), ("period_b", &label_b)])),
+        drill_tokens: None,
+    })
+}
+
+/// Метрика для диаграммы: сначала суммы, затем количества
+fn primary_measure(measures: &[String]) -> Option<&String> {
+    let has = |measure: &String, hints: &[&str]| hints.iter().any(|hint| measure.to_lowercase().contains(hint));
+    measures
+        .iter()
+        .find(|measure| has(measure, &["amount", "sum", "total", "volume", "revenue"]))
+        .or_else(|| measures.iter().find(|measure| has(measure, &["count", "cnt", "number", "transactions"])))
+        .or_else(|| measures.first())
+}
+
+fn period_label(language: Language, period: &Period) -> String {
+    tf(language, "analytics.period", &[("from", &period.from), ("to", &period.to)])
+}
@@ -0,0 +1,211 @@
+use crate::{
+    analysis::{statistical_insights, verify_analysis, AnalysisPart, MAX_INSIGHTS, AnalysisResult, Insight, InsightSignificance, COMPOUND_ANALYSIS_PROMPT_VERSION},
+    api::{
+        models::{BlockError, ChartData, OutputType, QueryRequest, QueryResponse, ResponseMeta, ResultBlock},
+        query::{log_query_audit, AuditIdentity},
+        scoped::execute_scoped,
+    },
+    auth::Principal,
+    error::AppError,
+    i18n::{t, tf},
+    state::AppState,
+    tenants::Tenant,
+    utils::{formatters, language::Language},
+};
+use std::time::Instant;
+use tracing::Instrument;
+
+/// Ответ на составной вопрос: каждый подвопрос проходит генерацию, валидацию и выполнение
+/// отдельно, результаты возвращаются блоками, анализ - один на все блоки.
+/// Ошибка подзапроса попадает в его блок; если не выполнился ни один - возвращается первая ошибка.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn answer_compound(
+    state: &AppState,
+    principal: &Principal,
+    tenant: Option<&Tenant>,
+    audit: &AuditIdentity,
+    req: &QueryRequest,
+    sub_questions: Vec<String>,
+    language: Language,
+    mut meta: ResponseMeta,
+    start: Instant,
+) -> Result<QueryResponse, AppError> {
+    let show_sql = req.include_sql && principal.role.can_view_sql();
+    let mut context = state.query_context.get_or_create_context(audit.user_id.clone()).await;
+    let mut blocks = Vec::with_capacity(sub_questions.len());
+    let mut executed: Vec<(String, String)> = Vec::new();  // (подвопрос, SQL) успешных блоков
+    let mut first_error: Option<AppError> = None;
+    let (mut generate_ms, mut validate_ms, mut execute_ms) = (0u64, 0u64, 0u64);
+
+    for (idx, sub_question) in sub_questions.into_iter().enumerate() {
+        let block_start = Instant::now();
+        let result = async {
+            let generate_start = Instant::now();
+            let generated = state.llm.generate_sql(&sub_question, &context.get_recent_queries(10))
+                .instrument(tracing::info_span!("generate_sql"))
+                .await?;
+            generate_ms += (generate_start.elapsed().as_millis() as u64).saturating_sub(generated.validate_ms);
+            validate_ms += generated.validate_ms;
+            meta.sql_repair_attempts += generated.repair_attempts;
+
+            let scoped = execute_scoped(state, principal, tenant, &generated.sql, req.use_cache, language).await;
+            Ok::<_, AppError>((generated.sql, scoped?))
+        }
+        .instrument(tracing::info_span!("sub_query", index = idx))
+        .await;
+        let elapsed = block_start.elapsed().as_millis() as u64;
+
+        match result {
+            Ok((sql, scoped)) => {
+                tracing::info!("Sub-query {} returned {} rows: {}", idx + 1, scoped.row_count, sql);
+                execute_ms += scoped.execution_time_ms;
+                let _ = log_query_audit(state, audit, &sub_question, &sql, true, elapsed).await;
+                context.add_query(sub_question.clone(), sql.clone());
+                let (table, chart_data) = format_block(&scoped.data, &req.output_type, language);
+                executed.push((sub_question.clone(), sql.clone()));
+                blocks.push(ResultBlock {
+                    question: sub_question,
+                    sql: if show_sql { sql } else { String::new() },
+                    row_count: scoped.row_count,
+                    data: scoped.data,
+                    table,
+                    chart_data,
+                    execution_time_ms: scoped.execution_time_ms,
+                    cached: scoped.cached,
+                    date_range: scoped.date_range,
+                    group_suppression: scoped.group_suppression,
+                    column_policies: scoped.column_policies,
+                    error: None,
+                });
+            }
+            Err(e) => {
+                tracing::warn!("Sub-query {} failed: {}", idx + 1, e);
+                let _ = log_query_audit(state, audit, &sub_question, "", false, elapsed).await;
+                let code = e.code();
+                blocks.push(ResultBlock {
+                    question: sub_question,
+                    sql: String::new(),
+                    data: vec![],
+                    row_count: 0,
+                    table: None,
+                    chart_data: None,
+                    execution_time_ms: 0,
+                    cached: false,
+                    date_range: None,
+                    group_suppression: None,
+                    column_policies: vec![],
+                    error: Some(BlockError { code, message: code.localized_message(language) }),
+                });
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    state.query_context.update_context(context).await;
+
+    if executed.is_empty() {
+        return Err(first_error.unwrap_or_else(|| AppError::BadRequest("Empty query plan".to_string())));
+    }
+
+    meta.timings.generate_sql_ms = Some(generate_ms);
+    meta.timings.validate_ms = Some(validate_ms);
+    meta.timings.execute_ms = Some(execute_ms);
+
+    // Один анализ на все успешные блоки
+    let analyze_start = Instant::now();
+    meta.analysis_prompt_version = Some(COMPOUND_ANALYSIS_PROMPT_VERSION.to_string());
+    let parts: Vec<AnalysisPart> = blocks
+        .iter()
+        .filter(|block| block.error.is_none())
+        .zip(&executed)
+        .map(|(block, (question, sql))| AnalysisPart { question, sql, data: &block.data })
+        .collect();
+    let analysis = match state.analysis.analyze_compound(&req.question, &parts, &language)
+        .instrument(tracing::info_span!("analyze"))
+        .await
+    {
+        Ok(mut analysis) => {
+            let datasets: Vec<&[serde_json::Value]> = parts.iter().map(|part| part.data).collect();
+            meta.verification = Some(verify_analysis(&mut analysis, &datasets, &req.question));
+            analysis
+        }
+        Err(e) => {
+            tracing::error!("Failed to generate compound analysis: {}", e);
+            meta.fallback_analysis = true;
+            fallback_analysis(&blocks, language)
+        }
+    };
+    meta.timings.analyze_ms = Some(analyze_start.elapsed().as_millis() as u64);
+
+    let total_time = start.elapsed().as_millis() as u64;
+    let _ = log_query_audit(state, audit, &req.question, "", true, total_time).await;
+
+    Ok(QueryResponse {
+        question: req.question.clone(),
+        sql: String::new(),
+        text_response: None,
+        data: vec![],
+        table: None,
+        chart_data: None,
+        execution_time_ms: total_time,
+        row_count: blocks.iter().map(|block| block.row_count).sum(),
+        cached: blocks.iter().all(|block| block.cached),
+        blocks,
+        evidence: vec![],
+        clarification: None,
+        analysis: Some(analysis),
+        meta,
+    })
+}
+
+/// Таблица и диаграмма блока по `output_type`; в режиме auto - для результатов из нескольких строк
+fn format_block(
+    data: &[serde_json::Value],
+    output_type: &OutputType,
+    language: Language,
+) -> (Option<String>, Option<ChartData>) {
+    let multi_row = data.len() > 1;
+    match output_type {
+        OutputType::Json => (None, None),
+        OutputType::Table => ((!data.is_empty()).then(|| formatters::format_as_table(data, language)), None),
+        OutputType::Chart => (None, formatters::format_as_chart_data(data, "auto", language)),
+        OutputType::Auto => (
+            multi_row.then(|| formatters::format_as_table(data, language)),
+            if multi_row && data.len() <= 20 {
+                formatters::format_as_chart_data(data, "auto", language)
+            } else {
+                None
+            },
+        ),
+    }
+}
+
+/// Анализ без LLM: по одному пункту на блок
+fn fallback_analysis(blocks: &[ResultBlock], language: Language) -> AnalysisResult {
+    let insights = blocks
+        .iter()
+        .map(|block| Insight {
+            title: block.question.clone(),
+            description: match &block.error {
+                Some(error) => error.message.to_string(),
+                None => tf(language, "analysis.result_rows", &[("count", &block.row_count)]),
+            },
+            significance: InsightSignificance::Medium,
+        })
+        // После пунктов по подзапросам - статистические факты по их данным
+        .chain(blocks.iter().flat_map(|block| statistical_insights(&block.data, language)))
+        .take(MAX_INSIGHTS.max(blocks.len()))
+        .collect();
+    let total_rows: usize = blocks.iter().map(|block| block.row_count).sum();
+
+    AnalysisResult {
+        headline: tf(language, "analysis.compound_results", &[("count", &blocks.len())]),
+        insights,
+        explanation: tf(language, "analysis.result_rows", &[("count", &total_rows)]),
+        suggested_questions: vec![
+            t(language, "analysis.show_details").to_string(),
+            t(language, "analysis.compare_periods").to_string(),
+        ],
+        chart_type: None,
+        data: vec![],
+    }
+}
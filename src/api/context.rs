@@ -1,5 +1,5 @@
-use crate::{api::models::ClearContextRequest, error::AppError, state::AppState};
-use axum::{extract::State, Json};
+use crate::{api::models::ClearContextRequest, auth::Principal, error::AppError, state::AppState};
+use axum::{extract::State, Extension, Json};
 use serde::Serialize;
 
 #[derive(Debug, Serialize)]
@@ -10,9 +10,22 @@ pub struct ClearContextResponse {
 
 pub async fn handle_clear_context(
     State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
     Json(req): Json<ClearContextRequest>,
 ) -> Result<Json<ClearContextResponse>, AppError> {
-    let user_id = req.user_id.unwrap_or_else(|| "anonymous".to_string());
+    // Свой контекст может очистить любой, чужой - только администратор
+    let user_id = match req.user_id.as_deref().filter(|id| !id.trim().is_empty()) {
+        Some(requested) if principal.is_authenticated() && requested != principal.id => {
+            if !principal.role.is_admin() {
+                return Err(AppError::Forbidden(
+                    "Only administrators can clear another user's context".to_string(),
+                ));
+            }
+            tracing::info!("Admin {} clears context of {}", principal.id, requested);
+            requested.to_string()
+        }
+        requested => principal.user_id(requested),
+    };
     
     state.query_context.clear_context(&user_id).await;
     
@@ -0,0 +1,166 @@
+use crate::{
+    analytics::drill::{drill_dimension, filters_condition, regroup, DrillFilter},
+    api::{
+        models::{ChartData, DrillMeta, OutputType, QueryResponse, ResponseMeta},
+        query::{log_query_audit, AuditIdentity},
+        scoped::execute_scoped_filtered,
+    },
+    auth::Principal,
+    error::AppError,
+    i18n::{column_label, t, tf},
+    llm::prompts::DRILL_PROMPT_VERSION,
+    privacy::k_anonymity::GroupSuppression,
+    state::AppState,
+    utils::{
+        formatters,
+        language::{apply_requested_language, response_language, Language},
+    },
+};
+use axum::{extract::State, Extension, Json};
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Instant;
+use tracing::Instrument;
+
+#[derive(Debug, Deserialize)]
+pub struct DrillRequest {
+    pub token: String,  // Токен точки из chart_data.drill_tokens
+    pub dimension: String,  // Измерение разбивки, например merchant_id
+    #[serde(default)]
+    pub output_type: OutputType,
+    #[serde(default)]
+    pub include_sql: bool,
+    #[serde(default)]
+    pub use_cache: bool,
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+/// Детализация точки диаграммы: SQL родителя перегруппировывается по новому измерению,
+/// строки `transactions` ограничиваются значениями точки (и предыдущих уровней). LLM не вызывается.
+pub async fn handle_drill(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Json(req): Json<DrillRequest>,
+) -> Result<Json<QueryResponse>, AppError> {
+    let start = Instant::now();
+    apply_requested_language(req.language.as_deref()).map_err(AppError::BadRequest)?;
+    let language = response_language();
+
+    let target = state.drill_tokens.resolve(&req.token, &principal.id).map_err(AppError::BadRequest)?;
+    let dimension = req.dimension.trim().to_lowercase();
+    let sql = regroup(&target.sql, &target.point.dimension, &dimension).map_err(AppError::BadRequest)?;
+    let path = target.path();
+    let question = drill_question(language, &path, &dimension);
+    tracing::info!("Drill-down: {} by {}", filters_condition(&path).unwrap_or_default(), dimension);
+
+    let tenant = state.tenants.for_principal(&principal);
+    let audit = AuditIdentity {
+        user_id: principal.user_id(None),
+        tenant_id: tenant.map(|t| t.id.clone()),
+    };
+    let execute_start = Instant::now();
+    let result = execute_scoped_filtered(&state, &principal, tenant, &sql, filters_condition(&path).as_deref(), req.use_cache, language)
+        .instrument(tracing::info_span!("execute_drill", dimension = %dimension))
+        .await;
+    let _ = log_query_audit(&state, &audit, &question, &sql, result.is_ok(), start.elapsed().as_millis() as u64).await;
+    let result = result?;
+
+    let mut meta = ResponseMeta {
+        provider: state.llm.provider_name().to_string(),
+        model: state.llm.model_name().to_string(),
+        prompt_version: DRILL_PROMPT_VERSION.to_string(),
+        ..Default::default()
+    };
+    meta.timings.execute_ms = Some(execute_start.elapsed().as_millis() as u64);
+
+    let mut context = state.query_context.get_or_create_context(audit.user_id.clone()).await;
+    context.add_query(question.clone(), sql.clone());
+    state.query_context.update_context(context).await;
+
+    let format_start = Instant::now();
+    let chart = || {
+        formatters::format_as_chart_data(&result.data, "auto", language).map(|chart| ChartData { title: Some(question.clone()), ..chart })
+    };
+    let (table, mut chart_data) = match req.output_type {
+        OutputType::Json => (None, None),
+        OutputType::Table => (Some(formatters::format_as_table(&result.data, language)), None),
+        OutputType::Chart => (None, chart()),
+        OutputType::Auto => (Some(formatters::format_as_table(&result.data, language)), chart()),
+    };
+    attach_drill_tokens(&state, &principal, &sql, &path, &result.data, result.group_suppression.as_ref(), language, chart_data.as_mut())
+        .await;
+    meta.timings.format_ms = Some(format_start.elapsed().as_millis() as u64);
+
+    meta.group_suppression = result.group_suppression;
+    meta.column_policies = result.column_policies;
+    meta.drill = Some(DrillMeta { path, dimension });
+
+    Ok(Json(QueryResponse {
+        question,
+        sql: if req.include_sql && principal.role.can_view_sql() { sql } else { String::new() },
+        text_response: None,
+        row_count: result.row_count,
+        data: result.data,
+        table,
+        chart_data,
+        execution_time_ms: start.elapsed().as_millis() as u64,
+        blocks: vec![],
+        evidence: vec![],
+        clarification: None,
+        analysis: None,
+        cached: result.cached,
+        meta,
+    }))
+}
+
+/// Добавляет токены детализации к точкам диаграммы, если ее подписи - значения измерения
+/// из `series::DIMENSIONS`, а запрос можно перегруппировать. SQL и фильтры сохраняются
+/// на сервере, токены ссылаются на них по id. Строка "прочие" (объединенные малые группы) токена не получает.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn attach_drill_tokens(
+    state: &AppState,
+    principal: &Principal,
+    sql: &str,
+    filters: &[DrillFilter],
+    data: &[Value],
+    group_suppression: Option<&GroupSuppression>,
+    language: Language,
+    chart: Option<&mut ChartData>,
+) {
+    let (Some(chart), Some(dimension)) = (chart, drill_dimension(sql, data)) else {
+        return;
+    };
+    if chart.labels.len() != data.len() {
+        return;
+    }
+    let other_bucket = group_suppression.is_some_and(|s| s.merged_into_other).then(|| t(language, "analysis.other_bucket"));
+    let mut points = Vec::with_capacity(data.len());
+    for (label, row) in chart.labels.iter().zip(data) {
+        let point = DrillFilter { dimension: dimension.to_string(), value: row.get(dimension).cloned().unwrap_or(Value::Null) };
+        // Подписи по другой колонке (например, по дате) - точки не детализируются
+        if point.label() != *label {
+            return;
+        }
+        points.push((other_bucket != Some(label.as_str())).then_some(point));
+    }
+
+    let query = state.drill_tokens.store(&principal.id, sql, filters);
+    let mut tokens = Vec::with_capacity(points.len());
+    for point in points {
+        match point.map(|point| state.drill_tokens.sign(&principal.id, &query, point)).transpose() {
+            Ok(token) => tokens.push(token),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                return;
+            }
+        }
+    }
+    chart.drill_tokens = Some(tokens);
+}
+
+/// "Almaty / POS по измерению «ID мерчанта»"
+fn drill_question(language: Language, path: &[DrillFilter], dimension: &str) -> String {
+    let filter = path.iter().map(DrillFilter::label).collect::<Vec<_>>().join(" / ");
+    tf(language, "analytics.drill_question", &[("filter", &filter), ("dimension", &column_label(language, dimension))])
+}
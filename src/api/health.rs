@@ -15,7 +15,7 @@ pub async fn health_check(
     };
     
     // Check LLM (simple test)
-    let llm_status = format!("{}", state.config.llm_provider);
+    let llm_status = state.config.llm_provider.to_string();
     
     Json(HealthResponse {
         status: "ok".to_string(),
@@ -0,0 +1,19 @@
+use crate::utils::language::{with_response_language, Language};
+use axum::{extract::Request, http::header, middleware::Next, response::Response};
+
+/// Задает язык ответа (сообщения об ошибках) по заголовку `Accept-Language`.
+/// Берется первый поддерживаемый язык из списка, веса `q` не учитываются.
+pub async fn response_language(request: Request, next: Next) -> Response {
+    let language = request
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|value| {
+            value
+                .split(',')
+                .filter_map(|tag| tag.split(';').next())
+                .find_map(Language::from_code)
+        });
+
+    with_response_language(language, next.run(request)).await
+}
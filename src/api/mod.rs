@@ -1,17 +1,52 @@
+mod admin;
 mod health;
+mod middleware;
+mod moderation;
 pub mod models;
 mod query;
+mod compound;
+mod compare;
+mod drill;
+mod agent;
+mod analytics;
+mod scoped;
 mod context;
+mod usage;
 
-use axum::{routing::{get, post}, Router};
-use crate::state::AppState;
+use axum::{routing::{delete, get, post}, Router};
+use crate::{auth, limits, state::AppState};
 
-pub fn routes() -> Router<AppState> {
-    Router::new()
-        .route("/health", get(health::health_check))
+pub fn routes(state: AppState) -> Router<AppState> {
+    // Endpoints администратора: управление API-ключами
+    let admin = Router::new()
+        .route("/keys", get(admin::list_api_keys).post(admin::create_api_key))
+        .route("/keys/:id", delete(admin::revoke_api_key))
+        .route("/usage", get(usage::admin_usage_report))
+        .route("/safety/users", get(moderation::list_flagged_users))
+        .route("/safety/users/:user_id/violations", get(moderation::user_violations))
+        .route("/safety/users/:user_id/unban", post(moderation::unban_user))
+        .route("/safety/settings", get(moderation::get_safety_settings).put(moderation::update_safety_settings))
+        .route_layer(axum::middleware::from_fn(auth::middleware::require_admin));
+
+    // Все, кроме /health, требует аутентификации.
+    // Порядок: лимит по IP -> аутентификация -> лимиты и квоты принципала -> handler
+    let protected = Router::new()
         .route("/query", post(query::handle_query))
+        .route("/query/drill", post(drill::handle_drill))
         .route("/chat", post(crate::chat::handler::handle_chat))
+        .route("/analytics/anomalies", post(analytics::handle_anomalies))
+        .route("/analytics/root-cause", post(analytics::handle_root_cause))
+        .route("/analytics/forecast", post(analytics::handle_forecast))
         .route("/context/clear", post(context::handle_clear_context))
-}
+        .route("/usage", get(usage::handle_usage))
+        .nest("/admin", admin)
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), limits::middleware::principal_limits))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), auth::middleware::authenticate))
+        .route_layer(axum::middleware::from_fn_with_state(state, limits::middleware::ip_rate_limit));
 
+    Router::new()
+        .route("/health", get(health::health_check))
+        .merge(protected)
+        .layer(axum::middleware::from_fn(middleware::response_language))
+}
 
@@ -1,8 +1,14 @@
 use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
-use crate::analysis::AnalysisResult;
+use chrono::{DateTime, NaiveDate, Utc};
+use crate::analysis::{AnalysisResult, AnalysisVerification};
+use crate::analytics::drill::DrillFilter;
+use crate::error::ErrorCode;
+use crate::llm::{sql_tool::SqlGeneration, tools::Period};
+use crate::privacy::{k_anonymity::GroupSuppression, AppliedColumnPolicy};
+use crate::utils::language::LanguageSource;
+use crate::utils::question_classifier::{Classification, Intent};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub enum OutputType {
     #[serde(rename = "table")]
     Table,
@@ -11,15 +17,10 @@ pub enum OutputType {
     #[serde(rename = "json")]
     Json,
     #[serde(rename = "auto")]
+    #[default]
     Auto,  // Автоматически определяет на основе данных
 }
 
-impl Default for OutputType {
-    fn default() -> Self {
-        OutputType::Auto
-    }
-}
-
 #[derive(Debug, Deserialize)]
 pub struct QueryRequest {
     pub question: String,
@@ -35,6 +36,32 @@ pub struct QueryRequest {
     pub session_id: Option<String>,  // Session ID (optional, for compatibility)
     #[serde(default)]
     pub output_type: OutputType,  // Тип вывода: table, chart, json, auto
+    #[serde(default)]
+    pub language: Option<String>,  // Язык ответа (ru, en, kk, uz, ky); важнее Accept-Language и определения по тексту
+    #[serde(default)]
+    pub intent: Option<Intent>,  // Явное намерение (ответ на уточняющий вопрос), классификация пропускается
+    #[serde(default)]
+    pub agent: bool,  // Режим агента: модель сама выбирает инструменты за несколько шагов
+    #[serde(default)]
+    pub compare: Option<ComparePeriods>,  // Сравнение периодов: один агрегат выполняется за оба периода
+}
+
+/// Периоды сравнения (границы включительно). Изменения считаются от периода A к периоду B.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct ComparePeriods {
+    pub period_a: Period,
+    pub period_b: Period,
+}
+
+/// Как построено сравнение: периоды, ключи выравнивания и метрики
+#[derive(Debug, Clone, Serialize)]
+pub struct ComparisonMeta {
+    pub period_a: Period,
+    pub period_b: Period,
+    pub keys: Vec<String>,
+    pub measures: Vec<String>,
+    pub rows_a: usize,
+    pub rows_b: usize,
 }
 
 #[derive(Debug, Serialize)]
@@ -51,10 +78,174 @@ pub struct QueryResponse {
     pub chart_data: Option<ChartData>,  // Данные для построения диаграммы
     pub execution_time_ms: u64,
     pub row_count: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub blocks: Vec<ResultBlock>,  // Результаты подзапросов составного вопроса
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub evidence: Vec<EvidenceStep>,  // Шаги агента, на которых построен отчет
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clarification: Option<Clarification>,  // Уточняющий вопрос, если намерение не удалось определить
     #[serde(skip_serializing_if = "Option::is_none")]
     pub analysis: Option<AnalysisResult>,  // LLM analysis if requested
     #[serde(default)]
     pub cached: bool,  // Whether result was from cache
+    pub meta: ResponseMeta,  // Тайминги этапов и происхождение ответа
+}
+
+/// Детализация точки: значения измерений, которыми ограничены строки, и измерение разбивки
+#[derive(Debug, Clone, Serialize)]
+pub struct DrillMeta {
+    pub path: Vec<DrillFilter>,
+    pub dimension: String,
+}
+
+/// Метаданные ответа: тайминги этапов пайплайна и то, как был получен ответ
+#[derive(Debug, Serialize, Default)]
+pub struct ResponseMeta {
+    pub timings: StageTimings,
+    pub provider: String,
+    pub model: String,
+    pub prompt_version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analysis_prompt_version: Option<String>,
+    pub sql_repair_attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sql_generation: Option<SqlGeneration>,  // Инструмент или текст; таблицы, цель и допущения модели
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_layer: Option<String>,  // Слой кэша, из которого пришел результат (memory)
+    pub fallback_analysis: bool,  // Анализ построен без LLM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_range: Option<DateRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_suppression: Option<GroupSuppression>,  // Группы меньше MIN_GROUP_SIZE скрыты/объединены
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub column_policies: Vec<AppliedColumnPolicy>,  // Замаскированные/скрытые колонки результата
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<LanguageMeta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intent: Option<Classification>,  // Намерение, уверенность и чем определено
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent: Option<AgentMeta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<AnalysisVerification>,  // Проверка чисел анализа по результату
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comparison: Option<ComparisonMeta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drill: Option<DrillMeta>,
+}
+
+/// Результат одного подзапроса составного вопроса
+#[derive(Debug, Serialize)]
+pub struct ResultBlock {
+    pub question: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub sql: String,  // Скрывается так же, как `sql` ответа
+    pub data: Vec<serde_json::Value>,
+    pub row_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chart_data: Option<ChartData>,
+    pub execution_time_ms: u64,
+    pub cached: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_range: Option<DateRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_suppression: Option<GroupSuppression>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub column_policies: Vec<AppliedColumnPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<BlockError>,  // Подзапрос не выполнен, остальные блоки возвращаются
+}
+
+/// Ошибка подзапроса в формате ошибок API
+#[derive(Debug, Serialize)]
+pub struct BlockError {
+    pub code: ErrorCode,
+    pub message: &'static str,
+}
+
+/// Шаг агента: вызванный инструмент и его результат
+#[derive(Debug, Serialize)]
+pub struct EvidenceStep {
+    pub step: usize,
+    pub tool: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thought: Option<String>,
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+    pub args: serde_json::Value,  // Аргументы инструмента (для run_sql - пусто, SQL в поле `sql`)
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub sql: String,  // Скрывается так же, как `sql` ответа
+    pub row_count: usize,
+    pub data: Vec<serde_json::Value>,
+    pub execution_time_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<BlockError>,
+}
+
+/// Итог работы агента
+#[derive(Debug, Serialize)]
+pub struct AgentMeta {
+    pub steps: usize,
+    pub tokens: u64,
+    pub stop_reason: AgentStopReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentStopReason {
+    FinalAnswer,  // Модель вернула итоговый отчет
+    StepLimit,  // Исчерпан AGENT_MAX_STEPS
+    TokenBudget,  // Исчерпан AGENT_MAX_TOKENS
+    ModelError,  // Модель недоступна посреди работы, отчет построен по выполненным шагам
+}
+
+/// Уточняющий вопрос: клиент повторяет вопрос с `intent` выбранного варианта
+#[derive(Debug, Serialize)]
+pub struct Clarification {
+    pub question: String,
+    pub options: Vec<ClarificationOption>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClarificationOption {
+    pub intent: Intent,
+    pub label: String,
+}
+
+/// Язык ответа и то, как он выбран
+#[derive(Debug, Serialize)]
+pub struct LanguageMeta {
+    pub code: &'static str,
+    pub source: LanguageSource,
+    /// Уверенность определения языка по тексту вопроса (0..1)
+    pub detection_confidence: f32,
+}
+
+/// Время этапов в миллисекундах (этап отсутствует, если не выполнялся)
+#[derive(Debug, Serialize, Default)]
+pub struct StageTimings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub classify_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generate_sql_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validate_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub execute_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analyze_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format_ms: Option<u64>,
+}
+
+/// Период данных, на которых построен ответ
+#[derive(Debug, Clone, Serialize)]
+pub struct DateRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub filtered: bool,  // true - период задан фильтром в запросе, false - даты из строк результата
 }
 
 #[derive(Debug, Serialize)]
@@ -63,6 +254,8 @@ pub struct ChartData {
     pub labels: Vec<String>,  // Метки для осей
     pub datasets: Vec<ChartDataset>,  // Данные для графиков
     pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drill_tokens: Option<Vec<Option<String>>>,  // Токены детализации точек (параллельно labels) для /api/query/drill
 }
 
 #[derive(Debug, Serialize)]
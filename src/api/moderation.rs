@@ -0,0 +1,115 @@
+use crate::{
+    auth::Principal,
+    error::AppError,
+    state::AppState,
+    utils::user_safety::{FlaggedUser, SafetySettings, ViolationRecord},
+};
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct ViolationsParams {
+    #[serde(default)]
+    pub limit: Option<i64>,  // По умолчанию 50, максимум 500
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserViolationsResponse {
+    pub user_id: String,
+    pub ban_remaining_secs: Option<i64>,
+    pub violations: Vec<ViolationRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnbanResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+/// Частичное обновление настроек модерации
+#[derive(Debug, Deserialize)]
+pub struct UpdateSafetySettingsRequest {
+    #[serde(default)]
+    pub max_warnings: Option<i32>,
+    #[serde(default)]
+    pub ban_duration_hours: Option<i32>,
+    #[serde(default)]
+    pub warning_decay_hours: Option<i32>,
+}
+
+/// Пользователи с действующими предупреждениями или баном
+pub async fn list_flagged_users(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<FlaggedUser>>, AppError> {
+    Ok(Json(state.user_safety.flagged_users().await?))
+}
+
+/// История нарушений пользователя
+pub async fn user_violations(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    Query(params): Query<ViolationsParams>,
+) -> Result<Json<UserViolationsResponse>, AppError> {
+    let limit = params.limit.unwrap_or(50).clamp(1, 500);
+    let violations = state.user_safety.violations(&user_id, limit).await?;
+    let ban_remaining_secs = state.user_safety.ban_remaining(&user_id).await?.map(|d| d.num_seconds());
+
+    Ok(Json(UserViolationsResponse {
+        user_id,
+        ban_remaining_secs,
+        violations,
+    }))
+}
+
+/// Снимает бан и все действующие предупреждения
+pub async fn unban_user(
+    State(state): State<AppState>,
+    Extension(admin): Extension<Principal>,
+    Path(user_id): Path<String>,
+) -> Result<Json<UnbanResponse>, AppError> {
+    if !state.user_safety.clear_warnings(&user_id, &admin.id).await? {
+        return Err(AppError::BadRequest(format!("User {} has no active warnings or ban", user_id)));
+    }
+    tracing::info!("Warnings and ban of {} cleared by {}", user_id, admin.id);
+    Ok(Json(UnbanResponse {
+        success: true,
+        message: format!("Warnings and ban of {} cleared", user_id),
+    }))
+}
+
+pub async fn get_safety_settings(
+    State(state): State<AppState>,
+) -> Json<SafetySettings> {
+    Json(state.user_safety.settings().await)
+}
+
+/// Меняет max_warnings, длительность бана и срок действия предупреждений без перезапуска
+pub async fn update_safety_settings(
+    State(state): State<AppState>,
+    Extension(admin): Extension<Principal>,
+    Json(req): Json<UpdateSafetySettingsRequest>,
+) -> Result<Json<SafetySettings>, AppError> {
+    let current = state.user_safety.settings().await;
+    let settings = SafetySettings {
+        max_warnings: req.max_warnings.unwrap_or(current.max_warnings),
+        ban_duration_hours: req.ban_duration_hours.unwrap_or(current.ban_duration_hours),
+        warning_decay_hours: req.warning_decay_hours.unwrap_or(current.warning_decay_hours),
+    };
+
+    if settings.max_warnings < 1 {
+        return Err(AppError::BadRequest("max_warnings must be at least 1".to_string()));
+    }
+    if settings.ban_duration_hours < 1 {
+        return Err(AppError::BadRequest("ban_duration_hours must be at least 1".to_string()));
+    }
+    if settings.warning_decay_hours < 0 {
+        return Err(AppError::BadRequest("warning_decay_hours must not be negative".to_string()));
+    }
+
+    state.user_safety.update_settings(settings, &admin.id).await?;
+    tracing::info!("Safety settings updated by {}: {:?}", admin.id, settings);
+    Ok(Json(settings))
+}
@@ -1,60 +1,83 @@
 use crate::{
-    api::models::{QueryRequest, QueryResponse},
-    cache::{Cache, CacheKey},
-    db::queries::execute_query,
+    analysis::{verify_analysis, ANALYSIS_PROMPT_VERSION},
+    analytics::forecast::parse_forecast_question,
+    api::agent::answer_with_agent,
+    api::analytics::answer_forecast,
+    api::compare::answer_comparison,
+    api::compound::answer_compound,
+    api::drill::attach_drill_tokens,
+    api::models::{Clarification, ClarificationOption, LanguageMeta, QueryRequest, QueryResponse, ResponseMeta},
+    api::scoped::execute_scoped,
+    auth::Principal,
     error::AppError,
-    state::{AppState, CachedQueryResult},
+    i18n,
+    llm::{
+        error::LlmError,
+        planner::looks_compound,
+        prompts::{compare_mode_question, CHAT_PROMPT_VERSION, RAW_SQL_PROMPT_VERSION, SQL_PROMPT_VERSION},
+        validator::validate_sql,
+    },
+    state::AppState,
+    utils::language::{
+        apply_requested_language, detect_language, detect_language_scored, response_language,
+        response_language_source, set_response_language, Language,
+    },
+    utils::question_classifier::{classify_keywords, Classification, Intent, IntentSource},
 };
-use axum::{extract::State, Json};
+use axum::{extract::State, Extension, Json};
 use std::time::Instant;
+use tracing::Instrument;
 
+#[tracing::instrument(
+    name = "handle_query",
+    skip_all,
+    fields(user_id = tracing::field::Empty, is_db_query = tracing::field::Empty, cached = tracing::field::Empty)
+)]
 pub async fn handle_query(
     State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
     Json(req): Json<QueryRequest>,
 ) -> Result<Json<QueryResponse>, AppError> {
     let start = Instant::now();
     
-    // Определяем user_id (из запроса или генерируем анонимный)
-    let user_id = req.user_id.clone().unwrap_or_else(|| {
-        // Если не указан, используем session_id или генерируем анонимный
-        req.session_id.clone().unwrap_or_else(|| "anonymous".to_string())
-    });
+    // user_id берется из аутентификации; без нее - из запроса (user_id или session_id)
+    let user_id = principal.user_id(req.user_id.as_deref().or(req.session_id.as_deref()));
+    tracing::Span::current().record("user_id", user_id.as_str());
+    
+    // Арендатор: весь SQL выполняется только по строкам его банков
+    let tenant = state.tenants.for_principal(&principal);
+    let audit = AuditIdentity {
+        user_id: user_id.clone(),
+        tenant_id: tenant.map(|t| t.id.clone()),
+    };
+    
+    let mut meta = ResponseMeta {
+        provider: state.llm.provider_name().to_string(),
+        model: state.llm.model_name().to_string(),
+        prompt_version: SQL_PROMPT_VERSION.to_string(),
+        ..Default::default()
+    };
     
     tracing::info!("Received question: {} (user_id: {}, analysis: {}, cache: {})", 
         req.question, user_id, req.include_analysis, req.use_cache);
     
+    // Язык ответа: поле `language`, затем Accept-Language, иначе - язык вопроса.
+    // Задается до проверки безопасности, чтобы предупреждения и бан были на языке пользователя.
+    apply_requested_language(req.language.as_deref()).map_err(AppError::BadRequest)?;
+    set_response_language(detect_language(&req.question));
+    
     // 0. Проверка безопасности пользователя
-    let (is_safe, safety_message) = state.user_safety.check_message_safety(&user_id, &req.question).await;
+    let (is_safe, safety_message) = state.user_safety.check_message_safety(&user_id, &req.question).await?;
     if !is_safe {
         if let Some(ban_msg) = safety_message {
-            return Err(AppError::BadRequest(ban_msg));
-        }
-    }
-    
-    // 0.1. Basic validation - reject obvious jailbreak attempts (дополнительная проверка)
-    let question_lower = req.question.to_lowercase();
-    let jailbreak_keywords = [
-        "ignore previous",
-        "forget all",
-        "you are now",
-        "you are a",
-        "act as",
-        "pretend to be",
-        "roleplay as",
-        "disregard instructions",
-    ];
-    
-    for keyword in &jailbreak_keywords {
-        if question_lower.contains(keyword) {
-            tracing::warn!("Potential jailbreak attempt detected: {}", keyword);
-            // Записываем нарушение
-            if let Some(warning) = state.user_safety.record_violation(
-                &user_id,
-                crate::utils::user_safety::ViolationType::JailbreakAttempt,
-                format!("Обнаружена попытка jailbreak: {}", keyword),
-            ).await {
-                tracing::warn!("User {} received warning: {}", user_id, warning);
+            // Бан возвращаем отдельным кодом с Retry-After, остальное - нарушение политики
+            if let Some(remaining) = state.user_safety.ban_remaining(&user_id).await? {
+                return Err(AppError::UserBanned {
+                    message: ban_msg,
+                    retry_after_secs: remaining.num_seconds().max(1) as u64,
+                });
             }
+            return Err(AppError::PolicyViolation(ban_msg));
         }
     }
     
@@ -68,209 +91,227 @@ pub async fn handle_query(
         question_clean
     };
     
-    // Определяем язык из очищенного вопроса (без префикса sql:)
-    use crate::utils::language::detect_language;
-    let language = detect_language(question_clean);
-           
-           // 1. Определяем, является ли вопрос запросом к БД или обычным вопросом
-           // Если есть SQL префикс или это suggested question (обычно они SQL запросы), считаем SQL запросом
-           use crate::utils::question_classifier::is_database_query;
-           let is_db_query = has_sql_prefix || is_database_query(&req.question);
+    // Raw SQL: `sql: SELECT ...` выполняется как есть (после валидации), без LLM
+    let raw_sql = has_sql_prefix && is_raw_sql(question_clean);
+    if raw_sql && !principal.role.can_run_raw_sql() {
+        return Err(AppError::Forbidden(format!(
+            "Raw SQL mode is not allowed for role '{}'", principal.role
+        )));
+    }
     
-    if !is_db_query {
-        // Это обычный вопрос, не про базу данных - отвечаем как в чате
-        tracing::info!("Question classified as regular chat, not database query");
+    if let Some(periods) = &req.compare {
+        for period in [&periods.period_a, &periods.period_b] {
+            if period.to < period.from {
+                return Err(AppError::BadRequest(format!("Period {}..{} ends before it starts", period.from, period.to)));
+            }
+        }
+    }
+    
+    // 1. Определяем язык и намерение: запрос к данным, разговор, справка или неясно
+    let classify_start = Instant::now();
+    let (language, keyword_classification) = {
+        let _span = tracing::info_span!("classify").entered();
         
-        // Генерируем обычный текстовый ответ
-        let text_response = state.llm.generate_chat_response(
-            question_clean,
-            &[], // Нет истории для /api/query (можно добавить позже)
-            &language,
-        ).await?;
+        // Определяем язык из очищенного вопроса (без префикса sql:); явно указанный клиентом важнее
+        let detection = detect_language_scored(question_clean);
+        set_response_language(detection.language);
+        let language = response_language();
+        meta.language = Some(LanguageMeta {
+            code: language.code(),
+            source: response_language_source(),
+            detection_confidence: detection.confidence,
+        });
         
+        // Явное намерение (ответ на уточнение) и префикс sql: не требуют классификации
+        let classification = match req.intent.filter(|intent| *intent != Intent::Ambiguous) {
+            Some(intent) => Classification::new(intent, 1.0, IntentSource::Request),
+            None => classify_keywords(&req.question),
+        };
+        (language, classification)
+    };
+    
+    // 1.0. Вопрос-прогноз ("какой будет объем в следующем месяце") отвечает модель прогноза, без генерации SQL
+    if !raw_sql && req.compare.is_none() && req.intent.is_none_or(|intent| intent == Intent::DataQuery) {
+        if let Some(forecast) = parse_forecast_question(question_clean) {
+            tracing::info!(?forecast, "Question answered by the forecast model");
+            meta.intent = Some(Classification::new(Intent::DataQuery, 1.0, IntentSource::Keywords));
+            meta.timings.classify_ms = Some(classify_start.elapsed().as_millis() as u64);
+            // SQL ряда записывается в аудит с исходным вопросом
+            let response = answer_forecast(&state, &principal, &req, forecast, language, meta, start).await?;
+            return Ok(Json(response));
+        }
+    }
+    
+    // Ключевые слова не дали уверенности - спрашиваем небольшую модель
+    let threshold = state.config.intent_confidence_threshold;
+    let mut classification = keyword_classification;
+    if classification.source == IntentSource::Keywords
+        && classification.confidence < threshold
+        && state.config.intent_llm_fallback
+    {
+        match state.llm.classify_intent(question_clean).await {
+            Ok(model) => classification = model,
+            Err(e) => tracing::warn!("Intent model unavailable, keeping keyword classification: {}", e),
+        }
+    }
+    let is_db_query = classification.intent == Intent::DataQuery;
+    tracing::info!(?language, ?classification, "Question classified");
+    tracing::Span::current().record("is_db_query", is_db_query);
+    meta.intent = Some(classification);
+    meta.timings.classify_ms = Some(classify_start.elapsed().as_millis() as u64);
+    
+    if classification.intent == Intent::Ambiguous || classification.confidence < threshold {
+        // Не угадываем: возвращаем уточняющий вопрос с вариантами намерений
+        let clarification = build_clarification(language, keyword_classification.intent);
         let total_time = start.elapsed().as_millis() as u64;
-        
-        // Логируем как обычный вопрос
-        let _ = log_query_audit(&state, &req.question, "", true, total_time).await;
-        
-        return Ok(Json(QueryResponse {
-            question: req.question,
-            sql: String::new(),
-            text_response: Some(text_response),
-            data: vec![],
-            table: None,
-            chart_data: None,
-            execution_time_ms: total_time,
-            row_count: 0,
-            analysis: None,
-            cached: false,
-        }));
+        let _ = log_query_audit(&state, &audit, &req.question, "", true, total_time).await;
+        return Ok(Json(text_only_response(req.question, clarification.question.clone(), Some(clarification), total_time, meta)));
+    }
+    
+    if classification.intent == Intent::Help {
+        // Справка - из каталога сообщений, без LLM
+        let total_time = start.elapsed().as_millis() as u64;
+        let _ = log_query_audit(&state, &audit, &req.question, "", true, total_time).await;
+        return Ok(Json(text_only_response(req.question, i18n::t(language, "intent.help").to_string(), None, total_time, meta)));
+    }
+    
+    if !is_db_query {
+        // Это обычный вопрос, не про базу данных - отвечаем как в чате
+        tracing::info!("Question classified as regular chat, not database query");
+        return answer_as_chat(&state, &audit, &req.question, question_clean, language, true, meta, start).await;
+    }
+    
+    // 1.1. Режим агента (по запросу клиента): модель сама выбирает инструменты за несколько шагов
+    if req.agent && !raw_sql && req.compare.is_none() {
+        if state.config.agent_max_steps == 0 {
+            return Err(AppError::BadRequest("Agent mode is disabled (AGENT_MAX_STEPS=0)".to_string()));
+        }
+        let response = answer_with_agent(&state, &principal, tenant, &audit, &req, question_clean, language, meta, start).await?;
+        return Ok(Json(response));
+    }
+    
+    // 1.2. Составной вопрос разбиваем на подзапросы (простые вопросы планировщику не отправляются)
+    if !raw_sql && req.compare.is_none() && looks_compound(question_clean) {
+        let plan_start = Instant::now();
+        let plan = state.llm.plan_question(question_clean)
+            .instrument(tracing::info_span!("plan"))
+            .await;
+        meta.timings.plan_ms = Some(plan_start.elapsed().as_millis() as u64);
+        match plan {
+            Ok(sub_questions) if sub_questions.len() > 1 => {
+                tracing::info!("Compound question split into {} sub-queries", sub_questions.len());
+                let response = answer_compound(&state, &principal, tenant, &audit, &req, sub_questions, language, meta, start).await?;
+                tracing::Span::current().record("cached", response.cached);
+                return Ok(Json(response));
+            }
+            Ok(_) => tracing::info!("Planner kept the question as a single query"),
+            Err(e) => tracing::warn!("Query planning failed, answering as a single query: {}", e),
+        }
     }
     
     // 2. Это SQL-запрос - генерируем SQL с учетом контекста
     let context = state.query_context.get_or_create_context(user_id.clone()).await;
-    let previous_queries: Vec<&crate::query_context::QueryContext> = 
-        context.get_recent_queries(10).iter().map(|q| *q).collect();
+    let previous_queries: Vec<&crate::query_context::QueryContext> = context.get_recent_queries(10);
     
     tracing::info!("Using context: {} previous queries", previous_queries.len());
     
-    let sql = match state.llm.generate_sql(question_clean, &previous_queries).await {
-        Ok(sql) => {
-            tracing::info!("Generated SQL: {}", sql);
-            sql
-        }
-        Err(e) => {
-            tracing::error!("Failed to generate SQL: {}. Trying chat API as fallback...", e);
-            // Если не удалось сгенерировать SQL, пробуем ответить через chat API
-            let text_response = state.llm.generate_chat_response(
-                question_clean,
-                &[],
-                &language,
-            ).await?;
-            
+    let sql = if raw_sql {
+        meta.prompt_version = RAW_SQL_PROMPT_VERSION.to_string();
+        let validate_start = Instant::now();
+        let sql = if question_clean.ends_with(';') {
+            question_clean.to_string()
+        } else {
+            format!("{};", question_clean)
+        };
+        let validated = validate_sql(&sql);
+        meta.timings.validate_ms = Some(validate_start.elapsed().as_millis() as u64);
+        if let Err(rejection) = validated {
             let total_time = start.elapsed().as_millis() as u64;
-            let _ = log_query_audit(&state, &req.question, "", false, total_time).await;
-            
-            return Ok(Json(QueryResponse {
-                question: req.question,
-                sql: String::new(),
-                text_response: Some(text_response),
-                data: vec![],
-                table: None,
-                chart_data: None,
-                execution_time_ms: total_time,
-                row_count: 0,
-                analysis: None,
-                cached: false,
-            }));
+            let _ = log_query_audit(&state, &audit, &req.question, &sql, false, total_time).await;
+            return Err(AppError::LLM(LlmError::SqlRejected(rejection).into()));
         }
-    };
+        tracing::info!("Using raw SQL from request");
+        sql
+    } else {
+        let generate_start = Instant::now();
+        // В режиме сравнения модель пишет агрегат без периода - периоды подставляет backend
+        let generation_question = match req.compare {
+            Some(_) => compare_mode_question(question_clean),
+            None => question_clean.to_string(),
+        };
+        let generated = state.llm.generate_sql(&generation_question, &previous_queries)
+            .instrument(tracing::info_span!("generate_sql"))
+            .await;
+        let generate_total_ms = generate_start.elapsed().as_millis() as u64;
     
-    // 3. Это SQL-запрос - выполняем его
-    // Check cache if enabled
-    let cache_key = CacheKey::from_sql(&sql);
-    let mut cached = false;
-    let data;
-    let execution_time;
-    let row_count;
-    
-    if req.use_cache {
-        if let Some(cached_result) = state.cache.get(&cache_key).await {
-            tracing::info!("Cache hit for SQL query");
-            cached = true;
-            data = cached_result.data;
-            execution_time = cached_result.execution_time_ms;
-            row_count = cached_result.row_count;
-        } else {
-            // Cache miss - execute query
-            let query_start = Instant::now();
-            match execute_query(&state.db, &sql).await {
-                Ok(result) => {
-                    data = result;
-                    execution_time = query_start.elapsed().as_millis() as u64;
-                    row_count = data.len();
-                }
-                Err(e) => {
-                    // Если ошибка SQL, возможно это обычный вопрос
-                    let error_str = e.to_string();
-                    if error_str.contains("syntax error") || 
-                       error_str.contains("invalid input syntax") ||
-                       error_str.contains("column") && error_str.contains("does not exist") {
-                        tracing::warn!("SQL execution error, treating as regular question: {}", error_str);
-                        
-                        // Генерируем обычный текстовый ответ
-                        let text_response = state.llm.generate_chat_response(
-                            &req.question,
-                            &[],
-                            &language,
-                        ).await?;
-                        
+        match generated {
+            Ok(generated) => {
+                tracing::info!("Generated SQL: {}", generated.sql);
+                meta.timings.generate_sql_ms = Some(generate_total_ms.saturating_sub(generated.validate_ms));
+                meta.timings.validate_ms = Some(generated.validate_ms);
+                meta.sql_repair_attempts = generated.repair_attempts;
+                meta.prompt_version = generated.prompt_version.to_string();
+                meta.sql_generation = Some(generated.generation);
+                generated.sql
+            }
+            Err(e) => {
+                // Недоступность модели и отклоненный валидатором SQL возвращаем клиенту как есть
+                if let Some(llm_error) = e.downcast_ref::<LlmError>() {
+                    if !llm_error.allows_chat_fallback() {
+                        tracing::warn!("Failed to generate SQL: {}", e);
                         let total_time = start.elapsed().as_millis() as u64;
-                        let _ = log_query_audit(&state, &req.question, "", true, total_time).await;
-                        
-                        return Ok(Json(QueryResponse {
-                            question: req.question,
-                            sql: String::new(),
-                            text_response: Some(text_response),
-                            data: vec![],
-                            table: None,
-                            chart_data: None,
-                            execution_time_ms: total_time,
-                            row_count: 0,
-                            analysis: None,
-                            cached: false,
-                        }));
+                        let _ = log_query_audit(&state, &audit, &req.question, "", false, total_time).await;
+                        return Err(e.into());
                     }
-                    // Для других ошибок пробрасываем дальше
-                    return Err(e.into());
                 }
+                tracing::error!("Failed to generate SQL: {}. Trying chat API as fallback...", e);
+                meta.timings.generate_sql_ms = Some(generate_total_ms);
+                // Если не удалось сгенерировать SQL, пробуем ответить через chat API
+                return answer_as_chat(&state, &audit, &req.question, question_clean, language, false, meta, start).await;
             }
-            
-            // Cache the result (TTL: 5 minutes for operational, 30 minutes for historical)
-            let ttl = if sql.to_lowercase().contains("current_date") || 
-                       sql.to_lowercase().contains("today") || 
-                       sql.to_lowercase().contains("last") {
-                300 // 5 minutes for time-sensitive queries
-            } else {
-                1800 // 30 minutes for historical data
-            };
-            
-            let cached_result = CachedQueryResult {
-                sql: sql.clone(),
-                data: data.clone(),
-                execution_time_ms: execution_time,
-                row_count,
-            };
-            state.cache.set(cache_key, cached_result, ttl).await;
-            tracing::info!("Cached query result (TTL: {}s)", ttl);
         }
-    } else {
-        // Cache disabled - execute query
-        let query_start = Instant::now();
-        match execute_query(&state.db, &sql).await {
-            Ok(result) => {
-                data = result;
-                execution_time = query_start.elapsed().as_millis() as u64;
-                row_count = data.len();
-            }
-            Err(e) => {
-                // Если ошибка SQL, возможно это обычный вопрос
-                let error_str = e.to_string();
-                if error_str.contains("syntax error") || 
-                   error_str.contains("invalid input syntax") ||
-                   error_str.contains("column") && error_str.contains("does not exist") {
-                    tracing::warn!("SQL execution error, treating as regular question: {}", error_str);
-                    
-                    // Генерируем обычный текстовый ответ
-                    let text_response = state.llm.generate_chat_response(
-                        &req.question,
-                        &[],
-                        &language,
-                    ).await?;
-                    
-                    let total_time = start.elapsed().as_millis() as u64;
-                    let _ = log_query_audit(&state, &req.question, "", true, total_time).await;
-                    
-                    return Ok(Json(QueryResponse {
-                        question: req.question,
-                        sql: String::new(),
-                        text_response: Some(text_response),
-                        data: vec![],
-                        table: None,
-                        chart_data: None,
-                        execution_time_ms: total_time,
-                        row_count: 0,
-                        analysis: None,
-                        cached: false,
-                    }));
-                }
-                // Для других ошибок пробрасываем дальше
-                return Err(e.into());
+    };
+    
+    // 2.1. Сравнение периодов: агрегат выполняется за оба периода, строки выравниваются по измерениям
+    if let Some(periods) = req.compare {
+        let response = answer_comparison(&state, &principal, tenant, &audit, &req, &sql, periods, language, meta, start).await?;
+        tracing::Span::current().record("cached", response.cached);
+        return Ok(Json(response));
+    }
+    
+    // 3. Это SQL-запрос - выполняем его с теми же ограничениями, что и остальные пути:
+    // доступ роли к колонкам, размер групп, RLS арендатора, кэш и политики персональных данных
+    let scoped = match execute_scoped(&state, &principal, tenant, &sql, req.use_cache, language).await {
+        Ok(scoped) => scoped,
+        Err(e) => {
+            // Ошибка в SQL модели - возможно, это обычный вопрос; ошибку в raw SQL возвращаем как есть
+            if !raw_sql && is_sql_execution_error(&e) {
+                tracing::warn!("SQL execution error, treating as regular question: {}", e);
+                return answer_as_chat(&state, &audit, &req.question, question_clean, language, true, meta, start).await;
             }
+            tracing::warn!("Query failed for {} ({}): {}", user_id, principal.role, e);
+            let total_time = start.elapsed().as_millis() as u64;
+            let _ = log_query_audit(&state, &audit, &req.question, &sql, false, total_time).await;
+            return Err(e);
         }
+    };
+    let cached = scoped.cached;
+    let execution_time = scoped.execution_time_ms;
+    let row_count = scoped.row_count;
+    let data = scoped.data;
+    match cached {
+        true => meta.cache_layer = Some("memory".to_string()),
+        false => meta.timings.execute_ms = Some(execution_time),
+    }
+    meta.date_range = scoped.date_range;
+    meta.group_suppression = scoped.group_suppression;
+    if let Some(suppression) = &meta.group_suppression {
+        tracing::info!("Suppressed {} groups smaller than {}", suppression.suppressed_groups, suppression.min_group_size);
     }
+    meta.column_policies = scoped.column_policies;
     
     let total_time = start.elapsed().as_millis() as u64;
+    tracing::Span::current().record("cached", cached);
     
     tracing::info!(
         "Query executed successfully: {} rows in {}ms (cached: {})",
@@ -281,24 +322,35 @@ pub async fn handle_query(
     
     // 4. Generate analysis (always for SQL queries to provide text description)
     // Analysis is needed to provide human-readable text descriptions instead of just tables
+    let analyze_start = Instant::now();
     let analysis = if req.include_analysis || is_db_query {
         tracing::info!("Generating LLM analysis for question: {}", req.question);
-        match state.analysis.analyze_results(&req.question, &sql, &data, &language).await {
-            Ok(analysis_result) => {
+        meta.analysis_prompt_version = Some(ANALYSIS_PROMPT_VERSION.to_string());
+        match state.analysis.analyze_results(&req.question, &sql, &data, &language)
+            .instrument(tracing::info_span!("analyze"))
+            .await
+        {
+            Ok(mut analysis_result) => {
                 tracing::info!("Analysis generated successfully: headline='{}', insights={}", 
                     analysis_result.headline, analysis_result.insights.len());
+                // Модель видит только первые строки - числа в тексте сверяются со всем результатом
+                meta.verification = Some(verify_analysis(&mut analysis_result, &[&data], &req.question));
                 Some(analysis_result)
             }
             Err(e) => {
                 tracing::error!("Failed to generate analysis: {} (question: {}, sql: {})", 
                     e, req.question, sql);
                 // Генерируем умный fallback анализ на основе данных
+                meta.fallback_analysis = true;
                 Some(generate_fallback_analysis(&req.question, &data, row_count, &language))
             }
         }
     } else {
         None
     };
+    if analysis.is_some() {
+        meta.timings.analyze_ms = Some(analyze_start.elapsed().as_millis() as u64);
+    }
     
     // 5. Сохраняем запрос в контекст пользователя
     let mut updated_context = state.query_context.get_or_create_context(user_id.clone()).await;
@@ -306,9 +358,11 @@ pub async fn handle_query(
     state.query_context.update_context(updated_context).await;
     
     // 6. Log to audit
-    let _ = log_query_audit(&state, &req.question, &sql, true, total_time).await;
+    let _ = log_query_audit(&state, &audit, &req.question, &sql, true, total_time).await;
     
     // 7. Форматируем данные в зависимости от output_type
+    let format_start = Instant::now();
+    let format_span = tracing::info_span!("format").entered();
     use crate::utils::formatters;
     use crate::api::models::OutputType;
     
@@ -379,14 +433,14 @@ pub async fn handle_query(
             };
             
             if needs_table && !data.is_empty() {
-                Some(formatters::format_as_table(&data))
+                Some(formatters::format_as_table(&data, language))
             } else {
                 None
             }
         }
     };
     
-    let chart_data = match &req.output_type {
+    let mut chart_data = match &req.output_type {
         OutputType::Chart => {
             let chart_type = if let Some(analysis) = &analysis {
                 analysis.chart_type.as_ref()
@@ -395,7 +449,7 @@ pub async fn handle_query(
             } else {
                 "auto".to_string()
             };
-            formatters::format_as_chart_data(&data, &chart_type)
+            formatters::format_as_chart_data(&data, &chart_type, language)
         }
         OutputType::Auto => {
             // Автоматически определяем, нужна ли диаграмма
@@ -414,16 +468,19 @@ pub async fn handle_query(
                 } else {
                     "auto".to_string()
                 };
-                formatters::format_as_chart_data(&data, &chart_type)
+                formatters::format_as_chart_data(&data, &chart_type, language)
             } else {
                 None
             }
         }
         _ => None,
     };
+    drop(format_span);
+    attach_drill_tokens(&state, &principal, &sql, &[], &data, meta.group_suppression.as_ref(), language, chart_data.as_mut()).await;
+    meta.timings.format_ms = Some(format_start.elapsed().as_millis() as u64);
     
     // 8. Prepare response (optionally hide SQL)
-    let response_sql = if req.include_sql {
+    let response_sql = if req.include_sql && principal.role.can_view_sql() {
         sql.clone()
     } else {
         String::new()
@@ -438,8 +495,12 @@ pub async fn handle_query(
         chart_data,
         execution_time_ms: total_time,
         row_count,
+        blocks: vec![],
+        evidence: vec![],
+        clarification: None,
         analysis,
         cached,
+        meta,
     }))
 }
 
@@ -449,47 +510,16 @@ fn generate_fallback_analysis(
     row_count: usize,
     language: &crate::utils::language::Language,
 ) -> crate::analysis::AnalysisResult {
-    use crate::analysis::{AnalysisResult, Insight, InsightSignificance, ChartType};
-    use crate::utils::language::Language;
-    
-    let (found_records, result_contains, show_details, compare_periods, main_result, found_for, shows_records, query_result, all_categories, compare_others) = match language {
-        Language::Russian => (
-            "Найдено {} записей",
-            "Результат запроса содержит {} записей",
-            "Показать детализацию",
-            "Сравнить с другими периодами",
-            "Основной результат",
-            "Найдено {} записей для {}",
-            "Результат показывает {} записей для категории '{}'",
-            "Результат запроса: {} строк данных",
-            "Показать все категории",
-            "Сравнить с другими",
-        ),
-        Language::English => (
-            "Found {} records",
-            "Query result contains {} records",
-            "Show details",
-            "Compare with other periods",
-            "Main result",
-            "Found {} records for {}",
-            "Result shows {} records for category '{}'",
-            "Query result: {} rows of data",
-            "Show all categories",
-            "Compare with others",
-        ),
-        Language::Kazakh => (
-            "{} жазба табылды",
-            "Сұрау нәтижесі {} жазбаны қамтиды",
-            "Толық мәліметтерді көрсету",
-            "Басқа кезеңдермен салыстыру",
-            "Негізгі нәтиже",
-            "{} үшін {} жазба табылды",
-            "Нәтиже '{}' категориясы үшін {} жазбаны көрсетеді",
-            "Сұрау нәтижесі: {} жол деректер",
-            "Барлық категорияларды көрсету",
-            "Басқалармен салыстыру",
-        ),
-    };
+    use crate::analysis::{statistical_insights, AnalysisResult, Insight, InsightSignificance, ChartType, MAX_INSIGHTS};
+    use crate::i18n::{t, tf};
+    
+    let language = *language;
+    // Доли, рост, тренд и выбросы по всем строкам - без LLM
+    let facts = statistical_insights(data, language);
+    let suggested_questions = |first: &str, second: &str| vec![
+        t(language, first).to_string(),
+        t(language, second).to_string(),
+    ];
     
     // Пытаемся извлечь информацию из данных для headline
     if let Some(first_row) = data.first() {
@@ -498,13 +528,10 @@ fn generate_fallback_analysis(
             if let Some(count) = obj.get("count").or_else(|| obj.get("transaction_count")).or_else(|| obj.get("total_transactions")) {
                 if let Some(count_num) = count.as_u64().or_else(|| count.as_i64().map(|v| v as u64)) {
                     return AnalysisResult {
-                        headline: found_records.replace("{}", &count_num.to_string()),
-                        insights: vec![],
-                        explanation: result_contains.replace("{}", &count_num.to_string()),
-                        suggested_questions: vec![
-                            show_details.to_string(),
-                            compare_periods.to_string(),
-                        ],
+                        headline: tf(language, "analysis.found_records", &[("count", &count_num)]),
+                        insights: facts,
+                        explanation: tf(language, "analysis.result_contains", &[("count", &count_num)]),
+                        suggested_questions: suggested_questions("analysis.show_details", "analysis.compare_periods"),
                         chart_type: if row_count > 1 && row_count <= 10 { Some(ChartType::Bar) } else { None },
                         data: data.to_vec(),
                     };
@@ -516,26 +543,22 @@ fn generate_fallback_analysis(
                 obj.keys().find(|k| k.contains("category") || k.contains("type") || k.contains("name")),
                 obj.keys().find(|k| k.contains("count") || k.contains("total"))
             ) {
-                let category = obj.get(category_key).and_then(|v| v.as_str()).unwrap_or(match language {
-                    Language::Russian => "категория",
-                    Language::English => "category",
-                    Language::Kazakh => "категория",
-                });
+                let category = obj.get(category_key).and_then(|v| v.as_str())
+                    .unwrap_or_else(|| t(language, "analysis.category"));
                 let count = obj.get(count_key).and_then(|v| v.as_u64()).unwrap_or(0);
+                let args: [(&str, &dyn std::fmt::Display); 2] = [("count", &count), ("category", &category)];
                 return AnalysisResult {
-                    headline: found_records.replace("{}", &count.to_string()),
-                    insights: vec![
-                        Insight {
-                            title: main_result.to_string(),
-                            description: found_for.replace("{}", &count.to_string()).replace("{}", category),
-                            significance: InsightSignificance::Medium,
-                        }
-                    ],
-                    explanation: shows_records.replace("{}", &count.to_string()).replace("{}", category),
-                    suggested_questions: vec![
-                        all_categories.to_string(),
-                        compare_others.to_string(),
-                    ],
+                    headline: tf(language, "analysis.found_records", &args),
+                    insights: std::iter::once(Insight {
+                        title: t(language, "analysis.main_result").to_string(),
+                        description: tf(language, "analysis.found_records_for", &args),
+                        significance: InsightSignificance::Medium,
+                    })
+                    .chain(facts)
+                    .take(MAX_INSIGHTS)
+                    .collect(),
+                    explanation: tf(language, "analysis.shows_records_for", &args),
+                    suggested_questions: suggested_questions("analysis.show_all_categories", "analysis.compare_others"),
                     chart_type: if row_count > 1 && row_count <= 10 { Some(ChartType::Bar) } else { None },
                     data: data.to_vec(),
                 };
@@ -545,20 +568,112 @@ fn generate_fallback_analysis(
     
     // Базовый fallback
     AnalysisResult {
-        headline: found_records.replace("{}", &row_count.to_string()),
-        insights: vec![],
-        explanation: query_result.replace("{}", &row_count.to_string()),
-        suggested_questions: vec![
-            show_details.to_string(),
-            compare_periods.to_string(),
-        ],
+        headline: tf(language, "analysis.found_records", &[("count", &row_count)]),
+        insights: facts,
+        explanation: tf(language, "analysis.result_rows", &[("count", &row_count)]),
+        suggested_questions: suggested_questions("analysis.show_details", "analysis.compare_periods"),
         chart_type: if row_count > 1 && row_count <= 10 { Some(ChartType::Table) } else { None },
         data: data.to_vec(),
     }
 }
 
-async fn log_query_audit(
+/// Ответ через chat API: вопрос не про данные или SQL для него получить не удалось.
+/// В аудит пишется без SQL, `success` - считать ли ответ успешным.
+#[allow(clippy::too_many_arguments)]
+async fn answer_as_chat(
+    state: &AppState,
+    audit: &AuditIdentity,
+    question: &str,
+    prompt: &str,
+    language: Language,
+    success: bool,
+    mut meta: ResponseMeta,
+    start: Instant,
+) -> Result<Json<QueryResponse>, AppError> {
+    meta.prompt_version = CHAT_PROMPT_VERSION.to_string();
+    // Истории для /api/query нет
+    let text_response = state.llm.generate_chat_response(prompt, &[], &language).await?;
+
+    let total_time = start.elapsed().as_millis() as u64;
+    let _ = log_query_audit(state, audit, question, "", success, total_time).await;
+
+    Ok(Json(text_only_response(question.to_string(), text_response, None, total_time, meta)))
+}
+
+/// Ошибка выполнения, после которой вопрос разумнее ответить текстом: модель написала неверный SQL
+fn is_sql_execution_error(error: &AppError) -> bool {
+    let message = error.to_string();
+    message.contains("syntax error")
+        || message.contains("invalid input syntax")
+        || message.contains("column") && message.contains("does not exist")
+}
+
+/// Текст после `sql:` - готовый запрос, а не вопрос на естественном языке
+fn is_raw_sql(question: &str) -> bool {
+    let lower = question.trim_start().to_lowercase();
+    ["select", "with"].iter().any(|keyword| {
+        lower.strip_prefix(keyword)
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_whitespace() || c == '('))
+    })
+}
+
+/// Ответ без данных: разговор, справка или уточняющий вопрос
+fn text_only_response(
+    question: String,
+    text: String,
+    clarification: Option<Clarification>,
+    execution_time_ms: u64,
+    meta: ResponseMeta,
+) -> QueryResponse {
+    QueryResponse {
+        question,
+        sql: String::new(),
+        text_response: Some(text),
+        data: vec![],
+        table: None,
+        chart_data: None,
+        execution_time_ms,
+        row_count: 0,
+        blocks: vec![],
+        evidence: vec![],
+        clarification,
+        analysis: None,
+        cached: false,
+        meta,
+    }
+}
+
+/// Уточняющий вопрос: сначала вариант, к которому склонялись ключевые слова
+fn build_clarification(language: Language, likely: Intent) -> Clarification {
+    let mut intents = vec![Intent::DataQuery, Intent::Chat, Intent::Help];
+    if let Some(pos) = intents.iter().position(|intent| *intent == likely) {
+        intents[..=pos].rotate_right(1);
+    }
+    Clarification {
+        question: i18n::t(language, "intent.clarify").to_string(),
+        options: intents
+            .into_iter()
+            .map(|intent| ClarificationOption {
+                intent,
+                label: i18n::t(language, match intent {
+                    Intent::DataQuery => "intent.option_data_query",
+                    Intent::Chat => "intent.option_chat",
+                    _ => "intent.option_help",
+                }).to_string(),
+            })
+            .collect(),
+    }
+}
+
+/// Кто выполнил запрос - для query_audit_log
+pub(super) struct AuditIdentity {
+    pub(super) user_id: String,
+    pub(super) tenant_id: Option<String>,
+}
+
+pub(super) async fn log_query_audit(
     state: &AppState,
+    identity: &AuditIdentity,
     question: &str,
     sql: &str,
     success: bool,
@@ -566,11 +681,12 @@ async fn log_query_audit(
 ) -> Result<(), sqlx::Error> {
     sqlx::query(
         r#"
-        INSERT INTO query_audit_log (user_id, question, generated_sql, success, execution_time_ms)
-        VALUES ($1, $2, $3, $4, $5)
+        INSERT INTO query_audit_log (user_id, tenant_id, question, generated_sql, success, execution_time_ms)
+        VALUES ($1, $2, $3, $4, $5, $6)
         "#,
     )
-    .bind("anonymous")  // TODO: Add auth later
+    .bind(&identity.user_id)
+    .bind(&identity.tenant_id)
     .bind(question)
     .bind(sql)
     .bind(success)
@@ -0,0 +1,133 @@
+use crate::{
+    api::models::DateRange,
+    auth::Principal,
+    cache::{Cache, CacheKey},
+    db::{date_range::resolve_date_range, queries::execute_user_query},
+    error::AppError,
+    i18n,
+    llm::{
+        error::LlmError,
+        validator::{check_column_access, validate_sql},
+    },
+    privacy::{k_anonymity::{enforce_min_group_size, inject_group_size, GroupSuppression}, Audience, AppliedColumnPolicy},
+    state::{AppState, CachedQueryResult},
+    tenants::{scope::scope_to_filter, Tenant},
+    utils::language::Language,
+};
+use std::time::Instant;
+use tracing::Instrument;
+
+/// Результат SQL, выполненного от имени принципала
+pub struct ScopedResult {
+    pub data: Vec<serde_json::Value>,
+    pub row_count: usize,
+    pub execution_time_ms: u64,
+    pub cached: bool,
+    pub date_range: Option<DateRange>,
+    pub group_suppression: Option<GroupSuppression>,
+    pub column_policies: Vec<AppliedColumnPolicy>,
+}
+
+/// Выполняет SQL со всеми ограничениями /api/query: валидатор, доступ роли к колонкам,
+/// размер групп агрегатов, фильтр арендатора, кэш и политики персональных данных.
+/// Через него выполняется SQL и основного запроса, и подзапросов, шагов агента, аналитики.
+pub async fn execute_scoped(
+    state: &AppState,
+    principal: &Principal,
+    tenant: Option<&Tenant>,
+    sql: &str,
+    use_cache: bool,
+    language: Language,
+) -> Result<ScopedResult, AppError> {
+    execute_scoped_filtered(state, principal, tenant, sql, None, use_cache, language).await
+}
+
+/// То же, что `execute_scoped`, но `transactions` дополнительно ограничена условием `row_filter`
+/// (например, периодом сравнения). Период данных (`date_range`)
+/// для такого запроса не определяется: его задает сам фильтр.
+pub async fn execute_scoped_filtered(
+    state: &AppState,
+    principal: &Principal,
+    tenant: Option<&Tenant>,
+    sql: &str,
+    row_filter: Option<&str>,
+    use_cache: bool,
+    language: Language,
+) -> Result<ScopedResult, AppError> {
+    validate_sql(sql).map_err(|rejection| AppError::LLM(LlmError::SqlRejected(rejection).into()))?;
+    if let Some(allowed) = principal.role.allowed_columns() {
+        check_column_access(sql, allowed).map_err(|rejection| AppError::Forbidden(rejection.message))?;
+    }
+
+    let exec_sql = match state.config.min_group_size {
+        0 => sql.to_string(),
+        _ => inject_group_size(sql)
+            .map_err(|rejection| AppError::LLM(LlmError::SqlRejected(rejection).into()))?
+            .unwrap_or_else(|| sql.to_string()),
+    };
+    // Арендатора ограничивает RLS при выполнении, CTE - только дополнительный фильтр строк
+    let exec_sql = match row_filter {
+        Some(filter) => scope_to_filter(&exec_sql, filter),
+        None => exec_sql,
+    };
+    let context: Vec<String> = tenant
+        .map(|tenant| format!("tenant:{}", tenant.id))
+        .into_iter()
+        .chain(row_filter.map(|filter| format!("filter:{}", filter)))
+        .collect();
+    let cache_key = match context.is_empty() {
+        true => CacheKey::from_sql(sql),
+        false => CacheKey::from_sql_with_context(sql, &context.join("|")),
+    };
+
+    let cached_result = match use_cache {
+        true => state.cache.get(&cache_key).instrument(tracing::info_span!("cache_lookup")).await,
+        false => None,
+    };
+    let cached = cached_result.is_some();
+    let (mut data, execution_time_ms) = match cached_result {
+        Some(result) => (result.data, result.execution_time_ms),
+        None => {
+            let query_start = Instant::now();
+            let data = execute_user_query(&state.db, &exec_sql, tenant).await?;
+            let execution_time_ms = query_start.elapsed().as_millis() as u64;
+            if use_cache {
+                let result = CachedQueryResult {
+                    sql: sql.to_string(),
+                    data: data.clone(),
+                    execution_time_ms,
+                    row_count: data.len(),
+                };
+                state.cache.set(cache_key, result, cache_ttl(sql)).await;
+            }
+            (data, execution_time_ms)
+        }
+    };
+
+    let group_suppression = enforce_min_group_size(sql, &mut data, state.config.min_group_size, i18n::t(language, "analysis.other_bucket"));
+    let date_range = match row_filter {
+        Some(_) => None,
+        None => resolve_date_range(sql, &data),
+    };
+    let column_policies = state.column_policy.apply(sql, &mut data, Audience::Role(principal.role));
+
+    Ok(ScopedResult {
+        row_count: data.len(),
+        data,
+        execution_time_ms,
+        cached,
+        date_range,
+        group_suppression,
+        column_policies,
+    })
+}
+
+/// TTL кэша: 5 минут для оперативных данных, 30 минут для исторических
+fn cache_ttl(sql: &str) -> u64 {
+    let sql = sql.to_lowercase();
+    if sql.contains("current_date") || sql.contains("today") || sql.contains("last") {
+        300
+    } else {
+        1800
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{
+    auth::Principal,
+    error::AppError,
+    limits::quota::{self, DailyUsage, Quotas},
+    state::AppState,
+};
+use axum::{
+    extract::{Query, State},
+    Extension, Json,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct UsageParams {
+    #[serde(default)]
+    pub days: Option<i64>,  // История за N дней (по умолчанию 7, максимум 90)
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageResponse {
+    pub user_id: String,
+    pub quotas: Quotas,
+    pub today: DailyUsage,
+    pub remaining_llm_calls: Option<u64>,
+    pub remaining_llm_tokens: Option<u64>,
+    pub resets_in_secs: u64,
+    pub history: Vec<DailyUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminUsageParams {
+    #[serde(default)]
+    pub day: Option<NaiveDate>,  // По умолчанию - сегодня (UTC)
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminUsageResponse {
+    pub day: NaiveDate,
+    pub quotas: Quotas,
+    pub users: Vec<DailyUsage>,
+}
+
+/// Расход и остаток квот текущего пользователя
+pub async fn handle_usage(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Query(params): Query<UsageParams>,
+) -> Result<Json<UsageResponse>, AppError> {
+    let days = params.days.unwrap_or(7).clamp(1, 90);
+    let quotas = Quotas::from_config(&state.config);
+    let today = quota::usage_for_day(&state.db, &principal.id, quota::today()).await?;
+    let history = quota::user_history(&state.db, &principal.id, days).await?;
+    let budget = quotas.budget(&today);
+
+    Ok(Json(UsageResponse {
+        user_id: principal.id,
+        quotas,
+        today,
+        remaining_llm_calls: budget.remaining_calls,
+        remaining_llm_tokens: budget.remaining_tokens,
+        resets_in_secs: quota::secs_until_reset(),
+        history,
+    }))
+}
+
+/// Расход всех пользователей за день (только для администраторов)
+pub async fn admin_usage_report(
+    State(state): State<AppState>,
+    Query(params): Query<AdminUsageParams>,
+) -> Result<Json<AdminUsageResponse>, AppError> {
+    let day = params.day.unwrap_or_else(quota::today);
+    let users = quota::usage_by_user(&state.db, day).await?;
+
+    Ok(Json(AdminUsageResponse {
+        day,
+        quotas: Quotas::from_config(&state.config),
+        users,
+    }))
+}
@@ -0,0 +1,138 @@
+use crate::auth::{AuthMethod, Principal, Role};
+use chrono::NaiveDateTime;
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+/// Префикс ключей: по нему ключ отличается от JWT в заголовке Authorization
+pub const API_KEY_PREFIX: &str = "pak_";
+
+/// Сколько символов ключа хранится открыто (для отображения в списке ключей)
+const DISPLAY_PREFIX_LEN: usize = 12;
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ApiKeyInfo {
+    pub id: i32,
+    pub name: String,
+    pub principal_id: String,
+    pub key_prefix: String,
+    #[sqlx(try_from = "String")]
+    pub role: Role,
+    pub tenant_id: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+/// Созданный ключ. `key` возвращается только один раз - в базе хранится лишь хеш.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+}
+
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", API_KEY_PREFIX, to_hex(&bytes))
+}
+
+/// Ключи случайные (256 бит), поэтому достаточно SHA-256 без соли
+pub fn hash_key(key: &str) -> String {
+    to_hex(&Sha256::digest(key.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(out, "{:02x}", b);
+    }
+    out
+}
+
+pub async fn create_key(
+    pool: &PgPool,
+    name: &str,
+    principal_id: &str,
+    role: Role,
+    tenant_id: Option<&str>,
+) -> Result<CreatedApiKey, sqlx::Error> {
+    let key = generate_key();
+    let info = sqlx::query_as::<_, ApiKeyInfo>(
+        r#"
+        INSERT INTO api_keys (name, principal_id, key_prefix, key_hash, role, tenant_id)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, name, principal_id, key_prefix, role, tenant_id, created_at, last_used_at, revoked_at
+        "#,
+    )
+    .bind(name)
+    .bind(principal_id)
+    .bind(&key[..DISPLAY_PREFIX_LEN])
+    .bind(hash_key(&key))
+    .bind(role.as_str())
+    .bind(tenant_id)
+    .fetch_one(pool)
+    .await?;
+
+    tracing::info!("Created API key {} for principal {} ({})", info.id, info.principal_id, info.role);
+    Ok(CreatedApiKey { key, info })
+}
+
+pub async fn list_keys(pool: &PgPool) -> Result<Vec<ApiKeyInfo>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKeyInfo>(
+        r#"
+        SELECT id, name, principal_id, key_prefix, role, tenant_id, created_at, last_used_at, revoked_at
+        FROM api_keys
+        ORDER BY id
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Отзывает ключ. Возвращает false, если ключ не найден или уже отозван.
+pub async fn revoke_key(pool: &PgPool, id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Проверяет ключ и возвращает принципала (None - ключ неизвестен или отозван)
+pub async fn verify_key(pool: &PgPool, key: &str) -> Result<Option<Principal>, sqlx::Error> {
+    let row: Option<(i32, String, String, String, Option<String>)> = sqlx::query_as(
+        "SELECT id, name, principal_id, role, tenant_id FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+    )
+    .bind(hash_key(key))
+    .fetch_optional(pool)
+    .await?;
+
+    let Some((key_id, name, principal_id, role, tenant)) = row else {
+        return Ok(None);
+    };
+    let role: Role = role.parse().map_err(|e: String| sqlx::Error::Decode(e.into()))?;
+
+    // last_used_at обновляем в фоне - на ответ это не влияет
+    let pool = pool.clone();
+    tokio::spawn(async move {
+        if let Err(e) = sqlx::query("UPDATE api_keys SET last_used_at = NOW() WHERE id = $1")
+            .bind(key_id)
+            .execute(&pool)
+            .await
+        {
+            tracing::debug!("Failed to update last_used_at for API key {}: {}", key_id, e);
+        }
+    });
+
+    Ok(Some(Principal {
+        id: principal_id,
+        name: Some(name),
+        method: AuthMethod::ApiKey { key_id },
+        role,
+        tenant,
+    }))
+}
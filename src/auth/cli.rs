@@ -0,0 +1,68 @@
+use crate::auth::{api_keys, Role};
+use anyhow::{bail, Context, Result};
+use sqlx::PgPool;
+
+const USAGE: &str = "\
+Usage:
+  payment-analytics-backend keys create --name <name> [--principal <id>] [--role viewer|analyst|admin] [--tenant <id>]
+  payment-analytics-backend keys list
+  payment-analytics-backend keys revoke <id>";
+
+/// Управление API-ключами из командной строки (`cargo run -- keys ...`).
+/// Нужен в первую очередь для создания первого ключа администратора.
+pub async fn run(args: &[String], pool: &PgPool) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("create") => {
+            let mut name = None;
+            let mut principal = None;
+            let mut role = Role::Analyst;
+            let mut tenant = None;
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--name" => name = rest.next().cloned(),
+                    "--principal" => principal = rest.next().cloned(),
+                    "--tenant" => tenant = rest.next().cloned(),
+                    "--role" => {
+                        role = rest.next()
+                            .with_context(|| format!("--role requires a value\n{}", USAGE))?
+                            .parse()
+                            .map_err(anyhow::Error::msg)?;
+                    }
+                    other => bail!("Unknown argument: {}\n{}", other, USAGE),
+                }
+            }
+            let name = name.with_context(|| format!("--name is required\n{}", USAGE))?;
+            // По умолчанию ID принципала совпадает с именем ключа
+            let principal = principal.unwrap_or_else(|| name.clone());
+
+            let created = api_keys::create_key(pool, &name, &principal, role, tenant.as_deref()).await?;
+            println!("Created API key #{} for principal '{}' (role: {})", created.info.id, principal, role);
+            println!("{}", created.key);
+            println!("Store it now - the key cannot be shown again.");
+        }
+        Some("list") => {
+            for key in api_keys::list_keys(pool).await? {
+                let status = match key.revoked_at {
+                    Some(at) => format!("revoked {}", at),
+                    None => "active".to_string(),
+                };
+                println!("#{:<4} {:<20} principal={:<20} {}... role={:<8} tenant={:<10} {}",
+                    key.id, key.name, key.principal_id, key.key_prefix, key.role,
+                    key.tenant_id.as_deref().unwrap_or("-"), status);
+            }
+        }
+        Some("revoke") => {
+            let id: i32 = args.get(1)
+                .and_then(|id| id.parse().ok())
+                .with_context(|| format!("Key id is required\n{}", USAGE))?;
+            if api_keys::revoke_key(pool, id).await? {
+                println!("API key #{} revoked", id);
+            } else {
+                bail!("API key #{} not found or already revoked", id);
+            }
+        }
+        _ => bail!("{}", USAGE),
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use crate::{
+    auth::{AuthMethod, Principal, Role},
+    config::Config,
+    error::AppError,
+};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+
+/// Claims JWT, выпущенного внешним identity-провайдером (HS256, общий секрет JWT_SECRET)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: usize,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// viewer | analyst | admin. Без роли - viewer (минимальные права)
+    #[serde(default)]
+    pub role: Option<String>,
+    /// ID арендатора (банка-эмитента). Без него - доступ ко всем банкам
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+pub fn verify_token(token: &str, config: &Config) -> Result<Principal, AppError> {
+    let secret = config.jwt_secret.as_deref()
+        .ok_or_else(|| AppError::Unauthorized("JWT authentication is not configured".to_string()))?;
+
+    let mut validation = Validation::new(Algorithm::HS256);
+    if let Some(issuer) = &config.jwt_issuer {
+        validation.set_issuer(&[issuer]);
+    }
+    match &config.jwt_audience {
+        Some(audience) => validation.set_audience(&[audience]),
+        None => validation.validate_aud = false,
+    }
+
+    let claims = decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &validation)
+        .map_err(|e| AppError::Unauthorized(format!("Invalid token: {}", e)))?
+        .claims;
+
+    if claims.sub.trim().is_empty() {
+        return Err(AppError::Unauthorized("Token has empty subject".to_string()));
+    }
+
+    let role = match claims.role.as_deref() {
+        Some(role) => role.parse().map_err(AppError::Unauthorized)?,
+        None => Role::Viewer,
+    };
+
+    Ok(Principal {
+        id: claims.sub,
+        name: claims.name,
+        method: AuthMethod::Jwt,
+        role,
+        tenant: claims.tenant,
+    })
+}
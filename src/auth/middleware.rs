@@ -0,0 +1,57 @@
+use crate::{
+    auth::{api_keys, extract_credential, jwt, Credential, Principal, Role},
+    error::AppError,
+    state::AppState,
+};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+
+/// Определяет принципала по API-ключу или JWT и кладет его в extensions запроса.
+/// При AUTH_ENABLED=false все запросы выполняются от анонимного принципала (роль Viewer,
+/// Admin - только с ANONYMOUS_ADMIN=true).
+pub async fn authenticate(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let principal = if state.config.auth_enabled {
+        let credential = extract_credential(request.headers())
+            .ok_or_else(|| AppError::Unauthorized("Missing API key or bearer token".to_string()))?;
+
+        match credential {
+            Credential::ApiKey(key) => api_keys::verify_key(&state.db, &key).await?
+                .ok_or_else(|| AppError::Unauthorized("Invalid or revoked API key".to_string()))?,
+            Credential::Jwt(token) => jwt::verify_token(&token, &state.config)?,
+        }
+    } else {
+        Principal::anonymous(if state.config.anonymous_admin { Role::Admin } else { Role::Viewer })
+    };
+
+    // Неизвестный арендатор - отказ, а не доступ ко всем банкам
+    if let Some(tenant) = &principal.tenant {
+        if state.tenants.get(tenant).is_none() {
+            return Err(AppError::Forbidden(format!("Unknown tenant '{}'", tenant)));
+        }
+    }
+
+    tracing::debug!("Authenticated principal: {} ({}, {}, {:?})",
+        principal.id, principal.name.as_deref().unwrap_or("-"), principal.role, principal.method);
+    request.extensions_mut().insert(principal);
+    Ok(next.run(request).await)
+}
+
+/// Пропускает только администраторов. Ставится после `authenticate`.
+pub async fn require_admin(
+    Extension(principal): Extension<Principal>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if !principal.role.is_admin() {
+        return Err(AppError::Forbidden("Administrator access required".to_string()));
+    }
+    Ok(next.run(request).await)
+}
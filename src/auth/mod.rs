@@ -0,0 +1,131 @@
+pub mod api_keys;
+pub mod cli;
+pub mod jwt;
+pub mod middleware;
+pub mod roles;
+
+use axum::http::{header, HeaderMap};
+pub use roles::Role;
+
+/// Аутентифицированный субъект запроса. Кладется в extensions middleware `authenticate`,
+/// хендлеры получают его через `Extension<Principal>`.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub id: String,
+    pub name: Option<String>,
+    pub method: AuthMethod,
+    pub role: Role,
+    /// Арендатор (банк-эмитент), данными которого ограничен принципал. None - все банки
+    pub tenant: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthMethod {
+    ApiKey { key_id: i32 },
+    Jwt,
+    /// Аутентификация отключена (AUTH_ENABLED=false)
+    Anonymous,
+}
+
+impl Principal {
+    /// Принципал при AUTH_ENABLED=false: Viewer, Admin - только с явным ANONYMOUS_ADMIN=true
+    pub fn anonymous(role: Role) -> Self {
+        Self {
+            id: "anonymous".to_string(),
+            name: None,
+            method: AuthMethod::Anonymous,
+            role,
+            tenant: None,
+        }
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.method != AuthMethod::Anonymous
+    }
+
+    /// Идентификатор пользователя для контекста, проверок безопасности и аудита.
+    /// После аутентификации всегда ID принципала - подставить чужой `user_id` нельзя.
+    /// Без аутентификации (локальная разработка) используется значение из запроса.
+    pub fn user_id(&self, requested: Option<&str>) -> String {
+        if self.is_authenticated() {
+            return self.id.clone();
+        }
+        requested
+            .filter(|id| !id.trim().is_empty())
+            .unwrap_or("anonymous")
+            .to_string()
+    }
+}
+
+/// Учетные данные из заголовков запроса
+#[derive(Debug, PartialEq, Eq)]
+pub enum Credential {
+    ApiKey(String),
+    Jwt(String),
+}
+
+/// `X-API-Key: <key>` или `Authorization: Bearer <key|jwt>`.
+/// Bearer-токен с префиксом API-ключа считается ключом, остальные - JWT.
+pub fn extract_credential(headers: &HeaderMap) -> Option<Credential> {
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        let key = key.trim();
+        if !key.is_empty() {
+            return Some(Credential::ApiKey(key.to_string()));
+        }
+    }
+
+    let authorization = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    let token = token.trim();
+    if token.is_empty() {
+        None
+    } else if token.starts_with(api_keys::API_KEY_PREFIX) {
+        Some(Credential::ApiKey(token.to_string()))
+    } else {
+        Some(Credential::Jwt(token.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_extract_credential() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(extract_credential(&headers), None);
+
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer pak_abc"));
+        assert_eq!(extract_credential(&headers), Some(Credential::ApiKey("pak_abc".to_string())));
+
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("bearer eyJhbGciOi.x.y"));
+        assert_eq!(extract_credential(&headers), Some(Credential::Jwt("eyJhbGciOi.x.y".to_string())));
+
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Basic dXNlcjpwYXNz"));
+        assert_eq!(extract_credential(&headers), None);
+
+        headers.insert("x-api-key", HeaderValue::from_static("pak_header"));
+        assert_eq!(extract_credential(&headers), Some(Credential::ApiKey("pak_header".to_string())));
+    }
+
+    #[test]
+    fn test_user_id_cannot_be_overridden_when_authenticated() {
+        let principal = Principal {
+            id: "analyst-1".to_string(),
+            name: None,
+            method: AuthMethod::Jwt,
+            role: Role::Analyst,
+            tenant: None,
+        };
+        assert_eq!(principal.user_id(Some("someone-else")), "analyst-1");
+
+        let anonymous = Principal::anonymous(Role::Viewer);
+        assert_eq!(anonymous.user_id(Some("user123")), "user123");
+        assert_eq!(anonymous.user_id(Some("  ")), "anonymous");
+        assert_eq!(anonymous.user_id(None), "anonymous");
+    }
+}
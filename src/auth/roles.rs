@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Роль принципала. Определяет видимость SQL, доступ к raw SQL, колонки и admin endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Только ответы на вопросы: без SQL в ответе, без идентификаторов
+    Viewer,
+    /// Видит SQL, может выполнять свой SELECT через `sql:`, без данных карт
+    Analyst,
+    /// Полный доступ, включая управление ключами и чужие контексты
+    Admin,
+}
+
+/// Колонки `transactions`, доступные всем ролям
+const VIEWER_COLUMNS: &[&str] = &[
+    "transaction_timestamp",
+    "issuer_bank_name",
+    "merchant_mcc",
+    "mcc_category",
+    "merchant_city",
+    "transaction_type",
+    "transaction_amount_kzt",
+    "original_amount",
+    "transaction_currency",
+    "acquirer_country_iso",
+    "pos_entry_mode",
+    "wallet_type",
+];
+
+/// Аналитику дополнительно доступны идентификаторы транзакций и мерчантов
+const ANALYST_COLUMNS: &[&str] = &[
+    "transaction_timestamp",
+    "issuer_bank_name",
+    "merchant_mcc",
+    "mcc_category",
+    "merchant_city",
+    "transaction_type",
+    "transaction_amount_kzt",
+    "original_amount",
+    "transaction_currency",
+    "acquirer_country_iso",
+    "pos_entry_mode",
+    "wallet_type",
+    "id",
+    "transaction_id",
+    "merchant_id",
+];
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Analyst => "analyst",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        *self == Role::Admin
+    }
+
+    /// Можно ли возвращать сгенерированный SQL (`include_sql`)
+    pub fn can_view_sql(&self) -> bool {
+        matches!(self, Role::Analyst | Role::Admin)
+    }
+
+    /// Можно ли выполнять собственный SELECT: `sql: SELECT ...`
+    pub fn can_run_raw_sql(&self) -> bool {
+        matches!(self, Role::Analyst | Role::Admin)
+    }
+
+    /// Разрешенные колонки `transactions` (None - все колонки)
+    pub fn allowed_columns(&self) -> Option<&'static [&'static str]> {
+        match self {
+            Role::Viewer => Some(VIEWER_COLUMNS),
+            Role::Analyst => Some(ANALYST_COLUMNS),
+            Role::Admin => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "viewer" => Ok(Role::Viewer),
+            "analyst" => Ok(Role::Analyst),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role '{}': expected viewer, analyst or admin", other)),
+        }
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
@@ -41,7 +41,7 @@ where
 
     async fn get(&self, key: &CacheKey) -> Option<Self::Value> {
         // Cleanup expired entries periodically (every 10th request)
-        if rand::random::<u8>() % 10 == 0 {
+        if rand::random::<u8>().is_multiple_of(10) {
             self.cleanup_expired().await;
         }
 
@@ -63,16 +63,6 @@ where
         let mut data = self.data.write().await;
         data.insert(key, entry);
     }
-
-    async fn invalidate(&self, key: &CacheKey) {
-        let mut data = self.data.write().await;
-        data.remove(key);
-    }
-
-    async fn clear(&self) {
-        let mut data = self.data.write().await;
-        data.clear();
-    }
 }
 
 impl<T> Default for MemoryCache<T>
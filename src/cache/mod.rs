@@ -59,7 +59,5 @@ pub trait Cache: Send + Sync {
     
     async fn get(&self, key: &CacheKey) -> Option<Self::Value>;
     async fn set(&self, key: CacheKey, value: Self::Value, ttl_seconds: u64);
-    async fn invalidate(&self, key: &CacheKey);
-    async fn clear(&self);
 }
 
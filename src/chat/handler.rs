@@ -1,10 +1,11 @@
 use crate::{
+    auth::Principal,
     chat::session::MessageRole,
     error::AppError,
     state::AppState,
-    utils::language::detect_language,
+    utils::language::{apply_requested_language, detect_language, response_language, set_response_language},
 };
-use axum::{extract::State, Json};
+use axum::{extract::State, Extension, Json};
 use serde::{Deserialize, Serialize};
 use std::time::Instant;
 
@@ -15,6 +16,8 @@ pub struct ChatRequest {
     pub session_id: String,  // Если не указан, будет создан новый
     #[serde(default)]
     pub user_id: String,  // Для идентификации пользователя
+    #[serde(default)]
+    pub language: Option<String>,  // Язык ответа (ru, en, kk, uz, ky); по умолчанию - язык сообщения
 }
 
 #[derive(Debug, Serialize)]
@@ -22,10 +25,13 @@ pub struct ChatResponse {
     pub message: String,
     pub session_id: String,
     pub response_time_ms: u64,
+    pub language: &'static str,
 }
 
+#[tracing::instrument(name = "handle_chat", skip_all)]
 pub async fn handle_chat(
     State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
     Json(req): Json<ChatRequest>,
 ) -> Result<Json<ChatResponse>, AppError> {
     let start = Instant::now();
@@ -37,23 +43,26 @@ pub async fn handle_chat(
         req.session_id.clone()
     };
     
-    let user_id = if req.user_id.is_empty() {
-        "anonymous".to_string()
-    } else {
-        req.user_id.clone()
-    };
+    let user_id = principal.user_id(Some(&req.user_id));
     
     tracing::info!("Chat request: session_id={}, user_id={}, message_len={}", 
         session_id, user_id, req.message.len());
     
+    // Явно указанный язык ответа (поле `language`) важнее Accept-Language и языка сообщения
+    apply_requested_language(req.language.as_deref()).map_err(AppError::BadRequest)?;
+    
     // Получаем или создаем сессию
-    let mut session = state.sessions.get_or_create_session(session_id.clone(), user_id).await;
+    let mut session = state.sessions.get_or_create_session(session_id.clone(), user_id.clone()).await;
+    if session.user_id != user_id {
+        return Err(AppError::Forbidden("Session belongs to another user".to_string()));
+    }
     
     // Добавляем сообщение пользователя
     session.add_message(MessageRole::User, req.message.clone());
     
-    // Определяем язык
-    let language = detect_language(&req.message);
+    // Определяем язык (явно указанный клиентом важнее)
+    set_response_language(detect_language(&req.message));
+    let language = response_language();
     
     // Получаем контекст из последних сообщений (максимум 10)
     let recent_messages = session.get_recent_messages(10);
@@ -82,6 +91,7 @@ pub async fn handle_chat(
         message: response,
         session_id,
         response_time_ms: response_time,
+        language: language.code(),
     }))
 }
 
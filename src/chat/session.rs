@@ -22,7 +22,6 @@ pub struct Session {
     pub id: String,
     pub user_id: String,
     pub messages: Vec<Message>,
-    pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
@@ -33,7 +32,6 @@ impl Session {
             id: session_id,
             user_id,
             messages: Vec::new(),
-            created_at: now,
             updated_at: now,
         }
     }
@@ -83,19 +81,9 @@ impl SessionManager {
         sessions.insert(session.id.clone(), session);
     }
 
-    pub async fn get_session(&self, session_id: &str) -> Option<Session> {
-        let sessions = self.sessions.read().await;
-        sessions.get(session_id).cloned()
-    }
-
     async fn cleanup_old_sessions(&self, sessions: &mut HashMap<String, Session>) {
         let cutoff = Utc::now() - chrono::Duration::hours(self.max_session_age_hours as i64);
         sessions.retain(|_, session| session.updated_at > cutoff);
     }
-
-    pub async fn clear_session(&self, session_id: &str) {
-        let mut sessions = self.sessions.write().await;
-        sessions.remove(session_id);
-    }
 }
 
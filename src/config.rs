@@ -4,14 +4,45 @@ use serde::Deserialize;
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub database_url: String,
+    pub db_statement_timeout_ms: u64,
     pub llm_provider: String,  // "ollama" | "openai" | "gemini"
     pub ollama_url: String,
     pub ollama_model: String,
     pub openai_api_key: Option<String>,
+    pub openai_model: String,
     pub gemini_api_key: Option<String>,
     pub gemini_model: String,
+    pub llm_timeout_secs: u64,  // Таймаут одного запроса к LLM
+    pub intent_model: Option<String>,  // Модель для классификации намерения (по умолчанию - основная)
+    pub intent_llm_fallback: bool,  // Спрашивать модель, если ключевые слова не дали уверенности
+    pub intent_confidence_threshold: f32,  // Ниже - уточняющий вопрос вместо угадывания
+    pub sql_tool_calling: bool,  // Генерация SQL через инструмент submit_sql (с откатом на текст)
+    pub agent_max_steps: usize,  // Шагов агента на вопрос (включая итоговый отчет), 0 - режим агента выключен
+    pub agent_max_tokens: u64,  // Токенов LLM на один запуск агента
     pub host: String,
     pub port: u16,
+    pub otlp_endpoint: Option<String>,  // OTLP/HTTP collector, например http://localhost:4318
+    pub trace_file: Option<String>,  // JSON-файл со спанами (для локальной отладки)
+    pub service_name: String,
+    pub auth_enabled: bool,  // false - без аутентификации (только для локальной разработки)
+    pub anonymous_admin: bool,  // true - при AUTH_ENABLED=false анонимный принципал - администратор
+    pub jwt_secret: Option<String>,  // HS256-секрет для проверки JWT
+    pub jwt_issuer: Option<String>,
+    pub jwt_audience: Option<String>,
+    pub rate_limit_per_minute: u32,  // На принципала, 0 - без ограничения
+    pub rate_limit_burst: u32,
+    pub ip_rate_limit_per_minute: u32,  // На IP, 0 - без ограничения
+    pub ip_rate_limit_burst: u32,
+    pub daily_llm_call_quota: u64,  // Вызовов LLM в день на пользователя, 0 - без ограничения
+    pub daily_llm_token_quota: u64,  // Токенов LLM в день на пользователя, 0 - без ограничения
+    pub max_warnings: i32,  // Предупреждений до бана (меняется через /api/admin/safety/settings)
+    pub ban_duration_hours: i32,
+    pub warning_decay_hours: i32,  // Через сколько часов предупреждение перестает учитываться, 0 - никогда
+    pub min_group_size: u64,  // k-анонимность: минимум транзакций в группе агрегата, 0 - без проверки
+    pub tenants: Option<String>,  // Арендаторы: "kaspi=Kaspi Bank;halyk=Halyk Bank" (дополняются таблицей tenants)
+    pub column_policy: Option<String>,  // Переопределения политик колонок: "card_id=viewer:deny,analyst:hash;..."
+    pub safety_policy_file: Option<String>,  // Файл правил политики безопасности (по умолчанию встроенный config/safety_policy.toml)
+    pub pii_hash_salt: Option<String>,  // Соль для хеширования идентификаторов (без нее - случайная на процесс)
 }
 
 impl Config {
@@ -31,6 +62,10 @@ impl Config {
         
         Ok(Self {
             database_url,
+            db_statement_timeout_ms: std::env::var("DB_STATEMENT_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30_000),
             llm_provider: std::env::var("LLM_PROVIDER")
                 .unwrap_or_else(|_| "ollama".to_string()),
             ollama_url: std::env::var("OLLAMA_URL")
@@ -38,18 +73,69 @@ impl Config {
             ollama_model: std::env::var("OLLAMA_MODEL")
                 .unwrap_or_else(|_| "mixtral:8x7b-instruct".to_string()),
             openai_api_key: std::env::var("OPENAI_API_KEY").ok(),
+            openai_model: std::env::var("OPENAI_MODEL")
+                .unwrap_or_else(|_| "gpt-4o-mini".to_string()),
             gemini_api_key: std::env::var("GEMINI_API_KEY")
                 .ok()
                 .or_else(|| std::env::var("LLM_API_KEY").ok()),
             gemini_model: std::env::var("GEMINI_MODEL")
                 .unwrap_or_else(|_| "gemini-2.0-flash-exp".to_string()),
+            llm_timeout_secs: std::env::var("LLM_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+            intent_model: std::env::var("INTENT_MODEL").ok().filter(|s| !s.trim().is_empty()),
+            intent_llm_fallback: std::env::var("INTENT_LLM_FALLBACK")
+                .map(|v| !matches!(v.to_lowercase().as_str(), "false" | "0" | "no"))
+                .unwrap_or(true),
+            intent_confidence_threshold: env_or("INTENT_CONFIDENCE_THRESHOLD", 0.5),
+            sql_tool_calling: std::env::var("SQL_TOOL_CALLING")
+                .map(|v| !matches!(v.to_lowercase().as_str(), "false" | "0" | "no"))
+                .unwrap_or(true),
+            agent_max_steps: env_or("AGENT_MAX_STEPS", 6),
+            agent_max_tokens: env_or("AGENT_MAX_TOKENS", 40_000),
             host: std::env::var("HOST")
                 .unwrap_or_else(|_| "0.0.0.0".to_string()),
             port: std::env::var("PORT")
                 .ok()
                 .and_then(|p| p.parse().ok())
                 .unwrap_or(3000),
+            otlp_endpoint: std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok(),
+            trace_file: std::env::var("TRACE_FILE").ok(),
+            service_name: std::env::var("OTEL_SERVICE_NAME")
+                .unwrap_or_else(|_| "payment-analytics-backend".to_string()),
+            auth_enabled: std::env::var("AUTH_ENABLED")
+                .map(|v| !matches!(v.to_lowercase().as_str(), "false" | "0" | "no"))
+                .unwrap_or(true),
+            anonymous_admin: std::env::var("ANONYMOUS_ADMIN")
+                .map(|v| matches!(v.to_lowercase().as_str(), "true" | "1" | "yes"))
+                .unwrap_or(false),
+            jwt_secret: std::env::var("JWT_SECRET").ok().filter(|s| !s.is_empty()),
+            jwt_issuer: std::env::var("JWT_ISSUER").ok(),
+            jwt_audience: std::env::var("JWT_AUDIENCE").ok(),
+            rate_limit_per_minute: env_or("RATE_LIMIT_PER_MINUTE", 30),
+            rate_limit_burst: env_or("RATE_LIMIT_BURST", 10),
+            ip_rate_limit_per_minute: env_or("IP_RATE_LIMIT_PER_MINUTE", 120),
+            ip_rate_limit_burst: env_or("IP_RATE_LIMIT_BURST", 30),
+            daily_llm_call_quota: env_or("DAILY_LLM_CALL_QUOTA", 1000),
+            daily_llm_token_quota: env_or("DAILY_LLM_TOKEN_QUOTA", 2_000_000),
+            max_warnings: env_or("MAX_WARNINGS", 5),
+            ban_duration_hours: env_or("BAN_DURATION_HOURS", 24),
+            warning_decay_hours: env_or("WARNING_DECAY_HOURS", 168),
+            min_group_size: env_or("MIN_GROUP_SIZE", 5),
+            tenants: std::env::var("TENANTS").ok().filter(|s| !s.trim().is_empty()),
+            column_policy: std::env::var("COLUMN_POLICY").ok().filter(|s| !s.trim().is_empty()),
+            safety_policy_file: std::env::var("SAFETY_POLICY_FILE").ok().filter(|s| !s.is_empty()),
+            pii_hash_salt: std::env::var("PII_HASH_SALT").ok().filter(|s| !s.is_empty()),
         })
     }
 }
 
+
+/// Числовая переменная окружения со значением по умолчанию
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
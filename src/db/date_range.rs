@@ -0,0 +1,321 @@
+use crate::{
+    api::models::DateRange,
+    llm::validator::{function_name, parse_query},
+};
+use chrono::{DateTime, Duration, Months, NaiveDate, NaiveDateTime, Utc};
+use serde_json::Value;
+use sqlparser::ast::{BinaryOperator, Expr, FunctionArg, FunctionArgExpr, FunctionArguments, Visit, Visitor};
+use std::ops::ControlFlow;
+
+/// Колонка, по которой определяется период данных
+const TIMESTAMP_COLUMN: &str = "transaction_timestamp";
+
+/// Границы периода, найденные в WHERE-условиях сгенерированного SQL
+#[derive(Debug, Default, PartialEq)]
+pub struct TimestampBounds {
+    /// В запросе есть хотя бы одно условие на `transaction_timestamp`
+    pub filtered: bool,
+    /// Нижняя граница (None - условия нет или его нельзя вычислить без базы)
+    pub from: Option<NaiveDate>,
+    /// Верхняя граница включительно
+    pub to: Option<NaiveDate>,
+}
+
+/// Определяет фактический период данных, на которых построен ответ, без обращения к базе:
+/// границы из фильтров по `transaction_timestamp` (литералы и `CURRENT_DATE`/`NOW()` ± `INTERVAL`),
+/// а где их нет - минимальная и максимальная даты в строках результата.
+/// Границы, зависящие от данных (`(SELECT MAX(...)) - INTERVAL ...`), берутся из строк.
+pub fn resolve_date_range(sql: &str, rows: &[Value]) -> Option<DateRange> {
+    resolve_date_range_at(sql, rows, Utc::now().naive_utc())
+}
+
+fn resolve_date_range_at(sql: &str, rows: &[Value], now: NaiveDateTime) -> Option<DateRange> {
+    let bounds = extract_timestamp_bounds(sql, now);
+    let (rows_min, rows_max) = row_dates(rows);
+
+    // Даты в строках могут быть усечены (DATE_TRUNC), поэтому строки только сужают нижнюю границу
+    let from = match (bounds.from, rows_min) {
+        (Some(f), Some(m)) => Some(f.max(m)),
+        (f, m) => f.or(m),
+    };
+    let to = bounds.to.or(rows_max);
+
+    if from.is_none() && to.is_none() {
+        return None;
+    }
+
+    Some(DateRange {
+        from,
+        to,
+        filtered: bounds.filtered,
+    })
+}
+
+/// Находит сравнения вида `transaction_timestamp >= <expr>` (в том числе `DATE(transaction_timestamp)`,
+/// `transaction_timestamp::date`, `BETWEEN`) и вычисляет границы периода.
+/// При нескольких условиях (OR, подзапросы) итоговый период их покрывает;
+/// если хотя бы одну границу вычислить нельзя, эта сторона периода остается неизвестной.
+pub fn extract_timestamp_bounds(sql: &str, now: NaiveDateTime) -> TimestampBounds {
+    let Ok(query) = parse_query(sql) else {
+        return TimestampBounds::default();
+    };
+    let mut collector = BoundCollector { now, lower: Vec::new(), upper: Vec::new() };
+    let _ = query.visit(&mut collector);
+
+    TimestampBounds {
+        filtered: !collector.lower.is_empty() || !collector.upper.is_empty(),
+        from: collector.lower.iter().copied().collect::<Option<Vec<_>>>().and_then(|d| d.into_iter().min()),
+        to: collector.upper.iter().copied().collect::<Option<Vec<_>>>().and_then(|d| d.into_iter().max()),
+    }
+}
+
+struct BoundCollector {
+    now: NaiveDateTime,
+    lower: Vec<Option<NaiveDate>>,
+    upper: Vec<Option<NaiveDate>>,
+}
+
+impl BoundCollector {
+    fn add(&mut self, op: &BinaryOperator, bound: &Expr) {
+        let value = evaluate(bound, self.now);
+        match op {
+            BinaryOperator::GtEq | BinaryOperator::Gt => self.lower.push(value.map(|v| v.date())),
+            BinaryOperator::LtEq => self.upper.push(value.map(|v| v.date())),
+            BinaryOperator::Lt => self.upper.push(value.map(|v| (v - Duration::microseconds(1)).date())),
+            BinaryOperator::Eq => {
+                self.lower.push(value.map(|v| v.date()));
+                self.upper.push(value.map(|v| v.date()));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Visitor for BoundCollector {
+    type Break = ();
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        match expr {
+            Expr::BinaryOp { left, op, right } if is_timestamp(left) => self.add(op, right),
+            // '2024-01-01' <= transaction_timestamp
+            Expr::BinaryOp { left, op, right } if is_timestamp(right) => {
+                let flipped = match op {
+                    BinaryOperator::Gt => BinaryOperator::Lt,
+                    BinaryOperator::GtEq => BinaryOperator::LtEq,
+                    BinaryOperator::Lt => BinaryOperator::Gt,
+                    BinaryOperator::LtEq => BinaryOperator::GtEq,
+                    op => op.clone(),
+                };
+                self.add(&flipped, left);
+            }
+            Expr::Between { expr, negated: false, low, high } if is_timestamp(expr) => {
+                self.add(&BinaryOperator::GtEq, low);
+                self.add(&BinaryOperator::LtEq, high);
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// `transaction_timestamp`, в том числе с приведением типа и внутри `DATE(...)`.
+/// `EXTRACT(YEAR FROM transaction_timestamp)` - не граница даты.
+fn is_timestamp(expr: &Expr) -> bool {
+    match expr {
+        Expr::Identifier(ident) => ident.value.eq_ignore_ascii_case(TIMESTAMP_COLUMN),
+        Expr::CompoundIdentifier(parts) => parts.last().is_some_and(|i| i.value.eq_ignore_ascii_case(TIMESTAMP_COLUMN)),
+        Expr::Cast { expr, .. } | Expr::Nested(expr) => is_timestamp(expr),
+        Expr::Function(function) if function_name(function).as_deref() == Some("date") => match &function.args {
+            FunctionArguments::List(list) => matches!(
+                list.args.as_slice(),
+                [FunctionArg::Unnamed(FunctionArgExpr::Expr(arg))] if is_timestamp(arg)
+            ),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Значение границы: литерал даты или времени, `CURRENT_DATE`/`NOW()` и арифметика с `INTERVAL`.
+/// Подзапросы и колонки не вычисляются.
+fn evaluate(expr: &Expr, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    match expr {
+        Expr::Value(sqlparser::ast::Value::SingleQuotedString(s)) | Expr::TypedString { value: s, .. } => parse_datetime(s),
+        Expr::Cast { expr, .. } | Expr::Nested(expr) => evaluate(expr, now),
+        Expr::Function(function) => match function_name(function)?.as_str() {
+            "current_date" => Some(now.date().and_hms_opt(0, 0, 0)?),
+            "current_timestamp" | "now" | "localtimestamp" => Some(now),
+            _ => None,
+        },
+        Expr::BinaryOp { left, op: op @ (BinaryOperator::Plus | BinaryOperator::Minus), right } => {
+            let Expr::Interval(interval) = right.as_ref() else {
+                return None;
+            };
+            let mut text = match interval.value.as_ref() {
+                Expr::Value(sqlparser::ast::Value::SingleQuotedString(s)) => s.clone(),
+                _ => return None,
+            };
+            // INTERVAL '7' DAY
+            if let Some(field) = &interval.leading_field {
+                text = format!("{} {}", text, field);
+            }
+            let base = evaluate(left, now)?;
+            let (months, duration) = parse_interval(&text)?;
+            match op {
+                BinaryOperator::Plus => base.checked_add_months(Months::new(months))?.checked_add_signed(duration),
+                _ => base.checked_sub_months(Months::new(months))?.checked_sub_signed(duration),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// `'2024-01-01'`, `'2024-01-01 10:00:00'`, `'2024-01-01T10:00:00+00:00'`
+fn parse_datetime(s: &str) -> Option<NaiveDateTime> {
+    let date: NaiveDate = s.get(..10)?.parse().ok()?;
+    match s[10..].chars().next() {
+        None => date.and_hms_opt(0, 0, 0),
+        Some('T' | ' ') => DateTime::parse_from_rfc3339(s).map(|d| d.naive_utc()).ok()
+            .or_else(|| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").ok())
+            .or_else(|| s.parse::<NaiveDateTime>().ok())
+            .or_else(|| date.and_hms_opt(0, 0, 0)),
+        Some(_) => None,
+    }
+}
+
+/// `'7 days'`, `'1 month'`, `'2 weeks 3 days'` - месяцы отдельно от фиксированной длительности
+fn parse_interval(text: &str) -> Option<(u32, Duration)> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.is_empty() || !words.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut months = 0u32;
+    let mut duration = Duration::zero();
+    for pair in words.chunks(2) {
+        let n: u32 = pair[0].parse().ok()?;
+        let unit = pair[1].to_ascii_lowercase();
+        match unit.trim_end_matches('s') {
+            "year" => months += n * 12,
+            "month" | "mon" => months += n,
+            "week" => duration += Duration::weeks(n.into()),
+            "day" => duration += Duration::days(n.into()),
+            "hour" => duration += Duration::hours(n.into()),
+            "minute" | "min" => duration += Duration::minutes(n.into()),
+            "second" | "sec" => duration += Duration::seconds(n.into()),
+            _ => return None,
+        }
+    }
+    Some((months, duration))
+}
+
+/// Минимальная и максимальная даты среди значений строк результата
+fn row_dates(rows: &[Value]) -> (Option<NaiveDate>, Option<NaiveDate>) {
+    let dates = rows.iter()
+        .filter_map(Value::as_object)
+        .flat_map(|row| row.values())
+        .filter_map(Value::as_str)
+        .filter_map(|s| parse_datetime(s).map(|d| d.date()));
+    dates.fold((None, None), |(min, max), d| {
+        (Some(min.map_or(d, |m: NaiveDate| m.min(d))), Some(max.map_or(d, |m: NaiveDate| m.max(d))))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn now() -> NaiveDateTime {
+        "2024-06-15T12:00:00".parse().unwrap()
+    }
+
+    fn date(s: &str) -> Option<NaiveDate> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_literal_bounds() {
+        let bounds = extract_timestamp_bounds(
+            "SELECT COUNT(*) FROM transactions WHERE transaction_timestamp >= '2024-01-01' AND transaction_timestamp < '2024-02-01';",
+            now(),
+        );
+        assert_eq!(bounds, TimestampBounds { filtered: true, from: date("2024-01-01"), to: date("2024-01-31") });
+    }
+
+    #[test]
+    fn test_relative_bound() {
+        let bounds = extract_timestamp_bounds(
+            "SELECT DATE_TRUNC('day', transaction_timestamp) AS day, COUNT(*) FROM transactions \
+             WHERE transaction_timestamp >= CURRENT_DATE - INTERVAL '7 days' GROUP BY 1 ORDER BY 1;",
+            now(),
+        );
+        assert_eq!(bounds, TimestampBounds { filtered: true, from: date("2024-06-08"), to: None });
+
+        let bounds = extract_timestamp_bounds(
+            "SELECT COUNT(*) FROM transactions WHERE transaction_timestamp > NOW() - INTERVAL '1 month';",
+            now(),
+        );
+        assert_eq!(bounds.from, date("2024-05-15"));
+
+        // Граница зависит от данных - ее дают строки результата
+        let bounds = extract_timestamp_bounds(
+            "SELECT COUNT(*) FROM transactions \
+             WHERE transaction_timestamp >= (SELECT MAX(transaction_timestamp) FROM transactions) - INTERVAL '14 days';",
+            now(),
+        );
+        assert_eq!(bounds, TimestampBounds { filtered: true, from: None, to: None });
+    }
+
+    #[test]
+    fn test_between_and_casts() {
+        let bounds = extract_timestamp_bounds(
+            "SELECT SUM(transaction_amount_kzt) FROM transactions \
+             WHERE DATE(transaction_timestamp) BETWEEN '2024-03-01' AND '2024-03-31' AND merchant_city = 'Almaty';",
+            now(),
+        );
+        assert_eq!((bounds.from, bounds.to), (date("2024-03-01"), date("2024-03-31")));
+
+        let bounds = extract_timestamp_bounds(
+            "SELECT COUNT(*) FROM transactions WHERE transaction_timestamp::date = DATE '2024-05-09';",
+            now(),
+        );
+        assert_eq!((bounds.from, bounds.to), (date("2024-05-09"), date("2024-05-09")));
+
+        // Несколько периодов через OR - итог их покрывает
+        let bounds = extract_timestamp_bounds(
+            "SELECT COUNT(*) FROM transactions WHERE '2024-01-01' <= transaction_timestamp AND transaction_timestamp < '2024-02-01' \
+             OR transaction_timestamp BETWEEN '2023-01-01' AND '2023-01-31';",
+            now(),
+        );
+        assert_eq!((bounds.from, bounds.to), (date("2023-01-01"), date("2024-01-31")));
+    }
+
+    #[test]
+    fn test_no_filter() {
+        let sql = "SELECT DATE_TRUNC('month', transaction_timestamp) AS month, COUNT(*) FROM transactions GROUP BY 1;";
+        assert_eq!(extract_timestamp_bounds(sql, now()), TimestampBounds::default());
+
+        let bounds = extract_timestamp_bounds(
+            "SELECT COUNT(*) FROM transactions WHERE EXTRACT(YEAR FROM transaction_timestamp) = 2024;",
+            now(),
+        );
+        assert_eq!(bounds, TimestampBounds::default());
+    }
+
+    #[test]
+    fn test_range_from_rows() {
+        let sql = "SELECT DATE_TRUNC('month', transaction_timestamp) AS month, COUNT(*) AS cnt FROM transactions \
+                   WHERE transaction_timestamp >= '2023-12-15' GROUP BY 1;";
+        let rows = vec![
+            json!({"month": "2024-01-01T00:00:00+00:00", "cnt": 10, "city": "Almaty"}),
+            json!({"month": "2024-03-01T00:00:00+00:00", "cnt": 7, "city": "2024"}),
+        ];
+        let range = resolve_date_range_at(sql, &rows, now()).unwrap();
+        assert_eq!((range.from, range.to, range.filtered), (date("2024-01-01"), date("2024-03-01"), true));
+
+        let range = resolve_date_range_at("SELECT MIN(transaction_timestamp)::date AS first_day FROM transactions;", &[json!({"first_day": "2023-01-02"})], now()).unwrap();
+        assert_eq!((range.from, range.to, range.filtered), (date("2023-01-02"), date("2023-01-02"), false));
+
+        assert!(resolve_date_range_at("SELECT merchant_city, COUNT(*) FROM transactions GROUP BY 1;", &[json!({"merchant_city": "Almaty"})], now()).is_none());
+    }
+}
@@ -1,4 +1,6 @@
+pub mod date_range;
 pub mod pool;
 pub mod queries;
 pub mod mock_data;
+pub mod schema;
 
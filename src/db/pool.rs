@@ -1,10 +1,15 @@
 use anyhow::Result;
-use sqlx::{PgPool, Pool, Postgres};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Pool, Postgres};
+use std::str::FromStr;
 
 pub type DbPool = Pool<Postgres>;
 
-pub async fn create_pool(database_url: &str) -> Result<DbPool> {
-    let pool = PgPool::connect(database_url).await?;
+/// `statement_timeout_ms` - лимит времени одного запроса на стороне PostgreSQL
+/// (превышение возвращается клиенту как `query_timeout`)
+pub async fn create_pool(database_url: &str, statement_timeout_ms: u64) -> Result<DbPool> {
+    let options = PgConnectOptions::from_str(database_url)?
+        .options([("statement_timeout", statement_timeout_ms.to_string())]);
+    let pool = PgPoolOptions::new().connect_with(options).await?;
     Ok(pool)
 }
-
@@ -1,10 +1,29 @@
-use crate::error::AppError;
-use sqlx::{PgPool, Row, Column, TypeInfo};
+use crate::{error::AppError, tenants::Tenant};
+use sqlx::{postgres::PgRow, PgPool, Row, Column, TypeInfo};
 
-pub async fn execute_query(pool: &PgPool, sql: &str) -> Result<Vec<serde_json::Value>, AppError> {
-    // Execute query and get rows
-    let rows = sqlx::query(sql).fetch_all(pool).await?;
-    
+/// Роль, под которой выполняется SQL пользователей и модели (migrations/007_query_role.sql)
+const QUERY_ROLE: &str = "analytics_reader";
+
+/// Выполняет SQL пользователя или модели в транзакции READ ONLY под ролью QUERY_ROLE
+/// (только SELECT на `transactions`): валидатор - не единственная защита служебных таблиц.
+/// Строки арендатора отбирает политика RLS `tenant_isolation` (migrations/008_tenant_rls.sql),
+/// без арендатора видны все банки.
+#[tracing::instrument(name = "db.execute", skip_all, fields(rows = tracing::field::Empty))]
+pub async fn execute_user_query(pool: &PgPool, sql: &str, tenant: Option<&Tenant>) -> Result<Vec<serde_json::Value>, AppError> {
+    let issuer_banks = tenant.map_or_else(|| "*".to_string(), Tenant::issuer_banks_setting);
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION READ ONLY").execute(&mut *tx).await?;
+    sqlx::query("SELECT set_config('app.issuer_banks', $1, true)").bind(issuer_banks).execute(&mut *tx).await?;
+    sqlx::query(&format!("SET LOCAL ROLE {}", QUERY_ROLE)).execute(&mut *tx).await?;
+    let rows = sqlx::query(sql).fetch_all(&mut *tx).await?;
+    tx.rollback().await?;
+
+    let results = rows_to_json(rows);
+    tracing::Span::current().record("rows", results.len());
+    Ok(results)
+}
+
+fn rows_to_json(rows: Vec<PgRow>) -> Vec<serde_json::Value> {
     // Convert rows to JSON using a simpler approach
     let mut results = Vec::new();
     for row in rows {
@@ -63,6 +82,13 @@ pub async fn execute_query(pool: &PgPool, sql: &str) -> Result<Vec<serde_json::V
                         serde_json::Value::Null
                     }
                 }
+                "DATE" => {
+                    if let Ok(v) = row.try_get::<chrono::NaiveDate, _>(column_name) {
+                        serde_json::Value::String(v.to_string())
+                    } else {
+                        serde_json::Value::Null
+                    }
+                }
                 "TIMESTAMP" | "TIMESTAMPTZ" => {
                     if let Ok(v) = row.try_get::<chrono::DateTime<chrono::Utc>, _>(column_name) {
                         serde_json::Value::String(v.to_rfc3339())
@@ -85,7 +111,7 @@ pub async fn execute_query(pool: &PgPool, sql: &str) -> Result<Vec<serde_json::V
         }
         results.push(serde_json::Value::Object(map));
     }
-    
-    Ok(results)
+
+    results
 }
 
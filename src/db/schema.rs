@@ -0,0 +1,24 @@
+use anyhow::Result;
+use sqlx::{Executor, PgPool};
+
+/// Служебные таблицы, которые нужны и для уже существующей базы
+/// (основные миграции запускаются только если нет таблицы `transactions`).
+/// Скрипты идемпотентны, поэтому выполняются при каждом старте.
+const AUXILIARY_SCHEMA: [(&str, &str); 7] = [
+    ("002_api_keys", include_str!("../../migrations/002_api_keys.sql")),
+    ("003_api_key_roles", include_str!("../../migrations/003_api_key_roles.sql")),
+    ("004_llm_usage", include_str!("../../migrations/004_llm_usage.sql")),
+    ("005_tenants", include_str!("../../migrations/005_tenants.sql")),
+    ("006_user_safety", include_str!("../../migrations/006_user_safety.sql")),
+    ("007_query_role", include_str!("../../migrations/007_query_role.sql")),
+    ("008_tenant_rls", include_str!("../../migrations/008_tenant_rls.sql")),
+];
+
+pub async fn ensure_auxiliary_tables(pool: &PgPool) -> Result<()> {
+    for (name, sql) in AUXILIARY_SCHEMA {
+        pool.execute(sql).await
+            .map_err(|e| anyhow::anyhow!("Failed to apply {}: {}", name, e))?;
+        tracing::debug!("Schema {} is up to date", name);
+    }
+    Ok(())
+}
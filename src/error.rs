@@ -1,56 +1,270 @@
+use crate::i18n;
+use crate::limits::quota;
+use crate::llm::error::{LlmError, SqlRejectionKind};
+use crate::utils::language::{response_language, Language};
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use serde::Serialize;
 use serde_json::json;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum AppError {
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
-    
+
     #[error("LLM error: {0}")]
     LLM(#[from] anyhow::Error),
-    
+
     #[error("Invalid SQL query: {0}")]
     #[allow(dead_code)]
     InvalidSQL(String),
-    
+
     #[error("Configuration error: {0}")]
     #[allow(dead_code)]
     Config(String),
-    
+
     #[error("{0}")]
     BadRequest(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Policy violation: {0}")]
+    PolicyViolation(String),
+
+    #[error("User banned: {message}")]
+    UserBanned { message: String, retry_after_secs: u64 },
+
+    #[error("Rate limited: {message}")]
+    RateLimited { message: String, retry_after_secs: u64 },
+
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+}
+
+/// Стабильные машиночитаемые коды ошибок (поле `code` в JSON-ответе).
+/// Значения не переименовывать - на них завязаны клиенты.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    PolicyViolation,
+    UserBanned,
+    RateLimited,
+    QuotaExceeded,
+    SqlRejected,
+    QueryTooExpensive,
+    LlmTimeout,
+    LlmUnavailable,
+    LlmRateLimited,
+    LlmError,
+    QueryTimeout,
+    DatabaseUnavailable,
+    DatabaseError,
+    ConfigError,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest | ErrorCode::PolicyViolation => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden | ErrorCode::UserBanned => StatusCode::FORBIDDEN,
+            ErrorCode::RateLimited | ErrorCode::QuotaExceeded | ErrorCode::LlmRateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::SqlRejected | ErrorCode::QueryTooExpensive => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::LlmTimeout | ErrorCode::QueryTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCode::LlmUnavailable | ErrorCode::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::LlmError => StatusCode::BAD_GATEWAY,
+            ErrorCode::DatabaseError | ErrorCode::ConfigError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Значение поля `code` (совпадает с serde-представлением)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::PolicyViolation => "policy_violation",
+            ErrorCode::UserBanned => "user_banned",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::QuotaExceeded => "quota_exceeded",
+            ErrorCode::SqlRejected => "sql_rejected",
+            ErrorCode::QueryTooExpensive => "query_too_expensive",
+            ErrorCode::LlmTimeout => "llm_timeout",
+            ErrorCode::LlmUnavailable => "llm_unavailable",
+            ErrorCode::LlmRateLimited => "llm_rate_limited",
+            ErrorCode::LlmError => "llm_error",
+            ErrorCode::QueryTimeout => "query_timeout",
+            ErrorCode::DatabaseUnavailable => "database_unavailable",
+            ErrorCode::DatabaseError => "database_error",
+            ErrorCode::ConfigError => "config_error",
+        }
+    }
+
+    /// Сообщение для пользователя из каталога `locales/*.toml` (ключ `error.<code>`)
+    pub fn localized_message(&self, language: Language) -> &'static str {
+        i18n::t(language, &format!("error.{}", self.as_str()))
+    }
+}
+
+/// Повтор после недоступности или лимита LLM, если провайдер не подсказал срок
+const LLM_RETRY_AFTER_SECS: u64 = 30;
+
+/// PostgreSQL: query_canceled (в том числе по statement_timeout)
+const PG_QUERY_CANCELED: &str = "57014";
+
+fn database_code(error: &sqlx::Error) -> ErrorCode {
+    match error {
+        sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => ErrorCode::DatabaseUnavailable,
+        sqlx::Error::Database(db) if db.code().as_deref() == Some(PG_QUERY_CANCELED) => ErrorCode::QueryTimeout,
+        _ => ErrorCode::DatabaseError,
+    }
+}
+
+impl AppError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::Database(e) => database_code(e),
+            AppError::LLM(e) => match e.downcast_ref::<LlmError>() {
+                Some(LlmError::Timeout { .. }) => ErrorCode::LlmTimeout,
+                Some(LlmError::Unavailable { .. }) => ErrorCode::LlmUnavailable,
+                Some(LlmError::RateLimited { .. }) => ErrorCode::LlmRateLimited,
+                Some(LlmError::SqlRejected(rejection)) if rejection.kind == SqlRejectionKind::TooExpensive => {
+                    ErrorCode::QueryTooExpensive
+                }
+                Some(LlmError::SqlRejected(_)) => ErrorCode::SqlRejected,
+                Some(LlmError::QuotaExceeded(_)) => ErrorCode::QuotaExceeded,
+                Some(LlmError::Provider { .. }) => ErrorCode::LlmError,
+                // Резервирование квоты перед вызовом модели идет через БД
+                None => e.downcast_ref::<sqlx::Error>().map_or(ErrorCode::LlmError, database_code),
+            },
+            AppError::InvalidSQL(_) => ErrorCode::SqlRejected,
+            AppError::Config(_) => ErrorCode::ConfigError,
+            AppError::BadRequest(_) => ErrorCode::BadRequest,
+            AppError::Unauthorized(_) => ErrorCode::Unauthorized,
+            AppError::Forbidden(_) => ErrorCode::Forbidden,
+            AppError::PolicyViolation(_) => ErrorCode::PolicyViolation,
+            AppError::UserBanned { .. } => ErrorCode::UserBanned,
+            AppError::RateLimited { .. } => ErrorCode::RateLimited,
+            AppError::QuotaExceeded(_) => ErrorCode::QuotaExceeded,
+        }
+    }
+
+    /// Текст поля `error` - конкретная причина ошибки. Ошибки БД и конфигурации не раскрываются
+    /// (схема, SQL), ответ провайдера LLM остается только в логах.
+    pub fn client_message(&self, language: Language) -> String {
+        match self {
+            AppError::Database(_) | AppError::Config(_) => self.code().localized_message(language).to_string(),
+            AppError::LLM(e) => match e.downcast_ref::<LlmError>() {
+                Some(llm_error) => llm_error.public_message(),
+                None => self.code().localized_message(language).to_string(),
+            },
+            AppError::InvalidSQL(msg)
+            | AppError::BadRequest(msg)
+            | AppError::Unauthorized(msg)
+            | AppError::Forbidden(msg)
+            | AppError::PolicyViolation(msg)
+            | AppError::QuotaExceeded(msg) => msg.clone(),
+            AppError::UserBanned { message, .. } | AppError::RateLimited { message, .. } => message.clone(),
+        }
+    }
+
+    /// Через сколько секунд имеет смысл повторить запрос
+    pub fn retry_after_secs(&self) -> Option<u64> {
+        if let AppError::LLM(e) = self {
+            if let Some(LlmError::RateLimited { retry_after_secs, .. }) = e.downcast_ref::<LlmError>() {
+                return Some(retry_after_secs.unwrap_or(LLM_RETRY_AFTER_SECS));
+            }
+        }
+        match self {
+            AppError::UserBanned { retry_after_secs, .. }
+            | AppError::RateLimited { retry_after_secs, .. } => Some(*retry_after_secs),
+            _ => match self.code() {
+                ErrorCode::QuotaExceeded => Some(quota::secs_until_reset()),
+                ErrorCode::LlmUnavailable => Some(LLM_RETRY_AFTER_SECS),
+                ErrorCode::DatabaseUnavailable => Some(5),
+                _ => None,
+            },
+        }
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AppError::Database(e) => {
-                tracing::error!("Database error: {:?}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
-            }
-            AppError::LLM(e) => {
-                tracing::error!("LLM error: {:?}", e);
-                (StatusCode::BAD_REQUEST, e.to_string())
-            }
-            AppError::InvalidSQL(msg) => {
-                (StatusCode::BAD_REQUEST, msg)
-            }
-            AppError::Config(msg) => {
-                (StatusCode::INTERNAL_SERVER_ERROR, msg)
-            }
-            AppError::BadRequest(msg) => {
-                (StatusCode::BAD_REQUEST, msg)
-            }
-        };
+        let code = self.code();
+        match code.status() {
+            status if status.is_server_error() => tracing::error!(code = ?code, "Request failed: {:?}", self),
+            _ => tracing::warn!(code = ?code, "Request rejected: {}", self),
+        }
+
+        let language = response_language();
+        let retry_after = self.retry_after_secs();
         let body = Json(json!({
-            "error": error_message,
+            "error": self.client_message(language),
+            "code": code,
+            "message": code.localized_message(language),
+            "retry_after_secs": retry_after,
         }));
-        (status, body).into_response()
+
+        let mut response = (code.status(), body).into_response();
+        if code == ErrorCode::Unauthorized {
+            response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        if let Some(secs) = retry_after {
+            if let Ok(value) = HeaderValue::from_str(&secs.to_string()) {
+                response.headers_mut().insert(header::RETRY_AFTER, value);
+            }
+        }
+        response
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body(error: AppError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_error_body() {
+        let (status, json) = body(AppError::BadRequest("Period 2024-02-01..2024-01-01 ends before it starts".to_string())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json["error"], "Period 2024-02-01..2024-01-01 ends before it starts");
+        assert_eq!(json["code"], "bad_request");
+        assert!(json["message"].is_string());
+
+        // Текст ответа провайдера клиенту не отдается
+        let provider_error = LlmError::Provider { provider: "OpenAI".to_string(), message: "sk-... invalid api key".to_string() };
+        let (status, json) = body(AppError::LLM(provider_error.into())).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(json["error"], "OpenAI API error");
+        assert!(!json.to_string().contains("sk-"));
+
+        let rate_limited = LlmError::RateLimited {
+            provider: "OpenAI".to_string(),
+            message: "Rate limit reached. Please try again in 20s.".to_string(),
+            retry_after_secs: Some(20),
+        };
+        let (status, json) = body(AppError::LLM(rate_limited.into())).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(json["code"], "llm_rate_limited");
+        assert_eq!(json["retry_after_secs"], 20);
+        assert!(!json.to_string().contains("Rate limit reached"));
+    }
+}
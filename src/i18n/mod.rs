@@ -0,0 +1,117 @@
+use crate::utils::language::Language;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::LazyLock;
+
+/// Каталоги сообщений по локалям (встроены в бинарник)
+const LOCALES: [(Language, &str); 5] = [
+    (Language::Russian, include_str!("../../locales/ru.toml")),
+    (Language::English, include_str!("../../locales/en.toml")),
+    (Language::Kazakh, include_str!("../../locales/kk.toml")),
+    (Language::Uzbek, include_str!("../../locales/uz.toml")),
+    (Language::Kyrgyz, include_str!("../../locales/ky.toml")),
+];
+
+/// Язык, тексты которого используются, если в каталоге локали нет ключа
+const FALLBACK_LANGUAGE: Language = Language::Russian;
+
+/// Ключ вида `section.name` -> текст
+type Messages = HashMap<String, String>;
+
+static CATALOG: LazyLock<HashMap<Language, Messages>> = LazyLock::new(|| {
+    LOCALES.iter()
+        .map(|(language, source)| {
+            let table: toml::Table = toml::from_str(source)
+                .unwrap_or_else(|e| panic!("Invalid message catalog for {:?}: {}", language, e));
+            let mut messages = Messages::new();
+            flatten("", &table, &mut messages);
+            (*language, messages)
+        })
+        .collect()
+});
+
+/// Вложенные таблицы TOML -> ключи через точку
+fn flatten(prefix: &str, table: &toml::Table, out: &mut Messages) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        match value {
+            toml::Value::Table(nested) => flatten(&key, nested, out),
+            toml::Value::String(text) => {
+                out.insert(key, text.clone());
+            }
+            other => tracing::warn!("Message catalog: key {} is not a string: {}", key, other),
+        }
+    }
+}
+
+/// Текст по ключу, если он есть в каталоге языка
+pub fn lookup(language: Language, key: &str) -> Option<&'static str> {
+    CATALOG.get(&language)?.get(key).map(String::as_str)
+}
+
+/// Текст по ключу на языке ответа (при отсутствии ключа - на русском)
+pub fn t(language: Language, key: &str) -> &'static str {
+    lookup(language, key)
+        .or_else(|| lookup(FALLBACK_LANGUAGE, key))
+        .unwrap_or_else(|| {
+            tracing::warn!("Message catalog: missing key {}", key);
+            ""
+        })
+}
+
+/// Текст с подстановками `{имя}`
+pub fn tf(language: Language, key: &str, args: &[(&str, &dyn Display)]) -> String {
+    args.iter().fold(t(language, key).to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{}}}", name), &value.to_string())
+    })
+}
+
+/// Подпись колонки результата для заголовков таблиц и диаграмм; неизвестные колонки - как есть
+pub fn column_label(language: Language, column: &str) -> String {
+    lookup(language, &format!("column.{}", column.to_lowercase()))
+        .map(str::to_string)
+        .unwrap_or_else(|| column.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_locales_have_same_keys() {
+        let keys = |language: Language| CATALOG[&language].keys().cloned().collect::<BTreeSet<_>>();
+        let reference = keys(FALLBACK_LANGUAGE);
+        assert!(!reference.is_empty());
+        for (language, _) in LOCALES {
+            let current = keys(language);
+            assert_eq!(
+                reference.symmetric_difference(&current).collect::<Vec<_>>(),
+                Vec::<&String>::new(),
+                "{:?} catalog differs from {:?}", language, FALLBACK_LANGUAGE
+            );
+            // Подстановки во всех локалях одинаковые
+            for key in &reference {
+                let placeholders = |text: &str| text.split('{').skip(1)
+                    .filter_map(|part| part.split_once('}').map(|(name, _)| name.to_string()))
+                    .collect::<BTreeSet<_>>();
+                assert_eq!(
+                    placeholders(&CATALOG[&language][key]),
+                    placeholders(&CATALOG[&FALLBACK_LANGUAGE][key]),
+                    "{:?}: placeholders of {} differ", language, key
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(
+            tf(Language::Kazakh, "analysis.found_records_for", &[("count", &3), ("category", &"POS")]),
+            "POS үшін 3 жазба табылды"
+        );
+        assert_eq!(t(Language::English, "error.user_banned"), "You are temporarily blocked.");
+        assert_eq!(column_label(Language::English, "merchant_city"), "City");
+        assert_eq!(column_label(Language::English, "my_alias"), "my_alias");
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{
+    auth::Principal,
+    error::AppError,
+    limits::quota::{self, Quotas},
+    llm::usage::{with_llm_quota, LlmBudget, QuotaAccount},
+    state::AppState,
+};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use std::net::SocketAddr;
+
+/// Ограничение частоты запросов по IP. Стоит до аутентификации,
+/// чтобы ограничивать и перебор ключей.
+pub async fn ip_rate_limit(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if let Some(ConnectInfo(addr)) = request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        let ip = addr.ip().to_string();
+        if let Err(retry_after_secs) = state.ip_rate_limiter.check(&ip).await {
+            tracing::warn!("Rate limit exceeded for IP {}", ip);
+            return Err(AppError::RateLimited {
+                message: format!("Too many requests from {}", ip),
+                retry_after_secs,
+            });
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Ограничение частоты запросов и дневные квоты LLM для аутентифицированного принципала.
+/// Вызовы и токены LLM резервируются и учитываются в `llm_usage_daily` при каждом обращении к модели (`llm::usage`).
+pub async fn principal_limits(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    // Без аутентификации все запросы от одного "anonymous" - действует только лимит по IP;
+    // расход LLM все равно считается (по нему ограничен бюджет токенов агента)
+    if !principal.is_authenticated() {
+        let (response, _) = with_llm_quota(None, next.run(request)).await;
+        return Ok(response);
+    }
+
+    if let Err(retry_after_secs) = state.rate_limiter.check(&principal.id).await {
+        tracing::warn!("Rate limit exceeded for principal {}", principal.id);
+        if let Err(e) = quota::record_request(&state.db, &principal.id, true).await {
+            tracing::warn!("Failed to record usage for {}: {}", principal.id, e);
+        }
+        return Err(AppError::RateLimited {
+            message: format!("Rate limit exceeded for {}", principal.id),
+            retry_after_secs,
+        });
+    }
+
+    let quotas = Quotas::from_config(&state.config);
+    let budget = if quotas.daily_llm_calls.is_some() || quotas.daily_llm_tokens.is_some() {
+        let today = quota::usage_for_day(&state.db, &principal.id, quota::today()).await?;
+        quotas.budget(&today)
+    } else {
+        LlmBudget::default()
+    };
+    // Быстрый отказ без выполнения запроса; гарантию дает резервирование перед каждым вызовом модели
+    if budget.is_exhausted() {
+        return Err(AppError::QuotaExceeded(format!("Daily LLM quota exceeded for {}", principal.id)));
+    }
+
+    let account = QuotaAccount {
+        pool: state.db.clone(),
+        user_id: principal.id.clone(),
+        quotas,
+    };
+    let (response, used) = with_llm_quota(Some(account), next.run(request)).await;
+    if let Err(e) = quota::record_request(&state.db, &principal.id, false).await {
+        tracing::warn!("Failed to record usage for {}: {}", principal.id, e);
+    }
+    tracing::debug!("LLM usage for {}: {} calls, {} tokens", principal.id, used.calls, used.tokens);
+
+    Ok(response)
+}
@@ -0,0 +1,5 @@
+pub mod middleware;
+pub mod quota;
+pub mod rate_limiter;
+
+pub use rate_limiter::RateLimiter;
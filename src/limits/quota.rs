@@ -0,0 +1,171 @@
+use crate::{config::Config, llm::usage::LlmBudget};
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+
+/// Счетчики пользователя за день (таблица `llm_usage_daily`)
+#[derive(Debug, Clone, Default, Serialize, sqlx::FromRow)]
+pub struct DailyUsage {
+    pub user_id: String,
+    pub day: NaiveDate,
+    pub requests: i64,
+    pub rate_limited: i64,
+    pub llm_calls: i64,
+    pub llm_tokens: i64,
+}
+
+/// Дневные квоты из конфигурации (None - без ограничения)
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Quotas {
+    pub daily_llm_calls: Option<u64>,
+    pub daily_llm_tokens: Option<u64>,
+}
+
+impl Quotas {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            daily_llm_calls: (config.daily_llm_call_quota > 0).then_some(config.daily_llm_call_quota),
+            daily_llm_tokens: (config.daily_llm_token_quota > 0).then_some(config.daily_llm_token_quota),
+        }
+    }
+
+    /// Остаток квоты с учетом уже потраченного за день
+    pub fn budget(&self, usage: &DailyUsage) -> LlmBudget {
+        LlmBudget {
+            remaining_calls: self.daily_llm_calls.map(|q| q.saturating_sub(usage.llm_calls.max(0) as u64)),
+            remaining_tokens: self.daily_llm_tokens.map(|q| q.saturating_sub(usage.llm_tokens.max(0) as u64)),
+        }
+    }
+}
+
+/// Квоты считаются по дням UTC
+pub fn today() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+/// Секунд до начала следующего дня (UTC) - когда квота обновится
+pub fn secs_until_reset() -> u64 {
+    let now = Utc::now();
+    let next_day = (now.date_naive() + chrono::Duration::days(1))
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is valid")
+        .and_utc();
+    (next_day - now).num_seconds().max(1) as u64
+}
+
+pub async fn usage_for_day(pool: &PgPool, user_id: &str, day: NaiveDate) -> Result<DailyUsage, sqlx::Error> {
+    let usage = sqlx::query_as::<_, DailyUsage>(
+        r#"
+        SELECT user_id, day, requests, rate_limited, llm_calls, llm_tokens
+        FROM llm_usage_daily
+        WHERE user_id = $1 AND day = $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(day)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(usage.unwrap_or_else(|| DailyUsage {
+        user_id: user_id.to_string(),
+        day,
+        ..Default::default()
+    }))
+}
+
+/// Засчитывает HTTP-запрос (или отказ по частоте). Вызовы и токены LLM
+/// учитываются отдельно, при каждом обращении к модели.
+pub async fn record_request(pool: &PgPool, user_id: &str, rate_limited: bool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO llm_usage_daily (user_id, day, requests, rate_limited)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id, day) DO UPDATE SET
+            requests = llm_usage_daily.requests + EXCLUDED.requests,
+            rate_limited = llm_usage_daily.rate_limited + EXCLUDED.rate_limited,
+            updated_at = NOW()
+        "#,
+    )
+    .bind(user_id)
+    .bind(today())
+    .bind(if rate_limited { 0i64 } else { 1 })
+    .bind(if rate_limited { 1i64 } else { 0 })
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Резервирует один вызов LLM: счетчик увеличивается, только если квота вызовов не исчерпана
+/// и токены еще остались. Проверка и запись - одна команда под блокировкой строки,
+/// поэтому параллельные запросы не проходят сверх квоты. false - квота исчерпана.
+pub async fn reserve_llm_call(pool: &PgPool, user_id: &str, quotas: &Quotas) -> Result<bool, sqlx::Error> {
+    let reserved = sqlx::query_scalar::<_, i64>(
+        r#"
+        INSERT INTO llm_usage_daily (user_id, day, llm_calls)
+        VALUES ($1, $2, 1)
+        ON CONFLICT (user_id, day) DO UPDATE SET
+            llm_calls = llm_usage_daily.llm_calls + 1,
+            updated_at = NOW()
+        WHERE ($3::BIGINT IS NULL OR llm_usage_daily.llm_calls + 1 <= $3)
+          AND ($4::BIGINT IS NULL OR llm_usage_daily.llm_tokens < $4)
+        RETURNING llm_calls
+        "#,
+    )
+    .bind(user_id)
+    .bind(today())
+    .bind(quotas.daily_llm_calls.map(|q| q as i64))
+    .bind(quotas.daily_llm_tokens.map(|q| q as i64))
+    .fetch_optional(pool)
+    .await?;
+    Ok(reserved.is_some())
+}
+
+/// Добавляет токены ответа модели к дневному счетчику
+pub async fn record_llm_tokens(pool: &PgPool, user_id: &str, tokens: u64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO llm_usage_daily (user_id, day, llm_tokens)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, day) DO UPDATE SET
+            llm_tokens = llm_usage_daily.llm_tokens + EXCLUDED.llm_tokens,
+            updated_at = NOW()
+        "#,
+    )
+    .bind(user_id)
+    .bind(today())
+    .bind(tokens as i64)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// История пользователя за последние `days` дней (новые сверху)
+pub async fn user_history(pool: &PgPool, user_id: &str, days: i64) -> Result<Vec<DailyUsage>, sqlx::Error> {
+    sqlx::query_as::<_, DailyUsage>(
+        r#"
+        SELECT user_id, day, requests, rate_limited, llm_calls, llm_tokens
+        FROM llm_usage_daily
+        WHERE user_id = $1 AND day > $2
+        ORDER BY day DESC
+        "#,
+    )
+    .bind(user_id)
+    .bind(today() - chrono::Duration::days(days))
+    .fetch_all(pool)
+    .await
+}
+
+/// Расход всех пользователей за день (больше всего токенов - сверху)
+pub async fn usage_by_user(pool: &PgPool, day: NaiveDate) -> Result<Vec<DailyUsage>, sqlx::Error> {
+    sqlx::query_as::<_, DailyUsage>(
+        r#"
+        SELECT user_id, day, requests, rate_limited, llm_calls, llm_tokens
+        FROM llm_usage_daily
+        WHERE day = $1
+        ORDER BY llm_tokens DESC, llm_calls DESC
+        "#,
+    )
+    .bind(day)
+    .fetch_all(pool)
+    .await
+}
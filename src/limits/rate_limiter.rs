@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// Корзины, не использовавшиеся дольше этого времени, удаляются (они все равно полные)
+const IDLE_BUCKET_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token bucket по ключу (принципал или IP): `burst` запросов сразу,
+/// дальше - `per_minute` запросов в минуту.
+pub struct RateLimiter {
+    buckets: Arc<RwLock<HashMap<String, Bucket>>>,
+    capacity: f64,
+    refill_per_sec: f64,
+}
+
+impl RateLimiter {
+    /// `per_minute = 0` отключает ограничение
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            buckets: Arc::new(RwLock::new(HashMap::new())),
+            capacity: burst.max(1) as f64,
+            refill_per_sec: per_minute as f64 / 60.0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.refill_per_sec > 0.0
+    }
+
+    /// Забирает токен для ключа. Err - через сколько секунд появится следующий токен.
+    pub async fn check(&self, key: &str) -> Result<(), u64> {
+        self.check_at(key, Instant::now()).await
+    }
+
+    async fn check_at(&self, key: &str, now: Instant) -> Result<(), u64> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.write().await;
+        if buckets.len() > 10_000 {
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated_at) < IDLE_BUCKET_TTL);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.refill_per_sec;
+            Err(wait.ceil().max(1.0) as u64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_burst_then_refill() {
+        let limiter = RateLimiter::new(60, 3); // 1 запрос в секунду, burst 3
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at("user", start).await.is_ok());
+        }
+        assert_eq!(limiter.check_at("user", start).await, Err(1));
+
+        // Другие ключи не затронуты
+        assert!(limiter.check_at("other", start).await.is_ok());
+
+        // Через секунду появляется один токен
+        let later = start + Duration::from_secs(1);
+        assert!(limiter.check_at("user", later).await.is_ok());
+        assert!(limiter.check_at("user", later).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let limiter = RateLimiter::new(0, 1);
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert!(limiter.check_at("user", now).await.is_ok());
+        }
+    }
+}
@@ -3,15 +3,42 @@ use anyhow::Result;
 use rig::providers::ollama::{Client as OllamaClient, ClientBuilder};
 use rig::providers::gemini::Client as GeminiClient;
 use rig::completion::CompletionRequest;
-use rig::completion::request::CompletionModel;
 use rig::completion::message::AssistantContent;
-use rig::message::{Message, UserContent};
+use rig::message::{Message, ToolChoice, UserContent};
 use rig::one_or_many::OneOrMany;
 use rig::client::completion::CompletionClient;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use super::error::{complete, LlmError};
+use super::planner::parse_plan;
+use super::prompts::{SQL_PROMPT_VERSION, SQL_TOOL_PROMPT_VERSION};
+use super::sql_tool::{parse_structured_sql, parse_structured_text, submit_sql_tool, SqlGeneration, SqlMode, SUBMIT_SQL_TOOL};
+use crate::utils::question_classifier::{parse_model_classification, Classification};
 
 pub struct LLMClient {
     provider: LLMProvider,
+    timeout: Duration,
+    /// Небольшая модель для классификации намерения (None - основная модель)
+    intent_model: Option<String>,
+    /// Генерировать SQL через инструмент submit_sql (SQL_TOOL_CALLING)
+    sql_tool_calling: bool,
+    /// Провайдер отклонил запрос с инструментами - дальше только текстовый режим
+    tools_unsupported: AtomicBool,
+}
+
+/// Результат генерации SQL вместе с информацией о том, как он был получен
+#[derive(Debug, Clone)]
+pub struct GeneratedSql {
+    pub sql: String,
+    /// Сколько раз пришлось чинить ответ модели, чтобы он прошел валидацию
+    pub repair_attempts: u32,
+    /// Время, потраченное на валидацию (мс)
+    pub validate_ms: u64,
+    /// Версия промпта, по которому получен SQL
+    pub prompt_version: &'static str,
+    /// Инструмент или текст, и что модель сообщила о запросе
+    pub generation: SqlGeneration,
 }
 
 enum LLMProvider {
@@ -23,6 +50,7 @@ enum LLMProvider {
         // TODO: Реализовать позже если нужно
         #[allow(dead_code)]
         api_key: String,
+        model: String,
     },
     Gemini {
         client: Arc<GeminiClient>,
@@ -53,7 +81,7 @@ impl LLMClient {
             "openai" => {
                 let api_key = config.openai_api_key.clone()
                     .ok_or_else(|| anyhow::anyhow!("OPENAI_API_KEY not set"))?;
-                LLMProvider::OpenAI { api_key }
+                LLMProvider::OpenAI { api_key, model: config.openai_model.clone() }
             }
             "gemini" => {
                 let api_key = config.gemini_api_key.clone()
@@ -70,14 +98,47 @@ impl LLMClient {
             _ => return Err(anyhow::anyhow!("Unknown LLM provider: {}. Supported: ollama, openai, gemini", config.llm_provider)),
         };
         
-        Ok(Self { provider })
+        Ok(Self {
+            provider,
+            timeout: Duration::from_secs(config.llm_timeout_secs),
+            intent_model: config.intent_model.clone(),
+            sql_tool_calling: config.sql_tool_calling,
+            tools_unsupported: AtomicBool::new(false),
+        })
+    }
+    
+    /// Имя провайдера (для логов и трейсинга)
+    pub fn provider_name(&self) -> &'static str {
+        match &self.provider {
+            LLMProvider::Ollama { .. } => "ollama",
+            LLMProvider::OpenAI { .. } => "openai",
+            LLMProvider::Gemini { .. } => "gemini",
+        }
     }
     
+    /// Имя модели (для логов и трейсинга)
+    pub fn model_name(&self) -> &str {
+        match &self.provider {
+            LLMProvider::Ollama { model, .. } | LLMProvider::OpenAI { model, .. } | LLMProvider::Gemini { model, .. } => model,
+        }
+    }
+    
+    #[tracing::instrument(
+        name = "llm.generate_sql",
+        skip_all,
+        fields(provider = self.provider_name(), model = self.model_name())
+    )]
     pub async fn generate_sql(
         &self,
         question: &str,
         previous_queries: &[&crate::query_context::QueryContext],
-    ) -> Result<String> {
+    ) -> Result<GeneratedSql> {
+        if self.sql_tool_calling && !self.tools_unsupported.load(Ordering::Relaxed) {
+            if let Some(generated) = self.generate_sql_with_tool(question, previous_queries).await? {
+                return Ok(generated);
+            }
+        }
+        
         let prompt = super::prompts::build_sql_generation_prompt(question, previous_queries);
         
         let raw_response = match &self.provider {
@@ -94,41 +155,176 @@ impl LLMClient {
         };
         
         let cleaned = super::prompts::clean_sql_response(&raw_response);
-        
-        // Validate SQL - if validation fails, try to clean and retry once
-        match super::validator::validate_sql(&cleaned) {
-            Ok(_) => Ok(cleaned),
-            Err(e) => {
-                tracing::warn!("SQL validation failed: {}. Attempting to fix...", e);
-                
-                // Try to extract SELECT statement if LLM added extra text
-                let sql_upper = cleaned.to_uppercase();
-                if let Some(select_pos) = sql_upper.find("SELECT") {
-                    let extracted = &cleaned[select_pos..];
-                    // Find the last semicolon
-                    if let Some(semicolon_pos) = extracted.rfind(';') {
-                        let fixed = &extracted[..=semicolon_pos];
-                        match super::validator::validate_sql(fixed) {
-                            Ok(_) => {
-                                tracing::info!("Successfully fixed SQL by extracting SELECT statement");
-                                Ok(fixed.to_string())
-                            }
-                            Err(e2) => {
-                                tracing::error!("Failed to fix SQL: {}", e2);
-                                Err(anyhow::anyhow!("Invalid SQL generated: {}. Please rephrase your question.", e))
-                            }
-                        }
+        Ok(validate_generated(cleaned, SQL_PROMPT_VERSION, SqlGeneration::text())?)
+    }
+    
+    /// Генерация SQL через инструмент submit_sql. None - нужен текстовый режим:
+    /// провайдер не поддерживает инструменты или аргументы не прошли проверку по схеме.
+    async fn generate_sql_with_tool(
+        &self,
+        question: &str,
+        previous_queries: &[&crate::query_context::QueryContext],
+    ) -> Result<Option<GeneratedSql>> {
+        let prompt = super::prompts::build_sql_tool_prompt(question, previous_queries);
+        let choice = match self.call_with_tools(&prompt).await {
+            Ok(choice) => choice,
+            Err(e) => match e.downcast_ref::<LlmError>() {
+                // Ошибка API на запрос с инструментами - скорее всего модель их не поддерживает
+                Some(LlmError::Provider { message, .. }) => {
+                    let message = message.to_lowercase();
+                    if message.contains("tool") || message.contains("function") {
+                        tracing::warn!("Model does not support tool calling, switching to text SQL generation: {}", message);
+                        self.tools_unsupported.store(true, Ordering::Relaxed);
                     } else {
-                        Err(anyhow::anyhow!("Invalid SQL generated: {}. Please rephrase your question.", e))
+                        tracing::warn!("Tool-calling request failed, retrying in text mode: {}", message);
                     }
-                } else {
-                    Err(anyhow::anyhow!("Invalid SQL generated: {}. Please rephrase your question.", e))
+                    return Ok(None);
+                }
+                _ => return Err(e),
+            },
+        };
+        
+        let tool_call = choice.iter().find_map(|content| match content {
+            AssistantContent::ToolCall(call) if call.function.name == SUBMIT_SQL_TOOL => Some(&call.function.arguments),
+            _ => None,
+        });
+        let text = response_text(choice.clone());
+        let (structured, mode) = match tool_call {
+            Some(arguments) => match parse_structured_sql(arguments) {
+                Ok(structured) => (structured, SqlMode::Tool),
+                Err(e) => {
+                    tracing::warn!("submit_sql arguments rejected, retrying in text mode: {}", e);
+                    return Ok(None);
+                }
+            },
+            None => match parse_structured_text(&text) {
+                Some(structured) => (structured, SqlMode::Json),
+                // Модель ответила просто текстом - разбираем его как в текстовом режиме
+                None if !text.is_empty() => {
+                    let cleaned = super::prompts::clean_sql_response(&text);
+                    return Ok(Some(validate_generated(cleaned, SQL_TOOL_PROMPT_VERSION, SqlGeneration::text())?));
                 }
+                None => return Ok(None),
+            },
+        };
+        
+        let generation = SqlGeneration::structured(mode, &structured);
+        Ok(Some(validate_generated(structured.sql, SQL_TOOL_PROMPT_VERSION, generation)?))
+    }
+    
+    /// Запрос на генерацию SQL с инструментом submit_sql; ответ - как его вернул провайдер
+    async fn call_with_tools(&self, prompt: &str) -> Result<OneOrMany<AssistantContent>> {
+        let request = |tool_choice: Option<ToolChoice>, additional_params: Option<serde_json::Value>| CompletionRequest {
+            preamble: Some(
+                "You are an expert PostgreSQL database architect. Answer by calling the submit_sql tool."
+                    .to_string(),
+            ),
+            chat_history: OneOrMany::one(Message::User {
+                content: OneOrMany::one(UserContent::text(prompt)),
+            }),
+            documents: vec![],
+            tools: vec![submit_sql_tool()],
+            temperature: Some(0.1),
+            max_tokens: Some(1024),
+            tool_choice,
+            additional_params,
+        };
+        
+        match &self.provider {
+            LLMProvider::Ollama { client, model } => {
+                // Ollama не поддерживает tool_choice: модель сама решает, вызывать ли инструмент
+                let comp_model = client.as_ref().completion_model(model);
+                Ok(complete(&comp_model, request(None, None), self.timeout, "Ollama").await?.choice)
+            }
+            LLMProvider::OpenAI { .. } => {
+                Err(anyhow::anyhow!("OpenAI not implemented yet"))
+            }
+            LLMProvider::Gemini { client, model } => {
+                let comp_model = client.as_ref().completion_model(model);
+                let tool_choice = ToolChoice::Specific { function_names: vec![SUBMIT_SQL_TOOL.to_string()] };
+                let additional_params = serde_json::json!({
+                    "generationConfig": { "temperature": 0.1, "maxOutputTokens": 1024 }
+                });
+                Ok(complete(&comp_model, request(Some(tool_choice), Some(additional_params)), self.timeout, "Gemini").await?.choice)
+            }
+        }
+    }
+    
+    /// Классификация намерения небольшой моделью (когда ключевых слов недостаточно)
+    #[tracing::instrument(
+        name = "llm.classify_intent",
+        skip_all,
+        fields(provider = self.provider_name(), model = self.intent_model.as_deref().unwrap_or(self.model_name()))
+    )]
+    pub async fn classify_intent(&self, question: &str) -> Result<Classification> {
+        let prompt = super::prompts::build_intent_prompt(question);
+        let model = self.intent_model.as_deref().unwrap_or(self.model_name());
+        let raw_response = self.call_json(model, "You are an intent classifier. Reply with JSON only.", &prompt, 64).await?;
+        
+        parse_model_classification(&raw_response)
+            .ok_or_else(|| anyhow::anyhow!("Unparseable intent classification: {}", raw_response))
+    }
+    
+    /// Разбиение составного вопроса на самостоятельные подвопросы (не больше MAX_SUB_QUERIES)
+    #[tracing::instrument(
+        name = "llm.plan",
+        skip_all,
+        fields(provider = self.provider_name(), model = self.model_name())
+    )]
+    pub async fn plan_question(&self, question: &str) -> Result<Vec<String>> {
+        let prompt = super::prompts::build_plan_prompt(question);
+        let raw_response = self.call_json(
+            self.model_name(),
+            "You are a query planner for a payment analytics database. Reply with JSON only.",
+            &prompt,
+            512,
+        ).await?;
+        
+        parse_plan(&raw_response)
+            .ok_or_else(|| anyhow::anyhow!("Unparseable query plan: {}", raw_response))
+    }
+    
+    /// Следующий шаг агента: ответ модели разбирается в `llm::tools::parse_agent_step`
+    pub async fn agent_step(&self, prompt: &str) -> Result<String> {
+        self.call_json(
+            self.model_name(),
+            "You are an analytics agent that investigates data with tools. Reply with JSON only.",
+            prompt,
+            1024,
+        ).await
+    }
+    
+    /// Короткий детерминированный вызов модели, ожидающий JSON в ответе
+    async fn call_json(&self, model: &str, preamble: &str, prompt: &str, max_tokens: u64) -> Result<String> {
+        match &self.provider {
+            LLMProvider::Ollama { client, .. } => {
+                let comp_model = client.as_ref().completion_model(model);
+                let request = json_request(preamble, prompt, max_tokens, None);
+                Ok(response_text(complete(&comp_model, request, self.timeout, "Ollama").await?.choice))
+            }
+            LLMProvider::OpenAI { .. } => {
+                Err(anyhow::anyhow!("OpenAI not implemented yet"))
+            }
+            LLMProvider::Gemini { client, .. } => {
+                let comp_model = client.as_ref().completion_model(model);
+                let request = json_request(preamble, prompt, max_tokens, Some(serde_json::json!({
+                    "generationConfig": {
+                        "temperature": 0.0,
+                        "maxOutputTokens": max_tokens,
+                        "responseMimeType": "application/json"
+                    }
+                })));
+                Ok(response_text(complete(&comp_model, request, self.timeout, "Gemini").await?.choice))
             }
         }
     }
     
     /// Generate chat response for regular conversation
+    #[tracing::instrument(
+        name = "llm.chat",
+        skip_all,
+        fields(provider = self.provider_name(), model = self.model_name())
+    )]
     pub async fn generate_chat_response(
         &self,
         message: &str,
@@ -175,10 +371,7 @@ impl LLMClient {
             additional_params: None,
         };
         
-        let response = comp_model
-            .completion(request)
-            .await
-            .map_err(|e| anyhow::anyhow!("Ollama API error: {}", e))?;
+        let response = complete(&comp_model, request, self.timeout, "Ollama").await?;
         
         let mut text_parts = Vec::new();
         for content in response.choice.iter() {
@@ -224,10 +417,7 @@ impl LLMClient {
             additional_params: Some(serde_json::Value::Object(additional_params)),
         };
         
-        let response = comp_model
-            .completion(request)
-            .await
-            .map_err(|e| anyhow::anyhow!("Gemini API error: {}", e))?;
+        let response = complete(&comp_model, request, self.timeout, "Gemini").await?;
         
         let mut text_parts = Vec::new();
         for content in response.choice.iter() {
@@ -266,10 +456,7 @@ impl LLMClient {
         };
         
         // Отправляем запрос через rig-core
-        let response = comp_model
-            .completion(request)
-            .await
-            .map_err(|e| anyhow::anyhow!("LLM error: {}", e))?;
+        let response = complete(&comp_model, request, self.timeout, "Ollama").await?;
         
         // Извлекаем текст из choice (OneOrMany<AssistantContent>)
         // OneOrMany реализует IntoIterator, можно итерировать напрямую
@@ -330,10 +517,7 @@ impl LLMClient {
         };
         
         // Отправляем запрос через rig-core
-        let response = comp_model
-            .completion(request)
-            .await
-            .map_err(|e| anyhow::anyhow!("Gemini API error: {}", e))?;
+        let response = complete(&comp_model, request, self.timeout, "Gemini").await?;
         
         // Извлекаем текст из choice (OneOrMany<AssistantContent>)
         let mut text_parts = Vec::new();
@@ -352,3 +536,87 @@ impl LLMClient {
         Ok(text)
     }
 }
+
+/// Проверяет SQL валидатором; если модель добавила текст вокруг запроса - вырезает SELECT ... ;
+fn validate_generated(
+    cleaned: String,
+    prompt_version: &'static str,
+    generation: SqlGeneration,
+) -> Result<GeneratedSql, LlmError> {
+    let validate_start = std::time::Instant::now();
+    let validated = match super::validator::validate_sql(&cleaned) {
+        Ok(_) => Ok((cleaned, 0)),
+        Err(e) => {
+            tracing::warn!("SQL validation failed: {}. Attempting to fix...", e);
+            
+            // Try to extract SELECT statement if LLM added extra text
+            let sql_upper = cleaned.to_uppercase();
+            if let Some(select_pos) = sql_upper.find("SELECT") {
+                let extracted = &cleaned[select_pos..];
+                // Find the last semicolon
+                if let Some(semicolon_pos) = extracted.rfind(';') {
+                    let fixed = &extracted[..=semicolon_pos];
+                    match super::validator::validate_sql(fixed) {
+                        Ok(_) => {
+                            tracing::info!("Successfully fixed SQL by extracting SELECT statement");
+                            Ok((fixed.to_string(), 1))
+                        }
+                        Err(e2) => {
+                            tracing::error!("Failed to fix SQL: {}", e2);
+                            Err(LlmError::SqlRejected(e2))
+                        }
+                    }
+                } else {
+                    Err(LlmError::SqlRejected(e))
+                }
+            } else {
+                Err(LlmError::SqlRejected(e))
+            }
+        }
+    };
+    let validate_ms = validate_start.elapsed().as_millis() as u64;
+    
+    let (sql, repair_attempts) = validated?;
+    Ok(GeneratedSql {
+        sql,
+        repair_attempts,
+        validate_ms,
+        prompt_version,
+        generation,
+    })
+}
+
+/// Запрос с нулевой температурой для служебных вызовов (классификатор, планировщик)
+fn json_request(
+    preamble: &str,
+    prompt: &str,
+    max_tokens: u64,
+    additional_params: Option<serde_json::Value>,
+) -> CompletionRequest {
+    CompletionRequest {
+        preamble: Some(preamble.to_string()),
+        chat_history: OneOrMany::one(Message::User {
+            content: OneOrMany::one(UserContent::text(prompt)),
+        }),
+        documents: vec![],
+        tools: vec![],
+        temperature: Some(0.0),
+        max_tokens: Some(max_tokens),
+        tool_choice: None,
+        additional_params,
+    }
+}
+
+/// Текстовые части ответа модели одной строкой
+fn response_text(choice: OneOrMany<AssistantContent>) -> String {
+    choice
+        .iter()
+        .filter_map(|content| match content {
+            AssistantContent::Text(text) => Some(text.text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,224 @@
+use rig::completion::request::{CompletionError, CompletionModel, CompletionRequest, CompletionResponse};
+use super::usage;
+use regex::Regex;
+use std::sync::LazyLock;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Типизированные ошибки LLM-слоя. Передаются через `anyhow::Error`
+/// и распознаются в `AppError` через `downcast_ref` для выбора кода и HTTP-статуса.
+#[derive(Error, Debug)]
+pub enum LlmError {
+    #[error("{provider} did not respond within {secs}s")]
+    Timeout { provider: String, secs: u64 },
+
+    #[error("{provider} is unavailable: {message}")]
+    Unavailable { provider: String, message: String },
+
+    #[error("{provider} API error: {message}")]
+    Provider { provider: String, message: String },
+
+    /// Провайдер ответил 429; `retry_after_secs` - его подсказка (заголовок `Retry-After` или текст ошибки)
+    #[error("{provider} rate limit exceeded: {message}")]
+    RateLimited { provider: String, message: String, retry_after_secs: Option<u64> },
+
+    #[error("Invalid SQL generated: {0}")]
+    SqlRejected(#[from] SqlRejection),
+
+    #[error("{0}")]
+    QuotaExceeded(String),
+}
+
+impl LlmError {
+    /// Можно ли вместо SQL ответить обычным текстом через chat API.
+    /// Если модель недоступна или SQL отклонен валидатором - нет, ошибку нужно вернуть клиенту.
+    pub fn allows_chat_fallback(&self) -> bool {
+        match self {
+            LlmError::Timeout { .. }
+            | LlmError::Unavailable { .. }
+            | LlmError::RateLimited { .. }
+            | LlmError::QuotaExceeded(_) => false,
+            LlmError::SqlRejected(rejection) => rejection.kind == SqlRejectionKind::Malformed,
+            LlmError::Provider { .. } => true,
+        }
+    }
+
+    /// Сообщение для клиента: текст ответа провайдера остается только в логах
+    pub fn public_message(&self) -> String {
+        match self {
+            LlmError::Unavailable { provider, .. } => format!("{} is unavailable", provider),
+            LlmError::Provider { provider, .. } => format!("{} API error", provider),
+            LlmError::RateLimited { provider, .. } => format!("{} rate limit exceeded", provider),
+            _ => self.to_string(),
+        }
+    }
+}
+
+/// Причина, по которой валидатор отклонил SQL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlRejectionKind {
+    /// Запрещенные команды (DROP, DELETE, ...)
+    Forbidden,
+    /// Запрос слишком тяжелый (SELECT * без LIMIT, слишком большой LIMIT)
+    TooExpensive,
+    /// Ответ модели не похож на корректный SELECT
+    Malformed,
+}
+
+#[derive(Error, Debug, Clone)]
+#[error("{message}")]
+pub struct SqlRejection {
+    pub kind: SqlRejectionKind,
+    pub message: String,
+}
+
+impl SqlRejection {
+    pub fn new(kind: SqlRejectionKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+/// Выполняет completion-запрос с таймаутом и переводит ошибки rig-core в `LlmError`.
+/// Здесь же резервируется вызов в дневной квоте пользователя и учитываются потраченные токены.
+pub async fn complete<M: CompletionModel>(
+    model: &M,
+    request: CompletionRequest,
+    timeout: Duration,
+    provider: &str,
+) -> anyhow::Result<CompletionResponse<M::Response>> {
+    usage::reserve_call().await?;
+    match tokio::time::timeout(timeout, model.completion(request)).await {
+        Ok(Ok(response)) => {
+            let tokens = response.usage.total_tokens
+                .max(response.usage.input_tokens + response.usage.output_tokens);
+            usage::record_tokens(tokens).await;
+            Ok(response)
+        }
+        Ok(Err(e)) => Err(classify_completion_error(provider, e).into()),
+        Err(_) => Err(LlmError::Timeout {
+            provider: provider.to_string(),
+            secs: timeout.as_secs(),
+        }
+        .into()),
+    }
+}
+
+/// Запрос к Ollama `/api/chat` с `format` (JSON Schema ответа). rig-core 0.24 кладет дополнительные
+/// параметры Ollama в `options`, поэтому запрос со схемой отправляется напрямую; квота, учет токенов
+/// и таймаут - как в `complete`. `body` - тело запроса без `stream`.
+pub async fn complete_ollama_format(
+    http: &reqwest::Client,
+    base_url: &str,
+    mut body: serde_json::Value,
+    timeout: Duration,
+) -> anyhow::Result<String> {
+    const PROVIDER: &str = "Ollama";
+    usage::reserve_call().await?;
+    body["stream"] = serde_json::Value::Bool(false);
+    let url = format!("{}/api/chat", base_url.trim_end_matches('/'));
+
+    let send = async {
+        let response = http.post(&url).json(&body).send().await.map_err(|e| LlmError::Unavailable {
+            provider: PROVIDER.to_string(),
+            message: e.to_string(),
+        })?;
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = response.headers().get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok());
+            let message = format!("{}: {}", status, response.text().await.unwrap_or_default());
+            let provider = PROVIDER.to_string();
+            return Err(match status.as_u16() {
+                429 => LlmError::RateLimited { retry_after_secs: retry_after.or_else(|| retry_hint(&message)), provider, message },
+                _ if status.is_server_error() => LlmError::Unavailable { provider, message },
+                _ => LlmError::Provider { provider, message },
+            });
+        }
+        response.json::<serde_json::Value>().await.map_err(|e| LlmError::Provider {
+            provider: PROVIDER.to_string(),
+            message: e.to_string(),
+        })
+    };
+    let response = match tokio::time::timeout(timeout, send).await {
+        Ok(response) => response?,
+        Err(_) => {
+            return Err(LlmError::Timeout {
+                provider: PROVIDER.to_string(),
+                secs: timeout.as_secs(),
+            }
+            .into())
+        }
+    };
+
+    let tokens = response["prompt_eval_count"].as_u64().unwrap_or(0) + response["eval_count"].as_u64().unwrap_or(0);
+    usage::record_tokens(tokens).await;
+    Ok(response["message"]["content"].as_str().unwrap_or_default().trim().to_string())
+}
+
+fn classify_completion_error(provider: &str, error: CompletionError) -> LlmError {
+    use rig::http_client::Error as HttpError;
+
+    let provider = provider.to_string();
+    let message = error.to_string();
+    match &error {
+        // Ошибки соединения (сервер не запущен, DNS, обрыв)
+        CompletionError::HttpError(HttpError::Instance(_)) => LlmError::Unavailable { provider, message },
+        CompletionError::HttpError(
+            HttpError::InvalidStatusCode(status) | HttpError::InvalidStatusCodeWithMessage(status, _),
+        ) if status.as_u16() == 429 => LlmError::RateLimited { retry_after_secs: retry_hint(&message), provider, message },
+        CompletionError::HttpError(
+            HttpError::InvalidStatusCode(status) | HttpError::InvalidStatusCodeWithMessage(status, _),
+        ) if status.is_server_error() => LlmError::Unavailable { provider, message },
+        // rig отдает тело ответа без статуса: 429 распознается по тексту (OpenAI, Gemini)
+        CompletionError::ProviderError(text) if RATE_LIMIT_TEXT.is_match(text) => {
+            LlmError::RateLimited { retry_after_secs: retry_hint(&message), provider, message }
+        }
+        _ => LlmError::Provider { provider, message },
+    }
+}
+
+static RATE_LIMIT_TEXT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)rate[ _]limit|too many requests|resource_exhausted|\b429\b").unwrap()
+});
+
+static RETRY_HINT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(?:try again in|retry after|retry in)\s*(\d+(?:\.\d+)?)\s*(ms|s|sec|seconds?)?\b").unwrap()
+});
+
+/// Подсказка провайдера, через сколько секунд повторить: "Please try again in 20s", "try again in 350ms"
+fn retry_hint(message: &str) -> Option<u64> {
+    let captures = RETRY_HINT.captures(message)?;
+    let value: f64 = captures[1].parse().ok()?;
+    let secs = match captures.get(2).map(|unit| unit.as_str().to_ascii_lowercase()) {
+        Some(unit) if unit == "ms" => value / 1000.0,
+        _ => value,
+    };
+    Some(secs.ceil().max(1.0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_classification() {
+        let error = classify_completion_error(
+            "OpenAI",
+            CompletionError::ProviderError(
+                r#"{"error":{"message":"Rate limit reached for gpt-4o-mini on requests per min. Please try again in 20s.","code":"rate_limit_exceeded"}}"#.to_string(),
+            ),
+        );
+        assert!(matches!(error, LlmError::RateLimited { retry_after_secs: Some(20), .. }));
+        assert_eq!(error.public_message(), "OpenAI rate limit exceeded");
+        assert!(!error.allows_chat_fallback());
+
+        assert_eq!(retry_hint("Please try again in 350ms."), Some(1));
+        assert_eq!(retry_hint("Too Many Requests"), None);
+        let error = classify_completion_error("Ollama", CompletionError::ProviderError("model not found".to_string()));
+        assert_eq!(error.public_message(), "Ollama API error");
+    }
+}
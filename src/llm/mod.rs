@@ -1,4 +1,9 @@
 pub mod client;
+pub mod error;
+pub mod planner;
 pub mod prompts;
+pub mod sql_tool;
+pub mod tools;
+pub mod usage;
 pub mod validator;
 
@@ -0,0 +1,87 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::LazyLock;
+
+/// Больше подзапросов не выполняем - каждый стоит вызова LLM и запроса к БД
+pub const MAX_SUB_QUERIES: usize = 4;
+
+/// Признаки составного вопроса: связка + новое действие, "в каждом", несколько вопросов в одном.
+/// Простые вопросы не отправляются планировщику, чтобы не тратить лишний вызов LLM.
+static COMPOUND_PATTERNS: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    [
+        r"\b(?:и|а также|а затем|затем|потом|плюс|кроме того)\s+(?:покажи|показать|выведи|вывести|сравни|сравнить|найди|найти|посчитай|посчитать|построй|дай|какой|какая|какие|сколько|топ)",
+        r"\b(?:and|also|then|plus|as well as)\s+(?:show|list|compare|find|give|count|calculate|build|display|what|which|how many|top)\b",
+        r"\b(?:және|сондай-ақ)\s+(?:көрсет|салыстыр|тап|есепте|қанша|топ)",
+        r"\b(?:va|hamda)\s+(?:ko'rsat|koʻrsat|solishtir|top|hisobla|qancha)",
+        r"\b(?:жана|ошондой эле)\s+(?:көрсөт|салыштыр|тап|эсепте|канча|топ)",
+        r"\b(?:в каждом|в каждой|для каждого|для каждой|по отдельности|in each|for each|each of|respectively|әрқайсысында|har birida|ар биринде)\b",
+        r"[?;]\s*\S",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).expect("valid compound regex"))
+    .collect()
+});
+
+/// Похож ли вопрос на составной (стоит ли звать планировщик): "сравни снятия в Алматы и Астане
+/// и покажи топ-5 мерчантов в каждом" разбивается на подвопросы, каждый - отдельный SQL
+pub fn looks_compound(question: &str) -> bool {
+    let text = question.to_lowercase();
+    COMPOUND_PATTERNS.iter().any(|pattern| pattern.is_match(text.trim()))
+}
+
+/// Ответ планировщика: `{"sub_questions": ["...", "..."]}` (допускается и просто массив строк).
+/// Пустые и повторяющиеся подвопросы отбрасываются, лишние - обрезаются до MAX_SUB_QUERIES.
+pub fn parse_plan(response: &str) -> Option<Vec<String>> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Plan {
+        Object { sub_questions: Vec<String> },
+        List(Vec<String>),
+    }
+
+    let start = response.find(['{', '['])?;
+    let end = response.rfind(['}', ']'])?;
+    if end < start {
+        return None;
+    }
+    let sub_questions = match serde_json::from_str::<Plan>(&response[start..=end]).ok()? {
+        Plan::Object { sub_questions } | Plan::List(sub_questions) => sub_questions,
+    };
+
+    let mut plan: Vec<String> = Vec::new();
+    for question in sub_questions {
+        let question = question.trim().to_string();
+        if !question.is_empty() && !plan.iter().any(|q| q.eq_ignore_ascii_case(&question)) {
+            plan.push(question);
+        }
+    }
+    plan.truncate(MAX_SUB_QUERIES);
+    (!plan.is_empty()).then_some(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_looks_compound() {
+        assert!(looks_compound("Compare ATM withdrawals in Almaty and Astana and show the top 5 merchants in each"));
+        assert!(looks_compound("Сколько транзакций в Алматы и покажи топ 5 мерчантов"));
+        assert!(looks_compound("Сколько транзакций было вчера? Какой средний чек?"));
+        assert!(!looks_compound("Сколько транзакций было вчера?"));
+        assert!(!looks_compound("Топ 10 городов по объему транзакций"));
+        assert!(!looks_compound("Compare Almaty and Astana"));
+    }
+
+    #[test]
+    fn test_parse_plan() {
+        let plan = parse_plan("```json\n{\"sub_questions\": [\"ATM withdrawals in Almaty vs Astana\", \" \", \"Top 5 merchants in Almaty\", \"top 5 merchants in almaty\"]}\n```").unwrap();
+        assert_eq!(plan, vec!["ATM withdrawals in Almaty vs Astana", "Top 5 merchants in Almaty"]);
+
+        let list = parse_plan("[\"a\", \"b\", \"c\", \"d\", \"e\"]").unwrap();
+        assert_eq!(list.len(), MAX_SUB_QUERIES);
+
+        assert!(parse_plan("{\"sub_questions\": []}").is_none());
+        assert!(parse_plan("no plan").is_none());
+    }
+}
@@ -1,15 +1,83 @@
-use crate::utils::language::{detect_language, Language};
+use crate::utils::language::{detect_language, response_language, response_language_source, Language, LanguageSource};
 use crate::query_context::QueryContext;
 
+/// Версии шаблонов промптов (возвращаются клиенту в `meta`, менять при изменении текста промпта)
+pub const SQL_PROMPT_VERSION: &str = "sql-v2";
+pub const SQL_TOOL_PROMPT_VERSION: &str = "sql-tool-v2";
+pub const CHAT_PROMPT_VERSION: &str = "chat-v1";
+/// Для `sql: SELECT ...` - запрос пользователя выполняется без LLM
+pub const RAW_SQL_PROMPT_VERSION: &str = "raw-sql";
+/// Вопрос-прогноз отвечается моделью прогноза, SQL ряда строится без LLM
+pub const FORECAST_PROMPT_VERSION: &str = "forecast";
+/// Детализация точки по токену: SQL следующего уровня строится из SQL родителя без LLM
+pub const DRILL_PROMPT_VERSION: &str = "drill";
+pub const AGENT_PROMPT_VERSION: &str = "agent-v1";
+
 pub fn build_sql_generation_prompt(
     question: &str,
     previous_queries: &[&QueryContext],
 ) -> String {
+    let error_msg = sql_error_language(question).error_message();
+    format!(
+        r#"{body}Generate ONLY the SQL query, no explanations or markdown formatting. If the question is not about database queries, return: SELECT '{error_msg}' as error;
+
+SQL QUERY:"#,
+        body = build_sql_prompt_body(question, previous_queries),
+    )
+}
+
+/// Промпт генерации SQL через инструмент submit_sql: тот же контекст, ответ - аргументы инструмента.
+/// Для моделей, которые не вызывают инструменты, - тот же JSON текстом.
+pub fn build_sql_tool_prompt(
+    question: &str,
+    previous_queries: &[&QueryContext],
+) -> String {
+    let error_msg = sql_error_language(question).error_message();
+    format!(
+        r#"{body}Call the submit_sql tool with:
+- sql: the SQL query (one SELECT ending with a semicolon)
+- tables: the tables the query reads
+- intent: aggregate, top_n, trend, comparison, breakdown or lookup
+- assumptions: how you interpreted ambiguous parts of the question (periods, city/bank names, metric), empty list if none
+If the question is not about database queries, submit sql: SELECT '{error_msg}' as error; with intent lookup.
+If you cannot call tools, answer with ONLY the JSON object of the tool arguments: {{"sql": "...", "tables": ["transactions"], "intent": "...", "assumptions": []}}"#,
+        body = build_sql_prompt_body(question, previous_queries),
+    )
+}
+
+/// Вопрос для генерации SQL в режиме сравнения периодов: периоды backend подставляет сам,
+/// модели нужен один агрегат без фильтра по дате. Уточнение - на языке вопроса, чтобы не сбить его определение.
+pub fn compare_mode_question(question: &str) -> String {
+    let hint = match detect_language(question) {
+        Language::Russian => "Периоды сравнения задаются отдельно: напиши один агрегирующий запрос без фильтров по transaction_timestamp.",
+        Language::English => "The comparison periods are applied separately: write one aggregate query without filters on transaction_timestamp.",
+        Language::Kazakh => "Салыстыру кезеңдері бөлек беріледі: transaction_timestamp бойынша сүзгісіз бір агрегаттық сұрау жаз.",
+        Language::Uzbek => "Taqqoslash davrlari alohida beriladi: transaction_timestamp bo'yicha filtrsiz bitta agregat so'rov yoz.",
+        Language::Kyrgyz => "Салыштыруу мезгилдери өзүнчө берилет: transaction_timestamp боюнча чыпкасыз бир агрегаттык суроо жаз.",
+    };
+    format!("{}\n{}", question.trim(), hint)
+}
+
+/// Текст ошибки - на языке ответа (он может быть задан клиентом явно), иначе - на языке вопроса
+fn sql_error_language(question: &str) -> Language {
+    match response_language_source() {
+        LanguageSource::Default => detect_language(question),
+        _ => response_language(),
+    }
+}
+
+/// Общая часть промптов генерации SQL: схема, правила, примеры, контекст и вопрос
+fn build_sql_prompt_body(
+    question: &str,
+    previous_queries: &[&QueryContext],
+) -> String {
+    // Примеры - на языке вопроса, текст ошибки - на языке ответа
     let language = detect_language(question);
+    let error_language = sql_error_language(question);
     let schema = get_database_schema();
-    let rules = get_sql_rules(&language);
+    let rules = get_sql_rules(&error_language);
     let examples = get_few_shot_examples(&language);
-    let error_msg = language.error_message();
+    let error_msg = error_language.error_message();
     
     // Формируем контекст предыдущих запросов
     let context_section = if previous_queries.is_empty() {
@@ -31,6 +99,16 @@ pub fn build_sql_generation_prompt(
                 "Сұрау",
                 "SQL"
             ),
+            Language::Uzbek => (
+                "\n\nOLDINGI SO'ROVLAR KONTEKSTI (suhbat kontekstini tushunish uchun):\n",
+                "Savol",
+                "SQL"
+            ),
+            Language::Kyrgyz => (
+                "\n\nМУРУНКУ СУРООЛОРДУН КОНТЕКСТИ (сүйлөшүүнүн контекстин түшүнүү үчүн):\n",
+                "Суроо",
+                "SQL"
+            ),
         };
         
         let mut context = String::from(context_label);
@@ -70,9 +148,7 @@ IMPORTANT ABOUT CHARTS AND GRAPHS:
 {context_section}
 USER QUESTION: {question}
 
-Generate ONLY the SQL query, no explanations or markdown formatting. If the question is not about database queries, return: SELECT '{error_msg}' as error;
-
-SQL QUERY:"#,
+"#,
         language_instruction = language.response_instruction(),
         context_section = context_section
     )
@@ -192,7 +268,7 @@ DATA RETRIEVAL RULES:
 25. When filtering by mcc_category: Use exact values like 'Dining & Restaurants', 'Grocery & Food Markets', etc. (case-sensitive)
 26. When filtering by pos_entry_mode: Use exact values: 'Contactless', 'ECOM', 'QR_Code', 'Swipe', or check for NULL
 27. When grouping by time periods: Use DATE_TRUNC('day', transaction_timestamp), DATE_TRUNC('month', transaction_timestamp), etc.
-28. Use window functions (RANK, ROW_NUMBER, LAG, LEAD) for advanced analytics when needed
+28. Keep aggregates (SUM, COUNT, AVG, ...) in the main SELECT: no aggregates inside subqueries, CTEs or UNION branches and no window functions over aggregated results - such queries are rejected by the minimum group size check. Whole-table subqueries like (SELECT MAX(transaction_timestamp) FROM transactions) are allowed
 29. End query with semicolon"#, error_msg)
 }
 
@@ -317,6 +393,62 @@ Q: "Төлем әдісі бойынша транзакциялар (pos_entry_m
 A: SELECT pos_entry_mode, COUNT(*) as transaction_count, SUM(transaction_amount_kzt) as total_kzt FROM transactions WHERE pos_entry_mode IS NOT NULL GROUP BY pos_entry_mode ORDER BY transaction_count DESC;
 
 Q: "P2P транзакциялар (кірген және шыққан)"
+A: SELECT transaction_type, COUNT(*) as transaction_count, SUM(transaction_amount_kzt) as total_kzt FROM transactions WHERE transaction_type IN ('P2P_IN', 'P2P_OUT') GROUP BY transaction_type;"#.to_string(),
+        Language::Uzbek => r#"EXAMPLES:
+
+Q: "2024 yilda qancha tranzaksiya bo'lgan?"
+A: SELECT COUNT(*) as total_transactions FROM transactions WHERE transaction_timestamp >= '2024-01-01' AND transaction_timestamp < '2025-01-01';
+
+Q: "Tengedagi tranzaksiyalar hajmi bo'yicha top 5 merchant"
+A: SELECT merchant_id, SUM(transaction_amount_kzt) as total_volume_kzt FROM transactions WHERE transaction_type = 'POS' GROUP BY merchant_id ORDER BY total_volume_kzt DESC LIMIT 5;
+
+Q: "Olmaotadagi Halyk Bank kartalari uchun o'rtacha tranzaksiya summasi"
+A: SELECT AVG(transaction_amount_kzt) as average_amount FROM transactions WHERE issuer_bank_name ILIKE '%halyk%' AND merchant_city ILIKE '%almaty%' AND transaction_type = 'POS';
+
+Q: "O'tgan oyda MCC toifalari bo'yicha tranzaksiyalar hajmi"
+A: SELECT mcc_category, SUM(transaction_amount_kzt) as total_volume, COUNT(*) as transaction_count FROM transactions WHERE DATE_TRUNC('month', transaction_timestamp) = DATE_TRUNC('month', CURRENT_DATE - INTERVAL '1 month') AND transaction_type = 'POS' GROUP BY mcc_category ORDER BY total_volume DESC;
+
+Q: "Tranzaksiyalar soni bo'yicha top 10 shahar"
+A: SELECT merchant_city, COUNT(*) as transaction_count, SUM(transaction_amount_kzt) as total_volume FROM transactions WHERE transaction_type = 'POS' GROUP BY merchant_city ORDER BY transaction_count DESC LIMIT 10;
+
+Q: "So'mdagi (UZS) to'lovlar mamlakatlar bo'yicha"
+A: SELECT acquirer_country_iso, COUNT(*) as transaction_count, SUM(original_amount) as total_uzs, SUM(transaction_amount_kzt) as total_kzt FROM transactions WHERE transaction_currency = 'UZS' GROUP BY acquirer_country_iso ORDER BY transaction_count DESC;
+
+Q: "Valyutalar bo'yicha tranzaksiyalar"
+A: SELECT transaction_currency, COUNT(*) as transaction_count, SUM(transaction_amount_kzt) as total_kzt FROM transactions GROUP BY transaction_currency ORDER BY transaction_count DESC;
+
+Q: "Oxirgi 7 kundagi kunlik tranzaksiyalar hajmi"
+A: SELECT DATE(transaction_timestamp) as date, SUM(transaction_amount_kzt) as daily_volume, COUNT(*) as transaction_count FROM transactions WHERE transaction_timestamp >= CURRENT_DATE - INTERVAL '7 days' AND transaction_type = 'POS' GROUP BY DATE(transaction_timestamp) ORDER BY date DESC;
+
+Q: "P2P tranzaksiyalar (kiruvchi va chiquvchi)"
+A: SELECT transaction_type, COUNT(*) as transaction_count, SUM(transaction_amount_kzt) as total_kzt FROM transactions WHERE transaction_type IN ('P2P_IN', 'P2P_OUT') GROUP BY transaction_type;"#.to_string(),
+        Language::Kyrgyz => r#"EXAMPLES:
+
+Q: "2024-жылы канча транзакция болгон?"
+A: SELECT COUNT(*) as total_transactions FROM transactions WHERE transaction_timestamp >= '2024-01-01' AND transaction_timestamp < '2025-01-01';
+
+Q: "Теңгедеги транзакциялардын көлөмү боюнча топ 5 мерчант"
+A: SELECT merchant_id, SUM(transaction_amount_kzt) as total_volume_kzt FROM transactions WHERE transaction_type = 'POS' GROUP BY merchant_id ORDER BY total_volume_kzt DESC LIMIT 5;
+
+Q: "Алматыдагы Halyk Bank карталары үчүн орточо транзакция суммасы"
+A: SELECT AVG(transaction_amount_kzt) as average_amount FROM transactions WHERE issuer_bank_name ILIKE '%halyk%' AND merchant_city ILIKE '%almaty%' AND transaction_type = 'POS';
+
+Q: "Өткөн айда MCC категориялары боюнча транзакциялардын көлөмү"
+A: SELECT mcc_category, SUM(transaction_amount_kzt) as total_volume, COUNT(*) as transaction_count FROM transactions WHERE DATE_TRUNC('month', transaction_timestamp) = DATE_TRUNC('month', CURRENT_DATE - INTERVAL '1 month') AND transaction_type = 'POS' GROUP BY mcc_category ORDER BY total_volume DESC;
+
+Q: "Транзакциялардын саны боюнча топ 10 шаар"
+A: SELECT merchant_city, COUNT(*) as transaction_count, SUM(transaction_amount_kzt) as total_volume FROM transactions WHERE transaction_type = 'POS' GROUP BY merchant_city ORDER BY transaction_count DESC LIMIT 10;
+
+Q: "Сомдогу (KGS) төлөмдөр өлкөлөр боюнча"
+A: SELECT acquirer_country_iso, COUNT(*) as transaction_count, SUM(original_amount) as total_kgs, SUM(transaction_amount_kzt) as total_kzt FROM transactions WHERE transaction_currency = 'KGS' GROUP BY acquirer_country_iso ORDER BY transaction_count DESC;
+
+Q: "Валюталар боюнча транзакциялар"
+A: SELECT transaction_currency, COUNT(*) as transaction_count, SUM(transaction_amount_kzt) as total_kzt FROM transactions GROUP BY transaction_currency ORDER BY transaction_count DESC;
+
+Q: "Акыркы 7 күндөгү күнүмдүк транзакциялардын көлөмү"
+A: SELECT DATE(transaction_timestamp) as date, SUM(transaction_amount_kzt) as daily_volume, COUNT(*) as transaction_count FROM transactions WHERE transaction_timestamp >= CURRENT_DATE - INTERVAL '7 days' AND transaction_type = 'POS' GROUP BY DATE(transaction_timestamp) ORDER BY date DESC;
+
+Q: "P2P транзакциялар (кирген жана чыккан)"
 A: SELECT transaction_type, COUNT(*) as transaction_count, SUM(transaction_amount_kzt) as total_kzt FROM transactions WHERE transaction_type IN ('P2P_IN', 'P2P_OUT') GROUP BY transaction_type;"#.to_string(),
     }
 }
@@ -359,6 +491,14 @@ pub fn build_chat_prompt(
             "Сәлем! Мен төлем транзакцияларының аналитикасына көмектесуші AI-көмекшісімін. Мен сізбен табиғи тілде сөйлесе аламын, сұрақтарға жауап бере аламын, деректерді талдауға көмектесе аламын және жай әңгімелесе аламын.",
             "Табиғи және достықпен жауап бер, нақты адам сияқты. Эмодзи қолдануға болады, бірақ өлшемді түрде (1-2 хабарламаға). Табиғи диалогты сақта, қажет болса нақтылау сұрақтарын қой. Пайдалы және сөйлесуге жағымды бол.",
         ),
+        Language::Uzbek => (
+            "Salom! Men to'lov tranzaksiyalari tahlili bo'yicha AI-yordamchiman. Siz bilan tabiiy tilda suhbatlasha olaman, savollarga javob bera olaman, ma'lumotlarni tahlil qilishda yordam bera olaman va shunchaki suhbatni davom ettira olaman.",
+            "Tabiiy va do'stona javob ber, xuddi jonli suhbatdosh kabi. Emojilarni me'yorida ishlatish mumkin (xabarga 1-2 ta). Tabiiy muloqotni saqla, kerak bo'lsa aniqlashtiruvchi savollar ber. Foydali va yoqimli suhbatdosh bo'l.",
+        ),
+        Language::Kyrgyz => (
+            "Салам! Мен төлөм транзакцияларынын аналитикасы боюнча AI-жардамчымын. Мен сиз менен табигый тилде сүйлөшө алам, суроолорго жооп бере алам, маалыматтарды талдоого жардам бере алам жана жөн гана маектеше алам.",
+            "Табигый жана достук маанайда жооп бер, чыныгы маектеш сыяктуу. Эмодзилерди ченеми менен колдонсо болот (билдирүүгө 1-2). Табигый диалогду сакта, керек болсо тактоочу суроолорду бер. Пайдалуу жана жагымдуу маектеш бол.",
+        ),
     };
     
     let (history_label, user_label, assistant_label, context_note, db_note) = match language {
@@ -383,6 +523,20 @@ pub fn build_chat_prompt(
             "Сіз достық AI-көмекшісісіз, табиғи тілде сөйлесе аласыз. Сіз пайдаланушыларға төлем транзакцияларының аналитикасы туралы сұрақтарға көмектесесіз, бірақ сіз сондай-ақ қалыпты әңгімелесе аласыз, жалпы сұрақтарға жауап бере аласыз, әзілдесе аласыз (ақылға сыйыстыра) және жағымды әңгімелесуші болуға болады. Маңызды: егер пайдаланушы дерекқор деректері туралы сұраса, sql: префиксін пайдалануды ұсыныңыз. Бірақ егер бұл жай әңгіме болса - табиғи түрде әңгімелесіңіз.",
             "КРИТИКАЛЫҚ МАҢЫЗДЫ: Егер пайдаланушы дерекқор деректері туралы сұраса, сіз API немесе кез келген endpoint-терді пайдалануды ұсынбауыңыз керек. Оның орнына, егер пайдаланушы деректер туралы сұрақ қойса, сізге sql: префиксін сұрақтың алдына қоюды ұсыныңыз, осылайша жүйе SQL сұрауларды автоматты түрде жасап, орындайды. ЕШҚАШАН API, endpoint-тер, /api/query немесе деректерге қол жеткізудің техникалық әдістерін атамаңыз. Сіз sql: префиксі арқылы SQL сұрауларды өзі анықтайтын және нәтижелерді адамға түсінікті шығаруға интерпретациялайтын чат-бот ретінде жұмыс істейсіз. Әрқашан нәтижелердің толық сипаттамасын және түсіндірмелерін беріңіз, тек кестелерге немесе деректерге ғана шектеліп қалмаңыз.",
         ),
+        Language::Uzbek => (
+            "\n\nOldingi xabarlar:\n",
+            "Foydalanuvchi",
+            "Yordamchi",
+            "Sen tabiiy tilda suhbatlasha oladigan do'stona AI-yordamchisan. Sen foydalanuvchilarga to'lov tranzaksiyalari tahlili bo'yicha savollarda yordam berasan, lekin oddiy suhbat qurishing, umumiy savollarga javob berishing, hazillashishing (me'yorida) va yoqimli suhbatdosh bo'lishing ham mumkin. Muhim: agar foydalanuvchi bazadagi ma'lumotlar haqida so'rasa, savoldan oldin sql: prefiksidan foydalanishni taklif qil. Agar bu shunchaki suhbat bo'lsa - tabiiy muloqot qil.",
+            "O'TA MUHIM: Agar foydalanuvchi bazadagi ma'lumotlar haqida so'rasa, API yoki biror endpoint'dan foydalanishni taklif QILMA. Buning o'rniga, agar foydalanuvchi ma'lumotlar haqida savol bersa, savol oldidan sql: prefiksini qo'yishni taklif qil, shunda tizim SQL so'rovni avtomatik yaratadi va bajaradi. HECH QACHON API, endpoint'lar, /api/query yoki ma'lumotlarga kirishning texnik usullarini tilga olma. Sen sql: prefiksi orqali SQL so'rovlarni o'zi aniqlaydigan va natijalarni tushunarli tilda izohlaydigan chat-bot sifatida ishlaysan. Har doim natijalarning batafsil tavsifi va izohini ber, faqat jadvallar yoki ma'lumotlar bilan cheklanma.",
+        ),
+        Language::Kyrgyz => (
+            "\n\nМурунку билдирүүлөр:\n",
+            "Колдонуучу",
+            "Жардамчы",
+            "Сен табигый тилде сүйлөшө алган достук AI-жардамчысың. Сен колдонуучуларга төлөм транзакцияларынын аналитикасы боюнча суроолордо жардам бересиң, бирок кадимки маек куруп, жалпы суроолорго жооп берип, тамашалашып (чегинде) жана жагымдуу маектеш боло аласың. Маанилүү: эгер колдонуучу базадагы маалыматтар тууралуу сураса, суроонун алдына sql: префиксин колдонууну сунушта. Эгер бул жөн гана маек болсо - табигый сүйлөш.",
+            "ӨТӨ МААНИЛҮҮ: Эгер колдонуучу базадагы маалыматтар тууралуу сураса, API же кандайдыр бир endpoint'терди колдонууну СУНУШТАБА. Анын ордуна, эгер колдонуучу маалыматтар тууралуу суроо берсе, суроонун алдына sql: префиксин коюуну сунушта, ошондо система SQL суроону автоматтык түрдө түзүп, аткарат. ЭЧ КАЧАН API, endpoint'терди, /api/query же маалыматтарга жетүүнүн техникалык жолдорун атаба. Сен sql: префикси аркылуу SQL суроолорду өзү аныктаган жана натыйжаларды түшүнүктүү тилде чечмелеген чат-бот катары иштейсиң. Ар дайым натыйжалардын толук сүрөттөмөсүн жана түшүндүрмөсүн бер, таблицалар же маалыматтар менен гана чектелбе.",
+        ),
     };
     
     let history_text = if history.is_empty() {
@@ -403,6 +557,8 @@ pub fn build_chat_prompt(
         Language::Russian => ("Текущее сообщение пользователя:", "Ответ:"),
         Language::English => ("Current user message:", "Answer:"),
         Language::Kazakh => ("Пайдаланушының ағымдағы хабарламасы:", "Жауап:"),
+        Language::Uzbek => ("Foydalanuvchining joriy xabari:", "Javob:"),
+        Language::Kyrgyz => ("Колдонуучунун учурдагы билдирүүсү:", "Жооп:"),
     };
     
     format!(
@@ -423,3 +579,99 @@ pub fn build_chat_prompt(
 {answer_label}"#
     )
 }
+
+/// Промпт классификатора намерения: ответ - только JSON `{"intent": ..., "confidence": ...}`.
+/// Инструкции на английском - вопрос может быть на любом из поддерживаемых языков.
+pub fn build_intent_prompt(question: &str) -> String {
+    format!(
+        r#"Classify the user's message sent to a payment transaction analytics assistant.
+The database contains card transactions: amounts, dates, merchants, MCC categories, cities, issuer banks, currencies, transaction types, wallets.
+
+Intents:
+- "data_query": the user wants numbers, lists, charts or analysis computed from the transaction data (counts, sums, averages, top-N, trends, comparisons, breakdowns).
+- "chat": greetings, thanks, small talk, jokes, general knowledge questions unrelated to the transaction data.
+- "help": the user asks what the assistant can do, how to use it or for example questions.
+- "ambiguous": the message could reasonably be either, or is too vague to act on.
+
+Examples:
+"How many transactions were there yesterday?" -> {{"intent": "data_query", "confidence": 0.95}}
+"show me a joke" -> {{"intent": "chat", "confidence": 0.9}}
+"what is a day" -> {{"intent": "chat", "confidence": 0.8}}
+"What can you do?" -> {{"intent": "help", "confidence": 0.9}}
+"cards" -> {{"intent": "ambiguous", "confidence": 0.4}}
+
+Answer with ONLY a JSON object: {{"intent": "<data_query|chat|help|ambiguous>", "confidence": <number from 0 to 1>}}
+
+Message: {question}"#
+    )
+}
+
+/// Промпт планировщика составного вопроса: ответ - только JSON `{"sub_questions": [...]}`.
+/// Подвопросы должны быть самодостаточными - каждый превращается в отдельный SQL без общего контекста.
+pub fn build_plan_prompt(question: &str) -> String {
+    let max = super::planner::MAX_SUB_QUERIES;
+    format!(
+        r#"Split the user's question about payment transactions into independent sub-questions.
+Each sub-question must be answerable by ONE SQL query over the transactions table and must be self-contained:
+repeat filters, cities, periods and metrics instead of referring to other sub-questions ("in each", "the same", "them").
+Write the sub-questions in the same language as the user's question.
+If the question needs only one query, return a single sub-question. Never return more than {max} sub-questions.
+
+Example:
+"Compare ATM withdrawals in Almaty and Astana and show the top 5 merchants in each" ->
+{{"sub_questions": ["Compare the number and amount of ATM withdrawals in Almaty and Astana", "Top 5 merchants by transaction amount in Almaty", "Top 5 merchants by transaction amount in Astana"]}}
+
+Answer with ONLY a JSON object: {{"sub_questions": ["...", "..."]}}
+
+Question: {question}"#
+    )
+}
+
+/// Промпт шага агента: схема, инструменты, уже выполненные шаги и их результаты.
+/// Ответ - только JSON `{"thought": ..., "tool": ..., "args": {...}}`; на последнем шаге разрешен только final_answer.
+pub fn build_agent_prompt(question: &str, transcript: &str, steps_left: usize, language: &Language) -> String {
+    let transcript = if transcript.is_empty() {
+        "(no steps yet)".to_string()
+    } else {
+        transcript.to_string()
+    };
+    let step_rule = if steps_left <= 1 {
+        "This is the LAST step: you MUST call final_answer now, using only the observations above.".to_string()
+    } else {
+        format!("You have {} steps left including the final answer. Call final_answer as soon as the observations answer the question.", steps_left)
+    };
+    let dimensions = crate::analytics::series::DIMENSIONS.join(", ");
+    let rows = super::tools::OBSERVATION_ROWS;
+
+    format!(
+        r#"You are an analytics agent for a payment transactions database. Investigate the user's question step by step.
+At each step call exactly ONE tool and wait for its observation.
+
+{schema}
+
+TOOLS:
+- run_sql: {{"sql": "<one SELECT over transactions ending with ;>"}}
+  Read-only PostgreSQL. Use LIMIT (max 1000) or aggregation. Cast SUM/AVG of amounts to float8, e.g. SUM(transaction_amount_kzt)::float8.
+  Only the first {rows} rows are shown to you.
+- describe_column: {{"column": "<column name>"}}
+  Range for numbers and dates, most frequent values for categorical columns.
+- compare_periods: {{"metric": "count|sum|avg", "period_a": {{"from": "YYYY-MM-DD", "to": "YYYY-MM-DD"}}, "period_b": {{"from": "YYYY-MM-DD", "to": "YYYY-MM-DD"}}, "dimension": "<optional column>", "filters": {{"<column>": "<value>"}}}}
+  Metric over two periods (dates inclusive) with absolute and percent change. dimension and filters columns: {dimensions}.
+- final_answer: {{"headline": "...", "insights": [{{"title": "...", "description": "...", "significance": "High|Medium|Low"}}], "explanation": "...", "suggested_questions": ["..."]}}
+  The report for the user. Use only numbers from the observations. {language_instruction}
+
+RULES:
+- Check the data range (describe_column transaction_timestamp) before using relative periods like "last month".
+- If a tool returns an error, fix the arguments instead of repeating the same call.
+- {step_rule}
+
+USER QUESTION: {question}
+
+PREVIOUS STEPS:
+{transcript}
+
+Answer with ONLY a JSON object: {{"thought": "<why this step>", "tool": "<tool name>", "args": {{...}}}}"#,
+        schema = get_database_schema(),
+        language_instruction = language.response_instruction(),
+    )
+}
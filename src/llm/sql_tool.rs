@@ -0,0 +1,200 @@
+use rig::completion::ToolDefinition;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::validator::ALLOWED_TABLES;
+
+/// Имя инструмента, через который модель возвращает SQL
+pub const SUBMIT_SQL_TOOL: &str = "submit_sql";
+
+/// Как был получен SQL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SqlMode {
+    Tool,  // Вызов инструмента submit_sql
+    Json,  // Тот же JSON текстом (модель не вызвала инструмент)
+    Text,  // Текст с SQL, разобранный эвристикой (модель без поддержки инструментов)
+}
+
+/// Что модель собиралась посчитать
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SqlIntent {
+    Aggregate,
+    TopN,
+    Trend,
+    Comparison,
+    Breakdown,
+    Lookup,
+}
+
+impl SqlIntent {
+    const ALL: [&'static str; 6] = ["aggregate", "top_n", "trend", "comparison", "breakdown", "lookup"];
+}
+
+/// Ответ submit_sql: `{"sql", "tables", "intent", "assumptions"}`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StructuredSql {
+    pub sql: String,
+    pub tables: Vec<String>,
+    pub intent: SqlIntent,
+    #[serde(default)]
+    pub assumptions: Vec<String>,
+}
+
+/// Способ генерации SQL и то, что модель сообщила о запросе (для `meta.sql_generation`)
+#[derive(Debug, Clone, Serialize)]
+pub struct SqlGeneration {
+    pub mode: SqlMode,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tables: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intent: Option<SqlIntent>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub assumptions: Vec<String>,
+}
+
+impl SqlGeneration {
+    pub fn text() -> Self {
+        Self { mode: SqlMode::Text, tables: vec![], intent: None, assumptions: vec![] }
+    }
+
+    pub fn structured(mode: SqlMode, structured: &StructuredSql) -> Self {
+        Self {
+            mode,
+            tables: structured.tables.clone(),
+            intent: Some(structured.intent),
+            assumptions: structured.assumptions.clone(),
+        }
+    }
+}
+
+/// JSON Schema аргументов submit_sql (передается провайдеру в `CompletionRequest.tools`)
+pub fn submit_sql_tool() -> ToolDefinition {
+    ToolDefinition {
+        name: SUBMIT_SQL_TOOL.to_string(),
+        description: "Submit the PostgreSQL SELECT query that answers the user's question, with the tables it reads, \
+            what it computes and the assumptions made while interpreting the question."
+            .to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "sql": {
+                    "type": "string",
+                    "description": "One read-only SELECT statement ending with a semicolon"
+                },
+                "tables": {
+                    "type": "array",
+                    "items": { "type": "string", "enum": ALLOWED_TABLES },
+                    "description": "Tables the query reads"
+                },
+                "intent": {
+                    "type": "string",
+                    "enum": SqlIntent::ALL,
+                    "description": "What the query computes"
+                },
+                "assumptions": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Interpretations made (periods, name mapping, metric choice); empty if none"
+                }
+            },
+            "required": ["sql", "tables", "intent", "assumptions"]
+        }),
+    }
+}
+
+/// Проверяет аргументы submit_sql по схеме. Ошибка - описание нарушения.
+pub fn parse_structured_sql(args: &Value) -> Result<StructuredSql, String> {
+    // Часть провайдеров передает аргументы строкой с JSON
+    let parsed = match args {
+        Value::String(raw) => serde_json::from_str::<Value>(raw).map_err(|e| format!("Arguments are not JSON: {}", e))?,
+        other => other.clone(),
+    };
+    let mut structured: StructuredSql = serde_json::from_value(parsed).map_err(|e| format!("Arguments do not match the schema: {}", e))?;
+
+    structured.sql = structured.sql.trim().to_string();
+    if structured.sql.is_empty() {
+        return Err("sql is empty".to_string());
+    }
+    if !structured.sql.ends_with(';') {
+        structured.sql.push(';');
+    }
+    if structured.tables.is_empty() {
+        return Err("tables is empty".to_string());
+    }
+    for table in structured.tables.iter_mut() {
+        *table = table.trim().to_lowercase();
+        if !ALLOWED_TABLES.contains(&table.as_str()) {
+            return Err(format!("Table '{}' is not allowed", table));
+        }
+    }
+    structured.assumptions.retain(|assumption| !assumption.trim().is_empty());
+    Ok(structured)
+}
+
+/// Аргументы submit_sql, которые модель без поддержки инструментов вернула текстом
+pub fn parse_structured_text(text: &str) -> Option<StructuredSql> {
+    let start = text.find('{')?;
+    let end = text.rfind('}')?;
+    if end < start {
+        return None;
+    }
+    let value: Value = serde_json::from_str(&text[start..=end]).ok()?;
+    // `{"name": "submit_sql", "arguments": {...}}` - вызов инструмента в тексте
+    let args = match value.get("arguments").or_else(|| value.get("parameters")) {
+        Some(args) => args,
+        None => &value,
+    };
+    parse_structured_sql(args).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_structured_sql() {
+        let structured = parse_structured_sql(&serde_json::json!({
+            "sql": " SELECT COUNT(*) FROM transactions ",
+            "tables": ["Transactions"],
+            "intent": "aggregate",
+            "assumptions": ["all time", " "]
+        }))
+        .unwrap();
+        assert_eq!(structured.sql, "SELECT COUNT(*) FROM transactions;");
+        assert_eq!(structured.tables, vec!["transactions"]);
+        assert_eq!(structured.intent, SqlIntent::Aggregate);
+        assert_eq!(structured.assumptions, vec!["all time"]);
+
+        let as_string = Value::String("{\"sql\": \"SELECT 1;\", \"tables\": [\"transactions\"], \"intent\": \"lookup\"}".to_string());
+        assert!(parse_structured_sql(&as_string).is_ok());
+
+        assert!(parse_structured_sql(&serde_json::json!({"sql": "SELECT 1;", "tables": ["api_keys"], "intent": "lookup"})).is_err());
+        assert!(parse_structured_sql(&serde_json::json!({"sql": "SELECT 1;", "tables": ["transactions"], "intent": "guess"})).is_err());
+        assert!(parse_structured_sql(&serde_json::json!({"sql": "", "tables": ["transactions"], "intent": "lookup"})).is_err());
+        assert!(parse_structured_sql(&serde_json::json!({"tables": ["transactions"], "intent": "lookup"})).is_err());
+    }
+
+    #[test]
+    fn test_parse_structured_text() {
+        let text = "```json\n{\"sql\": \"SELECT merchant_city, COUNT(*) FROM transactions GROUP BY merchant_city;\", \"tables\": [\"transactions\"], \"intent\": \"breakdown\", \"assumptions\": []}\n```";
+        assert_eq!(parse_structured_text(text).unwrap().intent, SqlIntent::Breakdown);
+
+        let call = "{\"name\": \"submit_sql\", \"arguments\": {\"sql\": \"SELECT 1;\", \"tables\": [\"transactions\"], \"intent\": \"top_n\"}}";
+        assert_eq!(parse_structured_text(call).unwrap().intent, SqlIntent::TopN);
+
+        assert!(parse_structured_text("SELECT COUNT(*) FROM transactions;").is_none());
+    }
+
+    #[test]
+    fn test_tool_schema_lists_intents() {
+        let tool = submit_sql_tool();
+        let intents = &tool.parameters["properties"]["intent"]["enum"];
+        for intent in SqlIntent::ALL {
+            let parsed: SqlIntent = serde_json::from_value(Value::String(intent.to_string())).unwrap();
+            assert_eq!(serde_json::to_value(parsed).unwrap(), Value::String(intent.to_string()));
+            assert!(intents.as_array().unwrap().contains(&Value::String(intent.to_string())));
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use crate::analysis::{AnalysisResult, Insight, InsightSignificance};
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use super::validator::TRANSACTION_COLUMNS;
+use crate::analytics::series::{check_dimension, PeriodAggregates, PeriodMetric, DIMENSIONS};
+
+/// Инструменты агента (имена - как в промпте и в цепочке доказательств)
+pub const RUN_SQL: &str = "run_sql";
+pub const DESCRIBE_COLUMN: &str = "describe_column";
+pub const COMPARE_PERIODS: &str = "compare_periods";
+pub const FINAL_ANSWER: &str = "final_answer";
+
+/// Сколько строк результата инструмента показывается модели
+pub const OBSERVATION_ROWS: usize = 20;
+
+/// Числовые колонки: describe_column считает min/max/avg
+const NUMERIC_COLUMNS: &[&str] = &["transaction_amount_kzt", "original_amount"];
+
+/// Шаг агента в ответе модели: `{"thought": "...", "tool": "run_sql", "args": {...}}`
+#[derive(Debug, Deserialize)]
+struct RawStep {
+    #[serde(default)]
+    thought: Option<String>,
+    tool: String,
+    #[serde(default)]
+    args: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgentStep {
+    pub thought: Option<String>,
+    pub action: AgentAction,
+    /// Аргументы как их прислала модель (для цепочки доказательств)
+    pub args: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AgentAction {
+    RunSql { sql: String },
+    DescribeColumn { column: String },
+    ComparePeriods(ComparePeriodsArgs),
+    FinalAnswer(FinalAnswer),
+}
+
+impl AgentAction {
+    pub fn tool(&self) -> &'static str {
+        match self {
+            AgentAction::RunSql { .. } => RUN_SQL,
+            AgentAction::DescribeColumn { .. } => DESCRIBE_COLUMN,
+            AgentAction::ComparePeriods(_) => COMPARE_PERIODS,
+            AgentAction::FinalAnswer(_) => FINAL_ANSWER,
+        }
+    }
+}
+
+/// Период с включительными границами (даты YYYY-MM-DD)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Period {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl Period {
+    /// Условие на transaction_timestamp (верхняя граница - следующий день, не включительно)
+    pub fn condition(&self) -> String {
+        format!(
+            "transaction_timestamp >= '{}' AND transaction_timestamp < '{}'",
+            self.from,
+            self.to + Duration::days(1)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ComparePeriodsArgs {
+    #[serde(default)]
+    pub metric: PeriodMetric,
+    pub period_a: Period,
+    pub period_b: Period,
+    #[serde(default)]
+    pub dimension: Option<String>,
+    /// Фильтры на равенство по колонкам-измерениям
+    #[serde(default)]
+    pub filters: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FinalAnswer {
+    pub headline: String,
+    #[serde(default)]
+    pub insights: Vec<FinalInsight>,
+    #[serde(default)]
+    pub explanation: String,
+    #[serde(default)]
+    pub suggested_questions: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FinalInsight {
+    pub title: String,
+    pub description: String,
+    #[serde(default)]
+    pub significance: Option<String>,
+}
+
+impl FinalAnswer {
+    pub fn into_analysis(self) -> AnalysisResult {
+        AnalysisResult {
+            headline: self.headline,
+            insights: self
+                .insights
+                .into_iter()
+                .map(|insight| Insight {
+                    title: insight.title,
+                    description: insight.description,
+                    significance: match insight.significance.as_deref() {
+                        Some("High") => InsightSignificance::High,
+                        Some("Medium") => InsightSignificance::Medium,
+                        _ => InsightSignificance::Low,
+                    },
+                })
+                .collect(),
+            explanation: self.explanation,
+            suggested_questions: self.suggested_questions,
+            chart_type: None,
+            data: vec![],
+        }
+    }
+}
+
+/// Разбирает ответ модели. Ошибка - текст, который возвращается модели, чтобы она исправила шаг.
+pub fn parse_agent_step(response: &str) -> Result<AgentStep, String> {
+    let start = response.find('{').ok_or("Response must be a JSON object")?;
+    let end = response.rfind('}').ok_or("Response must be a JSON object")?;
+    if end < start {
+        return Err("Response must be a JSON object".to_string());
+    }
+    let raw: RawStep = serde_json::from_str(&response[start..=end])
+        .map_err(|e| format!("Invalid step JSON: {}", e))?;
+
+    let invalid_args = |e: serde_json::Error| format!("Invalid args for {}: {}", raw.tool, e);
+    let action = match raw.tool.as_str() {
+        RUN_SQL => AgentAction::RunSql {
+            sql: raw.args.get("sql").and_then(Value::as_str).ok_or("run_sql requires args.sql")?.trim().to_string(),
+        },
+        DESCRIBE_COLUMN => AgentAction::DescribeColumn {
+            column: raw.args.get("column").and_then(Value::as_str).ok_or("describe_column requires args.column")?.trim().to_lowercase(),
+        },
+        COMPARE_PERIODS => AgentAction::ComparePeriods(serde_json::from_value(raw.args.clone()).map_err(invalid_args)?),
+        FINAL_ANSWER => AgentAction::FinalAnswer(serde_json::from_value(raw.args.clone()).map_err(invalid_args)?),
+        other => return Err(format!("Unknown tool '{}'. Use one of: {}, {}, {}, {}", other, RUN_SQL, DESCRIBE_COLUMN, COMPARE_PERIODS, FINAL_ANSWER)),
+    };
+
+    Ok(AgentStep {
+        thought: raw.thought.filter(|thought| !thought.trim().is_empty()),
+        action,
+        args: raw.args,
+    })
+}
+
+/// SQL для describe_column: диапазон для чисел и дат, частые значения для измерений
+pub fn describe_column_sql(column: &str) -> Result<String, String> {
+    if !TRANSACTION_COLUMNS.contains(&column) {
+        return Err(format!("Unknown column '{}'", column));
+    }
+    let sql = if NUMERIC_COLUMNS.contains(&column) {
+        format!(
+            "SELECT COUNT(*) AS count, COUNT({c}) AS non_null, MIN({c})::float8 AS min_value, MAX({c})::float8 AS max_value, ROUND(AVG({c}), 2)::float8 AS avg_value FROM transactions;",
+            c = column
+        )
+    } else if column == "transaction_timestamp" {
+        format!("SELECT COUNT(*) AS count, MIN({c}) AS min_value, MAX({c}) AS max_value FROM transactions;", c = column)
+    } else if DIMENSIONS.contains(&column) {
+        format!(
+            "SELECT {c} AS value, COUNT(*) AS count FROM transactions GROUP BY {c} ORDER BY count DESC LIMIT {limit};",
+            c = column,
+            limit = OBSERVATION_ROWS
+        )
+    } else {
+        // Идентификаторы: только число различных значений
+        format!("SELECT COUNT(*) AS count, COUNT(DISTINCT {c}) AS distinct_values FROM transactions;", c = column)
+    };
+    Ok(sql)
+}
+
+/// SQL для compare_periods: метрика за два периода в одной строке (по измерению - в строке на значение)
+pub fn compare_periods_sql(args: &ComparePeriodsArgs) -> Result<String, String> {
+    for period in [&args.period_a, &args.period_b] {
+        if period.to < period.from {
+            return Err(format!("Period {}..{} ends before it starts", period.from, period.to));
+        }
+    }
+    if let Some(dimension) = &args.dimension {
+        check_dimension(dimension)?;
+    }
+
+    let mut filters = Vec::new();
+    for (column, value) in &args.filters {
+        if !DIMENSIONS.contains(&column.as_str()) {
+            return Err(format!("Unknown filter column '{}'. Use one of: {}", column, DIMENSIONS.join(", ")));
+        }
+        filters.push(format!("{} = '{}'", column, value.replace('\'', "''")));
+    }
+
+    let aggregates = PeriodAggregates::new(args.metric, &args.period_a, &args.period_b, filters);
+    let sql = match &args.dimension {
+        Some(dimension) => format!(
+            "SELECT {d}, {select} FROM transactions WHERE {where_} GROUP BY {d} ORDER BY period_a DESC LIMIT {limit};",
+            d = dimension,
+            select = aggregates.select,
+            where_ = aggregates.condition,
+            limit = OBSERVATION_ROWS
+        ),
+        None => format!("SELECT {} FROM transactions WHERE {};", aggregates.select, aggregates.condition),
+    };
+    Ok(sql)
+}
+
+/// Число из ячейки результата (NUMERIC может прийти строкой)
+pub fn numeric_value(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Добавляет к строкам сравнения абсолютное (`change`) и относительное (`change_pct`) изменение period_b к period_a
+pub fn add_period_deltas(rows: &mut [Value]) {
+    for row in rows.iter_mut() {
+        let Some(obj) = row.as_object_mut() else { continue };
+        let (Some(a), Some(b)) = (
+            obj.get("period_a").and_then(numeric_value),
+            obj.get("period_b").and_then(numeric_value),
+        ) else {
+            continue;
+        };
+        let change = b - a;
+        obj.insert("change".to_string(), serde_json::json!((change * 100.0).round() / 100.0));
+        let pct = (a != 0.0).then(|| (change / a * 1000.0).round() / 10.0);
+        obj.insert("change_pct".to_string(), serde_json::json!(pct));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::validator::validate_sql;
+
+    #[test]
+    fn test_parse_agent_step() {
+        let step = parse_agent_step("```json\n{\"thought\": \"check volume\", \"tool\": \"run_sql\", \"args\": {\"sql\": \"SELECT COUNT(*) FROM transactions;\"}}\n```").unwrap();
+        assert_eq!(step.action, AgentAction::RunSql { sql: "SELECT COUNT(*) FROM transactions;".to_string() });
+        assert_eq!(step.thought.as_deref(), Some("check volume"));
+
+        let step = parse_agent_step("{\"tool\": \"final_answer\", \"args\": {\"headline\": \"ECOM fell\"}}").unwrap();
+        assert!(matches!(step.action, AgentAction::FinalAnswer(FinalAnswer { ref headline, .. }) if headline == "ECOM fell"));
+
+        assert!(parse_agent_step("{\"tool\": \"drop_table\", \"args\": {}}").unwrap_err().contains("Unknown tool"));
+        assert!(parse_agent_step("{\"tool\": \"run_sql\", \"args\": {}}").is_err());
+        assert!(parse_agent_step("I think we should").is_err());
+    }
+
+    #[test]
+    fn test_describe_column_sql() {
+        for column in ["transaction_amount_kzt", "transaction_timestamp", "merchant_city", "card_id"] {
+            let sql = describe_column_sql(column).unwrap();
+            assert!(validate_sql(&sql).is_ok(), "{}", sql);
+        }
+        assert!(describe_column_sql("password").is_err());
+    }
+
+    #[test]
+    fn test_compare_periods_sql() {
+        let args: ComparePeriodsArgs = serde_json::from_value(serde_json::json!({
+            "metric": "sum",
+            "period_a": {"from": "2024-09-01", "to": "2024-09-30"},
+            "period_b": {"from": "2024-10-01", "to": "2024-10-31"},
+            "dimension": "mcc_category",
+            "filters": {"transaction_type": "ECOM", "merchant_city": "Kyzyl'orda"}
+        }))
+        .unwrap();
+        let sql = compare_periods_sql(&args).unwrap();
+        assert!(validate_sql(&sql).is_ok(), "{}", sql);
+        assert!(sql.contains("transaction_timestamp < '2024-11-01'"));
+        assert!(sql.contains("merchant_city = 'Kyzyl''orda'"));
+        assert!(sql.contains("GROUP BY mcc_category"));
+
+        let bad = ComparePeriodsArgs { dimension: Some("card_id".to_string()), ..args.clone() };
+        assert!(compare_periods_sql(&bad).is_err());
+        let bad = ComparePeriodsArgs { filters: BTreeMap::from([("1=1 OR merchant_city".to_string(), "x".to_string())]), ..args };
+        assert!(compare_periods_sql(&bad).is_err());
+    }
+
+    #[test]
+    fn test_add_period_deltas() {
+        let mut rows = vec![
+            serde_json::json!({"period_a": 200, "period_b": "150.5"}),
+            serde_json::json!({"period_a": 0, "period_b": 10}),
+        ];
+        add_period_deltas(&mut rows);
+        assert_eq!(rows[0]["change"], serde_json::json!(-49.5));
+        assert_eq!(rows[0]["change_pct"], serde_json::json!(-24.8));
+        assert_eq!(rows[1]["change_pct"], Value::Null);
+    }
+}
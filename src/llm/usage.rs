@@ -0,0 +1,109 @@
+use super::error::LlmError;
+use crate::limits::quota::{self, Quotas};
+use sqlx::PgPool;
+use std::cell::Cell;
+
+/// Расход LLM за один HTTP-запрос
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LlmUsage {
+    pub calls: u64,
+    pub tokens: u64,
+}
+
+/// Остаток дневной квоты пользователя (None - без ограничения)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LlmBudget {
+    pub remaining_calls: Option<u64>,
+    pub remaining_tokens: Option<u64>,
+}
+
+impl LlmBudget {
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining_calls == Some(0) || self.remaining_tokens == Some(0)
+    }
+}
+
+/// Чей расход учитывается в `llm_usage_daily` и с какими дневными квотами
+#[derive(Clone)]
+pub struct QuotaAccount {
+    pub pool: PgPool,
+    pub user_id: String,
+    pub quotas: Quotas,
+}
+
+struct QuotaScope {
+    account: Option<QuotaAccount>,  // None - расход считается, но квоты нет (анонимный запрос)
+    used: Cell<LlmUsage>,
+}
+
+tokio::task_local! {
+    static LLM_QUOTA: QuotaScope;
+}
+
+/// Выполняет future с учетом вызовов LLM в квоте `account` (без нее - только подсчет) и возвращает фактический расход.
+/// Все completion-запросы идут через `llm::error::complete`, который резервирует вызов и учитывает токены.
+pub async fn with_llm_quota<F: std::future::Future>(account: Option<QuotaAccount>, future: F) -> (F::Output, LlmUsage) {
+    let scope = QuotaScope { account, used: Cell::new(LlmUsage::default()) };
+    LLM_QUOTA
+        .scope(scope, async {
+            let output = future.await;
+            let used = LLM_QUOTA.with(|scope| scope.used.get());
+            (output, used)
+        })
+        .await
+}
+
+/// Резервирует вызов в дневной квоте до обращения к провайдеру. Проверка и увеличение счетчика -
+/// один UPDATE, поэтому параллельные запросы пользователя не превышают квоту.
+/// Вне `with_llm_quota` (прогрев модели при старте) и без аккаунта ограничений нет.
+pub async fn reserve_call() -> anyhow::Result<()> {
+    if let Ok(Some(account)) = LLM_QUOTA.try_with(|scope| scope.account.clone()) {
+        if !quota::reserve_llm_call(&account.pool, &account.user_id, &account.quotas).await? {
+            return Err(LlmError::QuotaExceeded("Daily LLM quota exceeded".to_string()).into());
+        }
+    }
+    let _ = LLM_QUOTA.try_with(|scope| {
+        let mut used = scope.used.get();
+        used.calls += 1;
+        scope.used.set(used);
+    });
+    Ok(())
+}
+
+/// Расход LLM с начала текущего запроса (вне `with_llm_quota` - нулевой)
+pub fn current_usage() -> LlmUsage {
+    LLM_QUOTA.try_with(|scope| scope.used.get()).unwrap_or_default()
+}
+
+/// Засчитывает токены ответа сразу, чтобы их видели следующие резервирования
+pub async fn record_tokens(tokens: u64) {
+    let Ok(Some(account)) = LLM_QUOTA.try_with(|scope| {
+        let mut used = scope.used.get();
+        used.tokens += tokens;
+        scope.used.set(used);
+        scope.account.clone()
+    }) else {
+        return;
+    };
+    if let Err(e) = quota::record_llm_tokens(&account.pool, &account.user_id, tokens).await {
+        tracing::warn!("Failed to record LLM tokens for {}: {}", account.user_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_usage_counted_without_quota_account() {
+        let (inside, used) = with_llm_quota(None, async {
+            reserve_call().await.unwrap();
+            record_tokens(120).await;
+            current_usage()
+        })
+        .await;
+        assert_eq!(inside, LlmUsage { calls: 1, tokens: 120 });
+        assert_eq!(used, inside);
+        assert_eq!(current_usage(), LlmUsage::default());
+    }
+}
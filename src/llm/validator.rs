@@ -1,29 +1,45 @@
-use anyhow::{Result, bail};
+use super::error::{SqlRejection, SqlRejectionKind};
+use sqlparser::{
+    ast::{
+        Distinct, Expr, Function, FunctionArg, FunctionArgExpr, FunctionArguments, GroupByExpr, Ident, JoinConstraint,
+        JoinOperator, ObjectName, Query, Select, SelectItem as AstSelectItem, SetExpr, Statement, TableFactor, Visit, Visitor,
+    },
+    dialect::PostgreSqlDialect,
+    parser::Parser,
+    tokenizer::{Token as SqlToken, Tokenizer, Whitespace},
+};
+use std::ops::ControlFlow;
 
-pub fn validate_sql(sql: &str) -> Result<()> {
+macro_rules! reject {
+    ($kind:ident, $($arg:tt)*) => {
+        return Err(SqlRejection::new(SqlRejectionKind::$kind, format!($($arg)*)))
+    };
+}
+
+pub fn validate_sql(sql: &str) -> Result<(), SqlRejection> {
     let sql_upper = sql.to_uppercase();
     
     // Check for dangerous commands
     let dangerous = ["DROP", "DELETE", "UPDATE", "INSERT", "ALTER", "CREATE", "TRUNCATE"];
     for keyword in dangerous {
         if sql_upper.contains(keyword) {
-            bail!("Only SELECT queries are allowed. Found: {}", keyword);
+            reject!(Forbidden, "Only SELECT queries are allowed. Found: {}", keyword);
         }
     }
     
     // Must contain SELECT
     if !sql_upper.contains("SELECT") {
-        bail!("Query must contain SELECT statement");
+        reject!(Malformed, "Query must contain SELECT statement");
     }
     
     // Must end with semicolon
     if !sql.trim().ends_with(';') {
-        bail!("Query must end with semicolon");
+        reject!(Malformed, "Query must end with semicolon");
     }
     
     // Basic syntax check (простая версия)
     if sql_upper.contains("FROM") && !sql_upper.contains("SELECT") {
-        bail!("Invalid SQL syntax");
+        reject!(Malformed, "Invalid SQL syntax");
     }
     
     // CRITICAL: Check for SELECT * without LIMIT or aggregation
@@ -37,7 +53,7 @@ pub fn validate_sql(sql: &str) -> Result<()> {
                              sql_upper.contains("MIN") || sql_upper.contains("GROUP BY");
         
         if !has_limit && !has_aggregation {
-            bail!("SELECT * without LIMIT or aggregation is FORBIDDEN. Database contains millions of rows. Use aggregation (COUNT, SUM, GROUP BY) or LIMIT (max 100).");
+            reject!(TooExpensive, "SELECT * without LIMIT or aggregation is FORBIDDEN. Database contains millions of rows. Use aggregation (COUNT, SUM, GROUP BY) or LIMIT (max 100).");
         }
     }
     
@@ -51,7 +67,7 @@ pub fn validate_sql(sql: &str) -> Result<()> {
         
         // If no LIMIT and no aggregation, this is dangerous
         if !has_limit && !has_aggregation {
-            bail!("Query without LIMIT or aggregation is FORBIDDEN. Database contains millions of rows. Use aggregation (COUNT, SUM, GROUP BY) or LIMIT (max 100).");
+            reject!(TooExpensive, "Query without LIMIT or aggregation is FORBIDDEN. Database contains millions of rows. Use aggregation (COUNT, SUM, GROUP BY) or LIMIT (max 100).");
         }
         
         // If LIMIT is too large, reject it
@@ -60,16 +76,843 @@ pub fn validate_sql(sql: &str) -> Result<()> {
             if let Some(limit_pos) = sql_upper.find("LIMIT") {
                 let after_limit = &sql_upper[limit_pos + 5..];
                 // Find the number after LIMIT
-                let limit_str = after_limit.trim().split_whitespace().next().unwrap_or("");
+                let limit_str = after_limit.split_whitespace().next().unwrap_or("");
                 if let Ok(limit_val) = limit_str.parse::<u32>() {
                     if limit_val > 1000 {
-                        bail!("LIMIT value too large ({}). Maximum allowed is 1000. Use aggregation instead.", limit_val);
+                        reject!(TooExpensive, "LIMIT value too large ({}). Maximum allowed is 1000. Use aggregation instead.", limit_val);
                     }
                 }
             }
         }
     }
     
+    // Только таблица transactions (без служебных таблиц и обхода через схему)
+    check_query_structure(sql)?;
+    
     Ok(())
 }
 
+
+/// Все колонки таблицы `transactions` (см. migrations/001_init.sql)
+pub const TRANSACTION_COLUMNS: &[&str] = &[
+    "id",
+    "transaction_id",
+    "transaction_timestamp",
+    "card_id",
+    "expiry_date",
+    "issuer_bank_name",
+    "merchant_id",
+    "merchant_mcc",
+    "mcc_category",
+    "merchant_city",
+    "transaction_type",
+    "transaction_amount_kzt",
+    "original_amount",
+    "transaction_currency",
+    "acquirer_country_iso",
+    "pos_entry_mode",
+    "wallet_type",
+];
+
+/// Таблицы, доступные запросам пользователей (служебные таблицы - api_keys, аудит - недоступны)
+pub const ALLOWED_TABLES: &[&str] = &["transactions"];
+
+/// Табличные функции, допустимые во FROM (например, ряд дат для графиков)
+const ALLOWED_TABLE_FUNCTIONS: &[&str] = &["generate_series", "unnest"];
+
+/// Агрегатные функции: по ним запрос считается агрегирующим (размер групп проверяется)
+const AGGREGATE_FUNCTIONS: &[&str] = &[
+    "count", "sum", "avg", "min", "max", "stddev", "stddev_pop", "stddev_samp", "variance", "var_pop",
+    "var_samp", "percentile_cont", "percentile_disc", "mode", "string_agg", "array_agg", "bool_and",
+    "bool_or", "corr", "covar_pop", "covar_samp", "regr_slope", "regr_intercept",
+];
+
+/// Остальные функции, которые можно вызывать в запросах: оконные, математика, строки и даты.
+/// Функции вне этого списка и AGGREGATE_FUNCTIONS (`query_to_xml`, `set_config`, `pg_read_file`,
+/// `row_to_json`, ...) запрещены.
+const ALLOWED_FUNCTIONS: &[&str] = &[
+    // Оконные
+    "row_number", "rank", "dense_rank", "percent_rank", "cume_dist", "ntile", "lag", "lead",
+    "first_value", "last_value", "nth_value",
+    // Математика и условные
+    "abs", "round", "trunc", "ceil", "ceiling", "floor", "sqrt", "power", "exp", "ln", "log", "sign",
+    "mod", "div", "greatest", "least", "nullif", "coalesce", "width_bucket",
+    // Строки
+    "lower", "upper", "length", "char_length", "initcap", "concat", "concat_ws", "left", "right",
+    "lpad", "rpad", "ltrim", "rtrim", "btrim", "replace", "split_part", "strpos", "substr", "to_char",
+    "to_number",
+    // Даты
+    "date", "date_trunc", "date_part", "age", "now", "current_date", "current_timestamp",
+    "localtimestamp", "to_date", "to_timestamp", "make_date", "make_interval", "justify_days",
+    // Ряды
+    "generate_series", "unnest",
+];
+
+/// Разбирает SQL в один запрос SELECT (диалект PostgreSQL). Блочные комментарии запрещены:
+/// через них прячут имена таблиц от проверок.
+pub fn parse_query(sql: &str) -> Result<Query, SqlRejection> {
+    let dialect = PostgreSqlDialect {};
+    let tokens = Tokenizer::new(&dialect, sql)
+        .tokenize()
+        .map_err(|e| SqlRejection::new(SqlRejectionKind::Malformed, format!("Invalid SQL: {}", e)))?;
+    if tokens.iter().any(|t| matches!(t, SqlToken::Whitespace(Whitespace::MultiLineComment(_)))) {
+        reject!(Forbidden, "Block comments are not allowed");
+    }
+
+    let mut statements = Parser::new(&dialect)
+        .with_tokens(tokens)
+        .parse_statements()
+        .map_err(|e| SqlRejection::new(SqlRejectionKind::Malformed, format!("Invalid SQL: {}", e)))?;
+    if statements.len() != 1 {
+        reject!(Forbidden, "Only a single SELECT statement is allowed");
+    }
+    match statements.pop() {
+        Some(Statement::Query(query)) => Ok(*query),
+        _ => reject!(Forbidden, "Only SELECT queries are allowed"),
+    }
+}
+
+/// LIMIT основного запроса (None - без LIMIT или не числом)
+pub fn query_limit(sql: &str) -> Option<u64> {
+    match parse_query(sql).ok()?.limit? {
+        Expr::Value(sqlparser::ast::Value::Number(n, _)) => n.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Проверяет, что SQL обращается только к разрешенным колонкам `transactions`.
+/// Учитывается любое упоминание колонки (SELECT, WHERE, GROUP BY, агрегаты, JOIN USING),
+/// а также `SELECT *` / `t.*`, которые раскрывают все колонки.
+pub fn check_column_access(sql: &str, allowed: &[&str]) -> Result<(), SqlRejection> {
+    let query = parse_query(sql)?;
+    match query.visit(&mut ColumnGuard { allowed }) {
+        ControlFlow::Break(rejection) => Err(rejection),
+        ControlFlow::Continue(()) => Ok(()),
+    }
+}
+
+struct ColumnGuard<'a> {
+    allowed: &'a [&'a str],
+}
+
+impl ColumnGuard<'_> {
+    fn check(&self, ident: &Ident) -> ControlFlow<SqlRejection> {
+        let column = ident.value.to_lowercase();
+        if TRANSACTION_COLUMNS.contains(&column.as_str()) && !self.allowed.contains(&column.as_str()) {
+            return deny(SqlRejectionKind::Forbidden, format!("Access to column '{}' is not allowed for your role", column));
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+impl Visitor for ColumnGuard<'_> {
+    type Break = SqlRejection;
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<SqlRejection> {
+        for select in selects(&query.body) {
+            if select.projection.iter().any(|item| matches!(item, AstSelectItem::Wildcard(_) | AstSelectItem::QualifiedWildcard(..))) {
+                return deny(SqlRejectionKind::Forbidden, "SELECT * is not allowed for your role, list the columns explicitly".to_string());
+            }
+            for join in select.from.iter().flat_map(|table| &table.joins) {
+                if let Some(JoinConstraint::Using(columns)) = join_constraint(&join.join_operator) {
+                    columns.iter().try_for_each(|column| self.check(column))?;
+                }
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<SqlRejection> {
+        match expr {
+            Expr::Identifier(ident) => self.check(ident),
+            Expr::CompoundIdentifier(parts) => parts.last().map_or(ControlFlow::Continue(()), |ident| self.check(ident)),
+            _ => ControlFlow::Continue(()),
+        }
+    }
+}
+
+/// SELECT-блоки верхнего уровня запроса (ветви UNION/INTERSECT/EXCEPT и скобки)
+fn selects(body: &SetExpr) -> Vec<&Select> {
+    match body {
+        SetExpr::Select(select) => vec![select],
+        SetExpr::Query(query) => selects(&query.body),
+        SetExpr::SetOperation { left, right, .. } => selects(left).into_iter().chain(selects(right)).collect(),
+        _ => vec![],
+    }
+}
+
+fn join_constraint(operator: &JoinOperator) -> Option<&JoinConstraint> {
+    match operator {
+        JoinOperator::Inner(c)
+        | JoinOperator::LeftOuter(c)
+        | JoinOperator::RightOuter(c)
+        | JoinOperator::FullOuter(c)
+        | JoinOperator::Semi(c)
+        | JoinOperator::LeftSemi(c)
+        | JoinOperator::RightSemi(c)
+        | JoinOperator::Anti(c)
+        | JoinOperator::LeftAnti(c)
+        | JoinOperator::RightAnti(c) => Some(c),
+        JoinOperator::AsOf { constraint, .. } => Some(constraint),
+        JoinOperator::CrossJoin | JoinOperator::CrossApply | JoinOperator::OuterApply => None,
+    }
+}
+
+fn deny(kind: SqlRejectionKind, message: String) -> ControlFlow<SqlRejection> {
+    ControlFlow::Break(SqlRejection::new(kind, message))
+}
+
+/// Колонка результата основного SELECT и колонки `transactions`, значения которых в нее попадают
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputColumn {
+    /// Имя колонки в результате (как ее назовет PostgreSQL), если его можно определить
+    pub name: Option<String>,
+    /// Колонки `transactions` из выражения, кроме аргументов COUNT (число строк значений не раскрывает)
+    pub sources: Vec<String>,
+    /// Выражение ссылается на колонки подзапросов или CTE, источник которых не определить
+    pub unresolved: bool,
+}
+
+/// Колонки результата запроса и их возможные источники
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryOutputs {
+    /// Элементы основного SELECT (для UNION - объединенные по позициям ветвей), кроме `*`
+    pub columns: Vec<OutputColumn>,
+    /// Колонки `transactions`, которые выводит любой SELECT запроса (подзапросы, CTE):
+    /// источники для колонок, которые нельзя сопоставить точно
+    pub projected: Vec<String>,
+}
+
+/// Разбирает, из каких колонок `transactions` получены колонки результата:
+/// `card_id AS card`, `MAX(transaction_id) AS x`, `transaction_id || ''`. None - SQL не разбирается.
+pub fn query_outputs(sql: &str) -> Option<QueryOutputs> {
+    let query = parse_query(sql).ok()?;
+
+    let mut projections = Projections::default();
+    let _ = query.visit(&mut projections);
+    // Переименование колонок подзапроса (`s(a, b)`) скрывает источники - учитываются все колонки
+    let projected = match projections.renamed {
+        true => TRANSACTION_COLUMNS.iter().map(|column| column.to_string()).collect(),
+        false => projections.columns,
+    };
+
+    let branches = selects(&query.body);
+    // Имена колонок ссылаются прямо на `transactions`, а не на подзапросы и CTE
+    let direct = query.with.is_none() && branches.iter().all(|select| reads_transactions_only(select));
+    let wildcard = branches.iter().flat_map(|select| &select.projection)
+        .any(|item| matches!(item, AstSelectItem::Wildcard(_) | AstSelectItem::QualifiedWildcard(..)));
+
+    let mut columns: Vec<OutputColumn> = Vec::new();
+    // С `*` в UNION позиции ветвей не сопоставить: все колонки считаются несопоставленными
+    if branches.len() == 1 || !wildcard {
+        for (branch, select) in branches.iter().enumerate() {
+            for (i, item) in select.projection.iter().enumerate() {
+                let (name, expr) = match item {
+                    AstSelectItem::UnnamedExpr(expr) => (output_name(expr), expr),
+                    AstSelectItem::ExprWithAlias { expr, alias } => (Some(alias.value.to_lowercase()), expr),
+                    AstSelectItem::Wildcard(_) | AstSelectItem::QualifiedWildcard(..) => continue,
+                };
+                let mut refs = ExpressionColumns::default();
+                let _ = expr.visit(&mut refs);
+                let unresolved = refs.unresolved || !direct;
+                match columns.get_mut(i).filter(|_| branch > 0) {
+                    // Колонку UNION называет первая ветвь, значения дают все
+                    Some(column) => {
+                        column.sources.extend(refs.columns);
+                        column.unresolved |= unresolved;
+                    }
+                    None => columns.push(OutputColumn { name, sources: refs.columns, unresolved }),
+                }
+            }
+        }
+    }
+
+    Some(QueryOutputs { columns, projected })
+}
+
+/// Имя колонки результата без псевдонима (правила PostgreSQL): колонка, функция, приведение типа
+fn output_name(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Identifier(ident) => Some(ident.value.to_lowercase()),
+        Expr::CompoundIdentifier(parts) => parts.last().map(|ident| ident.value.to_lowercase()),
+        Expr::Function(function) => function.name.0.last().map(|ident| ident.value.to_lowercase()),
+        Expr::Cast { expr, .. } | Expr::Nested(expr) => output_name(expr),
+        Expr::Case { .. } => Some("case".to_string()),
+        _ => None,
+    }
+}
+
+/// FROM основного SELECT - только таблица `transactions` (с соединениями)
+fn reads_transactions_only(select: &Select) -> bool {
+    let is_transactions = |factor: &TableFactor| matches!(
+        factor,
+        TableFactor::Table { name, args: None, .. } if name.0.len() == 1 && name.0[0].value.eq_ignore_ascii_case("transactions")
+    );
+    select.from.iter().all(|table| {
+        is_transactions(&table.relation) && table.joins.iter().all(|join| is_transactions(&join.relation))
+    })
+}
+
+/// Колонки `transactions` в выражении (кроме аргументов COUNT)
+#[derive(Default)]
+struct ExpressionColumns {
+    columns: Vec<String>,
+    unresolved: bool,
+    in_count: usize,
+}
+
+impl ExpressionColumns {
+    fn add(&mut self, ident: &Ident) {
+        let column = ident.value.to_lowercase();
+        if !TRANSACTION_COLUMNS.contains(&column.as_str()) {
+            self.unresolved = true;
+        } else if !self.columns.contains(&column) {
+            self.columns.push(column);
+        }
+    }
+}
+
+fn is_count(expr: &Expr) -> bool {
+    matches!(expr, Expr::Function(function) if function.name.0.len() == 1 && function.name.0[0].value.eq_ignore_ascii_case("count"))
+}
+
+impl Visitor for ExpressionColumns {
+    type Break = ();
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        if is_count(expr) {
+            self.in_count += 1;
+        } else if self.in_count == 0 {
+            match expr {
+                Expr::Identifier(ident) => self.add(ident),
+                Expr::CompoundIdentifier(parts) => parts.last().into_iter().for_each(|ident| self.add(ident)),
+                _ => {}
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        if is_count(expr) {
+            self.in_count -= 1;
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Колонки `transactions` в списках SELECT всех уровней запроса
+#[derive(Default)]
+struct Projections {
+    columns: Vec<String>,
+    renamed: bool,
+}
+
+impl Visitor for Projections {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if query.with.iter().flat_map(|with| &with.cte_tables).any(|cte| !cte.alias.columns.is_empty()) {
+            self.renamed = true;
+        }
+        for item in selects(&query.body).into_iter().flat_map(|select| &select.projection) {
+            let (AstSelectItem::UnnamedExpr(expr) | AstSelectItem::ExprWithAlias { expr, .. }) = item else { continue };
+            let mut refs = ExpressionColumns::default();
+            let _ = expr.visit(&mut refs);
+            for column in refs.columns {
+                if !self.columns.contains(&column) {
+                    self.columns.push(column);
+                }
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, factor: &TableFactor) -> ControlFlow<()> {
+        if let TableFactor::Derived { alias: Some(alias), .. } = factor {
+            self.renamed |= !alias.columns.is_empty();
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Элемент списка SELECT верхнего уровня
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectItem {
+    /// Имя колонки в результате (псевдоним, имя колонки или функции), если его можно определить
+    pub name: Option<String>,
+    /// Элемент содержит агрегатную функцию
+    pub aggregate: bool,
+    /// Элемент - `COUNT(...)` или `SUM(...)`: значения групп можно складывать
+    pub additive: bool,
+}
+
+/// Форма агрегирующего запроса верхнего уровня
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateSelect {
+    pub items: Vec<SelectItem>,
+    pub grouped: bool,
+}
+
+/// Разбирает основной SELECT запроса, если он агрегирующий (GROUP BY, HAVING или агрегаты в списке).
+/// Ok(None) - построчная выборка. Ошибка - агрегаты, размер групп которых нельзя узнать,
+/// дописав `COUNT(*)` в основной SELECT: агрегаты в подзапросах и CTE (кроме итогов по всей
+/// таблице вроде `(SELECT MAX(transaction_timestamp) FROM transactions)`), оконные агрегаты,
+/// UNION/INTERSECT/EXCEPT, DISTINCT ON.
+pub fn aggregate_select(sql: &str) -> Result<Option<AggregateSelect>, SqlRejection> {
+    let query = parse_query(sql)?;
+    let mut nested = NestedAggregates::default();
+    if let ControlFlow::Break(rejection) = query.visit(&mut nested) {
+        return Err(rejection);
+    }
+
+    let branches = selects(&query.body);
+    let [select] = branches.as_slice() else {
+        if branches.iter().any(|select| aggregate_calls(select).aggregating) {
+            reject!(Forbidden, "Minimum group size cannot be enforced for UNION/INTERSECT/EXCEPT with aggregates: aggregate in a single SELECT");
+        }
+        return Ok(None);
+    };
+    let calls = aggregate_calls(select);
+    if calls.window_aggregate || (calls.aggregating && calls.window) {
+        reject!(Forbidden, "Minimum group size cannot be enforced for window functions over aggregates");
+    }
+    if !calls.aggregating {
+        return Ok(None);
+    }
+    if matches!(select.distinct, Some(Distinct::On(_))) {
+        reject!(Forbidden, "Minimum group size cannot be enforced for DISTINCT ON with aggregates");
+    }
+
+    let items = select.projection.iter()
+        .map(|item| match item {
+            AstSelectItem::UnnamedExpr(expr) => select_item(output_name(expr), expr),
+            AstSelectItem::ExprWithAlias { expr, alias } => select_item(Some(alias.value.to_lowercase()), expr),
+            AstSelectItem::Wildcard(_) | AstSelectItem::QualifiedWildcard(..) => SelectItem { name: None, aggregate: false, additive: false },
+        })
+        .collect();
+    Ok(Some(AggregateSelect { items, grouped: is_grouped(select) }))
+}
+
+fn select_item(name: Option<String>, expr: &Expr) -> SelectItem {
+    let mut calls = AggregateCalls::default();
+    let _ = expr.visit(&mut calls);
+    SelectItem { name, aggregate: calls.aggregating, additive: is_additive(expr) }
+}
+
+/// `COUNT(...)` / `SUM(...)`, возможно с приведением типа
+fn is_additive(expr: &Expr) -> bool {
+    match expr {
+        Expr::Cast { expr, .. } | Expr::Nested(expr) => is_additive(expr),
+        Expr::Function(function) => {
+            function.over.is_none() && matches!(function_name(function).as_deref(), Some("count" | "sum"))
+        }
+        _ => false,
+    }
+}
+
+/// Имя функции без схемы в нижнем регистре
+pub fn function_name(function: &Function) -> Option<String> {
+    match function.name.0.as_slice() {
+        [name] => Some(name.value.to_lowercase()),
+        _ => None,
+    }
+}
+
+fn is_grouped(select: &Select) -> bool {
+    match &select.group_by {
+        GroupByExpr::All(_) => true,
+        GroupByExpr::Expressions(expressions, _) => !expressions.is_empty(),
+    }
+}
+
+/// Агрегаты SELECT (без подзапросов)
+#[derive(Default)]
+struct AggregateCalls {
+    depth: usize,
+    aggregating: bool,
+    window: bool,
+    window_aggregate: bool,
+}
+
+fn aggregate_calls(select: &Select) -> AggregateCalls {
+    let mut calls = AggregateCalls::default();
+    for item in &select.projection {
+        if let AstSelectItem::UnnamedExpr(expr) | AstSelectItem::ExprWithAlias { expr, .. } = item {
+            let _ = expr.visit(&mut calls);
+        }
+    }
+    calls.aggregating |= is_grouped(select) || select.having.is_some();
+    calls
+}
+
+impl Visitor for AggregateCalls {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, _query: &Query) -> ControlFlow<()> {
+        self.depth += 1;
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<()> {
+        self.depth -= 1;
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        if let (0, Expr::Function(function)) = (self.depth, expr) {
+            let aggregate = function_name(function).is_some_and(|name| AGGREGATE_FUNCTIONS.contains(&name.as_str()));
+            match function.over {
+                Some(_) => {
+                    self.window = true;
+                    self.window_aggregate |= aggregate;
+                }
+                None => self.aggregating |= aggregate,
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Отклоняет агрегаты вложенных запросов (CTE, подзапросы), кроме итогов по всей таблице
+#[derive(Default)]
+struct NestedAggregates {
+    depth: usize,
+}
+
+impl Visitor for NestedAggregates {
+    type Break = SqlRejection;
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<SqlRejection> {
+        self.depth += 1;
+        if self.depth == 1 {
+            return ControlFlow::Continue(());
+        }
+        for select in selects(&query.body) {
+            let calls = aggregate_calls(select);
+            // `(SELECT MAX(transaction_timestamp) FROM transactions)` - одна группа из всех строк
+            let whole_table = !is_grouped(select) && select.selection.is_none() && select.having.is_none()
+                && !select.from.is_empty() && reads_transactions_only(select);
+            if calls.window_aggregate || (calls.aggregating && !whole_table) {
+                return deny(
+                    SqlRejectionKind::Forbidden,
+                    "Minimum group size cannot be enforced for aggregates in subqueries or CTEs: aggregate in the main SELECT".to_string(),
+                );
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<SqlRejection> {
+        self.depth -= 1;
+        ControlFlow::Continue(())
+    }
+}
+
+/// Проверяет структуру запроса: FROM/JOIN ссылаются только на `transactions`, CTE этого же
+/// запроса, подзапросы и разрешенные табличные функции; вызываются только функции из
+/// AGGREGATE_FUNCTIONS и ALLOWED_FUNCTIONS; нет ссылок на строку целиком (`row_to_json(t)`, `t.*` в аргументах),
+/// SELECT INTO и FOR UPDATE. Имена со схемой (`public.transactions`, `pg_catalog.*`) запрещены.
+pub fn check_query_structure(sql: &str) -> Result<(), SqlRejection> {
+    let query = parse_query(sql)?;
+    let mut relations = RelationNames::default();
+    let _ = query.visit(&mut relations);
+    let mut guard = StructureGuard { relations: relations.0, ctes: Vec::new() };
+    match query.visit(&mut guard) {
+        ControlFlow::Break(rejection) => Err(rejection),
+        ControlFlow::Continue(()) => Ok(()),
+    }
+}
+
+/// Имена и алиасы всех источников строк запроса (таблицы, CTE, подзапросы, функции)
+#[derive(Default)]
+struct RelationNames(Vec<String>);
+
+impl Visitor for RelationNames {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if let Some(with) = &query.with {
+            self.0.extend(with.cte_tables.iter().map(|cte| cte.alias.name.value.to_lowercase()));
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, factor: &TableFactor) -> ControlFlow<()> {
+        if let TableFactor::Table { name, .. } = factor {
+            self.0.extend(name.0.last().map(|ident| ident.value.to_lowercase()));
+        }
+        let alias = match factor {
+            TableFactor::Table { alias, .. }
+            | TableFactor::Derived { alias, .. }
+            | TableFactor::Function { alias, .. }
+            | TableFactor::UNNEST { alias, .. }
+            | TableFactor::NestedJoin { alias, .. } => alias.as_ref(),
+            _ => None,
+        };
+        self.0.extend(alias.map(|alias| alias.name.value.to_lowercase()));
+        ControlFlow::Continue(())
+    }
+}
+
+struct StructureGuard {
+    relations: Vec<String>,
+    /// Имена CTE, видимые в текущем запросе и его подзапросах
+    ctes: Vec<Vec<String>>,
+}
+
+impl StructureGuard {
+    fn check_set_expr(body: &SetExpr) -> ControlFlow<SqlRejection> {
+        match body {
+            SetExpr::Select(select) if select.into.is_some() => {
+                deny(SqlRejectionKind::Forbidden, "SELECT INTO is not allowed".to_string())
+            }
+            SetExpr::Select(_) | SetExpr::Query(_) | SetExpr::Values(_) => ControlFlow::Continue(()),
+            SetExpr::SetOperation { left, right, .. } => {
+                Self::check_set_expr(left)?;
+                Self::check_set_expr(right)
+            }
+            _ => deny(SqlRejectionKind::Forbidden, "Only SELECT queries are allowed".to_string()),
+        }
+    }
+
+    fn check_function_name(name: &ObjectName, allowed: &[&[&str]], place: &str) -> ControlFlow<SqlRejection> {
+        let [function] = name.0.as_slice() else {
+            return deny(SqlRejectionKind::Forbidden, format!("Schema-qualified function names are not allowed: {}", name));
+        };
+        let function = function.value.to_lowercase();
+        if !allowed.iter().any(|list| list.contains(&function.as_str())) {
+            return deny(SqlRejectionKind::Forbidden, format!("Function '{}' is not allowed{}", function, place));
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+impl Visitor for StructureGuard {
+    type Break = SqlRejection;
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<SqlRejection> {
+        if !query.locks.is_empty() || query.for_clause.is_some() {
+            return deny(SqlRejectionKind::Forbidden, "FOR UPDATE / FOR XML clauses are not allowed".to_string());
+        }
+        Self::check_set_expr(&query.body)?;
+        let ctes = query.with.iter().flat_map(|with| &with.cte_tables).map(|cte| cte.alias.name.value.to_lowercase());
+        self.ctes.push(ctes.collect());
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<SqlRejection> {
+        self.ctes.pop();
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, factor: &TableFactor) -> ControlFlow<SqlRejection> {
+        match factor {
+            TableFactor::Table { name, args: Some(_), .. } | TableFactor::Function { name, .. } => {
+                Self::check_function_name(name, &[ALLOWED_TABLE_FUNCTIONS], " in FROM")
+            }
+            TableFactor::Table { name, .. } => {
+                let [table] = name.0.as_slice() else {
+                    return deny(SqlRejectionKind::Forbidden, format!("Schema-qualified table names are not allowed: {}", name));
+                };
+                let table = table.value.to_lowercase();
+                let is_cte = self.ctes.iter().flatten().any(|cte| *cte == table);
+                if !ALLOWED_TABLES.contains(&table.as_str()) && !is_cte {
+                    return deny(SqlRejectionKind::Forbidden, format!("Access to table '{}' is not allowed", table));
+                }
+                ControlFlow::Continue(())
+            }
+            TableFactor::Derived { .. } | TableFactor::NestedJoin { .. } | TableFactor::UNNEST { .. } => ControlFlow::Continue(()),
+            other => deny(SqlRejectionKind::Forbidden, format!("Unsupported FROM item: {}", other)),
+        }
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<SqlRejection> {
+        match expr {
+            Expr::Function(function) => {
+                Self::check_function_name(&function.name, &[AGGREGATE_FUNCTIONS, ALLOWED_FUNCTIONS], "")?;
+                if let FunctionArguments::List(list) = &function.args {
+                    let whole_row = list.args.iter().any(|arg| {
+                        matches!(
+                            arg,
+                            FunctionArg::Unnamed(FunctionArgExpr::QualifiedWildcard(_))
+                                | FunctionArg::Named { arg: FunctionArgExpr::QualifiedWildcard(_), .. }
+                                | FunctionArg::ExprNamed { arg: FunctionArgExpr::QualifiedWildcard(_), .. }
+                        )
+                    });
+                    if whole_row {
+                        return deny(SqlRejectionKind::Forbidden, "Whole-row references are not allowed".to_string());
+                    }
+                }
+                ControlFlow::Continue(())
+            }
+            Expr::Identifier(ident) => {
+                let name = ident.value.to_lowercase();
+                if self.relations.contains(&name) && !TRANSACTION_COLUMNS.contains(&name.as_str()) {
+                    return deny(SqlRejectionKind::Forbidden, format!("Whole-row reference '{}' is not allowed", name));
+                }
+                ControlFlow::Continue(())
+            }
+            Expr::QualifiedWildcard(..) | Expr::CompositeAccess { .. } => {
+                deny(SqlRejectionKind::Forbidden, "Whole-row references are not allowed".to_string())
+            }
+            _ => ControlFlow::Continue(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALLOWED: &[&str] = &["transaction_timestamp", "merchant_city", "transaction_amount_kzt"];
+
+    #[test]
+    fn test_allowed_columns_pass() {
+        let sql = "SELECT merchant_city, COUNT(*), SUM(transaction_amount_kzt) AS total FROM transactions \
+                   WHERE transaction_timestamp >= '2024-01-01' AND merchant_city <> 'card_id' GROUP BY merchant_city LIMIT 10;";
+        assert!(check_column_access(sql, ALLOWED).is_ok());
+    }
+
+    #[test]
+    fn test_restricted_column_rejected() {
+        let err = check_column_access("SELECT COUNT(DISTINCT card_id) FROM transactions;", ALLOWED).unwrap_err();
+        assert!(err.message.contains("card_id"));
+
+        let err = check_column_access("SELECT t.\"CARD_ID\" FROM transactions t LIMIT 5;", ALLOWED).unwrap_err();
+        assert!(err.message.contains("card_id"));
+
+        // Экранированная кавычка в E-строке не сбивает разбор
+        let err = check_column_access("SELECT E'\\'', card_id FROM transactions LIMIT 5;", ALLOWED).unwrap_err();
+        assert!(err.message.contains("card_id"));
+    }
+
+    #[test]
+    fn test_table_access() {
+        assert!(check_query_structure(
+            "SELECT merchant_city, COUNT(*) FROM transactions t WHERE EXTRACT(YEAR FROM transaction_timestamp) = 2024 GROUP BY 1;"
+        ).is_ok());
+        assert!(check_query_structure(
+            "WITH daily AS (SELECT DATE(transaction_timestamp) AS d, COUNT(*) AS c FROM transactions GROUP BY 1) \
+             SELECT d, c FROM daily ORDER BY d LIMIT 10;"
+        ).is_ok());
+        assert!(check_query_structure(
+            "SELECT x.city FROM (SELECT merchant_city AS city FROM transactions) x JOIN transactions t ON t.merchant_city = x.city LIMIT 5;"
+        ).is_ok());
+
+        assert!(check_query_structure("SELECT key_hash FROM api_keys LIMIT 1;").is_err());
+        assert!(check_query_structure("SELECT COUNT(*) FROM public.transactions;").is_err());
+        assert!(check_query_structure("SELECT COUNT(*) FROM transactions t, query_audit_log q;").is_err());
+        assert!(check_query_structure("SELECT * FROM pg_read_file('/etc/passwd');").is_err());
+        assert!(check_query_structure(
+            "SELECT COUNT(*) FROM transactions t JOIN pg_catalog.pg_user u ON true;"
+        ).is_err());
+    }
+
+    #[test]
+    fn test_structure_bypasses_rejected() {
+        assert!(check_query_structure(
+            "SELECT DATE(transaction_timestamp) AS day, COUNT(*) FROM generate_series(1, 3) g, transactions \
+             WHERE transaction_timestamp >= CURRENT_DATE - INTERVAL '7 days' GROUP BY 1;"
+        ).is_ok());
+        assert!(check_query_structure(
+            "SELECT merchant_city, RANK() OVER (ORDER BY SUM(transaction_amount_kzt) DESC) FROM transactions GROUP BY 1;"
+        ).is_ok());
+
+        let bypasses = [
+            "SELECT key_hash, principal_id FROM /**/ api_keys LIMIT 5;",
+            "SELECT COUNT(*) FROM /**/ public.transactions;",
+            "SELECT row_to_json(t) FROM transactions t LIMIT 5;",
+            "SELECT to_char(t) FROM transactions t LIMIT 5;",
+            "SELECT (t).card_id FROM transactions t LIMIT 5;",
+            "SELECT E'\\'', card_id FROM api_keys LIMIT 5;",
+            "SELECT query_to_xml('select card_id from transactions', true, true, '') FROM transactions LIMIT 1;",
+            "SELECT pg_read_file('/etc/passwd') FROM transactions LIMIT 1;",
+            "SELECT set_config('app.issuer_banks', '*', true) FROM transactions LIMIT 1;",
+            "SELECT pg_catalog.lower(merchant_city) FROM transactions LIMIT 1;",
+            "SELECT merchant_city FROM transactions LIMIT 1 FOR UPDATE;",
+            "SELECT merchant_city INTO copy FROM transactions LIMIT 1;",
+            "SELECT 1 FROM transactions LIMIT 1; SELECT 2;",
+            "SELECT (SELECT key_hash FROM api_keys LIMIT 1), (WITH api_keys AS (SELECT 1) SELECT 1) FROM transactions LIMIT 1;",
+        ];
+        for sql in bypasses {
+            assert!(check_query_structure(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_query_outputs() {
+        let outputs = query_outputs(
+            "SELECT t.card_id AS card, transaction_id tx, expiry_date::text, MAX(transaction_id) AS x, \
+             transaction_id || '' AS s, COUNT(DISTINCT card_id) AS cards, merchant_city FROM transactions t GROUP BY 1, 2, 3, 7;",
+        ).unwrap();
+        let columns: Vec<_> = outputs.columns.iter().map(|c| (c.name.as_deref().unwrap(), c.sources.clone())).collect();
+        assert_eq!(columns, vec![
+            ("card", vec!["card_id".to_string()]),
+            ("tx", vec!["transaction_id".to_string()]),
+            ("expiry_date", vec!["expiry_date".to_string()]),
+            ("x", vec!["transaction_id".to_string()]),
+            ("s", vec!["transaction_id".to_string()]),
+            ("cards", vec![]),
+            ("merchant_city", vec!["merchant_city".to_string()]),
+        ]);
+        assert!(outputs.columns.iter().all(|c| !c.unresolved));
+
+        // Безымянное выражение и колонка подзапроса
+        let outputs = query_outputs("SELECT transaction_id + 0 FROM transactions LIMIT 5;").unwrap();
+        assert_eq!(outputs.columns[0].name, None);
+        let outputs = query_outputs("SELECT c FROM (SELECT card_id AS c FROM transactions) s LIMIT 5;").unwrap();
+        assert!(outputs.columns[0].unresolved);
+        assert_eq!(outputs.projected, vec!["card_id"]);
+        let outputs = query_outputs("SELECT d FROM (SELECT * FROM transactions) s(a, b, c, d) LIMIT 5;").unwrap();
+        assert_eq!(outputs.projected.len(), TRANSACTION_COLUMNS.len());
+
+        // UNION: значения колонки дают все ветви
+        let outputs = query_outputs("SELECT merchant_city AS v FROM transactions UNION SELECT card_id FROM transactions;").unwrap();
+        assert_eq!(outputs.columns[0].sources, vec!["merchant_city", "card_id"]);
+    }
+
+    #[test]
+    fn test_aggregate_select() {
+        let sql = "SELECT merchant_city, t.wallet_type, COUNT(*) AS cnt, SUM(transaction_amount_kzt) total, \
+                   ROUND(AVG(transaction_amount_kzt), 2) AS avg_amount \
+                   FROM transactions t GROUP BY 1, 2 ORDER BY cnt DESC LIMIT 10;";
+        let shape = aggregate_select(sql).unwrap().unwrap();
+        assert!(shape.grouped);
+        let names: Vec<_> = shape.items.iter().map(|i| i.name.clone().unwrap()).collect();
+        assert_eq!(names, vec!["merchant_city", "wallet_type", "cnt", "total", "avg_amount"]);
+        let additive: Vec<_> = shape.items.iter().map(|i| i.additive).collect();
+        assert_eq!(additive, vec![false, false, true, true, false]);
+        assert!(!shape.items[0].aggregate && shape.items[4].aggregate);
+
+        let shape = aggregate_select("WITH d AS (SELECT merchant_city FROM transactions) SELECT COUNT(*) FROM transactions;").unwrap().unwrap();
+        assert!(!shape.grouped);
+        assert_eq!(shape.items[0].name.as_deref(), Some("count"));
+
+        assert!(aggregate_select("SELECT merchant_city FROM transactions LIMIT 10;").unwrap().is_none());
+        assert!(aggregate_select("SELECT merchant_city, ROW_NUMBER() OVER () FROM transactions LIMIT 5;").unwrap().is_none());
+        // Размер групп таких агрегатов не узнать - запрос отклоняется, а не выполняется без проверки
+        assert!(aggregate_select("SELECT SUM(transaction_amount_kzt) OVER () FROM transactions LIMIT 5;").is_err());
+        assert!(aggregate_select("SELECT COUNT(*) FROM transactions UNION SELECT 1;").is_err());
+    }
+
+    #[test]
+    fn test_star_projection_rejected() {
+        assert!(check_column_access("SELECT * FROM transactions LIMIT 5;", ALLOWED).is_err());
+        assert!(check_column_access("SELECT t.* FROM transactions t LIMIT 5;", ALLOWED).is_err());
+        assert!(check_column_access(
+            "SELECT merchant_city FROM transactions a JOIN transactions b USING (card_id) LIMIT 5;",
+            ALLOWED
+        ).is_err());
+        assert!(check_column_access(
+            "SELECT merchant_city, transaction_amount_kzt * 2 FROM transactions LIMIT 5;",
+            ALLOWED
+        ).is_ok());
+    }
+
+    #[test]
+    fn test_query_limit() {
+        assert_eq!(query_limit("SELECT merchant_city, COUNT(*) FROM transactions GROUP BY 1 ORDER BY 2 DESC LIMIT 10;"), Some(10));
+        assert_eq!(query_limit("SELECT COUNT(*) FROM (SELECT * FROM transactions LIMIT 5) t"), None);
+        assert_eq!(query_limit("SELECT COUNT(*) FROM transactions"), None);
+    }
+}
@@ -1,31 +1,41 @@
 mod api;
 mod analysis;
+mod analytics;
+mod auth;
 mod cache;
 mod chat;
 mod query_context;
 mod config;
+mod limits;
 mod db;
 mod error;
+mod i18n;
 mod llm;
+mod privacy;
+mod safety;
 mod state;
+mod tenants;
 mod utils;
 
 use anyhow::Result;
 use axum::Router;
+use axum::http::HeaderName;
+use std::net::SocketAddr;
 use tower_http::cors::CorsLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    utils::logger::init();
-    
     // Load configuration
     let config = config::Config::from_env()?;
+    
+    // Initialize logging (console + optional OTLP / trace file)
+    utils::logger::init(&config);
     tracing::info!("Configuration loaded");
     
     // Initialize database
-    let db_pool = db::pool::create_pool(&config.database_url).await?;
+    let db_pool = db::pool::create_pool(&config.database_url, config.db_statement_timeout_ms).await?;
     tracing::info!("Database connected");
     
     // Run migrations only if tables don't exist (optional)
@@ -50,6 +60,13 @@ async fn main() -> Result<()> {
     } else {
         tracing::info!("Tables already exist, skipping migrations");
     }
+    db::schema::ensure_auxiliary_tables(&db_pool).await?;
+    
+    // CLI: управление API-ключами без запуска сервера
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("keys") {
+        return auth::cli::run(&args[1..], &db_pool).await;
+    }
     
     // Initialize LLM client
     let llm_client = llm::client::LLMClient::new(&config).await?;
@@ -63,13 +80,42 @@ async fn main() -> Result<()> {
     }
     
     // Create application state
-    let state = state::AppState::new(db_pool, llm_client, config.clone());
+    let tenant_registry = tenants::TenantRegistry::load(&db_pool, config.tenants.as_deref()).await?;
+    tracing::info!("Tenants loaded: {}", tenant_registry.len());
+    
+    let column_policy = privacy::ColumnPolicy::from_config(&config)?;
+    let safety_policy = safety::PolicyEngine::load(config.safety_policy_file.as_deref())?;
+    tracing::info!("Safety policy loaded: {} rules", safety_policy.rule_count());
+    let user_safety = utils::user_safety::UserSafetyManager::load(
+        db_pool.clone(),
+        utils::user_safety::SafetySettings {
+            max_warnings: config.max_warnings,
+            ban_duration_hours: config.ban_duration_hours,
+            warning_decay_hours: config.warning_decay_hours,
+        },
+        std::sync::Arc::new(safety_policy),
+    ).await?;
+    
+    let state = state::AppState::new(db_pool, llm_client, tenant_registry, column_policy, user_safety, config.clone());
+    
+    if !config.auth_enabled {
+        tracing::warn!("Authentication is disabled (AUTH_ENABLED=false): user_id from requests is trusted");
+        if config.anonymous_admin {
+            tracing::error!("ANONYMOUS_ADMIN=true: every unauthenticated request has the admin role. Never use this outside local development");
+        }
+    } else if config.anonymous_admin {
+        tracing::warn!("ANONYMOUS_ADMIN is ignored while authentication is enabled");
+    }
     
     // Build router
+    // Request ID: генерируется (или берется из заголовка x-request-id) и возвращается в ответе
+    let request_id_header = HeaderName::from_static(utils::logger::REQUEST_ID_HEADER);
     let app = Router::new()
-        .nest("/api", api::routes())
+        .nest("/api", api::routes(state.clone()))
         .layer(CorsLayer::permissive())
-        .layer(TraceLayer::new_for_http())
+        .layer(TraceLayer::new_for_http().make_span_with(utils::logger::request_span))
+        .layer(PropagateRequestIdLayer::new(request_id_header.clone()))
+        .layer(SetRequestIdLayer::new(request_id_header, MakeRequestUuid))
         .with_state(state);
     
     // Start server
@@ -77,7 +123,10 @@ async fn main() -> Result<()> {
     let listener = tokio::net::TcpListener::bind(&addr).await?;
     tracing::info!("Server running on http://{}", addr);
     
-    axum::serve(listener, app).await?;
+    // ConnectInfo нужен для ограничения частоты запросов по IP
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+    
+    utils::logger::shutdown();
     
     Ok(())
 }
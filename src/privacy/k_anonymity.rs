@@ -0,0 +1,223 @@
+use crate::llm::{
+    error::SqlRejection,
+    validator::{aggregate_select, parse_query, AggregateSelect},
+};
+use serde::Serialize;
+use serde_json::Value;
+use sqlparser::{
+    ast::{Expr, Ident, Select, SelectItem, SetExpr},
+    dialect::PostgreSqlDialect,
+    parser::Parser,
+};
+
+/// Служебная колонка с размером группы, которую backend добавляет в агрегирующие запросы
+pub const GROUP_SIZE_COLUMN: &str = "__group_size";
+
+/// Итог проверки минимального размера групп (возвращается в `meta`)
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupSuppression {
+    pub min_group_size: u64,
+    /// Сколько групп меньше порога убрано из результата
+    pub suppressed_groups: usize,
+    /// Убранные группы объединены в строку "прочие"
+    pub merged_into_other: bool,
+    /// Убранные строки: по ним аналитика отличает скрытые периоды от пустых. В ответ не попадают.
+    #[serde(skip)]
+    pub suppressed_rows: Vec<Value>,
+}
+
+/// Дописывает `COUNT(*)` в основной SELECT агрегирующего запроса, чтобы знать размер каждой группы.
+/// Ok(None) - запрос не агрегирующий, проверка не нужна. Агрегирующий запрос, который нельзя
+/// так дополнить (см. `aggregate_select`), отклоняется, а не выполняется без проверки.
+pub fn inject_group_size(sql: &str) -> Result<Option<String>, SqlRejection> {
+    if aggregate_select(sql)?.is_none() {
+        return Ok(None);
+    }
+    let mut query = parse_query(sql)?;
+    let Some(select) = main_select(&mut query.body) else {
+        return Ok(None);
+    };
+    select.projection.push(SelectItem::ExprWithAlias { expr: count_star(), alias: Ident::new(GROUP_SIZE_COLUMN) });
+    Ok(Some(query.to_string()))
+}
+
+fn main_select(body: &mut SetExpr) -> Option<&mut Select> {
+    match body {
+        SetExpr::Select(select) => Some(select),
+        SetExpr::Query(query) => main_select(&mut query.body),
+        _ => None,
+    }
+}
+
+fn count_star() -> Expr {
+    Parser::new(&PostgreSqlDialect {})
+        .try_with_sql("COUNT(*)")
+        .and_then(|mut parser| parser.parse_expr())
+        .expect("COUNT(*) expression")
+}
+
+/// Убирает из результата группы, в которые попало меньше `min_group_size` транзакций.
+/// Если в сумме убранные группы не меньше порога, они объединяются в строку "прочие":
+/// измерения получают метку `other_label`, COUNT/SUM складываются, остальные метрики - null.
+/// Служебная колонка размера группы удаляется из всех строк.
+pub fn enforce_min_group_size(
+    sql: &str,
+    rows: &mut Vec<Value>,
+    min_group_size: u64,
+    other_label: &str,
+) -> Option<GroupSuppression> {
+    let sizes: Vec<Option<u64>> = rows.iter_mut()
+        .map(|row| row.as_object_mut()
+            .and_then(|obj| obj.remove(GROUP_SIZE_COLUMN))
+            .and_then(|size| size.as_u64()))
+        .collect();
+    if min_group_size == 0 || sizes.iter().all(Option::is_none) {
+        return None;
+    }
+
+    let mut kept = Vec::with_capacity(rows.len());
+    let mut suppressed = Vec::new();
+    for (row, size) in rows.drain(..).zip(sizes) {
+        match size {
+            Some(size) if size < min_group_size => suppressed.push((row, size)),
+            _ => kept.push(row),
+        }
+    }
+    *rows = kept;
+
+    if suppressed.is_empty() {
+        return None;
+    }
+
+    let shape = aggregate_select(sql).ok().flatten();
+    let suppressed_total: u64 = suppressed.iter().map(|(_, size)| size).sum();
+    let merged_into_other = shape.as_ref().is_some_and(|s| s.grouped) && suppressed_total >= min_group_size;
+    let suppressed_rows: Vec<Value> = suppressed.into_iter().map(|(row, _)| row).collect();
+    if let (true, Some(shape)) = (merged_into_other, &shape) {
+        rows.push(other_row(shape, &suppressed_rows, other_label));
+    }
+
+    Some(GroupSuppression {
+        min_group_size,
+        suppressed_groups: suppressed_rows.len(),
+        merged_into_other,
+        suppressed_rows,
+    })
+}
+
+/// Строка "прочие" из убранных групп
+fn other_row(shape: &AggregateSelect, suppressed: &[Value], other_label: &str) -> Value {
+    let Some(first) = suppressed.first().and_then(Value::as_object) else {
+        return Value::Null;
+    };
+
+    let mut other = serde_json::Map::new();
+    for key in first.keys() {
+        let item = shape.items.iter().find(|item| item.name.as_deref() == Some(key.to_lowercase().as_str()));
+        let value = match item {
+            Some(item) if item.additive => sum_values(suppressed.iter().filter_map(|row| row.get(key))),
+            Some(item) if !item.aggregate => Value::String(other_label.to_string()),
+            _ => Value::Null,
+        };
+        other.insert(key.clone(), value);
+    }
+    Value::Object(other)
+}
+
+/// Сумма чисел (NUMERIC приходит из базы строкой). Целые остаются целыми.
+fn sum_values<'a>(values: impl Iterator<Item = &'a Value>) -> Value {
+    let mut int_sum: i64 = 0;
+    let mut float_sum = 0.0;
+    let mut all_ints = true;
+    for value in values {
+        match value {
+            Value::Number(n) if n.is_i64() => {
+                int_sum += n.as_i64().unwrap_or(0);
+                float_sum += n.as_f64().unwrap_or(0.0);
+            }
+            Value::Number(n) => {
+                all_ints = false;
+                float_sum += n.as_f64().unwrap_or(0.0);
+            }
+            Value::String(s) => {
+                all_ints = false;
+                float_sum += s.parse::<f64>().unwrap_or(0.0);
+            }
+            _ => {}
+        }
+    }
+    if all_ints {
+        Value::from(int_sum)
+    } else {
+        serde_json::Number::from_f64(float_sum).map_or(Value::Null, Value::Number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SQL: &str = "SELECT merchant_city, COUNT(*) AS cnt, SUM(transaction_amount_kzt) AS total, \
+                       AVG(transaction_amount_kzt) AS avg_amount FROM transactions GROUP BY merchant_city;";
+
+    #[test]
+    fn test_inject_group_size() {
+        assert_eq!(
+            inject_group_size("SELECT merchant_city, COUNT(*) FROM transactions GROUP BY 1;").unwrap().unwrap(),
+            "SELECT merchant_city, COUNT(*), COUNT(*) AS __group_size FROM transactions GROUP BY 1"
+        );
+        assert!(inject_group_size("SELECT merchant_city FROM transactions LIMIT 10;").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_uninstrumentable_aggregates_rejected() {
+        let rejected = [
+            "SELECT city, cnt FROM (SELECT merchant_city city, COUNT(*) cnt FROM transactions GROUP BY 1) s;",
+            "WITH c AS (SELECT merchant_city, COUNT(*) AS cnt FROM transactions GROUP BY 1) SELECT * FROM c;",
+            "SELECT merchant_city, SUM(transaction_amount_kzt) OVER (PARTITION BY merchant_city) FROM transactions LIMIT 5;",
+            "SELECT merchant_city, COUNT(*) FROM transactions GROUP BY 1 UNION SELECT 'x', 1;",
+            "SELECT merchant_city, (SELECT COUNT(*) FROM transactions t2 WHERE t2.card_id = t.card_id) FROM transactions t LIMIT 5;",
+        ];
+        for sql in rejected {
+            assert!(inject_group_size(sql).is_err(), "{}", sql);
+        }
+
+        // Итог по всей таблице в условии - одна большая группа
+        let sql = "SELECT date_trunc('hour', transaction_timestamp) AS period, COUNT(*) AS value FROM transactions \
+                   WHERE transaction_timestamp >= (SELECT MAX(transaction_timestamp) FROM transactions) - INTERVAL '14 days' GROUP BY 1;";
+        assert!(inject_group_size(sql).unwrap().unwrap().ends_with("COUNT(*) AS value, COUNT(*) AS __group_size FROM transactions WHERE transaction_timestamp >= (SELECT MAX(transaction_timestamp) FROM transactions) - INTERVAL '14 days' GROUP BY 1"));
+    }
+
+    #[test]
+    fn test_small_groups_merged_into_other() {
+        let mut rows = vec![
+            json!({"merchant_city": "Almaty", "cnt": 100, "total": "5000.50", "avg_amount": "50.00", "__group_size": 100}),
+            json!({"merchant_city": "Taraz", "cnt": 3, "total": "30.00", "avg_amount": "10.00", "__group_size": 3}),
+            json!({"merchant_city": "Turkestan", "cnt": 2, "total": "20.25", "avg_amount": "10.10", "__group_size": 2}),
+        ];
+        let suppression = enforce_min_group_size(SQL, &mut rows, 5, "Other").unwrap();
+        assert_eq!(suppression.suppressed_groups, 2);
+        assert!(suppression.merged_into_other);
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].get(GROUP_SIZE_COLUMN).is_none());
+        assert_eq!(rows[1], json!({"merchant_city": "Other", "cnt": 5, "total": 50.25, "avg_amount": null}));
+    }
+
+    #[test]
+    fn test_small_groups_suppressed() {
+        let mut rows = vec![
+            json!({"merchant_city": "Almaty", "cnt": 100, "__group_size": 100}),
+            json!({"merchant_city": "Taraz", "cnt": 3, "__group_size": 3}),
+        ];
+        let suppression = enforce_min_group_size(SQL, &mut rows, 5, "Other").unwrap();
+        assert!(!suppression.merged_into_other);
+        assert_eq!(rows, vec![json!({"merchant_city": "Almaty", "cnt": 100})]);
+        assert_eq!(suppression.suppressed_rows, vec![json!({"merchant_city": "Taraz", "cnt": 3})]);
+
+        // Все группы не меньше порога - отчета нет, служебная колонка убрана
+        let mut rows = vec![json!({"merchant_city": "Almaty", "cnt": 100, "__group_size": 100})];
+        assert!(enforce_min_group_size(SQL, &mut rows, 5, "Other").is_none());
+        assert_eq!(rows, vec![json!({"merchant_city": "Almaty", "cnt": 100})]);
+    }
+}
@@ -0,0 +1,325 @@
+pub mod k_anonymity;
+
+use crate::{auth::Role, config::Config, llm::validator::{query_outputs, QueryOutputs}};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Что делать со значением колонки в результате запроса (варианты - от мягкого к строгому)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnAction {
+    /// Значение без изменений
+    Allow,
+    /// Видны только последние 4 символа: `****1234`
+    Mask,
+    /// Стабильный псевдоним (соленый SHA-256) - можно группировать и сравнивать, но не восстановить
+    Hash,
+    /// Колонка удаляется из результата
+    Deny,
+}
+
+impl std::str::FromStr for ColumnAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "allow" => Ok(Self::Allow),
+            "mask" => Ok(Self::Mask),
+            "hash" => Ok(Self::Hash),
+            "deny" => Ok(Self::Deny),
+            other => anyhow::bail!("Unknown column action '{}': expected allow, mask, hash or deny", other),
+        }
+    }
+}
+
+/// Кому отдается результат: пользователю с ролью или LLM (промпт анализа)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Audience {
+    Role(Role),
+    Llm,
+}
+
+/// Действия для колонки по получателям
+#[derive(Debug, Clone, Copy)]
+struct ColumnRule {
+    viewer: ColumnAction,
+    analyst: ColumnAction,
+    admin: ColumnAction,
+    llm: ColumnAction,
+}
+
+impl ColumnRule {
+    fn action(&self, audience: Audience) -> ColumnAction {
+        match audience {
+            Audience::Role(Role::Viewer) => self.viewer,
+            Audience::Role(Role::Analyst) => self.analyst,
+            Audience::Role(Role::Admin) => self.admin,
+            Audience::Llm => self.llm,
+        }
+    }
+
+    fn set(&mut self, audience: &str, action: ColumnAction) -> anyhow::Result<()> {
+        match audience.trim().to_lowercase().as_str() {
+            "viewer" => self.viewer = action,
+            "analyst" => self.analyst = action,
+            "admin" => self.admin = action,
+            "llm" => self.llm = action,
+            other => anyhow::bail!("Unknown audience '{}': expected viewer, analyst, admin or llm", other),
+        }
+        Ok(())
+    }
+}
+
+/// Примененная к колонке результата политика (возвращается в `meta`)
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedColumnPolicy {
+    pub column: String,
+    /// Колонка `transactions`, если в результате она под псевдонимом
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_column: Option<String>,
+    pub action: ColumnAction,
+}
+
+/// Политики колонок с персональными данными. Применяются к строкам результата после выполнения
+/// запроса: для пользователя - по его роли, для промпта анализа - как для `llm`.
+#[derive(Debug, Clone)]
+pub struct ColumnPolicy {
+    rules: HashMap<String, ColumnRule>,
+    salt: String,
+}
+
+impl ColumnPolicy {
+    /// Политики по умолчанию с переопределениями из COLUMN_POLICY
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let salt = match &config.pii_hash_salt {
+            Some(salt) => salt.clone(),
+            None => {
+                tracing::warn!("PII_HASH_SALT is not set: hashed identifiers will change after restart");
+                rand::random::<[u8; 16]>().iter().map(|b| format!("{:02x}", b)).collect()
+            }
+        };
+        let mut policy = Self::with_salt(salt);
+        if let Some(spec) = &config.column_policy {
+            policy.apply_overrides(spec)?;
+        }
+        Ok(policy)
+    }
+
+    fn with_salt(salt: String) -> Self {
+        use ColumnAction::*;
+        let rule = |viewer, analyst, admin, llm| ColumnRule { viewer, analyst, admin, llm };
+        let rules = HashMap::from([
+            // Для viewer и analyst - как в списках колонок ролей (auth/roles.rs): запрещенные колонки удаляются
+            ("card_id".to_string(), rule(Deny, Deny, Mask, Deny)),
+            ("expiry_date".to_string(), rule(Deny, Deny, Mask, Deny)),
+            ("transaction_id".to_string(), rule(Deny, Mask, Allow, Hash)),
+        ]);
+        Self { rules, salt }
+    }
+
+    /// Формат: `card_id=viewer:deny,analyst:hash;merchant_id=llm:hash`.
+    /// Не указанные получатели сохраняют действие по умолчанию (для новой колонки - allow).
+    fn apply_overrides(&mut self, spec: &str) -> anyhow::Result<()> {
+        for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (column, actions) = entry.split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid COLUMN_POLICY entry '{}': expected column=role:action,...", entry))?;
+            let rule = self.rules.entry(column.trim().to_lowercase()).or_insert(ColumnRule {
+                viewer: ColumnAction::Allow,
+                analyst: ColumnAction::Allow,
+                admin: ColumnAction::Allow,
+                llm: ColumnAction::Allow,
+            });
+            for item in actions.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+                let (audience, action) = item.split_once(':')
+                    .ok_or_else(|| anyhow::anyhow!("Invalid COLUMN_POLICY item '{}': expected role:action", item))?;
+                rule.set(audience, action.parse()?)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn action(&self, column: &str, audience: Audience) -> ColumnAction {
+        self.rules.get(column).map_or(ColumnAction::Allow, |rule| rule.action(audience))
+    }
+
+    /// Применяет политики к строкам результата. Колонка результата получает самое строгое действие
+    /// среди колонок `transactions` в ее выражении (`card_id AS card`, `MAX(transaction_id) AS x`);
+    /// колонку, которую не сопоставить с элементом SELECT, - среди всех возможных источников.
+    /// Возвращает примененные политики (кроме allow).
+    pub fn apply(&self, sql: &str, rows: &mut [Value], audience: Audience) -> Vec<AppliedColumnPolicy> {
+        let outputs = query_outputs(sql);
+
+        let Some(first) = rows.first().and_then(Value::as_object) else {
+            return Vec::new();
+        };
+        let mut applied: Vec<AppliedColumnPolicy> = first.keys()
+            .filter_map(|key| {
+                let column = key.to_lowercase();
+                let (source, action) = self.sources(outputs.as_ref(), &column)
+                    .into_iter()
+                    .map(|source| {
+                        let action = self.action(&source, audience);
+                        (source, action)
+                    })
+                    .max_by_key(|(_, action)| *action)?;
+                (action != ColumnAction::Allow).then(|| AppliedColumnPolicy {
+                    column: key.clone(),
+                    source_column: (source != column).then_some(source),
+                    action,
+                })
+            })
+            .collect();
+        applied.sort_by(|a, b| a.column.cmp(&b.column));
+
+        for row in rows.iter_mut() {
+            let Some(obj) = row.as_object_mut() else { continue };
+            for policy in &applied {
+                match policy.action {
+                    ColumnAction::Allow => {}
+                    ColumnAction::Deny => {
+                        obj.remove(&policy.column);
+                    }
+                    ColumnAction::Mask | ColumnAction::Hash => {
+                        if let Some(value) = obj.get_mut(&policy.column) {
+                            *value = self.transform(value, policy.action);
+                        }
+                    }
+                }
+            }
+        }
+
+        applied
+    }
+
+    /// Колонки `transactions`, значения которых могут попасть в колонку результата `column`.
+    /// Имя самой колонки учитывается всегда; SQL, который не разобрать, - все колонки с политиками.
+    fn sources(&self, outputs: Option<&QueryOutputs>, column: &str) -> Vec<String> {
+        let mut sources = vec![column.to_string()];
+        let Some(outputs) = outputs else {
+            sources.extend(self.rules.keys().cloned());
+            return sources;
+        };
+        let matched: Vec<_> = outputs.columns.iter().filter(|c| c.name.as_deref() == Some(column)).collect();
+        if matched.is_empty() {
+            sources.extend(outputs.columns.iter().flat_map(|c| c.sources.iter().cloned()));
+            sources.extend(outputs.projected.iter().cloned());
+        }
+        for output in matched {
+            sources.extend(output.sources.iter().cloned());
+            if output.unresolved {
+                sources.extend(outputs.projected.iter().cloned());
+            }
+        }
+        sources
+    }
+
+    fn transform(&self, value: &Value, action: ColumnAction) -> Value {
+        let raw = match value {
+            Value::Null => return Value::Null,
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        match action {
+            ColumnAction::Mask => Value::String(mask(&raw)),
+            ColumnAction::Hash => {
+                let digest = Sha256::digest(format!("{}:{}", self.salt, raw).as_bytes());
+                Value::String(format!("{:x}", digest)[..16].to_string())
+            }
+            _ => value.clone(),
+        }
+    }
+}
+
+/// Оставляет последние 4 символа, короткие значения (дата окончания и т.п.) скрываются целиком
+fn mask(raw: &str) -> String {
+    let chars: Vec<char> = raw.chars().collect();
+    if chars.len() < 8 {
+        return "****".to_string();
+    }
+    format!("****{}", chars[chars.len() - 4..].iter().collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rows() -> Vec<Value> {
+        vec![json!({"card": "4400123412341234", "transaction_id": 987654321, "expiry_date": "12/27", "cnt": 3})]
+    }
+
+    const SQL: &str = "SELECT card_id AS card, transaction_id, expiry_date, COUNT(*) AS cnt FROM transactions GROUP BY 1, 2, 3 LIMIT 10;";
+
+    #[test]
+    fn test_role_policies() {
+        let policy = ColumnPolicy::with_salt("salt".to_string());
+
+        let mut viewer = rows();
+        let applied = policy.apply(SQL, &mut viewer, Audience::Role(Role::Viewer));
+        assert_eq!(applied.len(), 3);
+        assert_eq!(applied[0].source_column.as_deref(), Some("card_id"));
+        let row = viewer[0].as_object().unwrap();
+        assert!(!row.contains_key("card") && !row.contains_key("expiry_date") && !row.contains_key("transaction_id"));
+        assert_eq!(row["cnt"], 3);
+
+        let mut admin = rows();
+        policy.apply(SQL, &mut admin, Audience::Role(Role::Admin));
+        assert_eq!(admin[0]["card"], "****1234");
+        assert_eq!(admin[0]["expiry_date"], "****");
+        assert_eq!(admin[0]["transaction_id"], 987654321);
+    }
+
+    #[test]
+    fn test_hash_is_stable_and_salted() {
+        let policy = ColumnPolicy::with_salt("salt".to_string());
+        let mut a = rows();
+        let mut b = rows();
+        policy.apply(SQL, &mut a, Audience::Llm);
+        policy.apply(SQL, &mut b, Audience::Llm);
+        assert_eq!(a[0]["transaction_id"], b[0]["transaction_id"]);
+        assert!(a[0].get("card").is_none());
+
+        let mut c = rows();
+        ColumnPolicy::with_salt("other".to_string()).apply(SQL, &mut c, Audience::Llm);
+        assert_ne!(a[0]["transaction_id"], c[0]["transaction_id"]);
+    }
+
+    #[test]
+    fn test_expressions_over_policy_columns() {
+        let policy = ColumnPolicy::with_salt("salt".to_string());
+        let leaks = [
+            ("SELECT MAX(transaction_id) AS x FROM transactions;", "x"),
+            ("SELECT transaction_id || '' AS s FROM transactions LIMIT 5;", "s"),
+            ("SELECT transaction_id + 0 FROM transactions LIMIT 5;", "?column?"),
+            ("SELECT c FROM (SELECT transaction_id AS c FROM transactions) s LIMIT 5;", "c"),
+        ];
+        for (sql, column) in leaks {
+            let mut rows = vec![json!({column: 987654321})];
+            let applied = policy.apply(sql, &mut rows, Audience::Llm);
+            assert_eq!(applied[0].action, ColumnAction::Hash, "{}", sql);
+            assert_eq!(applied[0].source_column.as_deref(), Some("transaction_id"));
+            assert_ne!(rows[0][column], 987654321, "{}", sql);
+        }
+
+        // Число различных значений и остальные колонки не затрагиваются
+        let mut rows = vec![json!({"cards": 42, "merchant_city": "Almaty"})];
+        let sql = "SELECT COUNT(DISTINCT transaction_id) AS cards, merchant_city FROM transactions GROUP BY 2;";
+        assert!(policy.apply(sql, &mut rows, Audience::Llm).is_empty());
+        assert_eq!(rows[0]["cards"], 42);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut policy = ColumnPolicy::with_salt("salt".to_string());
+        policy.apply_overrides("card_id=admin:allow; merchant_id=llm:hash").unwrap();
+        assert_eq!(policy.action("card_id", Audience::Role(Role::Admin)), ColumnAction::Allow);
+        assert_eq!(policy.action("card_id", Audience::Role(Role::Viewer)), ColumnAction::Deny);
+        assert_eq!(policy.action("merchant_id", Audience::Llm), ColumnAction::Hash);
+        assert_eq!(policy.action("merchant_id", Audience::Role(Role::Viewer)), ColumnAction::Allow);
+
+        assert!(policy.apply_overrides("card_id=guest:allow").is_err());
+        assert!(policy.apply_overrides("card_id=admin:show").is_err());
+    }
+}
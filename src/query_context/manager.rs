@@ -83,11 +83,6 @@ impl QueryContextManager {
         contexts.insert(context.user_id.clone(), context);
     }
 
-    pub async fn get_context(&self, user_id: &str) -> Option<UserQueryContext> {
-        let contexts = self.contexts.read().await;
-        contexts.get(user_id).cloned()
-    }
-
     async fn cleanup_old_contexts(&self, contexts: &mut HashMap<String, UserQueryContext>) {
         let cutoff = Utc::now() - chrono::Duration::hours(self.max_context_age_hours as i64);
         contexts.retain(|_, context| context.updated_at > cutoff);
@@ -99,10 +94,5 @@ impl QueryContextManager {
             context.clear();
         }
     }
-
-    pub async fn remove_context(&self, user_id: &str) {
-        let mut contexts = self.contexts.write().await;
-        contexts.remove(user_id);
-    }
 }
 
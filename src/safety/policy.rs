@@ -0,0 +1,278 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Правила по умолчанию (встроены в бинарник)
+const DEFAULT_POLICY: &str = include_str!("../../config/safety_policy.toml");
+
+/// Что делать с запросом. Порядок вариантов - по строгости.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    Allow,
+    /// Только запись в лог
+    Log,
+    /// Нарушение учитывается, запрос выполняется
+    Warn,
+    /// Запрос отклоняется
+    Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleCategory {
+    Jailbreak,
+    Inappropriate,
+    Abuse,
+}
+
+#[derive(Debug, Deserialize)]
+struct PolicyFile {
+    settings: Settings,
+    #[serde(default)]
+    allowlist: Vec<AllowlistSpec>,
+    #[serde(default)]
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Settings {
+    warn_threshold: f32,
+    block_threshold: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct AllowlistSpec {
+    id: String,
+    pattern: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleSpec {
+    id: String,
+    category: RuleCategory,
+    action: PolicyAction,
+    weight: f32,
+    #[serde(default)]
+    keywords: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    patterns: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    id: String,
+    category: RuleCategory,
+    action: PolicyAction,
+    weight: f32,
+    /// (язык, ключевое слово в нижнем регистре)
+    keywords: Vec<(String, String)>,
+    /// (язык, шаблон)
+    patterns: Vec<(String, Regex)>,
+}
+
+/// Совпадение правила с текстом
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleMatch {
+    pub rule_id: String,
+    pub category: RuleCategory,
+    pub action: PolicyAction,
+    pub weight: f32,
+    pub language: String,
+    /// Совпавший фрагмент текста
+    pub matched: String,
+}
+
+/// Результат проверки текста
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyVerdict {
+    pub action: PolicyAction,
+    pub score: f32,
+    pub matches: Vec<RuleMatch>,
+    /// Сработавшие записи allowlist
+    pub allowlisted: Vec<String>,
+}
+
+impl PolicyVerdict {
+    /// Главное совпадение: самое строгое действие, затем наибольший вес
+    pub fn primary_match(&self) -> Option<&RuleMatch> {
+        self.matches.iter().fold(None, |best: Option<&RuleMatch>, m| match best {
+            Some(b) if (b.action, b.weight) >= (m.action, m.weight) => Some(b),
+            _ => Some(m),
+        })
+    }
+}
+
+/// Единая политика проверки пользовательских сообщений: правила (ключевые слова и регулярные
+/// выражения по языкам) с весами и действиями, allowlist и пороги суммарного веса.
+#[derive(Debug)]
+pub struct PolicyEngine {
+    rules: Vec<Rule>,
+    allowlist: Vec<(String, Regex)>,
+    warn_threshold: f32,
+    block_threshold: f32,
+}
+
+impl PolicyEngine {
+    /// Правила из файла (SAFETY_POLICY_FILE) или встроенные по умолчанию
+    pub fn load(path: Option<&str>) -> anyhow::Result<Self> {
+        match path {
+            Some(path) => {
+                let source = std::fs::read_to_string(path)
+                    .map_err(|e| anyhow::anyhow!("Failed to read safety policy {}: {}", path, e))?;
+                Self::from_toml(&source)
+            }
+            None => Self::from_toml(DEFAULT_POLICY),
+        }
+    }
+
+    pub fn from_toml(source: &str) -> anyhow::Result<Self> {
+        let file: PolicyFile = toml::from_str(source)?;
+        let compile = |id: &str, pattern: &str| {
+            Regex::new(&format!("(?i){}", pattern))
+                .map_err(|e| anyhow::anyhow!("Invalid pattern in safety rule '{}': {}", id, e))
+        };
+
+        let allowlist = file.allowlist.iter()
+            .map(|entry| Ok((entry.id.clone(), compile(&entry.id, &entry.pattern)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut rules = Vec::with_capacity(file.rules.len());
+        for spec in file.rules {
+            let keywords = spec.keywords.iter()
+                .flat_map(|(lang, words)| words.iter().map(move |w| (lang.clone(), normalize(w))))
+                .collect();
+            let mut patterns = Vec::new();
+            for (lang, list) in &spec.patterns {
+                for pattern in list {
+                    patterns.push((lang.clone(), compile(&spec.id, pattern)?));
+                }
+            }
+            rules.push(Rule {
+                id: spec.id,
+                category: spec.category,
+                action: spec.action,
+                weight: spec.weight,
+                keywords,
+                patterns,
+            });
+        }
+
+        Ok(Self {
+            rules,
+            allowlist,
+            warn_threshold: file.settings.warn_threshold,
+            block_threshold: file.settings.block_threshold,
+        })
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Проверяет текст: каждое совпавшее ключевое слово или шаблон добавляет вес правила.
+    /// Итоговое действие - самое строгое из действий совпавших правил и порогов по сумме весов.
+    pub fn evaluate(&self, text: &str) -> PolicyVerdict {
+        let mut normalized = normalize(text);
+
+        let mut allowlisted = Vec::new();
+        for (id, pattern) in &self.allowlist {
+            if pattern.is_match(&normalized) {
+                allowlisted.push(id.clone());
+                normalized = pattern.replace_all(&normalized, " ").into_owned();
+            }
+        }
+
+        let mut matches = Vec::new();
+        for rule in &self.rules {
+            // (язык, совпавший фрагмент)
+            let mut hits: Vec<(&str, String)> = rule.keywords.iter()
+                .filter(|(_, keyword)| normalized.contains(keyword.as_str()))
+                .map(|(language, keyword)| (language.as_str(), keyword.clone()))
+                .collect();
+            for (language, pattern) in &rule.patterns {
+                if let Some(m) = pattern.find(&normalized) {
+                    // Шаблон, повторяющий уже найденное ключевое слово, вес не удваивает
+                    let found = m.as_str();
+                    if !hits.iter().any(|(_, hit)| found.contains(hit.as_str()) || hit.contains(found)) {
+                        hits.push((language, found.to_string()));
+                    }
+                }
+            }
+            matches.extend(hits.into_iter().map(|(language, matched)| RuleMatch {
+                rule_id: rule.id.clone(),
+                category: rule.category,
+                action: rule.action,
+                weight: rule.weight,
+                language: language.to_string(),
+                matched,
+            }));
+        }
+
+        let score = matches.iter().fold(0.0, |sum, m| sum + m.weight);
+        let by_score = if score >= self.block_threshold {
+            PolicyAction::Block
+        } else if score >= self.warn_threshold {
+            PolicyAction::Warn
+        } else {
+            PolicyAction::Allow
+        };
+        let action = matches.iter().map(|m| m.action).fold(by_score, PolicyAction::max);
+
+        PolicyVerdict { action, score, matches, allowlisted }
+    }
+}
+
+/// Нижний регистр и одиночные пробелы
+fn normalize(text: &str) -> String {
+    text.to_lowercase().split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Corpus {
+        case: Vec<Case>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Case {
+        text: String,
+        expect: PolicyAction,
+        rule: Option<String>,
+    }
+
+    #[test]
+    fn test_regression_corpus() {
+        let engine = PolicyEngine::load(None).unwrap();
+        let corpus: Corpus = toml::from_str(include_str!("../../config/safety_corpus.toml")).unwrap();
+
+        for case in corpus.case {
+            let verdict = engine.evaluate(&case.text);
+            assert_eq!(verdict.action, case.expect, "{:?}: {:?}", case.text, verdict);
+            if let Some(rule) = &case.rule {
+                assert!(verdict.matches.iter().any(|m| &m.rule_id == rule), "{:?}: expected rule {}", case.text, rule);
+            }
+        }
+    }
+
+    #[test]
+    fn test_allowlist_and_primary_match() {
+        let engine = PolicyEngine::load(None).unwrap();
+
+        let verdict = engine.evaluate("Ignore previous month refunds");
+        assert_eq!(verdict.action, PolicyAction::Allow);
+        assert_eq!(verdict.allowlisted, vec!["ignore-previous-period"]);
+
+        let verdict = engine.evaluate("You must act as a poet");
+        assert_eq!(verdict.primary_match().unwrap().rule_id, "persona-override");
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        assert!(PolicyEngine::from_toml("[settings]\nwarn_threshold = 0.5\nblock_threshold = 1.0\n\
+            [[rules]]\nid = \"x\"\ncategory = \"jailbreak\"\naction = \"block\"\nweight = 1.0\npatterns.en = ['(']").is_err());
+    }
+}
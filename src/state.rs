@@ -1,11 +1,15 @@
 use crate::{
     analysis::AnalysisClient,
+    analytics::drill::DrillTokens,
     cache::MemoryCache,
     chat::session::SessionManager,
     config::Config,
+    limits::RateLimiter,
     db::pool::DbPool,
     llm::client::LLMClient,
+    privacy::ColumnPolicy,
     query_context::QueryContextManager,
+    tenants::TenantRegistry,
     utils::user_safety::UserSafetyManager,
 };
 use std::sync::Arc;
@@ -19,6 +23,11 @@ pub struct AppState {
     pub sessions: Arc<SessionManager>,
     pub query_context: Arc<QueryContextManager>,
     pub user_safety: Arc<UserSafetyManager>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub ip_rate_limiter: Arc<RateLimiter>,
+    pub tenants: Arc<TenantRegistry>,
+    pub column_policy: Arc<ColumnPolicy>,
+    pub drill_tokens: Arc<DrillTokens>,
     pub config: Config,
 }
 
@@ -31,12 +40,23 @@ pub struct CachedQueryResult {
 }
 
 impl AppState {
-    pub fn new(db: DbPool, llm: LLMClient, config: Config) -> Self {
-        let analysis = Arc::new(AnalysisClient::new(config.clone()));
+    pub fn new(
+        db: DbPool,
+        llm: LLMClient,
+        tenants: TenantRegistry,
+        column_policy: ColumnPolicy,
+        user_safety: UserSafetyManager,
+        config: Config,
+    ) -> Self {
+        let column_policy = Arc::new(column_policy);
+        let analysis = Arc::new(AnalysisClient::new(config.clone(), column_policy.clone()));
         let cache = Arc::new(MemoryCache::new());
         let sessions = Arc::new(SessionManager::new(24)); // Сессии хранятся 24 часа
         let query_context = Arc::new(QueryContextManager::new(24)); // Контекст хранится 24 часа
-        let user_safety = Arc::new(UserSafetyManager::new(5, 24)); // Макс 5 предупреждений, бан на 24 часа
+        let user_safety = Arc::new(user_safety);
+        let rate_limiter = Arc::new(RateLimiter::new(config.rate_limit_per_minute, config.rate_limit_burst));
+        let ip_rate_limiter = Arc::new(RateLimiter::new(config.ip_rate_limit_per_minute, config.ip_rate_limit_burst));
+        let drill_tokens = Arc::new(DrillTokens::new());
         
         Self {
             db,
@@ -46,6 +66,11 @@ impl AppState {
             sessions,
             query_context,
             user_safety,
+            rate_limiter,
+            ip_rate_limiter,
+            tenants: Arc::new(tenants),
+            column_policy,
+            drill_tokens,
             config,
         }
     }
@@ -0,0 +1,116 @@
+pub mod scope;
+
+use crate::auth::Principal;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+/// Арендатор - банк-эмитент (или группа банков), данные которого видят его пользователи
+#[derive(Debug, Clone, Serialize)]
+pub struct Tenant {
+    pub id: String,
+    pub name: String,
+    pub issuer_bank_names: Vec<String>,
+}
+
+impl Tenant {
+    /// Значение `app.issuer_banks` для политики RLS `tenant_isolation`: JSON-массив банков арендатора
+    pub fn issuer_banks_setting(&self) -> String {
+        serde_json::Value::from(self.issuer_bank_names.clone()).to_string()
+    }
+}
+
+/// Арендаторы из конфигурации (TENANTS) и таблицы `tenants`. Загружаются при старте.
+#[derive(Debug, Default)]
+pub struct TenantRegistry {
+    tenants: HashMap<String, Tenant>,
+}
+
+impl TenantRegistry {
+    pub async fn load(pool: &PgPool, config_spec: Option<&str>) -> anyhow::Result<Self> {
+        let mut tenants = HashMap::new();
+
+        if let Some(spec) = config_spec {
+            for tenant in parse_tenants(spec)? {
+                tenants.insert(tenant.id.clone(), tenant);
+            }
+        }
+
+        // Записи в базе переопределяют конфигурацию
+        let rows: Vec<(String, String, Vec<String>)> =
+            sqlx::query_as("SELECT id, name, issuer_bank_names FROM tenants")
+                .fetch_all(pool)
+                .await?;
+        for (id, name, issuer_bank_names) in rows {
+            tenants.insert(id.clone(), Tenant { id, name, issuer_bank_names });
+        }
+
+        for tenant in tenants.values() {
+            if tenant.issuer_bank_names.is_empty() {
+                anyhow::bail!("Tenant '{}' has no issuer banks", tenant.id);
+            }
+        }
+
+        Ok(Self { tenants })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Tenant> {
+        self.tenants.get(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tenants.len()
+    }
+
+    /// Арендатор принципала (None - принципал не привязан к арендатору и видит все банки)
+    pub fn for_principal(&self, principal: &Principal) -> Option<&Tenant> {
+        principal.tenant.as_deref().and_then(|id| self.get(id))
+    }
+}
+
+/// Формат TENANTS: `kaspi=Kaspi Bank;halyk=Halyk Bank|Halyk Finance`
+fn parse_tenants(spec: &str) -> anyhow::Result<Vec<Tenant>> {
+    spec.split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (id, banks) = entry.split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid TENANTS entry '{}': expected id=Bank A|Bank B", entry))?;
+            let issuer_bank_names: Vec<String> = banks.split('|')
+                .map(|bank| bank.trim().to_string())
+                .filter(|bank| !bank.is_empty())
+                .collect();
+            Ok(Tenant {
+                id: id.trim().to_string(),
+                name: issuer_bank_names.join(", "),
+                issuer_bank_names,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tenants() {
+        let tenants = parse_tenants("kaspi=Kaspi Bank; halyk=Halyk Bank|Halyk Finance;").unwrap();
+        assert_eq!(tenants.len(), 2);
+        assert_eq!(tenants[0].id, "kaspi");
+        assert_eq!(tenants[1].issuer_bank_names, vec!["Halyk Bank", "Halyk Finance"]);
+        assert_eq!(tenants[1].issuer_banks_setting(), r#"["Halyk Bank","Halyk Finance"]"#);
+
+        assert!(parse_tenants("kaspi").is_err());
+    }
+
+    #[test]
+    fn test_issuer_banks_setting_escapes_quotes() {
+        let tenant = Tenant {
+            id: "t".to_string(),
+            name: "t".to_string(),
+            issuer_bank_names: vec!["O'Bank".to_string(), "Bank \"X\"".to_string()],
+        };
+        assert_eq!(tenant.issuer_banks_setting(), r#"["O'Bank","Bank \"X\""]"#);
+    }
+}
@@ -0,0 +1,65 @@
+/// Оборачивает запрос так, что `transactions` внутри него - CTE с фильтром строк
+/// (период сравнения, значения детализации):
+///
+/// ```sql
+/// WITH transactions AS (SELECT * FROM public.transactions WHERE <filter>) <исходный запрос>
+/// ```
+///
+/// Все ссылки на `transactions` (в том числе в подзапросах и других CTE) попадают на отфильтрованную
+/// выборку. Обход через `public.transactions` блокирует валидатор (`check_query_structure`).
+/// Строки арендатора ограничивает не этот CTE, а политика RLS (см. `execute_user_query`).
+pub fn scope_to_filter(sql: &str, filter: &str) -> String {
+    let body = sql.trim();
+    let cte = format!("transactions AS (SELECT * FROM public.transactions WHERE {})", filter);
+
+    if let Some(rest) = strip_keyword(body, "WITH") {
+        // Наш CTE идет первым, чтобы остальные CTE запроса ссылались уже на него
+        if let Some(rest) = strip_keyword(rest, "RECURSIVE") {
+            return format!("WITH RECURSIVE {}, {}", cte, rest);
+        }
+        return format!("WITH {}, {}", cte, rest);
+    }
+
+    format!("WITH {} {}", cte, body)
+}
+
+/// Отрезает ключевое слово в начале строки (без учета регистра) и пробелы после него
+fn strip_keyword<'a>(s: &'a str, keyword: &str) -> Option<&'a str> {
+    let head = s.get(..keyword.len())?;
+    let rest = &s[keyword.len()..];
+    if head.eq_ignore_ascii_case(keyword) && rest.starts_with(char::is_whitespace) {
+        Some(rest.trim_start())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTER: &str = "issuer_bank_name IN ('Kaspi Bank')";
+
+    #[test]
+    fn test_plain_select() {
+        assert_eq!(
+            scope_to_filter("SELECT COUNT(*) FROM transactions;", FILTER),
+            "WITH transactions AS (SELECT * FROM public.transactions WHERE issuer_bank_name IN ('Kaspi Bank')) \
+             SELECT COUNT(*) FROM transactions;"
+        );
+    }
+
+    #[test]
+    fn test_existing_with() {
+        let scoped = scope_to_filter(
+            "with daily AS (SELECT DATE(transaction_timestamp) d FROM transactions) SELECT * FROM daily LIMIT 5;",
+            FILTER,
+        );
+        assert!(scoped.starts_with("WITH transactions AS (SELECT * FROM public.transactions WHERE"));
+        assert!(scoped.ends_with(", daily AS (SELECT DATE(transaction_timestamp) d FROM transactions) SELECT * FROM daily LIMIT 5;"));
+
+        let scoped = scope_to_filter("WITH RECURSIVE r AS (SELECT 1) SELECT * FROM r;", FILTER);
+        assert!(scoped.starts_with("WITH RECURSIVE transactions AS ("));
+        assert!(scoped.ends_with(", r AS (SELECT 1) SELECT * FROM r;"));
+    }
+}
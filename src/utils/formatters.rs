@@ -1,8 +1,10 @@
 use serde_json::Value;
 use crate::api::models::{ChartData, ChartDataset};
+use crate::i18n::column_label;
+use crate::utils::language::Language;
 
-/// Форматирует данные в таблицу (Markdown)
-pub fn format_as_table(data: &[Value]) -> String {
+/// Форматирует данные в таблицу (Markdown). Заголовки - подписи колонок на языке ответа.
+pub fn format_as_table(data: &[Value], language: Language) -> String {
     if data.is_empty() {
         return String::new();
     }
@@ -11,7 +13,7 @@ pub fn format_as_table(data: &[Value]) -> String {
     
     // Получаем все ключи из первой строки
     if let Some(first_obj) = data[0].as_object() {
-        let keys: Vec<String> = first_obj.keys().map(|k| k.clone()).collect();
+        let keys: Vec<String> = first_obj.keys().cloned().collect();
         
         if keys.is_empty() {
             return String::new();
@@ -20,17 +22,17 @@ pub fn format_as_table(data: &[Value]) -> String {
         // Формируем заголовок
         result.push_str("| ");
         for key in &keys {
-            result.push_str(key);
+            result.push_str(&column_label(language, key));
             result.push_str(" | ");
         }
-        result.push_str("\n");
+        result.push('\n');
         
         // Разделитель
-        result.push_str("|");
+        result.push('|');
         for _ in &keys {
             result.push_str(" --- |");
         }
-        result.push_str("\n");
+        result.push('\n');
 
         // Формируем строки данных
         for row in data {
@@ -50,48 +52,7 @@ pub fn format_as_table(data: &[Value]) -> String {
                     result.push_str(&value);
                     result.push_str(" | ");
                 }
-                result.push_str("\n");
-            }
-        }
-    }
-
-    result
-}
-
-/// Форматирует данные в CSV
-pub fn format_as_csv(data: &[Value]) -> String {
-    if data.is_empty() {
-        return String::new();
-    }
-
-    let mut result = String::new();
-    
-    if let Some(first_obj) = data[0].as_object() {
-        let keys: Vec<String> = first_obj.keys().map(|k| k.clone()).collect();
-        
-        // Заголовок
-        result.push_str(&keys.join(","));
-        result.push_str("\n");
-        
-        // Данные
-        for row in data {
-            if let Some(obj) = row.as_object() {
-                let values: Vec<String> = keys.iter()
-                    .map(|key| {
-                        let value = obj.get(key)
-                            .and_then(|v| {
-                                if v.is_number() {
-                                    Some(format!("{}", v.as_f64().unwrap_or(0.0)))
-                                } else {
-                                    v.as_str().map(|s| format!("\"{}\"", s.replace("\"", "\"\"")))
-                                }
-                            })
-                            .unwrap_or_else(|| "".to_string());
-                        value
-                    })
-                    .collect();
-                result.push_str(&values.join(","));
-                result.push_str("\n");
+                result.push('\n');
             }
         }
     }
@@ -102,8 +63,8 @@ pub fn format_as_csv(data: &[Value]) -> String {
 // Генерация изображений диаграмм реализована только в telegram_bot
 // Основной бэкенд возвращает только данные для диаграмм (ChartData)
 
-/// Преобразует данные в формат для диаграмм
-pub fn format_as_chart_data(data: &[Value], chart_type: &str) -> Option<ChartData> {
+/// Преобразует данные в формат для диаграмм (подпись набора данных - на языке ответа)
+pub fn format_as_chart_data(data: &[Value], chart_type: &str, language: Language) -> Option<ChartData> {
     if data.is_empty() {
         return None;
     }
@@ -161,10 +122,8 @@ pub fn format_as_chart_data(data: &[Value], chart_type: &str) -> Option<ChartDat
                             } else if let Some(ts) = val.as_i64() {
                                 // Преобразуем timestamp в дату
                                 Some(format!("{}", ts))
-                            } else if let Some(ts) = val.as_f64() {
-                                Some(format!("{}", ts as i64))
                             } else {
-                                None
+                                val.as_f64().map(|ts| format!("{}", ts as i64))
                             }
                         })
                         .collect();
@@ -187,11 +146,12 @@ pub fn format_as_chart_data(data: &[Value], chart_type: &str) -> Option<ChartDat
                             chart_type: final_type,
                             labels,
                             datasets: vec![ChartDataset {
-                                label: val_key.to_string(),
+                                label: column_label(language, val_key),
                                 data: values,
                                 background_color: None,
                             }],
                             title: None,
+                            drill_tokens: None,
                         });
                     }
                 }
@@ -233,11 +193,12 @@ pub fn format_as_chart_data(data: &[Value], chart_type: &str) -> Option<ChartDat
                             chart_type: "pie".to_string(),
                             labels,
                             datasets: vec![ChartDataset {
-                                label: val_key.to_string(),
+                                label: column_label(language, val_key),
                                 data: values,
                                 background_color: None,
                             }],
                             title: None,
+                            drill_tokens: None,
                         });
                     }
                 }
@@ -1,61 +1,214 @@
-/// Simple language detection based on character analysis
-/// Kazakh uses Cyrillic with specific letters: Ә, Ғ, Қ, Ң, Ө, Ұ, Ү, Һ, І
-pub fn detect_language(text: &str) -> Language {
-    if text.is_empty() {
-        return Language::English; // Default
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// Тексты для обучения n-граммных профилей языков (встроены в бинарник).
+/// У языка может быть несколько профилей (узбекский - латиница и кириллица), оценка - лучшая из них.
+const DETECTION_SAMPLES: [(Language, &str); 6] = [
+    (Language::Russian, include_str!("../../locales/detect/ru.txt")),
+    (Language::English, include_str!("../../locales/detect/en.txt")),
+    (Language::Kazakh, include_str!("../../locales/detect/kk.txt")),
+    (Language::Uzbek, include_str!("../../locales/detect/uz.txt")),
+    (Language::Uzbek, include_str!("../../locales/detect/uz-cyrl.txt")),
+    (Language::Kyrgyz, include_str!("../../locales/detect/ky.txt")),
+];
+
+/// Буквы, которых нет в русском алфавите, и языки, в которых они есть.
+/// Встретив такую букву, языки без нее получают штраф.
+const DISTINCTIVE_LETTERS: [(char, &[Language]); 11] = [
+    ('ә', &[Language::Kazakh]),
+    ('ғ', &[Language::Kazakh, Language::Uzbek]),
+    ('қ', &[Language::Kazakh, Language::Uzbek]),
+    ('ң', &[Language::Kazakh, Language::Kyrgyz]),
+    ('ө', &[Language::Kazakh, Language::Kyrgyz]),
+    ('ұ', &[Language::Kazakh]),
+    ('ү', &[Language::Kazakh, Language::Kyrgyz]),
+    ('һ', &[Language::Kazakh]),
+    ('і', &[Language::Kazakh]),
+    ('ў', &[Language::Uzbek]),
+    ('ҳ', &[Language::Uzbek]),
+];
+
+/// Штраф (к среднему логарифму вероятности n-грамм слова) за букву, которой нет в алфавите языка
+const LETTER_PENALTY: f64 = 1.5;
+
+/// Множитель оценок перед softmax: чем больше, тем увереннее ответ при той же разнице оценок
+const CONFIDENCE_SCALE: f64 = 2.0;
+
+/// Максимальная длина n-граммы
+const MAX_NGRAM: usize = 3;
+
+/// Частоты n-грамм языка (по длинам 1..=MAX_NGRAM)
+struct NgramProfile {
+    counts: HashMap<String, u32>,
+    /// Сумма частот и число разных n-грамм для каждой длины
+    totals: [(u32, usize); MAX_NGRAM],
+}
+
+impl NgramProfile {
+    fn train(text: &str) -> Self {
+        let mut counts = HashMap::new();
+        let mut totals = [(0, 0); MAX_NGRAM];
+        for gram in ngrams(text) {
+            let n = gram.chars().count();
+            let count = counts.entry(gram).or_insert(0);
+            if *count == 0 {
+                totals[n - 1].1 += 1;
+            }
+            *count += 1;
+            totals[n - 1].0 += 1;
+        }
+        Self { counts, totals }
     }
-    
-    let text_lower = text.to_lowercase();
-    
-    // Kazakh-specific Cyrillic characters
-    let kazakh_chars = ['ә', 'ғ', 'қ', 'ң', 'ө', 'ұ', 'ү', 'һ', 'і'];
-    let has_kazakh_chars = kazakh_chars.iter().any(|&c| text_lower.contains(c));
-    
-    // Kazakh-specific words (common words that are unique to Kazakh)
-    // Note: "транзакция" and "валюта" are common in both Russian and Kazakh, so we use more specific words
-    let kazakh_keywords = ["қанша", "неше", "қалай", "қайда", "қашан", "көрсет", "тарату", "бөлу", 
-                           "бойынша", "үшін", "жылы", "күнде", "қала", "мерчант"];
-    let has_kazakh_keywords = kazakh_keywords.iter().any(|&kw| text_lower.contains(kw));
-    
-    let cyrillic_count = text.chars().filter(|c| matches!(*c, 
-        'А'..='Я' | 'а'..='я' | 'Ё' | 'ё' | 
-        'Ә' | 'ә' | 'Ғ' | 'ғ' | 'Қ' | 'қ' | 'Ң' | 'ң' | 'Ө' | 'ө' | 
-        'Ұ' | 'ұ' | 'Ү' | 'ү' | 'Һ' | 'һ' | 'І' | 'і'
-    )).count();
-    let latin_count = text.chars().filter(|c| c.is_ascii_alphabetic()).count();
-    let total_letters = cyrillic_count + latin_count;
-    
-    if total_letters == 0 {
-        return Language::English; // Default if no letters
+
+    /// Логарифм вероятности n-граммы со сглаживанием Лапласа
+    fn log_prob(&self, gram: &str) -> f64 {
+        let (total, distinct) = self.totals[gram.chars().count() - 1];
+        let count = self.counts.get(gram).copied().unwrap_or(0);
+        ((count as f64 + 1.0) / (total as f64 + distinct as f64 + 1.0)).ln()
     }
-    
-    // If has Kazakh-specific characters or keywords, it's Kazakh
-    if has_kazakh_chars || has_kazakh_keywords {
-        Language::Kazakh
-    } else if latin_count > cyrillic_count {
-        // If more latin than cyrillic, it's English
-        Language::English
-    } else if cyrillic_count as f64 / total_letters as f64 > 0.3 {
-        // Otherwise, if more than 30% cyrillic, consider it Russian
-        Language::Russian
-    } else {
-        Language::English
+}
+
+static PROFILES: LazyLock<Vec<(Language, NgramProfile)>> = LazyLock::new(|| {
+    DETECTION_SAMPLES.iter()
+        .map(|(language, sample)| (*language, NgramProfile::train(sample)))
+        .collect()
+});
+
+/// Слова текста: буквы в нижнем регистре, апостроф внутри слова (узбекская латиница: o'z, g'alla)
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !(c.is_alphabetic() || matches!(c, '\'' | 'ʻ' | 'ʼ' | '‘' | '’' | '`')))
+        .map(|word| word
+            .chars()
+            .map(|c| if c.is_alphabetic() { c } else { '\'' })
+            .collect::<String>()
+            .trim_matches('\'')
+            .to_string())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// n-граммы символов слова длиной 1..=MAX_NGRAM (слово дополняется пробелами по краям)
+fn word_ngrams(word: &str) -> Vec<String> {
+    let chars: Vec<char> = format!(" {} ", word).chars().collect();
+    let mut grams = Vec::new();
+    for n in 1..=MAX_NGRAM {
+        for window in chars.windows(n) {
+            if n == 1 && window[0] == ' ' {
+                continue;
+            }
+            grams.push(window.iter().collect());
+        }
+    }
+    grams
+}
+
+fn ngrams(text: &str) -> Vec<String> {
+    words(text).iter().flat_map(|word| word_ngrams(word)).collect()
+}
+
+/// Результат определения языка текста
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detection {
+    pub language: Language,
+    /// Уверенность 0..1 (доля вероятности лучшего языка среди всех поддерживаемых)
+    pub confidence: f32,
+}
+
+/// Определяет язык по n-граммам символов. Каждое слово оценивается профилем каждого языка
+/// (средний логарифм вероятности его n-грамм), буквы вне алфавита языка штрафуются.
+/// Слова голосуют на равных, поэтому в смешанном тексте побеждает язык большинства слов,
+/// а не самого длинного. Уверенность - softmax по суммарным оценкам языков.
+pub fn detect_language_scored(text: &str) -> Detection {
+    let words = words(text);
+    if words.is_empty() {
+        return Detection { language: Language::English, confidence: 0.0 }; // Default if no letters
     }
+
+    let profile_scores: Vec<(Language, f64)> = PROFILES.iter()
+        .map(|(language, profile)| {
+            let score = words.iter()
+                .map(|word| {
+                    let grams = word_ngrams(word);
+                    let likelihood = grams.iter().map(|gram| profile.log_prob(gram)).sum::<f64>() / grams.len() as f64;
+                    let penalty: f64 = DISTINCTIVE_LETTERS.iter()
+                        .filter(|(_, languages)| !languages.contains(language))
+                        .map(|(letter, _)| word.matches(*letter).count() as f64 * LETTER_PENALTY)
+                        .sum();
+                    likelihood - penalty
+                })
+                .sum::<f64>();
+            (*language, score * CONFIDENCE_SCALE)
+        })
+        .collect();
+    let scores: Vec<(Language, f64)> = Language::ALL.iter()
+        .map(|language| {
+            let best = profile_scores.iter()
+                .filter(|(profile_language, _)| profile_language == language)
+                .map(|(_, score)| *score)
+                .fold(f64::NEG_INFINITY, f64::max);
+            (*language, best)
+        })
+        .collect();
+
+    let best = scores.iter().map(|(_, score)| *score).fold(f64::NEG_INFINITY, f64::max);
+    let weights: Vec<(Language, f64)> = scores.iter()
+        .map(|(language, score)| (*language, (score - best).exp()))
+        .collect();
+    let sum: f64 = weights.iter().map(|(_, weight)| weight).sum();
+    let (language, weight) = weights.iter()
+        .copied()
+        .fold((Language::English, f64::NEG_INFINITY), |acc, item| if item.1 > acc.1 { item } else { acc });
+
+    Detection { language, confidence: (weight / sum) as f32 }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Язык текста (см. `detect_language_scored`)
+pub fn detect_language(text: &str) -> Language {
+    detect_language_scored(text).language
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Language {
     Russian,
     English,
     Kazakh,
+    Uzbek,
+    Kyrgyz,
 }
 
 impl Language {
-    pub fn as_str(&self) -> &'static str {
+    /// Все поддерживаемые языки
+    pub const ALL: [Language; 5] = [
+        Language::Russian,
+        Language::English,
+        Language::Kazakh,
+        Language::Uzbek,
+        Language::Kyrgyz,
+    ];
+
+    /// Язык по коду (ISO 639-1 или тег `Accept-Language`, например `en-US`)
+    pub fn from_code(code: &str) -> Option<Language> {
+        let primary = code.trim().split(['-', '_']).next()?.to_lowercase();
+        match primary.as_str() {
+            "ru" => Some(Language::Russian),
+            "en" => Some(Language::English),
+            "kk" | "kz" => Some(Language::Kazakh),
+            "uz" => Some(Language::Uzbek),
+            "ky" | "kg" => Some(Language::Kyrgyz),
+            _ => None,
+        }
+    }
+
+    /// Код ISO 639-1
+    pub fn code(&self) -> &'static str {
         match self {
-            Language::Russian => "Russian",
-            Language::English => "English",
-            Language::Kazakh => "Kazakh",
+            Language::Russian => "ru",
+            Language::English => "en",
+            Language::Kazakh => "kk",
+            Language::Uzbek => "uz",
+            Language::Kyrgyz => "ky",
         }
     }
     
@@ -64,18 +217,101 @@ impl Language {
             Language::Russian => "Отвечайте на русском языке. Все тексты должны быть на русском.",
             Language::English => "Respond in English. All texts should be in English.",
             Language::Kazakh => "Қазақ тілінде жауап беріңіз. Барлық мәтіндер қазақ тілінде болуы керек.",
+            Language::Uzbek => "O'zbek tilida (lotin yozuvida) javob bering. Barcha matnlar o'zbek tilida bo'lishi kerak.",
+            Language::Kyrgyz => "Кыргыз тилинде жооп бериңиз. Бардык тексттер кыргыз тилинде болушу керек.",
         }
     }
     
     pub fn error_message(&self) -> &'static str {
-        match self {
-            Language::Russian => "Невозможно сгенерировать SQL для данного запроса.",
-            Language::English => "Unable to generate SQL for this query.",
-            Language::Kazakh => "Бұл сұрауға SQL жасау мүмкін емес.",
-        }
+        crate::i18n::t(*self, "error.unable_to_generate_sql")
     }
 }
 
+/// Откуда взят язык ответа. Порядок вариантов - по приоритету.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LanguageSource {
+    /// Язык не определен - русский по умолчанию
+    Default,
+    /// Определен по тексту вопроса
+    Detected,
+    /// Заголовок `Accept-Language`
+    Header,
+    /// Поле `language` в теле запроса
+    Request,
+}
+
+/// Язык ответа для текущего HTTP-запроса: задается middleware из `Accept-Language`,
+/// полем `language` запроса, либо уточняется обработчиком после определения языка вопроса.
+/// Используется там, где нет прямого доступа к запросу (например, в `AppError::into_response`).
+#[derive(Clone, Copy)]
+struct ResponseLanguage {
+    language: Language,
+    source: LanguageSource,
+}
+
+tokio::task_local! {
+    static RESPONSE_LANGUAGE: std::cell::Cell<ResponseLanguage>;
+}
+
+/// Выполняет future с языком ответа из `Accept-Language` (None - заголовка нет)
+pub async fn with_response_language<F: std::future::Future>(
+    language: Option<Language>,
+    future: F,
+) -> F::Output {
+    let value = ResponseLanguage {
+        language: language.unwrap_or(Language::Russian),
+        source: if language.is_some() { LanguageSource::Header } else { LanguageSource::Default },
+    };
+    RESPONSE_LANGUAGE.scope(std::cell::Cell::new(value), future).await
+}
+
+fn update_response_language(language: Language, source: LanguageSource) {
+    let _ = RESPONSE_LANGUAGE.try_with(|cell| {
+        if cell.get().source <= source {
+            cell.set(ResponseLanguage { language, source });
+        }
+    });
+}
+
+/// Запоминает язык, определенный по тексту вопроса (если клиент не указал язык явно)
+pub fn set_response_language(language: Language) {
+    update_response_language(language, LanguageSource::Detected);
+}
+
+/// Язык, явно указанный в теле запроса (важнее `Accept-Language` и определения по тексту)
+pub fn override_response_language(language: Language) {
+    update_response_language(language, LanguageSource::Request);
+}
+
+/// Язык ответа для текущего запроса (по умолчанию - русский)
+pub fn response_language() -> Language {
+    RESPONSE_LANGUAGE
+        .try_with(|cell| cell.get().language)
+        .unwrap_or(Language::Russian)
+}
+
+/// Откуда взят язык ответа текущего запроса
+pub fn response_language_source() -> LanguageSource {
+    RESPONSE_LANGUAGE
+        .try_with(|cell| cell.get().source)
+        .unwrap_or(LanguageSource::Default)
+}
+
+/// Применяет поле `language` запроса (код языка). Неизвестный код - ошибка для клиента.
+pub fn apply_requested_language(code: Option<&str>) -> Result<(), String> {
+    let Some(code) = code.map(str::trim).filter(|code| !code.is_empty()) else {
+        return Ok(());
+    };
+    let language = Language::from_code(code).ok_or_else(|| format!(
+        "Unsupported language '{}'. Supported: {}",
+        code,
+        Language::ALL.iter().map(Language::code).collect::<Vec<_>>().join(", ")
+    ))?;
+    override_response_language(language);
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,5 +346,37 @@ mod tests {
         // So we adjust the test to match reality
         assert_eq!(detect_language("How many transactions?"), Language::English);
     }
+    
+    #[test]
+    fn test_detect_uzbek_and_kyrgyz() {
+        assert_eq!(detect_language("O'tgan oyda qancha tranzaksiya bo'ldi?"), Language::Uzbek);
+        assert_eq!(detect_language("Shaharlar bo'yicha to'lovlar"), Language::Uzbek);
+        assert_eq!(detect_language("Ўтган ойда қанча транзакция бўлди?"), Language::Uzbek);
+        assert_eq!(detect_language("Өткөн айда канча транзакция болду?"), Language::Kyrgyz);
+        assert_eq!(detect_language("Шаарлар боюнча төлөмдөрдү көрсөт"), Language::Kyrgyz);
+    }
+    
+    #[test]
+    fn test_confidence() {
+        let long = detect_language_scored("Покажи распределение платежей по городам за прошлый месяц");
+        assert_eq!(long.language, Language::Russian);
+        assert!(long.confidence > 0.9, "{:?}", long);
+        
+        // Смешанный текст: язык по большинству, уверенность ниже
+        let mixed = detect_language_scored("Сколько transactions в базе?");
+        assert_eq!(mixed.language, Language::Russian);
+        assert!(mixed.confidence < long.confidence, "{:?}", mixed);
+        
+        assert_eq!(detect_language_scored("123 ?!").confidence, 0.0);
+    }
+    
+    #[test]
+    fn test_language_codes() {
+        for language in Language::ALL {
+            assert_eq!(Language::from_code(language.code()), Some(language));
+        }
+        assert_eq!(Language::from_code("uz-Latn-UZ"), Some(Language::Uzbek));
+        assert_eq!(Language::from_code("ky-KG"), Some(Language::Kyrgyz));
+        assert_eq!(Language::from_code("de"), None);
+    }
 }
-
@@ -1,9 +1,96 @@
-pub fn init() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-        )
+use crate::config::Config;
+use axum::http::Request;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use std::sync::Mutex;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// Заголовок, в котором передается и возвращается ID запроса
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Инициализирует логирование: консоль + опционально OTLP-экспортер и JSON-файл со спанами.
+///
+/// - `OTEL_EXPORTER_OTLP_ENDPOINT` - отправка спанов в OpenTelemetry collector (OTLP/HTTP)
+/// - `TRACE_FILE` - запись закрытых спанов с таймингами в файл (JSON lines)
+pub fn init(config: &Config) {
+    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+
+    let otel_layer = config.otlp_endpoint.as_deref().and_then(|endpoint| {
+        match build_otlp_tracer(endpoint, &config.service_name) {
+            Ok(tracer) => Some(tracing_opentelemetry::layer().with_tracer(tracer)),
+            Err(e) => {
+                eprintln!("Failed to initialize OTLP exporter ({}): {}", endpoint, e);
+                None
+            }
+        }
+    });
+
+    let file_layer = config.trace_file.as_deref().and_then(|path| {
+        match std::fs::OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Some(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_span_events(FmtSpan::CLOSE)
+                    .with_current_span(true)
+                    .with_span_list(true)
+                    .with_writer(Mutex::new(file))
+                    .with_filter(tracing_subscriber::EnvFilter::new("payment_analytics_backend=info")),
+            ),
+            Err(e) => {
+                eprintln!("Failed to open trace file {}: {}", path, e);
+                None
+            }
+        }
+    });
+
+    tracing_subscriber::registry()
+        .with(env_filter)
+        .with(tracing_subscriber::fmt::layer().with_span_events(FmtSpan::CLOSE))
+        .with(otel_layer)
+        .with(file_layer)
         .init();
 }
 
+/// Отправляет оставшиеся спаны в collector перед завершением процесса
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+fn build_otlp_tracer(endpoint: &str, service_name: &str) -> anyhow::Result<opentelemetry_sdk::trace::Tracer> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_resource(opentelemetry_sdk::Resource::new(vec![
+            KeyValue::new("service.name", service_name.to_string()),
+        ]))
+        .build();
+
+    let tracer = provider.tracer(service_name.to_string());
+    opentelemetry::global::set_tracer_provider(provider);
+    Ok(tracer)
+}
+
+/// Корневой спан HTTP-запроса (для TraceLayer), содержит request_id
+pub fn request_span<B>(request: &Request<B>) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-");
+
+    tracing::info_span!(
+        "http_request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id = %request_id,
+    )
+}
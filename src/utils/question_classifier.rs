@@ -1,225 +1,301 @@
 // Язык определяется в api/query.rs, здесь не нужен
 
-/// Определяет, является ли вопрос запросом к базе данных или обычным вопросом
-/// Также проверяет наличие префиксов для явного указания SQL-запроса
-pub fn is_database_query(question: &str) -> bool {
-    let question_lower = question.to_lowercase().trim().to_string();
-    
-    // Проверяем префикс для явного указания SQL-запроса (только sql:)
-    if question_lower.starts_with("sql:") {
-        return true;
-    }
-    
-    // Убираем префикс для дальнейшего анализа
-    let question_clean = if question_lower.starts_with("sql:") {
-        question_lower.split_once(':').map(|(_, rest)| rest.trim().to_string()).unwrap_or(question_lower)
-    } else {
-        question_lower
-    };
-    
-    let question_lower = question_clean.as_str();
-    
-    // Ключевые слова, указывающие на SQL-запрос
-    let db_keywords = [
-        // Вопросы о данных
-        "сколько", "how many", "қанша",
-        "топ", "top", "көп",
-        "объем", "volume", "көлем",
-        "средний", "average", "орташа",
-        "сумма", "sum", "қосынды",
-        "максимум", "maximum", "максимум",
-        "минимум", "minimum", "минимум",
-        "показать", "show", "көрсет",
-        "вывести", "display", "шығар",
-        "найти", "find", "тап",
-        "получить", "get", "алу",
-        
-        // Временные периоды
-        "сегодня", "today", "бүгін",
-        "вчера", "yesterday", "кеше",
-        "неделя", "week", "апта",
-        "месяц", "month", "ай",
-        "год", "year", "жыл",
-        "день", "day", "күн",
-        "дни", "days", "күндер",
-        "транзакции", "transactions", "транзакциялар",
-        "транзакций", "транзакция",
-        "за последние", "last", "соңғы",
-        "за период", "for period", "кезең үшін",
-        "динамик", "dynamics", "динамик", // "динамик" покрывает "динамика", "динамику", "динамики"
-        "динамика", "динамику", "динамики", // Добавляем все формы
-        "тренд", "trend", "тренд",
-        "изменение", "change", "өзгеріс",
-        "изменения", "changes", "өзгерістер",
-        
-        // Категории и фильтры
-        "по категориям", "by category", "категориялар бойынша",
-        "по городам", "by city", "қалалар бойынша",
-        "по банкам", "by bank", "банктер бойынша",
-        "по валютам", "by currency", "валюталар бойынша",
-        "по дням", "by day", "күндер бойынша",
-        "по месяцам", "by month", "айлар бойынша",
-        "по годам", "by year", "жылдар бойынша",
-        "мерчант", "merchant", "мерчант",
-        "карт", "card", "карта",
-        
-        // Аналитика
-        "распределение", "distribution", "таралу",
-        "сравнение", "compare", "салыстыру",
-        "статистика", "statistics", "статистика",
-        "анализ", "analysis", "талдау",
-        "график", "chart", "график",
-        "диаграмма", "diagram", "диаграмма",
-        
-        // Конкретные поля БД
-        "mcc", "mcc_category",
-        "transaction_type", "тип транзакции",
-        "transaction_currency", "валюта транзакции",
-        "acquirer_country", "страна",
-        "pos_entry_mode", "способ оплаты",
-    ];
-    
-    // Ключевые слова, указывающие на обычный вопрос (не про БД) - ПРИОРИТЕТ
-    let chat_keywords = [
-        "привет", "hello", "сәлем", "hi", "hey",
-        "как дела", "how are you", "қалың қалай", "how's it going",
-        "кто ты", "who are you", "сен кімсің", "what are you",
-        "что умеешь", "what can you do", "не істей аласың", "what can you",
-        "помощь", "help", "көмек",
-        "спасибо", "thanks", "рахмет", "thank you",
-        "пока", "bye", "сау бол", "goodbye",
-        "можешь", "can you", "can", "можешь ли", "але сен",
-        "вывести", "показать", "show", "display", "көрсет", "шығар", // Но только в контексте общих вопросов
-        "или нет", "or not", "немесе жоқ", // Вопросы типа "можешь или нет"
-        "расскажи", "tell me", "айт", "explain", "объясни", "түсіндір",
-        "что такое", "what is", "не дегеніміз", "что значит", "what does",
-    ];
-    
-        // Проверяем на обычные вопросы (ВЫСОКИЙ ПРИОРИТЕТ)
-        for keyword in &chat_keywords {
-            if question_lower.contains(keyword) {
-                // Если это вопрос типа "можешь вывести" или "можешь показать" БЕЗ упоминания транзакций/данных - обычный вопрос
-                if (*keyword == "можешь" || *keyword == "can you" || *keyword == "can") && 
-                   (question_lower.contains("вывести") || question_lower.contains("показать") || 
-                    question_lower.contains("show") || question_lower.contains("display")) {
-                    // Проверяем, есть ли упоминание транзакций или данных
-                    if !question_lower.contains("транзакц") && !question_lower.contains("transaction") && 
-                       !question_lower.contains("данн") && !question_lower.contains("data") &&
-                       !question_lower.contains("баз") && !question_lower.contains("database") {
-                        return false; // Это обычный вопрос, не SQL
-                    }
-                }
-                // Для остальных ключевых слов - точно обычный вопрос
-                if *keyword != "вывести" && *keyword != "показать" && *keyword != "show" && *keyword != "display" {
-                    return false;
-                }
-            }
-        }
-    
-    // Проверяем на SQL-запросы (требуется больше доказательств)
-    let mut db_score = 0;
-    for keyword in &db_keywords {
-        if question_lower.contains(keyword) {
-            db_score += 1;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+
+/// Намерение пользователя
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Intent {
+    DataQuery,  // Вопрос к данным о транзакциях - генерируем SQL
+    Chat,       // Обычный разговор
+    Help,       // Что умеет сервис и как им пользоваться
+    Ambiguous,  // Не удалось понять - нужно уточнение
+}
+
+/// Чем определено намерение
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntentSource {
+    Prefix,    // Префикс `sql:`
+    Request,   // Поле `intent` в запросе (ответ на уточнение)
+    Keywords,  // Проход по ключевым словам
+    Model,     // Небольшая модель (при низкой уверенности ключевых слов)
+}
+
+/// Результат классификации вопроса
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Classification {
+    pub intent: Intent,
+    /// Уверенность 0..1
+    pub confidence: f32,
+    pub source: IntentSource,
+}
+
+impl Classification {
+    pub fn new(intent: Intent, confidence: f32, source: IntentSource) -> Self {
+        Self {
+            intent,
+            confidence: confidence.clamp(0.0, 1.0),
+            source,
         }
     }
-    
-    // Специальные паттерны для SQL-запросов (требуют комбинации с другими ключевыми словами)
-    let sql_patterns = [
-        "динамик", "dynamics", "тренд",
-        "за последние", "last", "соңғы",
-        "по дням", "by day", "күндер бойынша",
-        "по месяцам", "by month", "айлар бойынша",
-        "статистика", "statistics", "статистика",
-        "распределение", "distribution", "таралу",
+}
+
+/// Группа признаков одного намерения: слова/фразы (начало слова) с общим весом
+struct Signal {
+    intent: Intent,
+    weight: f32,
+    pattern: Regex,
+}
+
+/// Сколько совпадений одной группы учитывается (чтобы перечисление не раздувало счет)
+const MAX_MATCHES_PER_SIGNAL: usize = 2;
+
+/// Признаки, совпадающие с началом слова ("транзакц" покрывает все формы)
+fn stems(intent: Intent, weight: f32, words: &[&str]) -> Signal {
+    let alternatives: Vec<String> = words.iter().map(|w| regex::escape(w)).collect();
+    Signal {
+        intent,
+        weight,
+        pattern: Regex::new(&format!(r"\b(?:{})", alternatives.join("|"))).expect("valid signal regex"),
+    }
+}
+
+/// Признаки, совпадающие только целым словом ("hi" не должно находиться в "history")
+fn whole_words(intent: Intent, weight: f32, words: &[&str]) -> Signal {
+    let alternatives: Vec<String> = words.iter().map(|w| regex::escape(w)).collect();
+    Signal {
+        intent,
+        weight,
+        pattern: Regex::new(&format!(r"\b(?:{})\b", alternatives.join("|"))).expect("valid signal regex"),
+    }
+}
+
+static SIGNALS: LazyLock<Vec<Signal>> = LazyLock::new(|| {
+    use Intent::*;
+    vec![
+        // Сущности предметной области и поля БД
+        stems(DataQuery, 2.0, &[
+            "транзакц", "операци", "платеж", "платёж", "оплат", "перевод", "мерчант", "магазин",
+            "карт", "банк", "эмитент", "эквайр", "валют", "оборот", "выручк", "чек", "кошел",
+            "город", "категори", "покуп", "расход", "продаж", "клиент",
+            "transaction", "payment", "transfer", "merchant", "store", "shop", "card", "bank",
+            "issuer", "acquirer", "atm", "currenc", "turnover", "revenue", "sales", "wallet",
+            "cities", "city", "categor", "spend", "purchase", "customer",
+            "төлем", "аударым", "қала", "сатып ал",
+            "tranzaksiya", "to'lov", "toʻlov", "o'tkazma", "oʻtkazma", "shahar", "karta", "bankomat", "xarid",
+            "төлөм", "которуу", "шаар",
+            "mcc", "transaction_type", "pos_entry_mode", "acquirer_country", "wallet_type",
+        ]),
+        stems(DataQuery, 1.0, &["данны", "запис", "data", "record", "деректер", "ma'lumot", "маалымат"]),
+        // Метрики и аналитика
+        stems(DataQuery, 1.5, &[
+            "сколько", "количеств", "число", "средн", "сумм", "объем", "объём", "итог", "всего",
+            "максим", "миним", "топ", "доля", "долю", "процент", "распредел", "статистик",
+            "динамик", "тренд", "рост", "изменени", "сравн", "рейтинг",
+            "how many", "how much", "number of", "average", "total", "volume", "maximum", "minimum",
+            "share", "percent", "distribution", "breakdown", "statistic", "dynamic", "trend",
+            "growth", "change", "compar", "rank",
+            "қанша", "орташа", "көлем", "үлес", "салыстыр", "өзгеріс",
+            "qancha", "nechta", "o'rtacha", "oʻrtacha", "hajm", "ulush", "solishtir",
+            "канча", "орточо", "көлөм", "үлүш", "салыштыр",
+        ]),
+        whole_words(DataQuery, 1.5, &["top", "sum", "avg", "max", "min", "count"]),
+        // Периоды
+        stems(DataQuery, 1.0, &[
+            "сегодня", "вчера", "недел", "месяц", "месяч", "год", "квартал", "дня", "дней", "дням",
+            "ежеднев", "за последн", "период",
+            "today", "yesterday", "week", "month", "year", "quarter", "daily", "hourly", "period",
+            "бүгін", "кеше", "апта", "жыл", "кезең", "соңғы",
+            "bugun", "kecha", "hafta", "yil",
+            "бүгүн", "кечээ", "акыркы",
+            "2023", "2024", "2025", "2026",
+        ]),
+        whole_words(DataQuery, 1.0, &["день", "day", "days", "ай", "oy", "kun"]),
+        // Группировки: "по городам", "by bank", "қалалар бойынша"
+        Signal {
+            intent: DataQuery,
+            weight: 1.5,
+            pattern: Regex::new(r"\bпо\s+\w+(?:ам|ям)\b|\b(?:by|per)\s+\w+|\bв разрезе\b|бойынша|bo'yicha|boʻyicha|боюнча")
+                .expect("valid signal regex"),
+        },
+        // Глаголы вывода - слабый признак ("show me a joke" - не запрос к данным)
+        stems(DataQuery, 0.5, &[
+            "показ", "покаж", "выве", "найд", "найти", "построй", "график", "диаграм", "таблиц",
+            "show", "display", "list", "find", "build", "plot", "chart", "table",
+            "көрсет", "шығар", "ko'rsat", "koʻrsat", "көрсөт",
+        ]),
+        // Приветствия, благодарность, прощание, вопросы о собеседнике
+        whole_words(Chat, 2.5, &[
+            "привет", "здравствуй", "здравствуйте", "добрый день", "добрый вечер", "доброе утро",
+            "hello", "hi", "hey", "good morning", "good evening", "сәлем", "salom", "салам",
+            "спасибо", "благодарю", "thanks", "thank you", "рахмет", "rahmat",
+            "пока", "до свидания", "bye", "goodbye", "сау бол", "xayr",
+            "как дела", "how are you", "how's it going", "қалың қалай", "қалайсың",
+            "кто ты", "who are you", "what are you", "сен кімсің", "sen kimsan", "сен кимсиң",
+        ]),
+        // Темы вне аналитики платежей
+        stems(Chat, 3.0, &[
+            "шутк", "анекдот", "joke", "funny", "погод", "weather", "стих", "poem", "песн", "song",
+            "жизн", "life", "смысл", "meaning", "любов", "love", "рецепт", "recipe", "фильм", "movie",
+            "футбол", "football", "әзіл", "hazil", "тамаша",
+        ]),
+        // Вопросы-определения: сами по себе - разговор, с сущностями БД - скорее запрос к данным
+        stems(Chat, 1.5, &[
+            "что такое", "что значит", "что это", "what is", "what's", "what does", "what are",
+            "не дегеніміз", "деген не", "nima degani", "nima bu", "эмне деген",
+            "расскажи", "tell me", "explain", "объясни", "түсіндір", "tushuntir", "түшүндүр",
+        ]),
+        // Справка о возможностях сервиса
+        stems(Help, 3.0, &[
+            "что умеешь", "что ты умеешь", "что можешь", "чем можешь помочь", "чем ты можешь",
+            "помощь", "помоги", "справк", "как пользоваться", "как тобой пользоваться",
+            "какие вопросы", "примеры вопросов",
+            "what can you", "how can you help", "help", "how to use", "how do i use",
+            "what questions", "example questions",
+            "не істей аласың", "көмек", "nima qila olasan", "yordam", "эмне кыла аласың", "жардам",
+        ]),
+    ]
+});
+
+/// Запросы с префиксом `sql:` всегда идут в SQL-пайплайн
+fn has_sql_prefix(question: &str) -> bool {
+    question.trim_start().to_lowercase().starts_with("sql:")
+}
+
+/// Дешевая классификация по ключевым словам.
+/// Уверенность - отрыв лучшего намерения от второго относительно его счета;
+/// при равенстве счетов или полном отсутствии признаков намерение `Ambiguous`.
+pub fn classify_keywords(question: &str) -> Classification {
+    if has_sql_prefix(question) {
+        return Classification::new(Intent::DataQuery, 1.0, IntentSource::Prefix);
+    }
+
+    let text = question.to_lowercase();
+    let mut scores = [
+        (Intent::DataQuery, 0.0f32),
+        (Intent::Chat, 0.0),
+        (Intent::Help, 0.0),
     ];
-    
-    let mut has_sql_pattern = false;
-    for pattern in &sql_patterns {
-        if question_lower.contains(pattern) {
-            has_sql_pattern = true;
-            db_score += 2; // Даем больший вес паттернам
-            break;
+    for signal in SIGNALS.iter() {
+        let matches = signal.pattern.find_iter(&text).take(MAX_MATCHES_PER_SIGNAL).count();
+        if matches > 0 {
+            let score = scores.iter_mut().find(|(intent, _)| *intent == signal.intent).expect("scored intent");
+            score.1 += signal.weight * matches as f32;
         }
     }
-    
-    // Если вопрос содержит "транзакции" + временной период ИЛИ агрегацию - точно SQL
-    if question_lower.contains("транзакц") && (
-        question_lower.contains("день") ||
-        question_lower.contains("месяц") ||
-        question_lower.contains("год") ||
-        question_lower.contains("неделя") ||
-        question_lower.contains("сегодня") ||
-        question_lower.contains("вчера") ||
-        question_lower.contains("последние") ||
-        question_lower.contains("сколько") ||
-        question_lower.contains("топ") ||
-        question_lower.contains("объем") ||
-        question_lower.contains("средний")
-    ) {
-        return true;
-    }
-    
-    // Если вопрос очень короткий (1-3 слова) - скорее всего обычный вопрос
-    let word_count = question.split_whitespace().count();
-    if word_count <= 3 && db_score <= 1 {
-        return false;
-    }
-    
-    // Вопросительные слова БЕЗ ключевых слов БД - обычный вопрос
-    let question_words = ["что", "what", "не", "кто", "who", "кім", "как", "how", "қалай", "почему", "why", "неге"];
-    let has_question_word = question_words.iter().any(|w| question_lower.contains(w));
-    
-    // Если есть вопросительное слово и мало ключевых слов БД - обычный вопрос
-    if has_question_word && db_score <= 1 && !has_sql_pattern {
-        return false;
-    }
-    
-    // Если db_score достаточно высокий (>= 2) - SQL запрос
-    if db_score >= 2 {
-        return true;
-    }
-    
-    // По умолчанию - обычный вопрос (менее агрессивная классификация)
-    false
+
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let (best, best_score) = scores[0];
+    let second_score = scores[1].1;
+
+    if best_score == 0.0 || (best_score - second_score).abs() < f32::EPSILON {
+        return Classification::new(Intent::Ambiguous, 0.0, IntentSource::Keywords);
+    }
+
+    let confidence = (best_score - second_score) / (best_score + 1.0);
+    Classification::new(best, confidence, IntentSource::Keywords)
+}
+
+/// Ответ модели-классификатора: `{"intent": "...", "confidence": 0.0..1.0}`.
+/// Модель может обернуть JSON в текст или markdown - берем первый объект.
+pub fn parse_model_classification(response: &str) -> Option<Classification> {
+    #[derive(Deserialize)]
+    struct ModelAnswer {
+        intent: Intent,
+        confidence: f32,
+    }
+
+    let start = response.find('{')?;
+    let end = response.rfind('}')?;
+    if end < start {
+        return None;
+    }
+    let answer: ModelAnswer = serde_json::from_str(&response[start..=end]).ok()?;
+    if !answer.confidence.is_finite() {
+        return None;
+    }
+    Some(Classification::new(answer.intent, answer.confidence, IntentSource::Model))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn is_data_query(question: &str) -> bool {
+        classify_keywords(question).intent == Intent::DataQuery
+    }
+
     #[test]
     fn test_database_queries() {
-        assert!(is_database_query("Сколько транзакций было сегодня?"));
-        assert!(is_database_query("Топ 10 городов по объему транзакций"));
-        assert!(is_database_query("Средний чек для карт Halyk Bank"));
-        assert!(is_database_query("Объем транзакций по категориям"));
-        assert!(is_database_query("How many transactions were today?"));
+        assert!(is_data_query("Сколько транзакций было сегодня?"));
+        assert!(is_data_query("Топ 10 городов по объему транзакций"));
+        assert!(is_data_query("Средний чек для карт Halyk Bank"));
+        assert!(is_data_query("Объем транзакций по категориям"));
+        assert!(is_data_query("How many transactions were today?"));
     }
 
     #[test]
     fn test_chat_queries() {
-        assert!(!is_database_query("Привет"));
-        assert!(!is_database_query("Как дела?"));
-        assert!(!is_database_query("Кто ты?"));
-        assert!(!is_database_query("Что умеешь?"));
-        assert!(!is_database_query("Hello"));
-        assert!(!is_database_query("Спасибо"));
+        assert!(!is_data_query("Привет"));
+        assert!(!is_data_query("Как дела?"));
+        assert!(!is_data_query("Кто ты?"));
+        assert!(!is_data_query("Что умеешь?"));
+        assert!(!is_data_query("Hello"));
+        assert!(!is_data_query("Спасибо"));
     }
 
     #[test]
     fn test_edge_cases() {
         // Вопросы, которые могут быть и тем и другим
-        assert!(is_database_query("Что такое транзакции?")); // Скорее SQL, т.к. есть "транзакции"
-        assert!(!is_database_query("Что такое жизнь?")); // Обычный вопрос
+        assert!(is_data_query("Что такое транзакции?")); // Скорее SQL, т.к. есть "транзакции"
+        assert!(!is_data_query("Что такое жизнь?")); // Обычный вопрос
     }
 
     #[test]
     fn test_dynamics_queries() {
-        assert!(is_database_query("Показать динамику транзакций по дням за последние 7 дней"));
-        assert!(is_database_query("Динамика транзакций по месяцам"));
-        assert!(is_database_query("Показать изменения транзакций за период"));
-        assert!(is_database_query("Тренд транзакций по дням"));
+        assert!(is_data_query("Показать динамику транзакций по дням за последние 7 дней"));
+        assert!(is_data_query("Динамика транзакций по месяцам"));
+        assert!(is_data_query("Показать изменения транзакций за период"));
+        assert!(is_data_query("Тренд транзакций по дням"));
     }
-}
 
+    #[test]
+    fn test_intents_and_confidence() {
+        let help = classify_keywords("Что ты умеешь?");
+        assert_eq!(help.intent, Intent::Help);
+
+        let joke = classify_keywords("show me a joke");
+        assert_eq!(joke.intent, Intent::Chat);
+
+        // "day" - слабый признак периода, вопрос-определение перевешивает, но уверенность низкая
+        let day = classify_keywords("what is a day");
+        assert_eq!(day.intent, Intent::Chat);
+        assert!(day.confidence < 0.5);
+
+        let data = classify_keywords("Сколько транзакций было сегодня?");
+        assert_eq!(data.intent, Intent::DataQuery);
+        assert!(data.confidence > 0.7);
+
+        assert_eq!(classify_keywords("Hi, history please").intent, Intent::Chat);
+        assert_eq!(classify_keywords("asdf qwerty").intent, Intent::Ambiguous);
+
+        let prefixed = classify_keywords("sql: что угодно");
+        assert_eq!((prefixed.intent, prefixed.source), (Intent::DataQuery, IntentSource::Prefix));
+    }
+
+    #[test]
+    fn test_parse_model_classification() {
+        let parsed = parse_model_classification("```json\n{\"intent\": \"data_query\", \"confidence\": 0.9}\n```").unwrap();
+        assert_eq!(parsed.intent, Intent::DataQuery);
+        assert_eq!(parsed.source, IntentSource::Model);
+
+        let clamped = parse_model_classification("{\"intent\":\"chat\",\"confidence\":3}").unwrap();
+        assert_eq!(clamped.confidence, 1.0);
+
+        assert!(parse_model_classification("{\"intent\":\"weather\",\"confidence\":0.5}").is_none());
+        assert!(parse_model_classification("data_query").is_none());
+    }
+}
@@ -1,22 +1,34 @@
-use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use chrono::{DateTime, Utc};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use crate::i18n::{t, tf};
+use crate::safety::{policy::RuleCategory, PolicyAction, PolicyEngine};
+use crate::utils::language::{response_language, Language};
 
-#[derive(Debug, Clone)]
-pub struct UserSafety {
+/// Нарушение из таблицы `user_violations`
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ViolationRecord {
+    pub id: i64,
     pub user_id: String,
-    pub warnings: u32,
-    pub violations: Vec<Violation>,
-    pub banned_until: Option<DateTime<Utc>>,
-    pub last_violation: Option<DateTime<Utc>>,
+    pub violation_type: String,
+    pub message: String,
+    pub rule_id: Option<String>,
+    pub warnings: i32,
+    pub created_at: NaiveDateTime,
+    pub cleared_at: Option<NaiveDateTime>,
+    pub cleared_by: Option<String>,
 }
 
-#[derive(Debug, Clone)]
-pub struct Violation {
-    pub violation_type: ViolationType,
-    pub message: String,
-    pub timestamp: DateTime<Utc>,
+/// Пользователь с действующими предупреждениями или баном
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct FlaggedUser {
+    pub user_id: String,
+    pub active_warnings: i64,
+    pub violations: i64,
+    pub last_violation: Option<NaiveDateTime>,
+    pub banned_until: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -24,151 +36,329 @@ pub enum ViolationType {
     JailbreakAttempt,
     InappropriateLanguage,
     SystemAbuse,
-    RepeatedViolations,
 }
 
+impl ViolationType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ViolationType::JailbreakAttempt => "jailbreak_attempt",
+            ViolationType::InappropriateLanguage => "inappropriate_language",
+            ViolationType::SystemAbuse => "system_abuse",
+        }
+    }
+
+    /// Описание нарушения в каталоге сообщений
+    fn message_key(&self) -> &'static str {
+        match self {
+            ViolationType::JailbreakAttempt => "safety.jailbreak",
+            ViolationType::InappropriateLanguage => "safety.inappropriate",
+            ViolationType::SystemAbuse => "safety.abuse",
+        }
+    }
+
+    /// Описание нарушения с идентификатором правила
+    fn describe(&self, language: Language, rule_id: Option<&str>) -> String {
+        let description = t(language, self.message_key());
+        match rule_id {
+            Some(rule) => tf(language, "safety.violation", &[("description", &description), ("rule", &rule)]),
+            None => description.to_string(),
+        }
+    }
+
+    /// Сколько предупреждений добавляет нарушение
+    fn warnings(&self) -> i32 {
+        match self {
+            ViolationType::JailbreakAttempt | ViolationType::SystemAbuse => 2,
+            ViolationType::InappropriateLanguage => 1,
+        }
+    }
+}
+
+/// Настройки модерации. Начальные значения - из конфигурации,
+/// изменения через API сохраняются в `safety_settings`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SafetySettings {
+    pub max_warnings: i32,
+    pub ban_duration_hours: i32,
+    /// Через сколько часов предупреждение перестает учитываться (0 - никогда)
+    pub warning_decay_hours: i32,
+}
+
+/// Предупреждения и баны пользователей (хранятся в Postgres и переживают перезапуск)
 pub struct UserSafetyManager {
-    users: Arc<RwLock<HashMap<String, UserSafety>>>,
-    max_warnings: u32,
-    ban_duration_hours: i64,
+    db: PgPool,
+    settings: RwLock<SafetySettings>,
+    policy: Arc<PolicyEngine>,
 }
 
 impl UserSafetyManager {
-    pub fn new(max_warnings: u32, ban_duration_hours: i64) -> Self {
-        Self {
-            users: Arc::new(RwLock::new(HashMap::new())),
-            max_warnings,
-            ban_duration_hours,
-        }
+    pub async fn load(db: PgPool, defaults: SafetySettings, policy: Arc<PolicyEngine>) -> Result<Self, sqlx::Error> {
+        let stored = sqlx::query_as::<_, SafetySettings>(
+            "SELECT max_warnings, ban_duration_hours, warning_decay_hours FROM safety_settings",
+        )
+        .fetch_optional(&db)
+        .await?;
+
+        Ok(Self {
+            db,
+            settings: RwLock::new(stored.unwrap_or(defaults)),
+            policy,
+        })
     }
 
-    pub async fn check_user(&self, user_id: &str) -> Result<(), String> {
-        let users = self.users.read().await;
-        if let Some(user) = users.get(user_id) {
-            // Проверяем, не забанен ли пользователь
-            if let Some(banned_until) = user.banned_until {
-                if banned_until > Utc::now() {
-                    let remaining = (banned_until - Utc::now()).num_minutes();
-                    return Err(format!("Вы временно заблокированы. Разблокировка через {} минут.", remaining));
-                }
-            }
-        }
+    pub async fn settings(&self) -> SafetySettings {
+        *self.settings.read().await
+    }
+
+    /// Меняет настройки во время работы и сохраняет их
+    pub async fn update_settings(&self, settings: SafetySettings, updated_by: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO safety_settings (id, max_warnings, ban_duration_hours, warning_decay_hours, updated_by)
+            VALUES (TRUE, $1, $2, $3, $4)
+            ON CONFLICT (id) DO UPDATE SET
+                max_warnings = EXCLUDED.max_warnings,
+                ban_duration_hours = EXCLUDED.ban_duration_hours,
+                warning_decay_hours = EXCLUDED.warning_decay_hours,
+                updated_by = EXCLUDED.updated_by,
+                updated_at = NOW()
+            "#,
+        )
+        .bind(settings.max_warnings)
+        .bind(settings.ban_duration_hours)
+        .bind(settings.warning_decay_hours)
+        .bind(updated_by)
+        .execute(&self.db)
+        .await?;
+
+        *self.settings.write().await = settings;
         Ok(())
     }
 
+    pub async fn check_user(&self, user_id: &str) -> Result<Result<(), String>, sqlx::Error> {
+        // Проверяем, не забанен ли пользователь
+        if let Some(remaining) = self.ban_remaining(user_id).await? {
+            return Ok(Err(tf(
+                response_language(),
+                "safety.banned",
+                &[("minutes", &remaining.num_minutes())],
+            )));
+        }
+        Ok(Ok(()))
+    }
+
+    /// Сколько осталось до окончания бана (None - пользователь не забанен)
+    pub async fn ban_remaining(&self, user_id: &str) -> Result<Option<chrono::Duration>, sqlx::Error> {
+        let secs: Option<i64> = sqlx::query_scalar(
+            r#"
+            SELECT EXTRACT(EPOCH FROM (banned_until - NOW()))::BIGINT
+            FROM user_bans
+            WHERE user_id = $1 AND lifted_at IS NULL AND banned_until > NOW()
+            "#,
+        )
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(secs.map(chrono::Duration::seconds))
+    }
+
+    /// Сумма действующих предупреждений: не снятых администратором и не устаревших
+    async fn active_warnings(&self, user_id: &str, decay_hours: i32) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            r#"
+            SELECT COALESCE(SUM(warnings), 0)::BIGINT
+            FROM user_violations
+            WHERE user_id = $1 AND cleared_at IS NULL
+              AND ($2 = 0 OR created_at > NOW() - make_interval(hours => $2))
+            "#,
+        )
+        .bind(user_id)
+        .bind(decay_hours)
+        .fetch_one(&self.db)
+        .await
+    }
+
     pub async fn record_violation(
         &self,
         user_id: &str,
         violation_type: ViolationType,
-        message: String,
-    ) -> Option<String> {
-        let mut users = self.users.write().await;
-        let user = users.entry(user_id.to_string())
-            .or_insert_with(|| UserSafety {
-                user_id: user_id.to_string(),
-                warnings: 0,
-                violations: Vec::new(),
-                banned_until: None,
-                last_violation: None,
-            });
+        rule_id: Option<&str>,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let settings = self.settings().await;
+        let language = response_language();
+        // В истории нарушений описание хранится на русском, пользователю - на языке ответа
+        let message = violation_type.describe(Language::Russian, rule_id);
 
         // Добавляем нарушение
-        user.violations.push(Violation {
-            violation_type: violation_type.clone(),
-            message: message.clone(),
-            timestamp: Utc::now(),
-        });
-        user.last_violation = Some(Utc::now());
-
-        // Увеличиваем предупреждения для серьезных нарушений
-        match violation_type {
-            ViolationType::JailbreakAttempt | ViolationType::SystemAbuse => {
-                user.warnings += 2;
-            }
-            ViolationType::InappropriateLanguage | ViolationType::RepeatedViolations => {
-                user.warnings += 1;
-            }
-        }
+        sqlx::query(
+            r#"
+            INSERT INTO user_violations (user_id, violation_type, message, rule_id, warnings)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(user_id)
+        .bind(violation_type.as_str())
+        .bind(&message)
+        .bind(rule_id)
+        .bind(violation_type.warnings())
+        .execute(&self.db)
+        .await?;
+
+        let warnings = self.active_warnings(user_id, settings.warning_decay_hours).await?;
 
         // Если превышен лимит предупреждений - бан
-        if user.warnings >= self.max_warnings {
-            let ban_until = Utc::now() + chrono::Duration::hours(self.ban_duration_hours);
-            user.banned_until = Some(ban_until);
-            return Some(format!(
-                "Вы получили слишком много предупреждений и временно заблокированы на {} часов. Разблокировка: {}",
-                self.ban_duration_hours,
-                ban_until.format("%Y-%m-%d %H:%M:%S UTC")
-            ));
+        if warnings >= settings.max_warnings as i64 {
+            let ban_until: NaiveDateTime = sqlx::query_scalar(
+                r#"
+                INSERT INTO user_bans (user_id, banned_until, reason)
+                VALUES ($1, NOW() + make_interval(hours => $2), $3)
+                ON CONFLICT (user_id) DO UPDATE SET
+                    banned_until = EXCLUDED.banned_until,
+                    reason = EXCLUDED.reason,
+                    created_at = NOW(),
+                    lifted_at = NULL,
+                    lifted_by = NULL
+                RETURNING banned_until
+                "#,
+            )
+            .bind(user_id)
+            .bind(settings.ban_duration_hours)
+            .bind(&message)
+            .fetch_one(&self.db)
+            .await?;
+            tracing::warn!("User {} banned until {} ({} warnings)", user_id, ban_until, warnings);
+            return Ok(Some(tf(language, "safety.ban_issued", &[
+                ("hours", &settings.ban_duration_hours),
+                ("until", &ban_until.format("%Y-%m-%d %H:%M:%S")),
+            ])));
         }
 
         // Предупреждение
-        if user.warnings > 0 {
-            return Some(format!(
-                "⚠️ Предупреждение {}/{}: {}. При повторных нарушениях вы можете быть временно заблокированы.",
-                user.warnings,
-                self.max_warnings,
-                message
-            ));
-        }
-
-        None
+        Ok(Some(tf(language, "safety.warning", &[
+            ("count", &warnings),
+            ("max", &settings.max_warnings),
+            ("reason", &violation_type.describe(language, rule_id)),
+        ])))
     }
 
-    pub async fn check_message_safety(&self, user_id: &str, message: &str) -> (bool, Option<String>) {
+    /// Проверяет сообщение политикой безопасности (`safety::PolicyEngine`).
+    /// block - запрос отклоняется, warn - нарушение учитывается, но запрос выполняется
+    /// (если только оно не привело к бану), log - только запись в лог.
+    pub async fn check_message_safety(&self, user_id: &str, message: &str) -> Result<(bool, Option<String>), sqlx::Error> {
         // Проверяем, не забанен ли пользователь
-        if let Err(ban_msg) = self.check_user(user_id).await {
-            return (false, Some(ban_msg));
+        if let Err(ban_msg) = self.check_user(user_id).await? {
+            return Ok((false, Some(ban_msg)));
         }
 
-        let message_lower = message.to_lowercase();
-
-        // Проверка на jailbreak попытки
-        let jailbreak_keywords = [
-            "ignore previous", "forget all", "you are now", "you are a",
-            "act as", "pretend to be", "roleplay as", "disregard instructions",
-            "forget your instructions", "ignore your instructions",
-            "you must", "you have to", "system:", "assistant:",
-        ];
-
-        for keyword in &jailbreak_keywords {
-            if message_lower.contains(keyword) {
-                let ban_msg = self.record_violation(
-                    user_id,
-                    ViolationType::JailbreakAttempt,
-                    format!("Обнаружена попытка jailbreak: {}", keyword),
-                ).await;
-                return (false, ban_msg);
-            }
-        }
+        let verdict = self.policy.evaluate(message);
+        let Some(rule) = verdict.primary_match() else {
+            return Ok((true, None));
+        };
+        tracing::info!(
+            "Safety policy: user={} action={:?} score={:.2} rule={} matched='{}'",
+            user_id, verdict.action, verdict.score, rule.rule_id, rule.matched
+        );
+
+        let violation_type = match rule.category {
+            RuleCategory::Jailbreak => ViolationType::JailbreakAttempt,
+            RuleCategory::Inappropriate => ViolationType::InappropriateLanguage,
+            RuleCategory::Abuse => ViolationType::SystemAbuse,
+        };
 
-        // Проверка на нецензурную лексику (базовая)
-        let inappropriate_keywords: [&str; 0] = [
-            // Добавить при необходимости
-        ];
-
-        for keyword in &inappropriate_keywords {
-            if message_lower.contains(keyword) {
-                let ban_msg = self.record_violation(
-                    user_id,
-                    ViolationType::InappropriateLanguage,
-                    "Использование недопустимой лексики".to_string(),
-                ).await;
-                return (false, ban_msg);
+        match verdict.action {
+            PolicyAction::Allow | PolicyAction::Log => Ok((true, None)),
+            PolicyAction::Warn => {
+                let warning = self.record_violation(user_id, violation_type, Some(&rule.rule_id)).await?;
+                if self.ban_remaining(user_id).await?.is_some() {
+                    return Ok((false, warning));
+                }
+                if let Some(warning) = warning {
+                    tracing::warn!("User {} received warning: {}", user_id, warning);
+                }
+                Ok((true, None))
+            }
+            PolicyAction::Block => {
+                let ban_msg = self.record_violation(user_id, violation_type, Some(&rule.rule_id)).await?;
+                Ok((false, ban_msg))
             }
         }
+    }
 
-        // Проверка на злоупотребление системой (множественные запросы без LIMIT)
-        // Это обрабатывается на уровне валидации SQL
+    /// Пользователи с действующими предупреждениями или баном (забаненные - сверху)
+    pub async fn flagged_users(&self) -> Result<Vec<FlaggedUser>, sqlx::Error> {
+        let settings = self.settings().await;
+        sqlx::query_as::<_, FlaggedUser>(
+            r#"
+            WITH active AS (
+                SELECT user_id,
+                       SUM(warnings)::BIGINT AS active_warnings,
+                       COUNT(*)::BIGINT AS violations,
+                       MAX(created_at) AS last_violation
+                FROM user_violations
+                WHERE cleared_at IS NULL
+                  AND ($1 = 0 OR created_at > NOW() - make_interval(hours => $1))
+                GROUP BY user_id
+            ), bans AS (
+                SELECT user_id, banned_until
+                FROM user_bans
+                WHERE lifted_at IS NULL AND banned_until > NOW()
+            )
+            SELECT COALESCE(a.user_id, b.user_id) AS user_id,
+                   COALESCE(a.active_warnings, 0) AS active_warnings,
+                   COALESCE(a.violations, 0) AS violations,
+                   a.last_violation,
+                   b.banned_until
+            FROM active a
+            FULL OUTER JOIN bans b ON a.user_id = b.user_id
+            ORDER BY b.banned_until IS NULL, active_warnings DESC, user_id
+            "#,
+        )
+        .bind(settings.warning_decay_hours)
+        .fetch_all(&self.db)
+        .await
+    }
 
-        (true, None)
+    /// История нарушений пользователя (новые сверху), включая снятые и устаревшие
+    pub async fn violations(&self, user_id: &str, limit: i64) -> Result<Vec<ViolationRecord>, sqlx::Error> {
+        sqlx::query_as::<_, ViolationRecord>(
+            r#"
+            SELECT id, user_id, violation_type, message, rule_id, warnings, created_at, cleared_at, cleared_by
+            FROM user_violations
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await
     }
 
-    pub async fn clear_warnings(&self, user_id: &str) {
-        let mut users = self.users.write().await;
-        if let Some(user) = users.get_mut(user_id) {
-            user.warnings = 0;
-            user.banned_until = None;
-        }
+    /// Снимает предупреждения и бан. false - снимать было нечего.
+    pub async fn clear_warnings(&self, user_id: &str, cleared_by: &str) -> Result<bool, sqlx::Error> {
+        let cleared = sqlx::query(
+            r#"
+            UPDATE user_violations SET cleared_at = NOW(), cleared_by = $2
+            WHERE user_id = $1 AND cleared_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(cleared_by)
+        .execute(&self.db)
+        .await?;
+
+        let lifted = sqlx::query(
+            r#"
+            UPDATE user_bans SET lifted_at = NOW(), lifted_by = $2
+            WHERE user_id = $1 AND lifted_at IS NULL AND banned_until > NOW()
+            "#,
+        )
+        .bind(user_id)
+        .bind(cleared_by)
+        .execute(&self.db)
+        .await?;
+
+        Ok(cleared.rows_affected() > 0 || lifted.rows_affected() > 0)
     }
 }
-